@@ -0,0 +1,106 @@
+// Benchmarks for mapped memory access, checksums and searches.
+// Run with `cargo bench`. Each benchmark scans memory repeatedly and reports
+// the time per scan. The "two lookups" variants resolve each address through
+// the mapping table by hand, as peek and poke did before the window table was
+// cached, and the "byte-at-a-time" variants checksum and search the banks as
+// they were before the SIMD and word-at-a-time fast paths.
+
+use std::fs::File;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lib_zx_sna::{Pattern, Snapshot};
+
+const SCANS: u32 = 500;
+
+fn time<F: FnMut()>(name: &str, mut scan: F) -> Duration {
+    scan(); // warm up
+    let start = Instant::now();
+    for _ in 0..SCANS {
+        scan();
+    }
+    let elapsed = start.elapsed() / SCANS;
+    println!("{:<28} {:>10.1?} per scan", name, elapsed);
+    elapsed
+}
+
+fn two_lookup_peek(snapshot: &Snapshot, address: u16) -> u8 {
+    if address < 0x4000 {
+        return 0xFF;
+    }
+    let bank_index = ((address >> 14) & 0x03) - 1;
+    snapshot.banks[snapshot.mapping()[bank_index as usize] as usize][(address & 0x3FFF) as usize]
+}
+
+fn two_lookup_poke(snapshot: &mut Snapshot, address: u16, value: u8) {
+    let bank_index = ((address >> 14) & 0x03) - 1;
+    let bank = snapshot.mapping()[bank_index as usize] as usize;
+    snapshot.banks[bank][(address & 0x3FFF) as usize] = value;
+}
+
+fn naive_checksum(bank: &[u8]) -> u16 {
+    bank.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16))
+}
+
+fn naive_find(bank: &[u8], needle: &[u8]) -> usize {
+    bank.windows(needle.len()).filter(|window| *window == needle).count()
+}
+
+fn main() {
+    let file = File::open("128k.sna").expect("Failed to open snapshot file");
+    let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+
+    let baseline = time("peek (two lookups)", || {
+        let mut sum = 0u32;
+        for address in 0x4000..=0xFFFF {
+            sum = sum.wrapping_add(two_lookup_peek(&snapshot, black_box(address)) as u32);
+        }
+        black_box(sum);
+    });
+    let cached = time("peek (window table)", || {
+        let mut sum = 0u32;
+        for address in 0x4000..=0xFFFF {
+            sum = sum.wrapping_add(snapshot.peek(black_box(address)) as u32);
+        }
+        black_box(sum);
+    });
+    println!("{:<28} {:>10.2}x", "peek speedup", baseline.as_secs_f64() / cached.as_secs_f64());
+
+    let baseline = time("poke (two lookups)", || {
+        for address in 0x4000..=0xFFFF {
+            two_lookup_poke(&mut snapshot, black_box(address), address as u8);
+        }
+    });
+    let cached = time("poke (window table)", || {
+        for address in 0x4000..=0xFFFF {
+            snapshot.poke(black_box(address), address as u8).unwrap();
+        }
+    });
+    println!("{:<28} {:>10.2}x", "poke speedup", baseline.as_secs_f64() / cached.as_secs_f64());
+
+    let baseline = time("checksum (byte-at-a-time)", || {
+        for bank in &snapshot.banks {
+            black_box(naive_checksum(black_box(bank)));
+        }
+    });
+    let fast = time("checksum (SIMD)", || {
+        for bank in 0..snapshot.banks.len() {
+            black_box(snapshot.checksum(black_box(bank)).unwrap());
+        }
+    });
+    println!("{:<28} {:>10.2}x", "checksum speedup", baseline.as_secs_f64() / fast.as_secs_f64());
+
+    // LD (nn),A : RET, which is rare enough that most of the scan finds nothing
+    let needle = [0x32, 0x00, 0x5B, 0xC9];
+    let baseline = time("search (byte-at-a-time)", || {
+        for bank in &snapshot.banks {
+            black_box(naive_find(black_box(bank), &needle));
+        }
+    });
+    let pattern = Pattern::new(&needle);
+    let banks: Vec<_> = (0..snapshot.banks.len()).map(lib_zx_sna::BankId).collect();
+    let fast = time("search (word-at-a-time)", || {
+        black_box(snapshot.find_pattern_in_banks(black_box(&pattern), &banks).unwrap());
+    });
+    println!("{:<28} {:>10.2}x", "search speedup", baseline.as_secs_f64() / fast.as_secs_f64());
+}
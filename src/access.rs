@@ -0,0 +1,349 @@
+use std::borrow::Cow;
+use std::ops::{Index, IndexMut, Range};
+
+use crate::{SnaError, Snapshot, MEM_16K, MEM_64K};
+
+impl Snapshot {
+    /// peek_u32 reads a little-endian 32-bit value from the memory MAPPED to the given address.
+    /// Returns SnaError::AddressOverflow if the value would run past 0xFFFF.
+    pub fn peek_u32(&self, address: u16) -> Result<u32, SnaError> {
+        if address > 0xFFFC {
+            return Err(SnaError::AddressOverflow { address, len: 4 });
+        }
+        Ok((self.peek_word(address)? as u32) | ((self.peek_word(address + 2)? as u32) << 16))
+    }
+
+    /// poke_u32 writes a little-endian 32-bit value to the memory MAPPED to the given address.
+    /// Returns SnaError::AddressOverflow if the value would run past 0xFFFF, or
+    /// SnaError::RomWrite if it starts in ROM, in which case nothing is written.
+    pub fn poke_u32(&mut self, address: u16, value: u32) -> Result<(), SnaError> {
+        if address > 0xFFFC {
+            return Err(SnaError::AddressOverflow { address, len: 4 });
+        }
+        if self.is_rom(address) {
+            return Err(SnaError::RomWrite(address));
+        }
+        self.poke_slice(address, &value.to_le_bytes())
+    }
+
+    /// flatten returns the whole address space as it is currently mapped: the paged ROM,
+    /// or 0xFF if no ROM image is attached, followed by the banks paged in above it, or
+    /// RAM throughout under +2A/+3 special paging. For tools that expect a flat image.
+    pub fn flatten(&self) -> [u8; MEM_64K] {
+        let mut memory = [0u8; MEM_64K];
+        self.flatten_into(&mut memory);
+        memory
+    }
+
+    /// flatten_into is flatten into a buffer, filling it from 0x0000 until either the
+    /// buffer or the address space ends. Returns the number of bytes written.
+    pub fn flatten_into(&self, buffer: &mut [u8]) -> usize {
+        let len = buffer.len().min(MEM_64K);
+        for (window, chunk) in buffer[..len].chunks_mut(MEM_16K).enumerate() {
+            match self.window_memory(window) {
+                Some(memory) => chunk.copy_from_slice(&memory[..chunk.len()]),
+                None => chunk.fill(0xFF),
+            }
+        }
+        len
+    }
+
+    /// peek_slice reads len bytes of the memory MAPPED from an address. A range within
+    /// one 16K window is borrowed straight from its bank or ROM; one that crosses into
+    /// another window is copied. Returns SnaError::AddressOverflow if it would run past
+    /// 0xFFFF.
+    pub fn peek_slice(&self, address: u16, len: usize) -> Result<Cow<'_, [u8]>, SnaError> {
+        let start = address as usize;
+        if start + len > MEM_64K {
+            return Err(SnaError::AddressOverflow { address, len });
+        }
+        let offset = start % MEM_16K;
+        match self.window_memory(start / MEM_16K) {
+            Some(memory) if offset + len <= MEM_16K => Ok(Cow::Borrowed(&memory[offset..offset + len])),
+            _ => Ok(Cow::Owned((start..start + len).map(|address| self.peek(address as u16)).collect())),
+        }
+    }
+
+    /// poke_slice writes bytes to the memory MAPPED from an address, a window at a time.
+    /// Returns SnaError::AddressOverflow if they would run past 0xFFFF, or
+    /// SnaError::RomWrite if they start in ROM, in which case nothing is written.
+    pub fn poke_slice(&mut self, address: u16, bytes: &[u8]) -> Result<(), SnaError> {
+        let mut start = address as usize;
+        if start + bytes.len() > MEM_64K {
+            return Err(SnaError::AddressOverflow { address, len: bytes.len() });
+        }
+        if self.is_rom(address) && !bytes.is_empty() {
+            return Err(SnaError::RomWrite(address));
+        }
+        if !self.guards.is_empty() {
+            self.check_guards(address, bytes)?;
+        }
+        let mut edits = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let offset = start % MEM_16K;
+            let (chunk, remaining) = rest.split_at(rest.len().min(MEM_16K - offset));
+            let bank = self.windows[start / MEM_16K];
+            edits.extend(self.start_edit(bank, offset..offset + chunk.len()));
+            self.banks[bank][offset..offset + chunk.len()].copy_from_slice(chunk);
+            start += chunk.len();
+            rest = remaining;
+        }
+        self.finish_edits(edits);
+        Ok(())
+    }
+
+    // returns the error poke_slice would give for a write of len bytes from an address,
+    // without writing or reporting anything, so that the writes of an edit made of
+    // several can all be checked before any is made
+    pub(crate) fn check_write(&self, address: u16, len: usize) -> Result<(), SnaError> {
+        if address as usize + len > MEM_64K {
+            return Err(SnaError::AddressOverflow { address, len });
+        }
+        if self.is_rom(address) && len > 0 {
+            return Err(SnaError::RomWrite(address));
+        }
+        self.check_blocked(address, len)
+    }
+
+    // the 16K mapped into a window of the address space, or None for ROM with no image
+    pub(crate) fn window_memory(&self, window: usize) -> Option<&[u8]> {
+        if window == 0 && !self.all_ram {
+            return self.roms.get(self.rom).map(|rom| &rom[..]);
+        }
+        Some(&self.banks[self.windows[window]])
+    }
+
+    /// fill sets a range of the memory MAPPED to a value, across bank boundaries.
+    /// Returns SnaError::RomWrite if the range starts in ROM, in which case nothing is
+    /// written.
+    pub fn fill(&mut self, range: Range<u16>, value: u8) -> Result<(), SnaError> {
+        let start = range.start;
+        self.poke_slice(start, &vec![value; range.len()])
+    }
+
+    /// copy_within copies a range of the memory MAPPED to another address, as memmove
+    /// does, so the two may overlap. The source may be in ROM. Returns
+    /// SnaError::AddressOverflow if the copy would run past 0xFFFF, or
+    /// SnaError::RomWrite if it would start in ROM, in which case nothing is written.
+    pub fn copy_within(&mut self, source: Range<u16>, destination: u16) -> Result<(), SnaError> {
+        let bytes = self.save_bin(source);
+        self.load_bin(destination, &bytes)
+    }
+
+    /// copy_from_snapshot copies a range of another snapshot's MAPPED memory to the same
+    /// addresses in this one, such as a level or a screen taken from a later snapshot of
+    /// the same game. Each snapshot is read and written through its own paging. Returns
+    /// SnaError::RomWrite if the range starts in ROM, in which case nothing is written.
+    pub fn copy_from_snapshot(&mut self, other: &Snapshot, range: Range<u16>) -> Result<(), SnaError> {
+        let start = range.start;
+        self.load_bin(start, &other.save_bin(range))
+    }
+
+    /// peek_i8 reads a signed byte from the memory MAPPED to the given address.
+    pub fn peek_i8(&self, address: u16) -> i8 {
+        self.peek(address) as i8
+    }
+
+    /// peek_i16 reads a signed little-endian 16-bit value from the memory MAPPED to the given address.
+    /// Returns SnaError::AddressOverflow if the address is 0xFFFF.
+    pub fn peek_i16(&self, address: u16) -> Result<i16, SnaError> {
+        Ok(self.peek_word(address)? as i16)
+    }
+
+    /// peek_bcd reads a packed BCD number of `len` bytes, most significant byte first,
+    /// from the memory MAPPED to the given address.
+    /// Returns Ok(None) if any nibble is not a decimal digit, and an error if the
+    /// number would run past 0xFFFF or `len` is more than 8 bytes.
+    pub fn peek_bcd(&self, address: u16, len: usize) -> Result<Option<u64>, SnaError> {
+        check_bcd_range(address, len)?;
+        let mut value: u64 = 0;
+        for i in 0..len {
+            let byte = self.peek(address + i as u16);
+            let (high, low) = (byte >> 4, byte & 0x0F);
+            if high > 9 || low > 9 {
+                return Ok(None);
+            }
+            value = value * 100 + (high * 10 + low) as u64;
+        }
+        Ok(Some(value))
+    }
+
+    /// poke_bcd writes a packed BCD number of `len` bytes, most significant byte first,
+    /// to the memory MAPPED to the given address.
+    /// Digits that do not fit in `len` bytes are dropped.
+    /// Returns an error, writing nothing, if the number would run past 0xFFFF, starts
+    /// in ROM or `len` is more than 8 bytes.
+    pub fn poke_bcd(&mut self, address: u16, len: usize, mut value: u64) -> Result<(), SnaError> {
+        check_bcd_range(address, len)?;
+        if self.is_rom(address) && len > 0 {
+            return Err(SnaError::RomWrite(address));
+        }
+        let mut bytes = vec![0u8; len];
+        for byte in bytes.iter_mut().rev() {
+            let digits = (value % 100) as u8;
+            *byte = ((digits / 10) << 4) | (digits % 10);
+            value /= 100;
+        }
+        self.poke_slice(address, &bytes)
+    }
+}
+
+// what an address below 0x4000 reads as when there is no ROM image
+static NO_ROM: u8 = 0xFF;
+
+/// Indexing reads mapped memory as peek does, so `snapshot[0x8000]` is the byte paged
+/// in at 0x8000.
+impl Index<u16> for Snapshot {
+    type Output = u8;
+
+    fn index(&self, address: u16) -> &u8 {
+        let offset = address as usize % MEM_16K;
+        self.window_memory(address as usize / MEM_16K).map_or(&NO_ROM, |memory| &memory[offset])
+    }
+}
+
+/// Indexing mutably writes mapped memory as poke does, so `snapshot[0x8000] = 0xC9`
+/// works. It panics if the address is in ROM or blocked by a guard, where poke would
+/// return an error, and writes this way are not reported to guards.
+impl IndexMut<u16> for Snapshot {
+    fn index_mut(&mut self, address: u16) -> &mut u8 {
+        if self.is_rom(address) {
+            panic!("Cannot write to ROM at 0x{:04X}", address);
+        }
+        if self.is_blocked(address) {
+            panic!("Write to 0x{:04X} is blocked by a guard", address);
+        }
+        &mut self.banks[self.windows[address as usize / MEM_16K]][address as usize % MEM_16K]
+    }
+}
+
+fn check_bcd_range(address: u16, len: usize) -> Result<(), SnaError> {
+    if len > 8 {
+        return Err(SnaError::InvalidLength { len, max: 8 });
+    }
+    if address as usize + len > 0x10000 {
+        return Err(SnaError::AddressOverflow { address, len });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_wide_and_signed() {
+        let file = File::open("48k.sna").expect("Failed to open snapshot file");
+        let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+
+        snapshot.poke_u32(0x8000, 0x1234_5678).unwrap();
+        assert_eq!([snapshot.peek(0x8000), snapshot.peek(0x8003)], [0x78, 0x12], "poke_u32 is not little-endian");
+        assert_eq!(snapshot.peek_u32(0x8000).unwrap(), 0x1234_5678);
+
+        snapshot.poke_word(0x8000, 0xFFFE).unwrap();
+        assert_eq!(snapshot.peek_i16(0x8000).unwrap(), -2);
+        assert_eq!(snapshot.peek_i8(0x8000), -2);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.bank_poke(3, 0x0000, 0x33).unwrap();
+        snapshot.write_0x7ffd(0x03).unwrap();
+        snapshot.poke(0x8000, 0x22).unwrap();
+        let memory = snapshot.flatten();
+        assert_eq!((memory[0x0000], memory[0x3FFF]), (0xFF, 0xFF), "No ROM is attached");
+        assert_eq!((memory[0x8000], memory[0xC000]), (0x22, 0x33));
+        assert!((0..=0xFFFF).all(|address| memory[address] == snapshot.peek(address as u16)), "flatten differs from peek");
+
+        snapshot.attach_rom(&[0x11; 0x8000]).unwrap();
+        let mut buffer = [0u8; 0x8001];
+        assert_eq!(snapshot.flatten_into(&mut buffer), 0x8001);
+        assert_eq!((buffer[0x0000], buffer[0x8000]), (0x11, 0x22));
+        assert_eq!(snapshot.flatten_into(&mut vec![0; 0x20000]), MEM_64K);
+    }
+
+    #[test]
+    fn test_slices() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.poke_slice(0xBFFE, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(snapshot.bank_peek(0, 0x0001).unwrap(), 4, "The write crosses into bank 0");
+        assert!(matches!(snapshot.peek_slice(0xBFFE, 2).unwrap(), Cow::Borrowed([1, 2])), "One window is borrowed");
+        assert!(matches!(snapshot.peek_slice(0xBFFE, 4).unwrap(), Cow::Owned(bytes) if bytes == [1, 2, 3, 4]));
+        assert_eq!(snapshot.peek_slice(0x3FFF, 2).unwrap()[..], [0xFF, 0x00]);
+        assert_eq!(snapshot.peek_slice(0xFFFF, 1).unwrap().len(), 1);
+        assert!(snapshot.peek_slice(0xFFFF, 2).is_err());
+
+        assert!(matches!(snapshot.poke_slice(0x3FFF, &[9, 9]), Err(SnaError::RomWrite(0x3FFF))));
+        assert!(matches!(snapshot.poke_slice(0xFFFF, &[9, 9]), Err(SnaError::AddressOverflow { .. })));
+        snapshot.poke_slice(0x4000, &[7; 0xC000]).unwrap();
+        assert!((0x4000..=0xFFFF).all(|address| snapshot.peek(address) == 7));
+    }
+
+    #[test]
+    fn test_index() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot[0x8000] = 0xC9;
+        snapshot[0xC000] += 1;
+        assert_eq!((snapshot.peek(0x8000), snapshot.bank_peek(0, 0).unwrap()), (0xC9, 1));
+        assert_eq!((snapshot[0x8000], snapshot[0x0000]), (0xC9, 0xFF));
+        snapshot.attach_rom(&[0xF3; 0x8000]).unwrap();
+        assert_eq!(snapshot[0x0000], 0xF3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot write to ROM")]
+    fn test_index_rom() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot[0x3FFF] = 0;
+    }
+
+    #[test]
+    fn test_fill_and_copy() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.fill(0xBFF0..0xC010, 0xAA).unwrap();
+        assert_eq!((snapshot.bank_peek(2, 0x3FF0).unwrap(), snapshot.bank_peek(0, 0x000F).unwrap()), (0xAA, 0xAA));
+        assert_eq!(snapshot.peek(0xC010), 0x00);
+        assert!(matches!(snapshot.fill(0x3FFF..0x4001, 1), Err(SnaError::RomWrite(0x3FFF))));
+        assert_eq!(snapshot.peek(0x4000), 0x00, "Nothing is written on failure");
+
+        snapshot.load_bin(0x8000, &[1, 2, 3, 4]).unwrap();
+        snapshot.copy_within(0x8000..0x8004, 0x8002).unwrap();
+        assert_eq!(snapshot.save_bin(0x8000..0x8006), [1, 2, 1, 2, 3, 4], "Overlapping copies work as memmove");
+        assert!(matches!(snapshot.copy_within(0x8000..0x8004, 0xFFFE), Err(SnaError::AddressOverflow { .. })));
+
+        let mut other = Snapshot::new_128k();
+        other.fill(0x4000..0x5800, 0xFF).unwrap();
+        snapshot.copy_from_snapshot(&other, 0x4000..0x5800).unwrap();
+        assert_eq!((snapshot.peek(0x57FF), snapshot.peek(0x5800)), (0xFF, 0x00));
+    }
+
+    #[test]
+    fn test_bcd() {
+        let file = File::open("48k.sna").expect("Failed to open snapshot file");
+        let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+
+        snapshot.poke_bcd(0x8000, 3, 12345).unwrap();
+        assert_eq!([snapshot.peek(0x8000), snapshot.peek(0x8001), snapshot.peek(0x8002)], [0x01, 0x23, 0x45]);
+        assert_eq!(snapshot.peek_bcd(0x8000, 3).unwrap(), Some(12345));
+
+        snapshot.poke(0x8001, 0x2A).unwrap();
+        assert_eq!(snapshot.peek_bcd(0x8000, 3).unwrap(), None, "Invalid BCD digit should not decode");
+    }
+
+    #[test]
+    fn test_special_paging_writes() {
+        let mut snapshot = Snapshot::new_plus3();
+        assert!(snapshot.is_rom(0x0000) && !snapshot.is_rom(0x4000));
+        assert!(matches!(snapshot.poke_u32(0x0000, 0), Err(SnaError::RomWrite(0x0000))));
+
+        snapshot.write_0x1ffd(0x01).unwrap();
+        assert!(!snapshot.is_rom(0x0000), "Special paging maps RAM at 0x0000");
+        snapshot.poke_u32(0x0000, 0x1234_5678).unwrap();
+        snapshot.poke_bcd(0x0004, 2, 1234).unwrap();
+        assert_eq!((snapshot.peek_u32(0x0000).unwrap(), snapshot.peek_bcd(0x0004, 2).unwrap()), (0x1234_5678, Some(1234)));
+        assert_eq!(snapshot.with_pokes(&[(0x0000, 1)], |view| view.peek(0x0000)).unwrap(), 1);
+    }
+}
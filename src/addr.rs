@@ -0,0 +1,169 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+
+use crate::{BankId, SnaError, Snapshot};
+
+/// A CPU address in the 64K address space, resolved through the current paging.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Addr(pub u16);
+
+/// A location within a specific bank, independent of the current paging.
+/// The offset is always in the range 0 to 0x3FFF.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BankAddr {
+    pub bank: BankId,
+    pub offset: u16,
+}
+
+impl Addr {
+    /// Returns the index of the 16K window the address falls in (0 for ROM, 1-3 for RAM).
+    pub fn window(self) -> usize {
+        (self.0 >> 14) as usize
+    }
+
+    /// Returns the offset of the address within its 16K window.
+    pub fn window_offset(self) -> u16 {
+        self.0 & 0x3FFF
+    }
+
+    /// Adds a value to the address, returning None if it would pass 0xFFFF.
+    pub fn checked_add(self, value: u16) -> Option<Addr> {
+        self.0.checked_add(value).map(Addr)
+    }
+}
+
+impl BankAddr {
+    /// Creates a bank address.
+    /// Returns SnaError::AddressOverflow if the offset is not within a 16K bank.
+    pub fn new(bank: BankId, offset: u16) -> Result<Self, SnaError> {
+        if offset > 0x3FFF {
+            return Err(SnaError::AddressOverflow { address: offset, len: 1 });
+        }
+        Ok(BankAddr { bank, offset })
+    }
+
+    /// Adds a value to the offset, returning None if it would leave the bank.
+    pub fn checked_add(self, value: u16) -> Option<BankAddr> {
+        match self.offset.checked_add(value) {
+            Some(offset) if offset <= 0x3FFF => Some(BankAddr { bank: self.bank, offset }),
+            _ => None,
+        }
+    }
+}
+
+impl From<u16> for Addr {
+    fn from(address: u16) -> Self {
+        Addr(address)
+    }
+}
+
+impl From<Addr> for u16 {
+    fn from(address: Addr) -> Self {
+        address.0
+    }
+}
+
+impl Add<u16> for Addr {
+    type Output = Addr;
+
+    /// Adds to the address, wrapping at 0xFFFF as the Z80 does.
+    fn add(self, value: u16) -> Addr {
+        Addr(self.0.wrapping_add(value))
+    }
+}
+
+impl Sub<Addr> for Addr {
+    type Output = u16;
+
+    /// Returns the distance between two addresses, wrapping at 0xFFFF.
+    fn sub(self, other: Addr) -> u16 {
+        self.0.wrapping_sub(other.0)
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:04X}", self.0)
+    }
+}
+
+impl fmt::Display for BankAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:0x{:04X}", self.bank.0, self.offset)
+    }
+}
+
+impl Snapshot {
+    /// resolve converts a mapped address into the bank and offset it currently refers to.
+    /// Returns None for addresses in ROM, which is below 0x4000 unless +2A/+3 special
+    /// paging has mapped RAM there.
+    pub fn resolve(&self, address: Addr) -> Option<BankAddr> {
+        self.layout().windows[address.window()].map(|bank| BankAddr {
+            bank,
+            offset: address.window_offset(),
+        })
+    }
+
+    /// address_of converts a bank address into the mapped address it can currently be reached at.
+    /// Returns None if the bank is not paged in.
+    pub fn address_of(&self, location: BankAddr) -> Option<Addr> {
+        self.layout().windows.iter().position(|&mapped| mapped == Some(location.bank))
+            .map(|window| Addr(0x4000 * window as u16 + location.offset))
+    }
+
+    /// peek_at reads a byte from the given bank address, regardless of paging.
+    /// Returns SnaError::BankOutOfRange if the snapshot has no such bank.
+    pub fn peek_at(&self, location: BankAddr) -> Result<u8, SnaError> {
+        self.bank_peek(location.bank.0, location.offset)
+    }
+
+    /// poke_at writes a byte to the given bank address, regardless of paging.
+    /// Returns SnaError::BankOutOfRange if the snapshot has no such bank.
+    pub fn poke_at(&mut self, location: BankAddr, value: u8) -> Result<(), SnaError> {
+        self.bank_poke(location.bank.0, location.offset, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_resolve() {
+        let file = File::open("128k.sna").expect("Failed to open snapshot file");
+        let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        snapshot.write_0x7ffd(3).unwrap();
+
+        assert_eq!(snapshot.resolve(Addr(0x1234)), None, "ROM addresses should not resolve to a bank");
+        let location = snapshot.resolve(Addr(0xC123)).expect("Failed to resolve address");
+        assert_eq!(location, BankAddr::new(BankId(3), 0x0123).unwrap());
+        assert_eq!(snapshot.address_of(location), Some(Addr(0xC123)));
+        assert_eq!(snapshot.address_of(BankAddr::new(BankId(4), 0).unwrap()), None, "Unmapped bank should have no address");
+
+        snapshot.poke_at(location, 0x42).unwrap();
+        assert_eq!(snapshot.peek(0xC123), 0x42, "poke_at did not write through to mapped memory");
+    }
+
+    #[test]
+    fn test_resolve_special_paging() {
+        let mut snapshot = Snapshot::new_plus3();
+        snapshot.write_0x1ffd(0x07).unwrap();
+
+        assert_eq!(snapshot.resolve(Addr(0x0123)), Some(BankAddr::new(BankId(4), 0x0123).unwrap()), "Special paging maps RAM at 0x0000");
+        assert_eq!(snapshot.resolve(Addr(0x4000)), Some(BankAddr::new(BankId(7), 0).unwrap()));
+        assert_eq!(snapshot.address_of(BankAddr::new(BankId(4), 0x10).unwrap()), Some(Addr(0x0010)));
+        assert_eq!(snapshot.address_of(BankAddr::new(BankId(5), 0).unwrap()), None, "Bank 5 is not mapped with special paging");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(Addr(0xFFFF) + 2, Addr(0x0001));
+        assert_eq!(Addr(0x8010) - Addr(0x8000), 0x10);
+        assert_eq!(Addr(0xFFFF).checked_add(1), None);
+        assert_eq!(BankAddr::new(BankId(0), 0x3FFF).unwrap().checked_add(1), None);
+        assert_eq!(BankAddr::new(BankId(0), 0x3FFE).unwrap().checked_add(1), Some(BankAddr::new(BankId(0), 0x3FFF).unwrap()));
+        assert_eq!(BankAddr::new(BankId(7), 0x1A).unwrap().to_string(), "7:0x001A");
+        assert!(matches!(BankAddr::new(BankId(0), 0x4000), Err(SnaError::AddressOverflow { address: 0x4000, len: 1 })));
+    }
+}
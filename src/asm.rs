@@ -0,0 +1,231 @@
+use crate::disasm::decode;
+use crate::{SnaError, Snapshot};
+
+fn error<T>(message: String) -> Result<T, SnaError> {
+    Err(SnaError::InvalidInstruction(message))
+}
+
+// An instruction with its numbers taken out: "LD (IX+N),N" and [5, 66].
+// A number after a minus sign is negated, and in an index displacement the minus
+// becomes a plus, so that (IX-3) and (IX+$FD) compare equal.
+#[derive(PartialEq, Eq, Debug)]
+struct Template {
+    text: String,
+    numbers: Vec<(i32, bool)>,  // value, and whether it was written in hex
+}
+
+fn parse_literal(token: &str) -> Option<(i32, bool)> {
+    let (digits, radix) = if let Some(hex) = token.strip_prefix("0X").or_else(|| token.strip_prefix(['$', '&', '#'])) {
+        (hex, 16)
+    } else if let Some(hex) = token.strip_suffix('H') {
+        (hex, 16)
+    } else if let Some(binary) = token.strip_prefix('%') {
+        (binary, 2)
+    } else {
+        (token, 10)
+    };
+    i32::from_str_radix(digits, radix).ok().filter(|value| *value <= 0xFFFF).map(|value| (value, radix == 16))
+}
+
+// splits an instruction into a template and its numbers, or None if a number is malformed
+fn template(text: &str) -> Option<Template> {
+    let text = text.trim().to_ascii_uppercase();
+    let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+    let operands: String = operands.chars().filter(|c| !c.is_whitespace()).collect();
+    let operands = operands.replace("(IX)", "(IX+0)").replace("(IY)", "(IY+0)");
+
+    let mut template = Template { text: format!("{} ", mnemonic), numbers: Vec::new() };
+    let mut chars = operands.chars().peekable();
+    while let Some(c) = chars.next() {
+        let starts_number = c.is_ascii_digit() || "$&#%".contains(c);
+        let follows_name = template.text.ends_with(|last: char| last.is_ascii_alphanumeric() || last == '\'');
+        if !starts_number || follows_name {
+            template.text.push(c);
+            continue;
+        }
+        let mut token = c.to_string();
+        while let Some(&next) = chars.peek().filter(|next| next.is_ascii_alphanumeric()) {
+            token.push(next);
+            chars.next();
+        }
+        let (mut value, hex) = parse_literal(&token)?;
+        if template.text.ends_with('-') {
+            template.text.pop();
+            if template.text.ends_with("(IX") || template.text.ends_with("(IY") {
+                template.text.push('+');
+            }
+            value = -value;
+        }
+        template.text.push('N');
+        template.numbers.push((value, hex));
+    }
+    Some(template)
+}
+
+// Every encoding the decoder knows, shortest first, with operand bytes left out.
+fn candidates() -> impl Iterator<Item = Vec<u8>> {
+    let prefixed = |prefix: &'static [u8]| (0..=255u8).map(move |opcode| [prefix, &[opcode]].concat());
+    prefixed(&[])
+        .chain(prefixed(&[0xCB]))
+        .chain(prefixed(&[0xED]))
+        .chain(prefixed(&[0xDD]))
+        .chain(prefixed(&[0xFD]))
+        .chain(prefixed(&[0xDD, 0xCB, 0x00]))
+        .chain(prefixed(&[0xFD, 0xCB, 0x00]))
+        .filter(|encoding| !matches!(encoding[encoding.len() - 1], 0xCB | 0xDD | 0xED | 0xFD) || encoding.len() > 2)
+}
+
+fn fetch(bytes: &[u8], addr: u16) -> impl Fn(u16) -> u8 + '_ {
+    move |address| bytes.get(address.wrapping_sub(addr) as usize).copied().unwrap_or(0)
+}
+
+// fills a candidate's operand bytes from the numbers written in the instruction
+fn encode(candidate: &[u8], numbers: &[(i32, bool)], addr: u16) -> Option<Vec<u8>> {
+    let skeleton = decode(addr, fetch(candidate, addr));
+    let mut bytes = skeleton.bytes.clone();
+    // the displacement of a DDCB or FDCB instruction comes before its final opcode byte
+    let positions: Vec<usize> = if bytes.len() > 3 && bytes[1] == 0xCB { vec![2] } else { (candidate.len()..bytes.len()).collect() };
+
+    // numbers that change when the operand bytes do are operands; the rest, such as
+    // BIT 7 or RST $38, are part of the opcode and are checked when decoding
+    let mut probe = bytes.clone();
+    positions.iter().for_each(|&position| probe[position] = 1);
+    let zeroed = template(&skeleton.to_string())?.numbers;
+    let probed = template(&decode(addr, fetch(&probe, addr)).to_string())?.numbers;
+
+    let mut positions = positions.into_iter();
+    let relative = matches!(skeleton.mnemonic, "JR" | "DJNZ");
+    for ((&(value, _), &(zero, _)), &(one, _)) in numbers.iter().zip(&zeroed).zip(&probed) {
+        if zero == one {
+            continue;
+        }
+        let position = positions.next()?;
+        if relative {
+            let offset = value - (addr as i32 + bytes.len() as i32);
+            if !(-128..=127).contains(&offset) {
+                return None;
+            }
+            bytes[position] = offset as u8;
+        } else if one == 0x0101 {
+            let word = u16::try_from(value).ok()?;
+            bytes[position..position + 2].copy_from_slice(&word.to_le_bytes());
+            positions.next();
+        } else {
+            if !(-128..=255).contains(&value) {
+                return None;
+            }
+            bytes[position] = value as u8;
+        }
+    }
+    positions.next().is_none().then_some(bytes)
+}
+
+/// assemble encodes a single Z80 instruction for an address, such as `ld a,5`, `jp $8000`
+/// or `set 7,(ix+3)`, written the way the disassembler writes it. Case and spacing do
+/// not matter. Numbers are decimal, hex with a `0x`, `$`, `&` or `#` prefix or an `H`
+/// suffix, or binary with a `%` prefix. Relative jumps take their target address, which
+/// is why the address is needed. `DB` takes a list of bytes. Returns
+/// SnaError::InvalidInstruction if the text cannot be assembled.
+pub fn assemble(text: &str, addr: u16) -> Result<Vec<u8>, SnaError> {
+    let wanted = template(text).ok_or_else(|| SnaError::InvalidInstruction(format!("Malformed number in '{}'", text.trim())))?;
+    if wanted.text.starts_with("DB ") {
+        return wanted.numbers.iter()
+            .map(|&(value, _)| u8::try_from(value).or_else(|_| error(format!("DB value {} is not a byte", value))))
+            .collect();
+    }
+
+    let mut recognised = false;
+    for candidate in candidates() {
+        let skeleton = decode(addr, fetch(&candidate, addr));
+        if skeleton.mnemonic == "DB" {
+            continue;
+        }
+        let found = template(&skeleton.to_string()).expect("The disassembler writes valid numbers");
+        if found.text != wanted.text || found.numbers.len() != wanted.numbers.len() {
+            continue;
+        }
+        recognised = true;
+        let Some(bytes) = encode(&candidate, &wanted.numbers, addr) else { continue };
+        let decoded = template(&decode(addr, fetch(&bytes, addr)).to_string()).expect("The disassembler writes valid numbers");
+        // negative bytes and displacements decode as their unsigned or signed twins
+        let displacement = decoded.text.contains("+N");
+        let same = |(&(found, _), &(wanted, _)): (&(i32, bool), &(i32, bool))| {
+            found == wanted || ((wanted < 0 || displacement) && found & 0xFF == wanted & 0xFF)
+        };
+        if decoded.numbers.iter().zip(&wanted.numbers).all(same) {
+            return Ok(bytes);
+        }
+    }
+    if recognised {
+        error(format!("An operand of '{}' is out of range", text.trim()))
+    } else {
+        error(format!("'{}' is not a Z80 instruction", text.trim()))
+    }
+}
+
+impl Snapshot {
+    /// asm assembles a single instruction and pokes it at an address in mapped memory,
+    /// returning the address after it so that instructions can be written in sequence.
+    /// See [`assemble`](crate::assemble) for the syntax.
+    pub fn asm(&mut self, addr: u16, text: &str) -> Result<u16, SnaError> {
+        let bytes = assemble(text, addr)?;
+        self.poke_slice(addr, &bytes)?;
+        Ok(addr.wrapping_add(bytes.len() as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(text: &str) -> Vec<u8> {
+        assemble(text, 0x8000).unwrap_or_else(|e| panic!("Failed to assemble '{}': {}", text, e))
+    }
+
+    #[test]
+    fn test_assemble() {
+        assert_eq!(bytes("nop"), [0x00]);
+        assert_eq!(bytes("ret"), [0xC9]);
+        assert_eq!(bytes("ld a,5"), [0x3E, 0x05]);
+        assert_eq!(bytes("LD  HL, $1234"), [0x21, 0x34, 0x12]);
+        assert_eq!(bytes("jp 0x9000"), [0xC3, 0x00, 0x90]);
+        assert_eq!(bytes("call nz,#9000"), [0xC4, 0x00, 0x90]);
+        assert_eq!(bytes("jr $8000"), [0x18, 0xFE]);
+        assert_eq!(bytes("djnz 8010h"), [0x10, 0x0E]);
+        assert_eq!(bytes("ld (23560),a"), [0x32, 0x08, 0x5C]);
+        assert_eq!(bytes("ld a,-1"), [0x3E, 0xFF]);
+        assert_eq!(bytes("ex af,af'"), [0x08]);
+        assert_eq!(bytes("bit 7,(hl)"), [0xCB, 0x7E]);
+        assert_eq!(bytes("rst 38h"), [0xFF]);
+        assert_eq!(bytes("im 2"), [0xED, 0x5E]);
+        assert_eq!(bytes("ldir"), [0xED, 0xB0]);
+        assert_eq!(bytes("neg"), [0xED, 0x44]);
+        assert_eq!(bytes("ld bc,(%1000)"), [0xED, 0x4B, 0x08, 0x00]);
+        assert_eq!(bytes("ld (ix-3),42"), [0xDD, 0x36, 0xFD, 0x2A]);
+        assert_eq!(bytes("ld a,(iy)"), [0xFD, 0x7E, 0x00]);
+        assert_eq!(bytes("set 1,(iy+$05)"), [0xFD, 0xCB, 0x05, 0xCE]);
+        assert_eq!(bytes("res 0,(ix-1)"), [0xDD, 0xCB, 0xFF, 0x86]);
+        assert_eq!(bytes("jp (ix)"), [0xDD, 0xE9]);
+        assert_eq!(bytes("db 1,$FF"), [0x01, 0xFF]);
+
+        assert!(assemble("ld a,256", 0x8000).is_err(), "256 is not a byte");
+        assert!(assemble("jr $9000", 0x8000).is_err(), "The target is out of reach");
+        assert!(assemble("bit 8,a", 0x8000).is_err(), "There is no bit 8");
+        assert!(matches!(assemble("mov a,b", 0x8000), Err(SnaError::InvalidInstruction(_))));
+        assert!(assemble("ld a,$zz", 0x8000).is_err());
+    }
+
+    #[test]
+    fn test_asm() {
+        let mut snapshot = Snapshot::new_48k();
+        let mut addr = 0x8000;
+        for text in ["ld b,10", "djnz $8002", "ret"] {
+            addr = snapshot.asm(addr, text).expect("Failed to assemble");
+        }
+        assert_eq!(addr, 0x8005);
+        let listing: Vec<String> = snapshot.disassemble(0x8000).take(3).map(|instruction| instruction.to_string()).collect();
+        assert_eq!(listing, ["LD B,$0A", "DJNZ $8002", "RET"]);
+        assert!(matches!(snapshot.asm(0x0000, "nop"), Err(SnaError::RomWrite(0x0000))), "ROM cannot be patched");
+        assert!(snapshot.asm(0xFFFF, "jp 0").is_err(), "The instruction runs past 0xFFFF");
+    }
+}
@@ -0,0 +1,333 @@
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "compression")]
+use std::sync::{Arc, OnceLock};
+
+use crate::slab::Memory;
+use crate::{SnaError, Snapshot, SnapshotType, MEM_16K};
+
+// the memory of a bank, a fixed-size array so masked offsets never need a bounds check
+type Storage = [u8; MEM_16K];
+
+// with the compression feature the memory is filled in lazily, as it is missing while
+// the bank is compressed; without it the memory is always there
+#[cfg(feature = "compression")]
+type Data = OnceLock<Memory>;
+#[cfg(not(feature = "compression"))]
+type Data = Memory;
+
+/// A single 16K memory bank.
+/// Banks are reference counted so that cloning a `Snapshot` only copies
+/// pointers. The underlying memory is copied the first time a shared bank
+/// is written to (copy-on-write), leaving every other clone untouched.
+/// With the `compression` feature a bank can also be held LZ4 compressed,
+/// in which case it is decompressed transparently the next time it is read.
+#[derive(Clone, Debug)]
+pub struct Bank {
+    data: Data,
+    #[cfg(feature = "compression")]
+    packed: Option<Arc<Vec<u8>>>,
+}
+
+impl Bank {
+    /// Creates a new bank filled with zeroes.
+    pub fn new() -> Self {
+        Bank::from_storage([0u8; MEM_16K])
+    }
+
+    /// Creates a new bank from a 16K slice of memory.
+    /// Returns SnaError::InvalidLength if the slice is not exactly 16K long.
+    pub fn from_slice(data: &[u8]) -> Result<Self, SnaError> {
+        if data.len() != MEM_16K {
+            return Err(SnaError::InvalidLength { len: data.len(), max: MEM_16K });
+        }
+        Ok(Bank::from_storage(to_storage(data)))
+    }
+
+    pub(crate) fn from_storage(data: Storage) -> Self {
+        Bank::from_memory(Memory::new(data))
+    }
+
+    // from_memory creates a bank holding memory allocated elsewhere, such as a slab slot
+    pub(crate) fn from_memory(memory: Memory) -> Self {
+        Bank {
+            data: Data::from(memory),
+            #[cfg(feature = "compression")]
+            packed: None,
+        }
+    }
+
+    /// Returns true if the memory of this bank is shared with another clone
+    /// and will therefore be copied on the next write.
+    #[cfg(feature = "compression")]
+    pub fn is_shared(&self) -> bool {
+        match self.data.get() {
+            Some(data) => data.is_shared(),
+            None => false,
+        }
+    }
+
+    /// Returns true if the memory of this bank is shared with another clone
+    /// and will therefore be copied on the next write.
+    #[cfg(not(feature = "compression"))]
+    pub fn is_shared(&self) -> bool {
+        self.data.is_shared()
+    }
+
+    // in_slab returns whether the bank's memory is a slot of a slab
+    #[cfg(test)]
+    pub(crate) fn in_slab(&self) -> bool {
+        #[cfg(feature = "compression")]
+        return self.data.get().is_some_and(Memory::in_slab);
+        #[cfg(not(feature = "compression"))]
+        return self.data.in_slab();
+    }
+
+    /// read returns the byte at the given offset, masked to the 16K bank.
+    #[inline]
+    pub(crate) fn read(&self, offset: u16) -> u8 {
+        self.storage()[(offset & 0x3FFF) as usize]
+    }
+
+    /// write stores a byte at the given offset, masked to the 16K bank.
+    #[inline]
+    pub(crate) fn write(&mut self, offset: u16, value: u8) {
+        self.storage_mut()[(offset & 0x3FFF) as usize] = value;
+    }
+
+    /// as_array returns the memory of the bank as a fixed-size array, so that code
+    /// indexing it with offsets masked to 16K compiles without bounds checks.
+    #[inline]
+    pub fn as_array(&self) -> &[u8; MEM_16K] {
+        self.storage()
+    }
+
+    /// as_array_mut is as_array for writing, copying the bank first if it is shared.
+    #[inline]
+    pub fn as_array_mut(&mut self) -> &mut [u8; MEM_16K] {
+        self.storage_mut()
+    }
+
+    #[cfg(feature = "compression")]
+    #[inline]
+    fn storage(&self) -> &Storage {
+        self.data.get_or_init(|| self.inflate())
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[inline]
+    fn storage(&self) -> &Storage {
+        &self.data
+    }
+
+    #[cfg(feature = "compression")]
+    fn storage_mut(&mut self) -> &mut Storage {
+        if self.data.get().is_none() {
+            let data = self.inflate();
+            let _ = self.data.set(data);
+        }
+        self.packed = None;
+        self.data.get_mut().expect("Bank data was just initialised").make_mut()
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[inline]
+    fn storage_mut(&mut self) -> &mut Storage {
+        self.data.make_mut()
+    }
+
+    /// compress stores the bank LZ4 compressed and releases the uncompressed memory.
+    /// The bank is decompressed again on the next access.
+    #[cfg(feature = "compression")]
+    pub fn compress(&mut self) {
+        if let Some(data) = self.data.take() {
+            self.packed = Some(Arc::new(lz4_flex::compress_prepend_size(data.as_slice())));
+        }
+    }
+
+    /// Returns true if the bank is currently only held in compressed form.
+    #[cfg(feature = "compression")]
+    pub fn is_compressed(&self) -> bool {
+        self.data.get().is_none()
+    }
+
+    #[cfg(feature = "compression")]
+    fn inflate(&self) -> Memory {
+        let packed = self.packed.as_ref().expect("Bank has neither plain nor compressed data");
+        let data = lz4_flex::decompress_size_prepended(packed).expect("Compressed bank data is corrupt");
+        Memory::new(to_storage(&data))
+    }
+}
+
+impl Default for Bank {
+    fn default() -> Self {
+        Bank::new()
+    }
+}
+
+impl PartialEq for Bank {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Bank {}
+
+impl Deref for Bank {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.storage().as_slice()
+    }
+}
+
+impl DerefMut for Bank {
+    /// Mutable access detaches the bank from any clones sharing it, and
+    /// drops the compressed copy as it is about to become stale.
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.storage_mut().as_mut_slice()
+    }
+}
+
+/// Identifies a bank by its index in `Snapshot::banks`.
+/// For 128K snapshots this is the hardware bank number (0-7). For 48K
+/// snapshots banks 0, 1 and 2 hold 0x4000, 0x8000 and 0xC000 respectively.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BankId(pub usize);
+
+/// Describes how a bank is used by the machine.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BankInfo {
+    pub mapped_at: Option<u16>,   // address the bank is currently paged in at, if any
+    pub contended: bool,          // whether access to the bank is contended by the ULA
+    pub screen: bool,             // whether the bank can hold a displayed screen
+}
+
+impl Snapshot {
+    /// bank_info describes the given bank: where it is mapped with the current paging,
+    /// whether the machine contends it and whether it can hold the displayed screen.
+    /// Returns None if the snapshot has no such bank.
+    pub fn bank_info(&self, bank: BankId) -> Option<BankInfo> {
+        if bank.0 >= self.banks.len() {
+            return None;
+        }
+        let mapped_at = self.layout().windows.iter().position(|&mapped| mapped == Some(bank))
+            .map(|window| 0x4000 * window as u16);
+        let screen = match self.snapshot_type {
+            SnapshotType::Snapshot48 => bank.0 == 0,
+            SnapshotType::Snapshot128 => bank.0 == 5 || bank.0 == 7,
+        };
+        Some(BankInfo { mapped_at, contended: self.machine().is_contended(bank.0), screen })
+    }
+
+    /// banks iterates over every bank in the snapshot with its id and usage information,
+    /// so all banks can be processed without knowing how many the machine has. Each
+    /// bank is a 16K array, so masked offsets into it need no bounds checks.
+    pub fn banks(&self) -> impl Iterator<Item = (BankId, &[u8; MEM_16K], BankInfo)> + '_ {
+        self.banks.iter().enumerate().map(|(index, bank)| {
+            let id = BankId(index);
+            (id, bank.as_array(), self.bank_info(id).expect("The bank exists"))
+        })
+    }
+
+    /// banks_mut calls f with every bank in the snapshot in turn, with its id and usage
+    /// information, so all banks can be edited without knowing how many the machine has.
+    /// As with bank_poke the writes are not checked against guards, which watch mapped
+    /// addresses, but they are journaled, as one change. Banks are passed as `Bank`
+    /// rather than slices so that a bank shared with a clone is only copied if it is
+    /// actually written to.
+    pub fn banks_mut<F: FnMut(BankId, &mut Bank, BankInfo)>(&mut self, mut f: F) {
+        let mut edits = Vec::new();
+        for index in 0..self.banks.len() {
+            let info = self.bank_info(BankId(index)).expect("The bank exists");
+            let old = self.is_journaling().then(|| self.banks[index].clone());
+            f(BankId(index), &mut self.banks[index], info);
+            edits.extend(old.and_then(|old| self.bank_edit(index, &old)));
+        }
+        self.finish_edits(edits);
+    }
+}
+
+fn to_storage(data: &[u8]) -> Storage {
+    data.try_into().expect("Bank data must be exactly 16K long.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_on_write() {
+        let mut bank = Bank::new();
+        bank[0] = 0x12;
+        let mut copy = bank.clone();
+        assert!(bank.is_shared() && copy.is_shared(), "Cloned bank should share memory");
+
+        copy[0] = 0x34;
+        assert!(!bank.is_shared() && !copy.is_shared(), "Written bank should no longer share memory");
+        assert_eq!(bank[0], 0x12, "Original bank was modified by a write to its clone");
+        assert_eq!(copy[0], 0x34, "Clone did not receive the write");
+
+        copy.as_array_mut()[0x3FFF] = 0x56;
+        assert_eq!((copy.as_array()[0x3FFF], bank.as_array()[0x3FFF]), (0x56, 0x00));
+    }
+
+    #[test]
+    fn test_from_slice() {
+        assert_eq!(Bank::from_slice(&[7; MEM_16K]).unwrap()[0x3FFF], 7);
+        assert!(matches!(Bank::from_slice(&[0; 100]), Err(SnaError::InvalidLength { len: 100, max: MEM_16K })));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression() {
+        let mut bank = Bank::new();
+        bank[0x1234] = 0x56;
+        bank.compress();
+        assert!(bank.is_compressed(), "Bank should be compressed");
+        assert_eq!(bank[0x1234], 0x56, "Compressed bank did not read back correctly");
+
+        bank.compress();
+        bank[0x1235] = 0x78;
+        bank.compress();
+        assert_eq!(bank[0x1234], 0x56, "Write to a compressed bank lost existing data");
+        assert_eq!(bank[0x1235], 0x78, "Write to a compressed bank was lost");
+    }
+
+    #[test]
+    fn test_banks_iterator() {
+        let file = std::fs::File::open("128k.sna").expect("Failed to open snapshot file");
+        let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        let paged = snapshot.mapping[2] as usize;
+
+        let banks: Vec<(BankId, BankInfo)> = snapshot.banks().map(|(id, data, info)| {
+            assert_eq!(data.len(), MEM_16K, "Bank {:?} is not 16K", id);
+            (id, info)
+        }).collect();
+        assert_eq!(banks.len(), 8, "A 128K snapshot should have 8 banks");
+        assert_eq!(banks[5].1, BankInfo { mapped_at: Some(0x4000), contended: true, screen: true });
+        assert_eq!(banks[2].1, BankInfo { mapped_at: Some(0x8000), contended: false, screen: false });
+        assert_eq!(banks[paged].1.mapped_at, Some(0xC000));
+
+        snapshot.start_journal();
+        let shared = snapshot.clone();
+        snapshot.banks_mut(|id, bank, _| {
+            if id.0 != 0 {
+                bank[0] = id.0 as u8;
+            }
+        });
+        assert_eq!(snapshot.bank_peek(3, 0).unwrap(), 3, "Write through banks_mut was lost");
+        assert!(snapshot.banks[0].is_shared() && !snapshot.banks[1].is_shared(), "Only written banks are copied");
+        assert_eq!(snapshot.changes().len(), 1, "The writes are one change");
+        assert!(snapshot.undo());
+        assert!((0..8).all(|bank| snapshot.banks[bank] == shared.banks[bank]), "The writes are undone");
+        assert_eq!(snapshot.bank_info(BankId(8)), None);
+
+        let mut snapshot = Snapshot::new_plus3();
+        snapshot.write_0x1ffd(0x07).unwrap();
+        assert_eq!(snapshot.bank_info(BankId(4)), Some(BankInfo { mapped_at: Some(0x0000), contended: true, screen: false }), "Special paging maps bank 4 at 0x0000");
+        assert_eq!(snapshot.bank_info(BankId(5)).unwrap().mapped_at, None);
+        assert!(!snapshot.bank_info(BankId(1)).unwrap().contended, "The +3 contends banks 4 to 7");
+        snapshot.set_machine(crate::Machine::Pentagon128).unwrap();
+        assert!(!snapshot.bank_info(BankId(5)).unwrap().contended, "The Pentagon has no contended memory");
+    }
+}
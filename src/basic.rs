@@ -0,0 +1,463 @@
+//! Reading the BASIC program stored in a snapshot.
+//!
+//! The program runs from PROG to VARS as a list of lines. Each line is its number
+//! (big-endian, unlike everything else on the Spectrum), the length of the rest of the
+//! line (little-endian), and the tokenized text ending with 0x0D. Keywords are stored
+//! as single bytes from 0xA3 up, and each number in the text is followed by a hidden
+//! 0x0E and its value in the calculator's 5-byte form, which listings do not show.
+//!
+//! The variables follow from VARS to E_LINE, ending with 0x80. Each starts with its
+//! first letter, whose top three bits give its type, and numbers are stored in 5-byte
+//! form.
+
+use std::fmt;
+
+use crate::layout::SYSVARS_START;
+use crate::{charset, zxfloat, SnaError, Snapshot, SystemVariables, SYSVARS_LEN};
+
+/// The first keyword token: SPECTRUM, which with PLAY is only understood by 128K BASIC.
+pub const FIRST_TOKEN: u8 = 0xA3;
+
+/// The keywords for tokens 0xA3 to 0xFF.
+pub const TOKENS: [&str; 93] = [
+    "SPECTRUM", "PLAY", "RND", "INKEY$", "PI", "FN", "POINT", "SCREEN$", "ATTR", "AT", "TAB",
+    "VAL$", "CODE", "VAL", "LEN", "SIN", "COS", "TAN", "ASN", "ACS", "ATN", "LN", "EXP", "INT",
+    "SQR", "SGN", "ABS", "PEEK", "IN", "USR", "STR$", "CHR$", "NOT", "BIN", "OR", "AND", "<=",
+    ">=", "<>", "LINE", "THEN", "TO", "STEP", "DEF FN", "CAT", "FORMAT", "MOVE", "ERASE", "OPEN #",
+    "CLOSE #", "MERGE", "VERIFY", "BEEP", "CIRCLE", "INK", "PAPER", "FLASH", "BRIGHT", "INVERSE",
+    "OVER", "OUT", "LPRINT", "LLIST", "STOP", "READ", "DATA", "RESTORE", "NEW", "BORDER",
+    "CONTINUE", "DIM", "REM", "FOR", "GO TO", "GO SUB", "INPUT", "LOAD", "LIST", "LET", "PAUSE",
+    "NEXT", "POKE", "PRINT", "PLOT", "RUN", "SAVE", "RANDOMIZE", "IF", "CLS", "DRAW", "CLEAR",
+    "RETURN", "COPY",
+];
+
+const NUMBER: u8 = 0x0E;
+const ENTER: u8 = 0x0D;
+const REM: u8 = 0xEA;
+
+/// A line of a BASIC program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub number: u16,
+    pub address: u16,   // where the line starts, at its line number
+    pub body: Vec<u8>,  // the tokenized text, including the 0x0D that ends it
+}
+
+impl Line {
+    /// text returns the line, without its number, as LIST shows it.
+    pub fn text(&self) -> String {
+        detokenize(&self.body)
+    }
+
+    /// len returns the number of bytes the line takes up in memory.
+    pub fn len(&self) -> usize {
+        4 + self.body.len()
+    }
+
+    /// is_empty returns whether the line has no text, not even the 0x0D that ends it.
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty()
+    }
+}
+
+impl fmt::Display for Line {
+    /// Formats the line as LIST does, with the number right aligned in four columns.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>4}{}", self.number, self.text())
+    }
+}
+
+/// lines splits tokenized program bytes, stored from the given address, into lines.
+/// It stops at the first line number above 9999, which marks the end of a program
+/// when it is followed directly by the variables area, or at a line that runs past the
+/// end of the bytes.
+pub fn lines(program: &[u8], address: u16) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while let [high, low, length_low, length_high, ..] = program[offset..] {
+        let number = u16::from_be_bytes([high, low]);
+        let length = u16::from_le_bytes([length_low, length_high]) as usize;
+        if number > 9999 || offset + 4 + length > program.len() {
+            break;
+        }
+        let body = program[offset + 4..offset + 4 + length].to_vec();
+        lines.push(Line { number, address: address.wrapping_add(offset as u16), body });
+        offset += 4 + length;
+    }
+    lines
+}
+
+/// detokenize turns the tokenized text of a line into what LIST shows, expanding
+/// keywords with the spacing the ROM gives them and leaving out hidden numbers and
+/// the parameters of embedded colour controls.
+pub fn detokenize(body: &[u8]) -> String {
+    let mut text = String::new();
+    let mut quoted = false;
+    let mut rem = false;
+    let mut bytes = body.iter().copied();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            ENTER => break,
+            NUMBER if !quoted && !rem => {
+                bytes.nth(4);
+            }
+            // INK, PAPER, FLASH, BRIGHT, INVERSE and OVER take one byte, AT and TAB two
+            0x10..=0x15 => {
+                bytes.next();
+            }
+            0x16 | 0x17 => {
+                bytes.nth(1);
+            }
+            FIRST_TOKEN..=0xFF => {
+                let keyword = TOKENS[(byte - FIRST_TOKEN) as usize];
+                // keywords from OR on, and 128K's own, follow a space
+                let leading = !(0xA5..0xC5).contains(&byte);
+                if leading && !text.ends_with(' ') {
+                    text.push(' ');
+                }
+                text.push_str(keyword);
+                // those ending in a letter or $, except RND, INKEY$ and PI, are followed by one
+                let trailing = keyword.ends_with(|c: char| c.is_ascii_alphabetic() || c == '$') && !(0xA5..0xA8).contains(&byte);
+                if trailing {
+                    text.push(' ');
+                }
+                rem |= byte == REM;
+            }
+            _ => {
+                quoted ^= byte == b'"' && !rem;
+                text.push(charset::to_char(byte).unwrap_or('?'));
+            }
+        }
+    }
+    text
+}
+
+/// The value of a BASIC variable.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f64),
+    String(String),
+    NumberArray { dims: Vec<u16>, values: Vec<f64> },      // elements with the last subscript changing fastest
+    CharArray { dims: Vec<u16>, text: String },            // characters with the last subscript changing fastest
+    ForLoop { value: f64, limit: f64, step: f64, line: u16, statement: u8 },   // a FOR control variable and where NEXT loops back to
+}
+
+/// A BASIC variable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    pub name: String,   // as BASIC writes it, e.g. "score", "a$"; arrays are named without their brackets
+    pub address: u16,   // where the variable starts, at its first letter
+    pub value: Value,
+}
+
+impl fmt::Display for Variable {
+    /// Formats the variable as an assignment, such as `a$="HELLO"` or `b(3)=1,2,3`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dims = |dims: &[u16]| dims.iter().map(|dim| dim.to_string()).collect::<Vec<_>>().join(",");
+        match &self.value {
+            Value::Number(value) => write!(f, "{}={}", self.name, value),
+            Value::String(text) => write!(f, "{}=\"{}\"", self.name, text),
+            Value::NumberArray { dims: sizes, values } => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "{}({})={}", self.name, dims(sizes), values.join(","))
+            }
+            Value::CharArray { dims: sizes, text } => write!(f, "{}({})=\"{}\"", self.name, dims(sizes), text),
+            Value::ForLoop { value, limit, step, line, statement } => {
+                write!(f, "{}={} TO {} STEP {} (line {}:{})", self.name, value, limit, step, line, statement)
+            }
+        }
+    }
+}
+
+/// variables decodes a variables area stored from the given address. It stops at the
+/// 0x80 that ends the area, or at a variable that runs past the end of the bytes.
+pub fn variables(area: &[u8], address: u16) -> Vec<Variable> {
+    let mut variables = Vec::new();
+    let mut offset = 0;
+    while let Some((variable, len)) = variable_at(area, offset, address.wrapping_add(offset as u16)) {
+        variables.push(variable);
+        offset += len;
+    }
+    variables
+}
+
+fn number(bytes: &[u8]) -> Option<f64> {
+    Some(zxfloat::decode(bytes.get(..5)?.try_into().ok()?))
+}
+
+fn word(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*bytes.get(offset)?, *bytes.get(offset + 1)?]))
+}
+
+// decodes the variable at an offset, returning it and the bytes it takes up
+fn variable_at(area: &[u8], offset: usize, address: u16) -> Option<(Variable, usize)> {
+    let bytes = &area[offset..];
+    let first = *bytes.first()?;
+    let letter = ((first & 0x1F) | 0x60) as char;
+    let variable = |name: String, value: Value| Variable { name, address, value };
+    match first >> 5 {
+        // a number with a one letter name
+        0b011 => Some((variable(letter.to_string(), Value::Number(number(&bytes[1..])?)), 6)),
+        // a number with a longer name, whose last letter has bit 7 set
+        0b101 => {
+            let end = bytes.iter().skip(1).position(|&byte| byte & 0x80 != 0)? + 1;
+            let name: String = std::iter::once(letter).chain(bytes[1..=end].iter().map(|&byte| (byte & 0x7F).to_ascii_lowercase() as char)).collect();
+            Some((variable(name, Value::Number(number(&bytes[end + 1..])?)), end + 6))
+        }
+        // a string
+        0b010 => {
+            let len = word(bytes, 1)? as usize;
+            let text = charset::decode(bytes.get(3..3 + len)?);
+            Some((variable(format!("{}$", letter), Value::String(text)), 3 + len))
+        }
+        // a FOR control variable
+        0b111 => {
+            let value = Value::ForLoop {
+                value: number(&bytes[1..])?,
+                limit: number(&bytes[6..])?,
+                step: number(&bytes[11..])?,
+                line: word(bytes, 16)?,
+                statement: *bytes.get(18)?,
+            };
+            Some((variable(letter.to_string(), value), 19))
+        }
+        // arrays: the length of the rest, the number of dimensions, their sizes, then the elements
+        0b100 | 0b110 => {
+            let len = 3 + word(bytes, 1)? as usize;
+            let count = *bytes.get(3)? as usize;
+            let dims: Vec<u16> = (0..count).map(|dim| word(bytes, 4 + dim * 2)).collect::<Option<_>>()?;
+            let elements = bytes.get(4 + count * 2..len)?;
+            if first >> 5 == 0b100 {
+                let values = elements.chunks_exact(5).map(number).collect::<Option<_>>()?;
+                Some((variable(letter.to_string(), Value::NumberArray { dims, values }), len))
+            } else {
+                let text = charset::decode(elements);
+                Some((variable(format!("{}$", letter), Value::CharArray { dims, text }), len))
+            }
+        }
+        // 0x80 ends the area
+        _ => None,
+    }
+}
+
+impl Snapshot {
+    /// basic_variables returns the BASIC variables, found through VARS.
+    /// The result is empty if the system variables do not describe a variables area.
+    pub fn basic_variables(&self) -> Vec<Variable> {
+        let sysvars = self.sysvars();
+        if sysvars.vars < 0x4000 || sysvars.e_line < sysvars.vars {
+            return Vec::new();
+        }
+        let area: Vec<u8> = (sysvars.vars..sysvars.e_line).map(|address| self.peek(address)).collect();
+        variables(&area, sysvars.vars)
+    }
+
+    /// basic_variable returns the value of a BASIC variable, such as `score` or `a$`.
+    /// As in BASIC, case and spaces in the name do not matter.
+    pub fn basic_variable(&self, name: &str) -> Option<Value> {
+        let name: String = name.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_lowercase();
+        self.basic_variables().into_iter().find(|variable| variable.name == name).map(|variable| variable.value)
+    }
+
+    /// basic_lines returns the lines of the BASIC program, found through PROG and VARS.
+    /// The result is empty if the system variables do not describe a program.
+    pub fn basic_lines(&self) -> Vec<Line> {
+        let sysvars = self.sysvars();
+        if sysvars.prog < 0x4000 || sysvars.vars < sysvars.prog {
+            return Vec::new();
+        }
+        let program: Vec<u8> = (sysvars.prog..sysvars.vars).map(|address| self.peek(address)).collect();
+        lines(&program, sysvars.prog)
+    }
+
+    /// list_basic returns the BASIC program as LIST would show it, one line per line.
+    pub fn list_basic(&self) -> String {
+        self.basic_lines().iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// set_basic_line stores a tokenized line, ending with 0x0D, as the line with the
+    /// given number, replacing any line that already has it or inserting it in order.
+    /// Everything from the line to STKEND moves up or down and the system variables
+    /// that point into it are adjusted, as the ROM does when a line is entered.
+    /// Returns SnaError::InvalidFormat if the number is above 9999, the body does not
+    /// end with 0x0D or there is no program, and SnaError::InvalidLength if the line
+    /// does not fit in the spare memory below the machine stack.
+    pub fn set_basic_line(&mut self, number: u16, body: &[u8]) -> Result<(), SnaError> {
+        if number > 9999 {
+            return Err(SnaError::InvalidFormat(format!("Line number {} is above 9999", number)));
+        }
+        if body.last() != Some(&0x0D) {
+            return Err(SnaError::InvalidFormat("A BASIC line must end with 0x0D".to_string()));
+        }
+        let program = self.basic_program()?;
+        let (address, old_len) = match program.iter().find(|line| line.number >= number) {
+            Some(line) if line.number == number => (line.address, line.len()),
+            Some(line) => (line.address, 0),
+            None => (self.sysvars().vars, 0),
+        };
+        let mut bytes = number.to_be_bytes().to_vec();
+        bytes.extend((body.len() as u16).to_le_bytes());
+        bytes.extend(body);
+        self.resize_basic(address, old_len, &bytes)
+    }
+
+    /// delete_basic_line removes the line with the given number, moving everything
+    /// after it down as set_basic_line does. Returns whether there was such a line, or
+    /// SnaError::InvalidFormat if there is no program.
+    pub fn delete_basic_line(&mut self, number: u16) -> Result<bool, SnaError> {
+        match self.basic_program()?.into_iter().find(|line| line.number == number) {
+            Some(line) => self.resize_basic(line.address, line.len(), &[]).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    // the program lines, or an error if the system variables do not describe a program
+    fn basic_program(&self) -> Result<Vec<Line>, SnaError> {
+        let sysvars = self.sysvars();
+        if sysvars.prog < 0x4000 || sysvars.vars < sysvars.prog || sysvars.stkend < sysvars.vars {
+            return Err(SnaError::InvalidFormat("The system variables do not describe a BASIC program".to_string()));
+        }
+        Ok(self.basic_lines())
+    }
+
+    // replaces old_len bytes at the address with the new bytes, moving the rest of the
+    // BASIC areas up to STKEND and the pointers into them, as the ROM's POINTERS does
+    fn resize_basic(&mut self, address: u16, old_len: usize, bytes: &[u8]) -> Result<(), SnaError> {
+        let mut sysvars = self.sysvars();
+        let end = sysvars.stkend as usize;
+        let stkend = end + bytes.len() - old_len;
+        let free = ({ self.header.sp } as usize).saturating_sub(end);
+        if bytes.len() > old_len && stkend > { self.header.sp } as usize {
+            return Err(SnaError::InvalidLength { len: bytes.len() - old_len, max: free });
+        }
+        let tail: Vec<u8> = (address as usize + old_len..end).map(|at| self.peek(at as u16)).collect();
+        let moved: Vec<u8> = bytes.iter().chain(&tail).copied().collect();
+        self.check_write(address, moved.len())?;
+        self.check_write(SYSVARS_START, SYSVARS_LEN)?;
+
+        let pointers: [fn(&mut SystemVariables) -> &mut u16; 11] = [
+            |s| &mut s.vars, |s| &mut s.dest, |s| &mut s.nxtlin, |s| &mut s.datadd, |s| &mut s.e_line,
+            |s| &mut s.k_cur, |s| &mut s.ch_add, |s| &mut s.x_ptr, |s| &mut s.worksp, |s| &mut s.stkbot,
+            |s| &mut s.stkend,
+        ];
+        for pointer in pointers {
+            let value = pointer(&mut sysvars);
+            if *value >= address {
+                // pointers into the replaced bytes end up at their start
+                let moved = (*value as usize).max(address as usize + old_len) + bytes.len() - old_len;
+                *value = moved as u16;
+            }
+        }
+        self.grouped(|snapshot| {
+            snapshot.poke_slice(address, &moved)?;
+            snapshot.set_sysvars(&sysvars)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_detokenize() {
+        // PRINT AT 0,1;"HI": GO TO 10 with the hidden forms of 0, 1 and 10
+        let body = [
+            0xF5, 0xAC, b'0', 0x0E, 0, 0, 0, 0, 0, b',', b'1', 0x0E, 0, 0, 1, 0, 0, b';', b'"', b'H', b'I', b'"',
+            b':', 0xEC, b'1', b'0', 0x0E, 0, 0, 10, 0, 0, 0x0D,
+        ];
+        assert_eq!(detokenize(&body), " PRINT AT 0,1;\"HI\": GO TO 10");
+        // IF a<=b THEN LET x=RND: REM the PI in here is still a keyword
+        let body = [0xFA, b'a', 0xC7, b'b', 0xCB, 0xF1, b'x', b'=', 0xA5, b':', 0xEA, b'`', 0x0E, 0xA7, 0x0D];
+        assert_eq!(detokenize(&body), " IF a <=b THEN LET x=RND: REM £?PI");
+        // INK 2 embedded as a colour control
+        assert_eq!(detokenize(&[b'"', 0x10, 0x02, b'A', b'"', 0x0D]), "\"A\"");
+    }
+
+    #[test]
+    fn test_list_basic() {
+        let snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions { basic: true, ..FixtureOptions::default() });
+        let program = snapshot.basic_lines();
+        assert_eq!(program.len(), 1);
+        assert_eq!(program[0].number, 10);
+        assert_eq!(program[0].address, 0x5CCB);
+        assert_eq!(program[0].len(), fixtures::FIXTURE_BASIC.len());
+        assert_eq!(snapshot.list_basic(), "  10 PRINT \"HELLO\"\n");
+
+        let blank = Snapshot::new_48k();
+        assert!(blank.basic_lines().is_empty());
+        assert!(lines(&[0x00, 0x0A, 0x09, 0x00], 0x5CCB).is_empty(), "The line runs past the end of the program");
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut area = vec![0x61, 0x00, 0x00, 0x2A, 0x00, 0x00];                     // a=42
+        area.extend([0xB3, 0x43, 0xCF, 0x00, 0xFF, 0xFF, 0xFF, 0x00]);              // sco=-1
+        area.extend([0x42, 0x02, 0x00, b'H', b'I']);                                // b$="HI"
+        area.extend([0x83, 0x0D, 0x00, 0x01, 0x02, 0x00]);                          // DIM c(2)
+        area.extend([0x00, 0x00, 0x01, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00, 0x00]);  // c(1)=1, c(2)=1
+        area.extend([0xC4, 0x07, 0x00, 0x02, 0x02, 0x00, 0x01, 0x00, b'A', b'B']);  // DIM d$(2,1)
+        area.extend([0xE9, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x00, 0x00,
+                     0x00, 0x00, 0x01, 0x00, 0x00, 0x14, 0x00, 0x02]);               // FOR i=1 TO 10 at line 20:2
+        area.push(0x80);
+
+        let found = variables(&area, 0x5D00);
+        assert_eq!(found.len(), 6);
+        assert_eq!(found[0], Variable { name: "a".to_string(), address: 0x5D00, value: Value::Number(42.0) });
+        assert_eq!(found[1].name, "sco");
+        assert_eq!(found[1].value, Value::Number(-1.0));
+        assert_eq!(found[1].address, 0x5D06);
+        assert_eq!(found[2].value, Value::String("HI".to_string()));
+        assert_eq!(found[3].value, Value::NumberArray { dims: vec![2], values: vec![1.0, 1.0] });
+        assert_eq!(found[4].to_string(), "d$(2,1)=\"AB\"");
+        assert_eq!(found[5].value, Value::ForLoop { value: 1.0, limit: 10.0, step: 1.0, line: 20, statement: 2 });
+        assert!(variables(&area[..3], 0x5D00).is_empty(), "The number runs past the end of the area");
+
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions { basic: true, ..FixtureOptions::default() });
+        assert!(snapshot.basic_variables().is_empty(), "The fixture has no variables");
+        let mut sysvars = snapshot.sysvars();
+        for (offset, &byte) in area.iter().enumerate() {
+            snapshot.poke(sysvars.vars + offset as u16, byte).unwrap();
+        }
+        sysvars.e_line = sysvars.vars + area.len() as u16;
+        snapshot.set_sysvars(&sysvars).unwrap();
+        assert_eq!(snapshot.basic_variable("S CO"), Some(Value::Number(-1.0)));
+        assert_eq!(snapshot.basic_variable("b$"), Some(Value::String("HI".to_string())));
+        assert_eq!(snapshot.basic_variable("z"), None);
+    }
+
+    #[test]
+    fn test_set_basic_line() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions { basic: true, ..FixtureOptions::default() });
+        let before = snapshot.sysvars();
+        // RANDOMIZE USR 32768 with its hidden number
+        let usr = [0xF9, 0xC0, b'3', b'2', b'7', b'6', b'8', 0x0E, 0x00, 0x00, 0x00, 0x80, 0x00, 0x0D];
+        snapshot.set_basic_line(20, &usr).unwrap();
+        snapshot.set_basic_line(5, &[0xFB, 0x0D]).unwrap();
+        assert_eq!(snapshot.list_basic(), "   5 CLS \n  10 PRINT \"HELLO\"\n  20 RANDOMIZE USR 32768\n");
+        let after = snapshot.sysvars();
+        let grown = (6 + 4 + usr.len()) as u16;
+        assert_eq!(after.prog, before.prog);
+        assert_eq!(after.vars, before.vars + grown);
+        assert_eq!(after.e_line, before.e_line + grown);
+        assert_eq!(after.stkend, before.stkend + grown);
+        assert_eq!(snapshot.peek(after.vars), 0x80, "The variables area moved with VARS");
+        assert_eq!(snapshot.peek(after.e_line), 0x0D, "The edit line moved with E_LINE");
+
+        snapshot.set_basic_line(10, &[0xF5, b'1', 0x0D]).unwrap();
+        assert_eq!(snapshot.basic_lines()[1].text(), " PRINT 1");
+        assert!(snapshot.delete_basic_line(5).unwrap());
+        assert!(!snapshot.delete_basic_line(5).unwrap());
+        assert_eq!(snapshot.list_basic(), "  10 PRINT 1\n  20 RANDOMIZE USR 32768\n");
+        assert_eq!(snapshot.sysvars().stkend, before.stkend + (4 + usr.len() - 6) as u16);
+
+        assert!(matches!(snapshot.set_basic_line(10000, &[0x0D]), Err(SnaError::InvalidFormat(_))));
+        assert!(matches!(snapshot.set_basic_line(10, &[0xF5]), Err(SnaError::InvalidFormat(_))));
+        let mut long = vec![0x20; 0xB000];
+        long.push(0x0D);
+        assert!(matches!(snapshot.set_basic_line(30, &long), Err(SnaError::InvalidLength { .. })));
+        assert!(Snapshot::new_48k().delete_basic_line(10).is_err(), "There is no program");
+    }
+}
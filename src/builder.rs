@@ -0,0 +1,265 @@
+use std::fmt;
+
+use crate::{Bank, Machine, Snapshot, SnapshotExtension, SnapshotHeader, SnapshotType, MEM_16K};
+
+/// The SP of a blank snapshot, just below the UDGs where the ROM leaves it.
+const BLANK_SP: u16 = 0xFF58;
+/// The IY of a blank snapshot, which the ROM expects to point at the system variables.
+const BLANK_IY: u16 = 0x5C3A;
+
+fn blank_header() -> SnapshotHeader {
+    SnapshotHeader {
+        iy: BLANK_IY,
+        interrupt: 0x04,    // interrupts enabled
+        sp: BLANK_SP,
+        int_mode: 1,
+        border_color: 7,
+        ..SnapshotHeader::default()
+    }
+}
+
+impl Snapshot {
+    /// new_48k creates a 48K snapshot with zeroed memory, interrupts enabled in IM 1,
+    /// a white border and SP at 0xFF58. The stacked PC is 0x0000, so until it is set
+    /// the snapshot resets the machine when loaded.
+    pub fn new_48k() -> Snapshot {
+        let mut snapshot = Snapshot {
+            header: blank_header(),
+            banks: vec![Bank::new(), Bank::new(), Bank::new()],
+            mapping: [0, 1, 2],
+            ..Snapshot::default()
+        };
+        snapshot.remap();
+        snapshot
+    }
+
+    /// new_plus3 creates a +2A/+3 snapshot, which is new_128k with port 0x1FFD at 0:
+    /// normal paging with ROM 0 selected.
+    pub fn new_plus3() -> Snapshot {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.set_machine(Machine::SpectrumPlus3).expect("A +2A/+3 has the banks of a 128K");
+        snapshot
+    }
+
+    /// new_128k creates a 128K snapshot with zeroed memory in all eight banks, bank 0
+    /// paged at 0xC000 with the 128K ROM selected, PC 0x0000 and otherwise the same
+    /// registers as new_48k.
+    pub fn new_128k() -> Snapshot {
+        let mut snapshot = Snapshot {
+            snapshot_type: SnapshotType::Snapshot128,
+            header: blank_header(),
+            extension: Some(SnapshotExtension { pc: 0x0000, x7ffd: 0x00, tr_dos: 0 }),
+            banks: (0..8).map(|_| Bank::new()).collect(),
+            mapping: [5, 2, 0],
+            ..Snapshot::default()
+        };
+        snapshot.remap();
+        snapshot
+    }
+}
+
+/// An error from building a snapshot.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BuildError {
+    pub message: String,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+fn error<T>(message: String) -> Result<T, BuildError> {
+    Err(BuildError { message })
+}
+
+/// Builds a snapshot from scratch, checking every value before anything is built.
+/// Registers not set keep the values of [`Snapshot::new_48k`] and [`Snapshot::new_128k`].
+#[derive(Clone)]
+pub struct SnapshotBuilder {
+    machine: Machine,
+    header: SnapshotHeader,
+    pc: u16,
+    x7ffd: Option<u8>,
+    banks: Vec<(usize, Vec<u8>)>,
+}
+
+impl Default for SnapshotBuilder {
+    fn default() -> Self {
+        SnapshotBuilder {
+            machine: Machine::Spectrum48,
+            header: blank_header(),
+            pc: 0x0000,
+            x7ffd: None,
+            banks: Vec::new(),
+        }
+    }
+}
+
+impl SnapshotBuilder {
+    /// new creates a builder for a blank 48K snapshot.
+    pub fn new() -> SnapshotBuilder {
+        SnapshotBuilder::default()
+    }
+
+    /// machine sets the model the snapshot is for.
+    pub fn machine(&mut self, machine: Machine) -> &mut SnapshotBuilder {
+        self.machine = machine;
+        self
+    }
+
+    /// header replaces every register at once.
+    pub fn header(&mut self, header: SnapshotHeader) -> &mut SnapshotBuilder {
+        self.header = header;
+        self
+    }
+
+    /// pc sets where execution resumes. For 48K snapshots it is stored on the stack at SP.
+    pub fn pc(&mut self, pc: u16) -> &mut SnapshotBuilder {
+        self.pc = pc;
+        self
+    }
+
+    /// sp sets the stack pointer.
+    pub fn sp(&mut self, sp: u16) -> &mut SnapshotBuilder {
+        self.header.sp = sp;
+        self
+    }
+
+    /// border sets the border colour, from 0 to 7.
+    pub fn border(&mut self, border: u8) -> &mut SnapshotBuilder {
+        self.header.border_color = border;
+        self
+    }
+
+    /// int_mode sets the interrupt mode, from 0 to 2.
+    pub fn int_mode(&mut self, int_mode: u8) -> &mut SnapshotBuilder {
+        self.header.int_mode = int_mode;
+        self
+    }
+
+    /// interrupts sets whether interrupts are enabled.
+    pub fn interrupts(&mut self, enabled: bool) -> &mut SnapshotBuilder {
+        self.header.interrupt = if enabled { 0x04 } else { 0x00 };
+        self
+    }
+
+    /// x7ffd sets the last value written to port 0x7FFD on a 128K machine.
+    pub fn x7ffd(&mut self, value: u8) -> &mut SnapshotBuilder {
+        self.x7ffd = Some(value);
+        self
+    }
+
+    /// bank sets the 16K contents of a bank. For 48K snapshots banks 0, 1 and 2 are
+    /// the memory at 0x4000, 0x8000 and 0xC000.
+    pub fn bank(&mut self, bank: usize, data: &[u8]) -> &mut SnapshotBuilder {
+        self.banks.push((bank, data.to_vec()));
+        self
+    }
+
+    /// validate checks every value set on the builder, without building anything.
+    pub fn validate(&self) -> Result<(), BuildError> {
+        let header = self.header;
+        if header.border_color > 7 {
+            return error(format!("Border colour {} is not between 0 and 7", header.border_color));
+        }
+        if header.int_mode > 2 {
+            return error(format!("Interrupt mode {} is not 0, 1 or 2", header.int_mode));
+        }
+        if header.interrupt & !0x04 != 0 {
+            return error(format!("Interrupt state 0x{:02X} has bits other than IFF2 set", header.interrupt));
+        }
+        for (bank, data) in &self.banks {
+            if *bank >= self.machine.bank_count() {
+                return error(format!("The {} has no bank {}", self.machine, bank));
+            }
+            if data.len() != MEM_16K {
+                return error(format!("Bank {} holds {} bytes rather than 16K", bank, data.len()));
+            }
+        }
+        match (self.machine.snapshot_type(), self.x7ffd) {
+            (SnapshotType::Snapshot48, Some(_)) => return error(format!("The {} has no port 0x7FFD", self.machine)),
+            (SnapshotType::Snapshot48, None) => {
+                let sp = { header.sp };
+                if !(0x4000..0xFFFF).contains(&sp) {
+                    return error(format!("SP 0x{:04X} cannot hold the PC of a 48K snapshot", sp));
+                }
+            }
+            (SnapshotType::Snapshot128, Some(value)) if value & 0xC0 != 0 => {
+                return error(format!("Port 0x7FFD value 0x{:02X} sets unused bits", value));
+            }
+            (SnapshotType::Snapshot128, _) => {}
+        }
+        Ok(())
+    }
+
+    /// build validates the builder and produces the snapshot.
+    pub fn build(&self) -> Result<Snapshot, BuildError> {
+        self.validate()?;
+        let mut snapshot = match self.machine.snapshot_type() {
+            SnapshotType::Snapshot48 => Snapshot::new_48k(),
+            SnapshotType::Snapshot128 => Snapshot::new_128k(),
+        };
+        snapshot.set_machine(self.machine).expect("The snapshot is of the machine's type");
+        snapshot.header = self.header;
+        for (bank, data) in &self.banks {
+            snapshot.banks[*bank] = Bank::from_slice(data).expect("validate has checked the bank sizes");
+        }
+        if let Some(extension) = snapshot.extension.as_mut() {
+            extension.x7ffd = self.x7ffd.unwrap_or(0);
+            snapshot.mapping[2] = extension.x7ffd & 0x07;
+            snapshot.remap();
+        }
+        snapshot.set_pc(self.pc).expect("SP was checked to be in RAM");
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank_snapshots() {
+        let snapshot = Snapshot::new_48k();
+        assert_eq!(snapshot.banks.len(), 3);
+        assert_eq!(({ snapshot.header.sp }, snapshot.header.int_mode, snapshot.header.border_color), (BLANK_SP, 1, 7));
+        assert_eq!(snapshot.peek_word(BLANK_SP).ok(), Some(0x0000), "The stacked PC should be 0");
+        assert!(snapshot.check_stack().is_ok());
+
+        let mut snapshot = Snapshot::new_128k();
+        assert_eq!(snapshot.banks.len(), 8);
+        assert!((0..8).all(|bank| snapshot.checksum(bank).unwrap() == 0), "Banks should be zeroed");
+        snapshot.poke(0xC000, 0x42).unwrap();
+        assert_eq!(snapshot.bank_peek(0, 0).ok(), Some(0x42), "Bank 0 should be paged at 0xC000");
+
+        let mut bin = Vec::new();
+        snapshot.write_to(&mut bin).expect("Failed to write snapshot");
+        assert_eq!(bin.len(), 131103);
+    }
+
+    #[test]
+    fn test_builder() {
+        let screen = vec![0xAA; MEM_16K];
+        let snapshot = SnapshotBuilder::new().machine(Machine::Spectrum128).pc(0x8000).border(1).x7ffd(0x13).bank(5, &screen).build()
+            .expect("Failed to build snapshot");
+        assert_eq!(snapshot.snapshot_type, SnapshotType::Snapshot128);
+        assert_eq!(snapshot.extension.map(|extension| extension.pc), Some(0x8000));
+        assert_eq!((snapshot.mapping, snapshot.header.border_color), ([5, 2, 3], 1));
+        assert_eq!(snapshot.peek(0x4000), 0xAA);
+
+        let snapshot = SnapshotBuilder::new().pc(0x8000).sp(0x9000).bank(0, &screen).build().expect("Failed to build snapshot");
+        assert_eq!(snapshot.peek_word(0x9000).ok(), Some(0x8000), "A 48K PC should be stored at SP");
+        assert_eq!(snapshot.peek(0x4000), 0xAA);
+
+        assert!(SnapshotBuilder::new().border(8).build().is_err(), "Border 8 is invalid");
+        assert!(SnapshotBuilder::new().int_mode(3).build().is_err(), "IM 3 is invalid");
+        assert!(SnapshotBuilder::new().bank(3, &screen).build().is_err(), "A 48K snapshot has no bank 3");
+        assert!(SnapshotBuilder::new().bank(0, &screen[1..]).build().is_err(), "Banks must be 16K");
+        assert!(SnapshotBuilder::new().x7ffd(0).build().is_err(), "A 48K snapshot has no port 0x7FFD");
+        assert!(SnapshotBuilder::new().sp(0x3000).build().is_err(), "A 48K PC cannot be stored in ROM");
+    }
+}
@@ -0,0 +1,132 @@
+// The snapshot as the memory and I/O of an emulated Z80, so an emulator can run
+// code against a snapshot in place rather than copying its memory out and back.
+
+use crate::{JoystickType, Snapshot, SnapshotType};
+
+/// The memory and ports a Z80 emulator reads and writes.
+pub trait MemoryBus {
+    /// read returns the byte at an address.
+    fn read(&mut self, address: u16) -> u8;
+
+    /// write stores a byte at an address.
+    fn write(&mut self, address: u16, value: u8);
+
+    /// read_io returns the byte an IN from a port sees. By default nothing answers,
+    /// so the bus floats high.
+    fn read_io(&mut self, _port: u16) -> u8 {
+        0xFF
+    }
+
+    /// write_io performs an OUT to a port. By default nothing listens.
+    fn write_io(&mut self, _port: u16, _value: u8) {}
+}
+
+impl MemoryBus for Snapshot {
+    /// read returns the byte paged in at the address, as peek does.
+    fn read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
+
+    /// write stores a byte as poke does. Writes to ROM are ignored, as on the machine.
+    fn write(&mut self, address: u16, value: u8) {
+        let _ = self.poke(address, value);
+    }
+
+    /// read_io answers the keyboard on port 0xFE and, when the snapshot has their state,
+    /// the Kempston joystick on port 0x1F and the AY register on port 0xFFFD.
+    fn read_io(&mut self, port: u16) -> u8 {
+        match (&self.input, &self.ay) {
+            (Some(input), _) if port & 0x0001 == 0 => input.read_0xfe((port >> 8) as u8),
+            (Some(input), _) if input.joystick == JoystickType::Kempston && port & 0x00E0 == 0 => input.kempston,
+            (_, Some(ay)) if port & 0xC002 == 0xC000 => ay.read_0xfffd(),
+            _ => 0xFF,
+        }
+    }
+
+    /// write_io sets the border through port 0xFE and, on 128K machines, pages through
+    /// 0x7FFD and 0x1FFD and drives the AY through 0xFFFD and 0xBFFD, decoding addresses
+    /// partially as the machine does. Writes the snapshot has no hardware for are ignored.
+    fn write_io(&mut self, port: u16, value: u8) {
+        if port & 0x0001 == 0 {
+            self.header.border_color = value & 0x07;
+        }
+        if self.snapshot_type != SnapshotType::Snapshot128 || port & 0x0002 != 0 {
+            return;
+        }
+        let machine = self.machine();
+        match port & 0xF000 {
+            0x1000 if machine.has_0x1ffd() => {
+                let _ = self.write_0x1ffd(value);
+            }
+            0xC000..=0xF000 => {
+                if let Some(ay) = self.ay.as_mut() {
+                    ay.write_0xfffd(value);
+                }
+            }
+            0x8000..=0xB000 => {
+                if let Some(ay) = self.ay.as_mut() {
+                    ay.write_0xbffd(value);
+                }
+            }
+            // the +2A/+3 also needs A14 set, so that 0x1FFD is not taken for 0x7FFD
+            0x4000..=0x7000 => {
+                let _ = self.write_0x7ffd(value);
+            }
+            _ if !machine.has_0x1ffd() => {
+                let _ = self.write_0x7ffd(value);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AyState, InputState, Machine};
+
+    // code that runs through the bus rather than the snapshot
+    fn copy<B: MemoryBus>(bus: &mut B, from: u16, to: u16, len: u16) {
+        for offset in 0..len {
+            let value = bus.read(from + offset);
+            bus.write(to + offset, value);
+        }
+    }
+
+    #[test]
+    fn test_memory_bus() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.poke(0x8000, 0x2A).unwrap();
+        copy(&mut snapshot, 0x8000, 0x9000, 2);
+        assert_eq!(snapshot.peek(0x9000), 0x2A);
+        snapshot.write(0x0000, 0x01);
+        assert_eq!(snapshot.read(0x0000), 0xFF, "ROM writes are ignored");
+
+        snapshot.write_io(0x00FE, 0x13);
+        assert_eq!(snapshot.header.border_color, 3);
+        assert_eq!(snapshot.read_io(0x7FFE), 0xFF, "No keyboard state");
+        let mut input = InputState { joystick: JoystickType::Kempston, kempston: 0x10, ..InputState::default() };
+        input.set_key(7, 0, true).unwrap();
+        snapshot.input = Some(input);
+        assert_eq!(snapshot.read_io(0x7FFE), 0xFE, "SPACE is down");
+        assert_eq!(snapshot.read_io(0x001F), 0x10);
+        snapshot.write_io(0x7FFD, 0x07);
+        assert_eq!(snapshot.mapping, [0, 1, 2], "A 48K has no paging");
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.ay = Some(AyState::default());
+        snapshot.write_io(0x7FFD, 0x03);
+        assert_eq!(snapshot.mapping[2], 3);
+        snapshot.write_io(0x3FFD, 0x04);
+        assert_eq!(snapshot.mapping[2], 4, "A 128K only decodes A15 and A1");
+        snapshot.write_io(0xFFFD, 0x07);
+        snapshot.write_io(0xBFFD, 0x38);
+        assert_eq!(snapshot.read_io(0xFFFD), 0x38);
+
+        snapshot.set_machine(Machine::SpectrumPlus3).unwrap();
+        snapshot.write_io(0x1FFD, 0x01);
+        assert_eq!(snapshot.x1ffd, Some(0x01));
+        snapshot.write(0x0000, 0x55);
+        assert_eq!(snapshot.read(0x0000), 0x55, "Special paging maps RAM at 0x0000");
+    }
+}
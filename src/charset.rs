@@ -0,0 +1,81 @@
+//! The Spectrum character set and its UTF-8 equivalents.
+//!
+//! Codes 0x20 to 0x7F are ASCII except for 0x5E, 0x60 and 0x7F, which print as ↑, £
+//! and ©. Codes 0x80 to 0x8F are the block graphics, each a character cell divided into
+//! quarters, which map to the Unicode quadrant blocks. Codes 0x90 to 0xA4 are the user
+//! defined graphics A to U, whose shapes live in RAM, and map to the circled letters Ⓐ
+//! to Ⓤ as placeholders. Control codes and keyword tokens have no character.
+
+use crate::SnaError;
+
+/// The first user defined graphic, A.
+pub const FIRST_UDG: u8 = 0x90;
+
+/// The number of user defined graphics, A to U.
+pub const UDGS: u8 = 21;
+
+// the block graphics from 0x80, with bit 0 for the top right quarter, bit 1 the top
+// left, bit 2 the bottom right and bit 3 the bottom left
+const BLOCKS: [char; 16] = [' ', '▝', '▘', '▀', '▗', '▐', '▚', '▜', '▖', '▞', '▌', '▛', '▄', '▟', '▙', '█'];
+
+/// to_char returns the character a code prints as, or None for control codes and
+/// keyword tokens.
+pub fn to_char(code: u8) -> Option<char> {
+    match code {
+        0x5E => Some('↑'),
+        0x60 => Some('£'),
+        0x7F => Some('©'),
+        0x20..=0x7E => Some(code as char),
+        0x80..=0x8F => Some(BLOCKS[(code - 0x80) as usize]),
+        FIRST_UDG..=0xA4 => char::from_u32('Ⓐ' as u32 + (code - FIRST_UDG) as u32),
+        _ => None,
+    }
+}
+
+/// from_char returns the code of a character, or None if the Spectrum cannot print it.
+/// '^' is taken as ↑, and a space is always 0x20 rather than the empty block graphic.
+pub fn from_char(c: char) -> Option<u8> {
+    match c {
+        '↑' | '^' => Some(0x5E),
+        '£' => Some(0x60),
+        '©' => Some(0x7F),
+        '`' => None,
+        ' '..='~' => Some(c as u8),
+        'Ⓐ'..='Ⓤ' => Some(FIRST_UDG + (c as u32 - 'Ⓐ' as u32) as u8),
+        _ => BLOCKS.iter().position(|&block| block == c).map(|index| 0x80 + index as u8),
+    }
+}
+
+/// decode converts Spectrum text to a string, with ? for codes that are not characters.
+pub fn decode(bytes: &[u8]) -> String {
+    bytes.iter().map(|&code| to_char(code).unwrap_or('?')).collect()
+}
+
+/// encode converts a string to Spectrum text. Returns SnaError::InvalidFormat naming
+/// the first character that the Spectrum cannot print.
+pub fn encode(text: &str) -> Result<Vec<u8>, SnaError> {
+    text.chars()
+        .map(|c| from_char(c).ok_or_else(|| SnaError::InvalidFormat(format!("'{}' is not in the Spectrum character set", c))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charset() {
+        assert_eq!(decode(b"HELLO"), "HELLO");
+        assert_eq!(decode(&[0x60, 0x31, 0x7F, 0x5E]), "£1©↑");
+        assert_eq!(decode(&[0x80, 0x83, 0x8C, 0x8F, 0x90, 0xA4, 0x0D, 0xA5]), " ▀▄█ⒶⓊ??");
+        assert_eq!(encode("£1 © 2^3").unwrap(), [0x60, 0x31, 0x20, 0x7F, 0x20, 0x32, 0x5E, 0x33]);
+        assert!(matches!(encode("café"), Err(SnaError::InvalidFormat(_))));
+        assert_eq!(from_char('`'), None);
+
+        for code in 0x20..=0xA4 {
+            if code != 0x80 {
+                assert_eq!(to_char(code).and_then(from_char), Some(code), "Code {:02X} did not survive conversion", code);
+            }
+        }
+    }
+}
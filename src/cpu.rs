@@ -0,0 +1,123 @@
+// Taking and restoring snapshots from a running emulator. The emulator converts its
+// registers to and from a header; the snapshot deals with where the PC is kept.
+
+use crate::{SnaError, Snapshot, SnapshotHeader, MEM_16K, MEM_48K};
+
+/// The registers of an emulated Z80. An emulator implements the conversions to and
+/// from a snapshot header, and gets load_state for restoring a snapshot with them.
+pub trait CpuState {
+    /// header returns the registers in the form a snapshot header holds them, with the
+    /// SP the CPU is using and IFF2 in bit 2 of interrupt. The border is ignored.
+    fn header(&self) -> SnapshotHeader;
+
+    /// pc returns the program counter.
+    fn pc(&self) -> u16;
+
+    /// set_header sets the registers from a snapshot header whose SP is the one the CPU
+    /// is to use.
+    fn set_header(&mut self, header: &SnapshotHeader);
+
+    /// set_pc sets the program counter.
+    fn set_pc(&mut self, pc: u16);
+
+    /// load_state sets the registers from a snapshot. The PC of a 48K snapshot is popped
+    /// off its stack, as the loader's RETN does, leaving the memory unchanged. Returns
+    /// SnaError::InvalidFormat if the SP of a 48K snapshot is not in RAM.
+    fn load_state(&mut self, snapshot: &Snapshot) -> Result<(), SnaError> {
+        let pc = snapshot.pc().ok_or_else(|| SnaError::InvalidFormat("The stacked PC is not in RAM".to_string()))?;
+        self.set_header(&SnapshotHeader { sp: snapshot.cpu_sp(), ..snapshot.header });
+        self.set_pc(pc);
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// from_state takes a snapshot of a running machine from its CPU and RAM: 48K from
+    /// 0x4000, or the eight banks of a 128K in bank order, which is then paged as
+    /// new_128k is until changed with set_paging_state. The PC of a 48K snapshot is
+    /// pushed onto the stack. Returns SnaError::InvalidFormat for any other length of
+    /// RAM, or an error from set_state.
+    pub fn from_state<C: CpuState>(cpu: &C, memory: &[u8]) -> Result<Snapshot, SnaError> {
+        let mut snapshot = match memory.len() {
+            MEM_48K => Snapshot::new_48k(),
+            len if len == MEM_16K * 8 => Snapshot::new_128k(),
+            len => return Err(SnaError::InvalidFormat(format!("RAM must be 48K or 128K, not {} bytes", len))),
+        };
+        for (bank, data) in snapshot.banks.iter_mut().zip(memory.chunks(MEM_16K)) {
+            bank.copy_from_slice(data);
+        }
+        snapshot.set_state(cpu)?;
+        Ok(snapshot)
+    }
+
+    /// set_state replaces the registers of the snapshot with those of a CPU, keeping the
+    /// border. The PC of a 48K snapshot is pushed onto the stack, overwriting the two
+    /// bytes below SP as an interrupt would. Nothing is changed if it cannot be pushed
+    /// into RAM, which returns SnaError::RomWrite or SnaError::AddressOverflow.
+    pub fn set_state<C: CpuState>(&mut self, cpu: &C) -> Result<(), SnaError> {
+        let previous = self.header;
+        self.header = SnapshotHeader { border_color: previous.border_color, ..cpu.header() };
+        self.push_pc(cpu.pc()).inspect_err(|_| self.header = previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FIXTURE_PC, FIXTURE_SP};
+    use crate::SnapshotType;
+
+    // an emulator's CPU with just enough registers to tell them apart
+    #[derive(Default)]
+    struct Cpu {
+        hl: u16,
+        sp: u16,
+        pc: u16,
+        iff2: bool,
+    }
+
+    impl CpuState for Cpu {
+        fn header(&self) -> SnapshotHeader {
+            SnapshotHeader { hl: self.hl, sp: self.sp, interrupt: if self.iff2 { 0x04 } else { 0x00 }, ..SnapshotHeader::default() }
+        }
+
+        fn pc(&self) -> u16 {
+            self.pc
+        }
+
+        fn set_header(&mut self, header: &SnapshotHeader) {
+            (self.hl, self.sp, self.iff2) = (header.hl, header.sp, header.interrupt & 0x04 != 0);
+        }
+
+        fn set_pc(&mut self, pc: u16) {
+            self.pc = pc;
+        }
+    }
+
+    #[test]
+    fn test_cpu_state() {
+        let mut cpu = Cpu::default();
+        cpu.load_state(&fixtures::generate(SnapshotType::Snapshot48, Default::default())).unwrap();
+        assert_eq!((cpu.pc, cpu.sp), (FIXTURE_PC, FIXTURE_SP + 2), "The PC is popped off the stack");
+
+        cpu.hl = 0x1234;
+        cpu.iff2 = true;
+        let snapshot = Snapshot::from_state(&cpu, &[0x55; MEM_48K]).unwrap();
+        assert_eq!((snapshot.pc(), { snapshot.header.sp }, { snapshot.header.hl }), (Some(FIXTURE_PC), FIXTURE_SP, 0x1234));
+        assert_eq!(snapshot.header.interrupt, 0x04);
+        assert_eq!(snapshot.peek(0x4000), 0x55);
+        let mut restored = Cpu::default();
+        restored.load_state(&snapshot).unwrap();
+        assert_eq!((restored.pc, restored.sp, restored.hl), (cpu.pc, cpu.sp, cpu.hl));
+
+        let snapshot = Snapshot::from_state(&cpu, &[0xAA; MEM_16K * 8]).unwrap();
+        assert_eq!((snapshot.pc(), { snapshot.header.sp }), (Some(FIXTURE_PC), FIXTURE_SP + 2), "A 128K keeps the PC aside");
+        assert_eq!(snapshot.bank_peek(7, 0).unwrap(), 0xAA);
+        assert!(matches!(Snapshot::from_state(&cpu, &[0; MEM_16K]), Err(SnaError::InvalidFormat(_))));
+
+        let mut snapshot = Snapshot::new_48k();
+        cpu.sp = 0x4001;
+        assert!(snapshot.set_state(&cpu).is_err(), "The PC cannot be pushed into ROM");
+        assert_eq!({ snapshot.header.hl }, 0, "Nothing changes on failure");
+    }
+}
@@ -0,0 +1,101 @@
+// Digests of banks, for checking snapshots in large archives against known-good
+// copies. CRC-32 is always available; SHA-1 and SHA-256 need the digests feature.
+
+#[cfg(feature = "digests")]
+use sha2::Digest;
+
+use crate::{SnaError, Snapshot};
+
+// the reflected IEEE polynomial used by zip, PNG and TZX tools
+const CRC32_POLYNOMIAL: u32 = 0xEDB88320;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32_POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+impl Snapshot {
+    /// bank_crc32 returns the CRC-32 of a bank, as zip and most archive tools compute
+    /// it. Returns SnaError::BankOutOfRange if the snapshot has no such bank.
+    pub fn bank_crc32(&self, bank: usize) -> Result<u32, SnaError> {
+        self.check_bank(bank)?;
+        Ok(crc32(&self.banks[bank]))
+    }
+
+    /// bank_sha1 returns the SHA-1 digest of a bank. Returns SnaError::BankOutOfRange if
+    /// the snapshot has no such bank.
+    #[cfg(feature = "digests")]
+    pub fn bank_sha1(&self, bank: usize) -> Result<[u8; 20], SnaError> {
+        self.check_bank(bank)?;
+        Ok(sha1::Sha1::digest(&self.banks[bank][..]).into())
+    }
+
+    /// bank_sha256 returns the SHA-256 digest of a bank. Returns SnaError::BankOutOfRange
+    /// if the snapshot has no such bank.
+    #[cfg(feature = "digests")]
+    pub fn bank_sha256(&self, bank: usize) -> Result<[u8; 32], SnaError> {
+        self.check_bank(bank)?;
+        Ok(sha2::Sha256::digest(&self.banks[bank][..]).into())
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| (crc >> 8) ^ CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "digests")]
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+
+        let mut snapshot = Snapshot::new_48k();
+        assert_eq!(snapshot.bank_crc32(1).unwrap(), 0xAB54D286);
+        snapshot.poke(0x8000, 0x55).unwrap();
+        assert_eq!(snapshot.bank_crc32(1).unwrap(), 0x2447224B);
+        assert!(matches!(snapshot.bank_crc32(3), Err(SnaError::BankOutOfRange { bank: 3, banks: 3 })));
+        assert!(matches!(snapshot.checksum(3), Err(SnaError::BankOutOfRange { bank: 3, banks: 3 })));
+    }
+
+    #[test]
+    fn test_memory_checksum() {
+        let snapshot = Snapshot::try_from(std::fs::read("48k.sna").expect("Failed to read snapshot file")).expect("Failed to parse snapshot");
+        assert_eq!(snapshot.memory_checksum(), [59066u16, 0, 11458].iter().fold(0u16, |sum, &checksum| sum.wrapping_add(checksum)));
+
+        let mut snapshot = Snapshot::new_128k();
+        assert_eq!(snapshot.memory_checksum(), 0);
+        snapshot.bank_poke(1, 0, 0xFF).unwrap();
+        snapshot.bank_poke(7, 0, 0x02).unwrap();
+        assert_eq!(snapshot.memory_checksum(), 0x101, "Banks that are not paged in are summed too");
+    }
+
+    #[cfg(feature = "digests")]
+    #[test]
+    fn test_sha() {
+        let snapshot = Snapshot::new_48k();
+        assert_eq!(hex(&snapshot.bank_sha1(0).unwrap()), "897256b6709e1a4da9daba92b6bde39ccfccd8c1");
+        assert_eq!(hex(&snapshot.bank_sha256(0).unwrap()), "4fe7b59af6de3b665b67788cc2f99892ab827efae3a467342b3bb4e3bc8e5bfe");
+        assert!(snapshot.bank_sha256(3).is_err());
+    }
+}
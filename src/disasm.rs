@@ -0,0 +1,596 @@
+//! A Z80 disassembler over mapped memory.
+//!
+//! Every documented instruction is decoded, including the CB, ED, DD and FD prefixed
+//! sets, along with the common undocumented ones: the IXH/IXL/IYH/IYL halves, SLL and
+//! the DDCB/FDCB forms that also copy their result into a register. Opcodes that do
+//! nothing are shown as `DB` with their bytes. Numbers are written in hex with a `$`
+//! prefix, and relative jumps are shown with their absolute target.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::trainer::ParseError;
+use crate::{SnaError, Snapshot};
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const ALU: [(&str, &str); 8] = [("ADD", "A,"), ("ADC", "A,"), ("SUB", ""), ("SBC", "A,"), ("AND", ""), ("XOR", ""), ("OR", ""), ("CP", "")];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SLL", "SRL"];
+const IM: [&str; 8] = ["0", "0", "1", "2", "0", "0", "1", "2"];
+const BLOCK: [[&str; 4]; 4] = [
+    ["LDI", "CPI", "INI", "OUTI"],
+    ["LDD", "CPD", "IND", "OUTD"],
+    ["LDIR", "CPIR", "INIR", "OTIR"],
+    ["LDDR", "CPDR", "INDR", "OTDR"],
+];
+const UNPREFIXED_X0_Z7: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+/// A decoded instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,             // the instruction's encoding, prefixes included
+    pub mnemonic: &'static str,     // e.g. "LD"
+    pub operands: String,           // e.g. "A,(IX+$05)", or empty
+    pub target: Option<u16>,        // where a jump, call or RST goes
+}
+
+impl Instruction {
+    /// next_addr returns the address of the instruction that follows in memory.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl Instruction {
+    /// with_symbols formats the instruction with every address that has a symbol
+    /// replaced by its name, such as `CALL print_string` rather than `CALL $9000`.
+    pub fn with_symbols(&self, symbols: &Symbols) -> String {
+        let mut text = self.to_string();
+        // operands hold at most one 16-bit number, written as $ and four hex digits
+        if let Some(start) = text.find('$').filter(|&start| text.len() >= start + 5) {
+            let digits = &text[start + 1..start + 5];
+            let whole = !text[start + 5..].starts_with(|c: char| c.is_ascii_hexdigit());
+            if let (Ok(address), true) = (u16::from_str_radix(digits, 16), whole) {
+                if let Some(name) = symbols.get(address) {
+                    text.replace_range(start..start + 5, name);
+                }
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+fn hex8(value: u8) -> String {
+    format!("${:02X}", value)
+}
+
+fn hex16(value: u16) -> String {
+    format!("${:04X}", value)
+}
+
+struct Decoder<F: Fn(u16) -> u8> {
+    fetch: F,
+    pc: u16,
+    bytes: Vec<u8>,
+    index: Option<&'static str>,    // "IX" or "IY" after a DD or FD prefix
+    displacement: Option<i8>,
+    target: Option<u16>,
+}
+
+impl<F: Fn(u16) -> u8> Decoder<F> {
+    fn next(&mut self) -> u8 {
+        let byte = (self.fetch)(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        self.bytes.push(byte);
+        byte
+    }
+
+    fn n(&mut self) -> String {
+        let n = self.next();
+        hex8(n)
+    }
+
+    fn word(&mut self) -> u16 {
+        let low = self.next();
+        let high = self.next();
+        u16::from_le_bytes([low, high])
+    }
+
+    fn nn(&mut self) -> String {
+        let word = self.word();
+        hex16(word)
+    }
+
+    fn relative(&mut self) -> String {
+        let offset = self.next() as i8;
+        let target = self.pc.wrapping_add(offset as u16);
+        self.target = Some(target);
+        hex16(target)
+    }
+
+    // the address of a JP or CALL
+    fn absolute(&mut self) -> String {
+        let target = self.word();
+        self.target = Some(target);
+        hex16(target)
+    }
+
+    // HL, or IX/IY after a prefix
+    fn hl(&self) -> &'static str {
+        self.index.unwrap_or("HL")
+    }
+
+    // (HL), or (IX+d)/(IY+d) after a prefix, fetching the displacement on first use
+    fn indirect(&mut self) -> String {
+        match self.index {
+            None => "(HL)".to_string(),
+            Some(index) => {
+                let displacement = match self.displacement {
+                    Some(displacement) => displacement,
+                    None => {
+                        let displacement = self.next() as i8;
+                        self.displacement = Some(displacement);
+                        displacement
+                    }
+                };
+                if displacement < 0 {
+                    format!("({}-{})", index, hex8(displacement.unsigned_abs()))
+                } else {
+                    format!("({}+{})", index, hex8(displacement as u8))
+                }
+            }
+        }
+    }
+
+    // an 8-bit register operand, with H and L replaced by index halves after a prefix
+    fn r(&mut self, i: u8) -> String {
+        match (i, self.index) {
+            (6, _) => self.indirect(),
+            (4, Some(index)) => format!("{}H", index),
+            (5, Some(index)) => format!("{}L", index),
+            _ => R[i as usize].to_string(),
+        }
+    }
+
+    fn rp(&self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP[p as usize] }
+    }
+
+    fn rp2(&self, p: u8) -> &'static str {
+        if p == 2 { self.hl() } else { RP2[p as usize] }
+    }
+
+    fn decode(mut self, addr: u16) -> Instruction {
+        let (mnemonic, operands) = self.decode_operation();
+        Instruction { addr, bytes: self.bytes, mnemonic, operands, target: self.target }
+    }
+
+    fn decode_operation(&mut self) -> (&'static str, String) {
+        let mut opcode = self.next();
+        while opcode == 0xDD || opcode == 0xFD {
+            let index = if opcode == 0xDD { "IX" } else { "IY" };
+            let following = (self.fetch)(self.pc);
+            if matches!(following, 0xDD | 0xED | 0xFD) {
+                // a prefix followed by another prefix does nothing
+                return ("DB", hex8(opcode));
+            }
+            self.index = Some(index);
+            opcode = self.next();
+        }
+        match opcode {
+            0xCB => self.decode_cb(),
+            0xED => self.decode_ed(),
+            _ => self.decode_unprefixed(opcode),
+        }
+    }
+
+    fn decode_unprefixed(&mut self, opcode: u8) -> (&'static str, String) {
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (0, 0) => match y {
+                0 => ("NOP", String::new()),
+                1 => ("EX", "AF,AF'".to_string()),
+                2 => ("DJNZ", self.relative()),
+                3 => ("JR", self.relative()),
+                _ => ("JR", format!("{},{}", CC[y as usize - 4], self.relative())),
+            },
+            (0, 1) if q == 0 => ("LD", format!("{},{}", self.rp(p), self.nn())),
+            (0, 1) => ("ADD", format!("{},{}", self.hl(), self.rp(p))),
+            (0, 2) => match (q, p) {
+                (0, 0) => ("LD", "(BC),A".to_string()),
+                (0, 1) => ("LD", "(DE),A".to_string()),
+                (0, 2) => ("LD", format!("({}),{}", self.nn(), self.hl())),
+                (0, _) => ("LD", format!("({}),A", self.nn())),
+                (_, 0) => ("LD", "A,(BC)".to_string()),
+                (_, 1) => ("LD", "A,(DE)".to_string()),
+                (_, 2) => ("LD", format!("{},({})", self.hl(), self.nn())),
+                (_, _) => ("LD", format!("A,({})", self.nn())),
+            },
+            (0, 3) => (if q == 0 { "INC" } else { "DEC" }, self.rp(p).to_string()),
+            (0, 4) => ("INC", self.r(y)),
+            (0, 5) => ("DEC", self.r(y)),
+            (0, 6) => {
+                let target = self.r(y);
+                ("LD", format!("{},{}", target, self.n()))
+            }
+            (0, _) => (UNPREFIXED_X0_Z7[y as usize], String::new()),
+            (1, 6) if y == 6 => ("HALT", String::new()),
+            (1, _) => {
+                // with (IX+d) on one side, H and L on the other are not replaced
+                if y == 6 || z == 6 {
+                    let (target, source) = if y == 6 {
+                        (self.indirect(), R[z as usize].to_string())
+                    } else {
+                        (R[y as usize].to_string(), self.indirect())
+                    };
+                    ("LD", format!("{},{}", target, source))
+                } else {
+                    ("LD", format!("{},{}", self.r(y), self.r(z)))
+                }
+            }
+            (2, _) => {
+                let (mnemonic, prefix) = ALU[y as usize];
+                (mnemonic, format!("{}{}", prefix, self.r(z)))
+            }
+            (_, 0) => ("RET", CC[y as usize].to_string()),
+            (_, 1) => match (q, p) {
+                (0, _) => ("POP", self.rp2(p).to_string()),
+                (_, 0) => ("RET", String::new()),
+                (_, 1) => ("EXX", String::new()),
+                (_, 2) => ("JP", format!("({})", self.hl())),
+                (_, _) => ("LD", format!("SP,{}", self.hl())),
+            },
+            (_, 2) => ("JP", format!("{},{}", CC[y as usize], self.absolute())),
+            (_, 3) => match y {
+                0 => ("JP", self.absolute()),
+                2 => ("OUT", format!("({}),A", self.n())),
+                3 => ("IN", format!("A,({})", self.n())),
+                4 => ("EX", format!("(SP),{}", self.hl())),
+                5 => ("EX", "DE,HL".to_string()),
+                6 => ("DI", String::new()),
+                _ => ("EI", String::new()),
+            },
+            (_, 4) => ("CALL", format!("{},{}", CC[y as usize], self.absolute())),
+            (_, 5) if q == 0 => ("PUSH", self.rp2(p).to_string()),
+            (_, 5) => ("CALL", self.absolute()),
+            (_, 6) => {
+                let (mnemonic, prefix) = ALU[y as usize];
+                (mnemonic, format!("{}{}", prefix, self.n()))
+            }
+            (_, _) => {
+                self.target = Some(y as u16 * 8);
+                ("RST", hex8(y * 8))
+            }
+        }
+    }
+
+    fn decode_cb(&mut self) -> (&'static str, String) {
+        if self.index.is_some() {
+            // DDCB d op and FDCB d op: the displacement comes before the opcode
+            self.displacement = Some(self.next() as i8);
+        }
+        let opcode = self.next();
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let operand = if self.index.is_some() { self.indirect() } else { self.r(z) };
+        // the undocumented indexed forms that also copy the result into a register
+        let copy = if self.index.is_some() && z != 6 && x != 1 { format!(",{}", R[z as usize]) } else { String::new() };
+        match x {
+            0 => (ROT[y as usize], format!("{}{}", operand, copy)),
+            1 => ("BIT", format!("{},{}", y, operand)),
+            2 => ("RES", format!("{},{}{}", y, operand, copy)),
+            _ => ("SET", format!("{},{}{}", y, operand, copy)),
+        }
+    }
+
+    fn decode_ed(&mut self) -> (&'static str, String) {
+        // prefixes do not apply to ED instructions
+        self.index = None;
+        let opcode = self.next();
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (1, 0) if y == 6 => ("IN", "(C)".to_string()),
+            (1, 0) => ("IN", format!("{},(C)", R[y as usize])),
+            (1, 1) if y == 6 => ("OUT", "(C),0".to_string()),
+            (1, 1) => ("OUT", format!("(C),{}", R[y as usize])),
+            (1, 2) => (if q == 0 { "SBC" } else { "ADC" }, format!("HL,{}", RP[p as usize])),
+            (1, 3) if q == 0 => ("LD", format!("({}),{}", self.nn(), RP[p as usize])),
+            (1, 3) => ("LD", format!("{},({})", RP[p as usize], self.nn())),
+            (1, 4) => ("NEG", String::new()),
+            (1, 5) => (if y == 1 { "RETI" } else { "RETN" }, String::new()),
+            (1, 6) => ("IM", IM[y as usize].to_string()),
+            (1, 7) => match y {
+                0 => ("LD", "I,A".to_string()),
+                1 => ("LD", "R,A".to_string()),
+                2 => ("LD", "A,I".to_string()),
+                3 => ("LD", "A,R".to_string()),
+                4 => ("RRD", String::new()),
+                5 => ("RLD", String::new()),
+                _ => ("DB", format!("$ED,{}", hex8(opcode))),
+            },
+            (2, 0..=3) if y >= 4 => (BLOCK[y as usize - 4][z as usize], String::new()),
+            _ => ("DB", format!("$ED,{}", hex8(opcode))),
+        }
+    }
+}
+
+/// decode decodes the instruction at an address, reading its bytes through a fetch
+/// function so any memory can be disassembled.
+pub fn decode<F: Fn(u16) -> u8>(addr: u16, fetch: F) -> Instruction {
+    Decoder { fetch, pc: addr, bytes: Vec::new(), index: None, displacement: None, target: None }.decode(addr)
+}
+
+/// A table of names for addresses, used to label disassembly.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+}
+
+// a symbol file address: decimal, hex with a 0x, $, & or # prefix or an H suffix, or bare
+// hex when the file lists addresses without marking them
+fn parse_symbol_address(text: &str, bare_hex: bool) -> Option<u16> {
+    let text = text.trim();
+    let value = if let Some(hex) = text.strip_prefix('#') {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = text.strip_suffix(['h', 'H']) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if bare_hex && text.chars().all(|c| c.is_ascii_hexdigit()) {
+        u32::from_str_radix(text, 16).ok()
+    } else {
+        crate::trainer::parse_number(text)
+    };
+    value.and_then(|value| u16::try_from(value).ok())
+}
+
+fn is_symbol_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || "_.?@".contains(c))
+}
+
+impl Symbols {
+    /// new creates an empty symbol table.
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// insert names an address, replacing any name it already had.
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names.insert(address, name.to_string());
+    }
+
+    /// get returns the name of an address.
+    pub fn get(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// address returns the address with a name.
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.names.iter().find(|(_, symbol)| *symbol == name).map(|(&address, _)| address)
+    }
+
+    /// len returns the number of symbols.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// is_empty returns true if there are no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// iter returns the symbols in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(&address, name)| (address, name.as_str()))
+    }
+
+    /// parse reads the symbols in a .sym or .map file as written by assemblers and
+    /// emulators. Each line is one of `name: EQU address`, `name = address`,
+    /// `address name` or `name address`. In the two column forms an address without a
+    /// prefix or suffix is hex, as map files write them. Anything after a `;` is a
+    /// comment, and blank lines are ignored.
+    pub fn parse(text: &str) -> Result<Symbols, ParseError> {
+        let mut symbols = Symbols::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ParseError { line: index + 1, message };
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let tokens: Vec<&str> = line.split(|c: char| c.is_whitespace() || c == '=').filter(|token| !token.is_empty()).collect();
+            let (name, address) = match tokens.as_slice() {
+                [name, equ, address] if equ.eq_ignore_ascii_case("EQU") => (name.trim_end_matches(':'), parse_symbol_address(address, false)),
+                [name, address] if line.contains('=') => (*name, parse_symbol_address(address, false)),
+                [first, second] => match parse_symbol_address(first, true) {
+                    Some(address) => (*second, Some(address)),
+                    None => (first.trim_end_matches(':'), parse_symbol_address(second, true)),
+                },
+                _ => return Err(error(format!("expected a name and an address in '{}'", line))),
+            };
+            if !is_symbol_name(name) {
+                return Err(error(format!("'{}' is not a symbol name", name)));
+            }
+            let address = address.ok_or_else(|| error(format!("no valid address in '{}'", line)))?;
+            symbols.insert(address, name);
+        }
+        Ok(symbols)
+    }
+
+    /// load reads a .sym or .map file. Parse errors are returned as SnaError::InvalidFormat.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Symbols, SnaError> {
+        let text = std::fs::read_to_string(path)?;
+        Symbols::parse(&text).map_err(|e| SnaError::InvalidFormat(e.to_string()))
+    }
+}
+
+/// An iterator over the instructions in mapped memory, from a start address to the
+/// end of memory. Created by [`Snapshot::disassemble`].
+pub struct Disassembler<'a> {
+    snapshot: &'a Snapshot,
+    addr: Option<u16>,
+}
+
+impl Iterator for Disassembler<'_> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Instruction> {
+        let addr = self.addr?;
+        let instruction = decode(addr, |address| self.snapshot.peek(address));
+        self.addr = addr.checked_add(instruction.bytes.len() as u16);
+        Some(instruction)
+    }
+}
+
+impl Snapshot {
+    /// disassemble returns an iterator over the instructions in mapped memory from an
+    /// address, such as the PC, through the current paging. Memory below 0x4000 reads
+    /// as 0xFF unless a ROM image is attached, as the snapshot holds no ROM.
+    pub fn disassemble(&self, addr: u16) -> Disassembler<'_> {
+        Disassembler { snapshot: self, addr: Some(addr) }
+    }
+
+    /// disassemble_one decodes the single instruction at an address in mapped memory.
+    pub fn disassemble_one(&self, addr: u16) -> Instruction {
+        decode(addr, |address| self.peek(address))
+    }
+
+    // the instructions that start within a range
+    fn disassemble_range(&self, range: Range<u16>) -> impl Iterator<Item = Instruction> + '_ {
+        let end = range.end;
+        self.disassemble(range.start).take_while(move |instruction| instruction.addr < end)
+    }
+
+    /// auto_labels returns the symbols with a label added for every jump, call and RST
+    /// target in a range that has no name yet. Labels are `L` followed by the address
+    /// in hex, such as `L8004`.
+    pub fn auto_labels(&self, range: Range<u16>, symbols: &Symbols) -> Symbols {
+        let mut labelled = symbols.clone();
+        let targets: BTreeSet<u16> = self.disassemble_range(range.clone()).filter_map(|instruction| instruction.target).collect();
+        for target in targets.into_iter().filter(|target| range.contains(target)) {
+            if labelled.get(target).is_none() {
+                labelled.insert(target, &format!("L{:04X}", target));
+            }
+        }
+        labelled
+    }
+
+    /// listing disassembles a range of mapped memory into text, one instruction per
+    /// line after its address. Addresses are replaced by their symbols, branch targets
+    /// without one get an automatic label, and each label gets a line of its own before
+    /// the instruction it names.
+    pub fn listing(&self, range: Range<u16>, symbols: &Symbols) -> String {
+        let symbols = self.auto_labels(range.clone(), symbols);
+        let mut listing = String::new();
+        for instruction in self.disassemble_range(range) {
+            if let Some(name) = symbols.get(instruction.addr) {
+                listing.push_str(&format!("{}:\n", name));
+            }
+            listing.push_str(&format!("{:04X}  {}\n", instruction.addr, instruction.with_symbols(&symbols)));
+        }
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8]) -> String {
+        let instruction = decode(0x8000, |address| bytes.get((address - 0x8000) as usize).copied().unwrap_or(0));
+        assert_eq!(instruction.bytes.len(), bytes.len(), "Wrong length for {}", instruction);
+        instruction.to_string()
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(text(&[0x00]), "NOP");
+        assert_eq!(text(&[0x3E, 0x05]), "LD A,$05");
+        assert_eq!(text(&[0x21, 0x34, 0x12]), "LD HL,$1234");
+        assert_eq!(text(&[0x18, 0xFE]), "JR $8000");
+        assert_eq!(text(&[0x20, 0x03]), "JR NZ,$8005");
+        assert_eq!(text(&[0xCD, 0x00, 0x90]), "CALL $9000");
+        assert_eq!(text(&[0x32, 0x00, 0x40]), "LD ($4000),A");
+        assert_eq!(text(&[0x76]), "HALT");
+        assert_eq!(text(&[0x96]), "SUB (HL)");
+        assert_eq!(text(&[0x8F]), "ADC A,A");
+        assert_eq!(text(&[0xFF]), "RST $38");
+        assert_eq!(text(&[0x08]), "EX AF,AF'");
+        assert_eq!(text(&[0xCB, 0x7E]), "BIT 7,(HL)");
+        assert_eq!(text(&[0xCB, 0x31]), "SLL C");
+        assert_eq!(text(&[0xED, 0xB0]), "LDIR");
+        assert_eq!(text(&[0xED, 0x56]), "IM 1");
+        assert_eq!(text(&[0xED, 0x43, 0x00, 0x5C]), "LD ($5C00),BC");
+        assert_eq!(text(&[0xED, 0x70]), "IN (C)");
+        assert_eq!(text(&[0xED, 0x00]), "DB $ED,$00");
+    }
+
+    #[test]
+    fn test_decode_indexed() {
+        assert_eq!(text(&[0xDD, 0x7E, 0x05]), "LD A,(IX+$05)");
+        assert_eq!(text(&[0xFD, 0x75, 0xFE]), "LD (IY-$02),L");
+        assert_eq!(text(&[0xDD, 0x36, 0x01, 0x42]), "LD (IX+$01),$42");
+        assert_eq!(text(&[0xDD, 0x65]), "LD IXH,IXL");
+        assert_eq!(text(&[0xFD, 0x21, 0x3A, 0x5C]), "LD IY,$5C3A");
+        assert_eq!(text(&[0xDD, 0xE9]), "JP (IX)");
+        assert_eq!(text(&[0xDD, 0xEB]), "EX DE,HL");
+        assert_eq!(text(&[0xDD, 0xCB, 0x03, 0x46]), "BIT 0,(IX+$03)");
+        assert_eq!(text(&[0xFD, 0xCB, 0xFF, 0xC6]), "SET 0,(IY-$01)");
+        assert_eq!(text(&[0xDD, 0xCB, 0x00, 0x00]), "RLC (IX+$00),B");
+        assert_eq!(decode(0, |address| [0xDD, 0xFD][address as usize & 1]).to_string(), "DB $DD");
+
+        let bytes = [0xDD, 0xED, 0x46];
+        let instruction = decode(0, |address| bytes[address as usize]);
+        assert_eq!((instruction.to_string(), instruction.next_addr()), ("DB $DD".to_string(), 1), "A prefix before ED should be skipped");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let mut snapshot = Snapshot::new_48k();
+        // LD B,10 : loop: DJNZ loop : RET
+        for (i, &byte) in [0x06, 0x0A, 0x10, 0xFE, 0xC9].iter().enumerate() {
+            snapshot.poke(0x8000 + i as u16, byte).unwrap();
+        }
+        let listing: Vec<String> = snapshot.disassemble(0x8000).take(3).map(|instruction| format!("{:04X} {}", instruction.addr, instruction)).collect();
+        assert_eq!(listing, ["8000 LD B,$0A", "8002 DJNZ $8002", "8004 RET"]);
+        assert_eq!(snapshot.disassemble(0xFFFE).count(), 2, "Disassembly should stop at the end of memory");
+        assert_eq!(snapshot.disassemble_one(0x8002).bytes, [0x10, 0xFE]);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let text = "; symbols\nprint_string: EQU 0x9000\nSCREEN = $4000\n8004 loop\nfinish 8010H\n\n";
+        let symbols = Symbols::parse(text).expect("Failed to parse symbols");
+        assert_eq!(symbols.len(), 4);
+        assert_eq!((symbols.get(0x9000), symbols.get(0x8004), symbols.address("finish")), (Some("print_string"), Some("loop"), Some(0x8010)));
+        assert_eq!(Symbols::parse("x EQU").map_err(|e| e.line), Err(1));
+        assert!(Symbols::parse("1abc 8000").is_err(), "Names cannot start with a digit");
+
+        let mut snapshot = Snapshot::new_48k();
+        // CALL print_string : LD ($4000),A : JR -2 : RET
+        for (i, &byte) in [0xCD, 0x00, 0x90, 0x32, 0x00, 0x40, 0x18, 0xFE, 0xC9].iter().enumerate() {
+            snapshot.poke(0x8000 + i as u16, byte).unwrap();
+        }
+        let mut symbols = Symbols::new();
+        symbols.insert(0x9000, "print_string");
+        symbols.insert(0x4000, "SCREEN");
+        assert_eq!(snapshot.listing(0x8000..0x8009, &symbols), "8000  CALL print_string\n8003  LD (SCREEN),A\nL8006:\n8006  JR L8006\n8008  RET\n");
+        assert_eq!(snapshot.disassemble_one(0x8000).target, Some(0x9000));
+        assert_eq!(snapshot.disassemble_one(0x8003).target, None);
+    }
+}
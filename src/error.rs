@@ -0,0 +1,96 @@
+use std::fmt;
+use std::io;
+
+use crate::SnapshotType;
+
+/// An error from loading or accessing a snapshot.
+#[derive(Debug)]
+pub enum SnaError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// The data is not the size of any .sna variant.
+    InvalidSize(usize),
+    /// A write to an address in ROM, below 0x4000.
+    RomWrite(u16),
+    /// A multi-byte access starting at the address runs past 0xFFFF.
+    AddressOverflow { address: u16, len: usize },
+    /// A bank index beyond the banks the snapshot has.
+    BankOutOfRange { bank: usize, banks: usize },
+    /// An operation that needs a 128K snapshot was used on a 48K one.
+    Not128K,
+    /// An operation that needs port 0x1FFD was used on a model without it, which only
+    /// the +2A/+3 and Scorpion 256 have.
+    NotPlus3,
+    /// An operation between snapshots of different types.
+    TypeMismatch { expected: SnapshotType, found: SnapshotType },
+    /// An operation between snapshots with different numbers of banks, such as a
+    /// Scorpion 256 and a 128K.
+    BankCountMismatch { expected: usize, found: usize },
+    /// A value length outside what the operation supports.
+    InvalidLength { len: usize, max: usize },
+    /// The data is not a valid snapshot in the format being read.
+    InvalidFormat(String),
+    /// Assembler text that is not a Z80 instruction or has an operand out of range.
+    InvalidInstruction(String),
+    /// Screen coordinates outside the 256x192 pixels or 32x24 attribute cells.
+    OffScreen { x: usize, y: usize },
+    /// Execution did not reach the address within the number of instructions allowed.
+    NotReached { pc: u16, steps: usize },
+    /// A write into a range of memory blocked by the named guard.
+    Guarded { address: u16, guard: String },
+    /// No free memory of at least the given number of bytes was found.
+    NoFreeMemory { len: usize },
+    /// Fewer bytes displaced by a hook than the JP written over them takes.
+    DisplacedTooShort { len: usize, min: usize },
+    /// A stack pointer that would crash the machine as soon as the snapshot is loaded.
+    BadStack { sp: u16, reason: String },
+}
+
+impl fmt::Display for SnaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnaError::Io(e) => write!(f, "I/O error: {}", e),
+            SnaError::InvalidSize(len) => write!(f, "{} bytes is not the size of a .sna file", len),
+            SnaError::RomWrite(address) => write!(f, "Cannot write to ROM at 0x{:04X}", address),
+            SnaError::AddressOverflow { address, len } => write!(f, "{} byte access at 0x{:04X} runs past 0xFFFF", len, address),
+            SnaError::BankOutOfRange { bank, banks } => write!(f, "Bank {} is out of range for a snapshot with {} banks", bank, banks),
+            SnaError::Not128K => write!(f, "Operation requires a 128K snapshot"),
+            SnaError::NotPlus3 => write!(f, "Operation requires a +2A/+3 or Scorpion snapshot"),
+            SnaError::TypeMismatch { expected, found } => write!(f, "Expected a {} snapshot but found a {} one", expected, found),
+            SnaError::BankCountMismatch { expected, found } => write!(f, "Expected a snapshot with {} banks but found one with {}", expected, found),
+            SnaError::InvalidLength { len, max } => write!(f, "Length {} is not supported, the maximum is {}", len, max),
+            SnaError::InvalidFormat(message) => write!(f, "{}", message),
+            SnaError::InvalidInstruction(message) => write!(f, "{}", message),
+            SnaError::OffScreen { x, y } => write!(f, "({}, {}) is off the screen", x, y),
+            SnaError::Guarded { address, guard } => write!(f, "Write to 0x{:04X} is blocked by guard '{}'", address, guard),
+            SnaError::NoFreeMemory { len } => write!(f, "No free memory for {} bytes", len),
+            SnaError::DisplacedTooShort { len, min } => write!(f, "At least {} bytes must be displaced, not {}", min, len),
+            SnaError::BadStack { sp, reason } => write!(f, "SP 0x{:04X} would crash on load: {}", sp, reason),
+            SnaError::NotReached { pc, steps } => write!(f, "PC 0x{:04X} was not reached in {} instructions", pc, steps),
+        }
+    }
+}
+
+impl std::error::Error for SnaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnaError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnaError {
+    fn from(e: io::Error) -> Self {
+        SnaError::Io(e)
+    }
+}
+
+impl From<SnaError> for io::Error {
+    fn from(e: SnaError) -> Self {
+        match e {
+            SnaError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,909 @@
+//! A small Z80 interpreter for running a snapshot's code, such as a decruncher or a
+//! loader stub, without leaving the crate.
+//!
+//! Every documented instruction is executed, along with the undocumented IXH/IXL and
+//! IYH/IYL forms, SLL and the register copies of the DDCB/FDCB instructions. Timing is
+//! not modelled: instructions run one at a time with no T-state counts or contention,
+//! and the only interrupt is the one a HALT with interrupts enabled waits for, which is
+//! taken at once. Memory and ports go through [`MemoryBus`], so OUTs page a 128K
+//! snapshot as they would on the machine.
+
+use crate::{CpuState, MemoryBus, SnaError, Snapshot, SnapshotHeader};
+
+const S: u8 = 0x80;     // sign
+const Z: u8 = 0x40;     // zero
+const Y: u8 = 0x20;     // undocumented, bit 5 of the result
+const H: u8 = 0x10;     // half carry
+const X: u8 = 0x08;     // undocumented, bit 3 of the result
+const PV: u8 = 0x04;    // parity or overflow
+const N: u8 = 0x02;     // subtract
+const C: u8 = 0x01;     // carry
+
+const HALT: u8 = 0x76;
+
+// the register HL stands for after a DD or FD prefix
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Index {
+    Hl,
+    Ix,
+    Iy,
+}
+
+// S, Z, Y and X for a result
+fn sz53(value: u8) -> u8 {
+    let mut flags = value & (S | Y | X);
+    if value == 0 {
+        flags |= Z;
+    }
+    flags
+}
+
+// S, Z, Y, X and even parity for a result
+fn sz53p(value: u8) -> u8 {
+    sz53(value) | if value.count_ones() & 1 == 0 { PV } else { 0 }
+}
+
+/// The registers of a Z80.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Z80 {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub af_prime: u16,
+    pub bc_prime: u16,
+    pub de_prime: u16,
+    pub hl_prime: u16,
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub i: u8,
+    pub r: u8,
+    pub iff1: bool,
+    pub iff2: bool,
+    pub im: u8,
+}
+
+impl CpuState for Z80 {
+    fn header(&self) -> SnapshotHeader {
+        SnapshotHeader {
+            i: self.i,
+            hl_prime: self.hl_prime,
+            de_prime: self.de_prime,
+            bc_prime: self.bc_prime,
+            af_prime: self.af_prime,
+            hl: self.hl(),
+            de: self.de(),
+            bc: self.bc(),
+            iy: self.iy,
+            ix: self.ix,
+            interrupt: if self.iff2 { 0x04 } else { 0x00 },
+            r: self.r,
+            af: u16::from_be_bytes([self.a, self.f]),
+            sp: self.sp,
+            int_mode: self.im,
+            border_color: 0,
+        }
+    }
+
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    fn set_header(&mut self, header: &SnapshotHeader) {
+        let header = *header;
+        [self.a, self.f] = header.af.to_be_bytes();
+        [self.b, self.c] = header.bc.to_be_bytes();
+        [self.d, self.e] = header.de.to_be_bytes();
+        [self.h, self.l] = header.hl.to_be_bytes();
+        (self.af_prime, self.bc_prime, self.de_prime, self.hl_prime) = (header.af_prime, header.bc_prime, header.de_prime, header.hl_prime);
+        (self.ix, self.iy, self.sp) = (header.ix, header.iy, header.sp);
+        (self.i, self.r, self.im) = (header.i, header.r, header.int_mode & 0x03);
+        self.iff2 = header.interrupt & 0x04 != 0;
+        self.iff1 = self.iff2;
+    }
+
+    fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+}
+
+impl Z80 {
+    /// bc returns the BC register pair.
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    /// de returns the DE register pair.
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    /// hl returns the HL register pair.
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    // HL, IX or IY
+    fn index(&self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.hl(),
+            Index::Ix => self.ix,
+            Index::Iy => self.iy,
+        }
+    }
+
+    fn set_index(&mut self, index: Index, value: u16) {
+        match index {
+            Index::Hl => self.set_hl(value),
+            Index::Ix => self.ix = value,
+            Index::Iy => self.iy = value,
+        }
+    }
+
+    // the register pair table: BC, DE, HL (or IX/IY) and SP
+    fn rp(&self, p: u8, index: Index) -> u16 {
+        match p {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.index(index),
+            _ => self.sp,
+        }
+    }
+
+    fn set_rp(&mut self, p: u8, index: Index, value: u16) {
+        match p {
+            0 => self.set_bc(value),
+            1 => self.set_de(value),
+            2 => self.set_index(index, value),
+            _ => self.sp = value,
+        }
+    }
+
+    // the register pair table for PUSH and POP, with AF in place of SP
+    fn rp2(&self, p: u8, index: Index) -> u16 {
+        match p {
+            3 => u16::from_be_bytes([self.a, self.f]),
+            _ => self.rp(p, index),
+        }
+    }
+
+    fn set_rp2(&mut self, p: u8, index: Index, value: u16) {
+        match p {
+            3 => [self.a, self.f] = value.to_be_bytes(),
+            _ => self.set_rp(p, index, value),
+        }
+    }
+
+    // an 8-bit register by its number in an opcode, B, C, D, E, H, L, (HL) and A, with
+    // H and L standing for the halves of IX or IY after a prefix; (HL) is read at address
+    fn reg<B: MemoryBus>(&self, bus: &mut B, r: u8, index: Index, address: u16) -> u8 {
+        match r {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => (self.index(index) >> 8) as u8,
+            5 => self.index(index) as u8,
+            6 => bus.read(address),
+            _ => self.a,
+        }
+    }
+
+    fn set_reg<B: MemoryBus>(&mut self, bus: &mut B, r: u8, index: Index, address: u16, value: u8) {
+        match r {
+            0 => self.b = value,
+            1 => self.c = value,
+            2 => self.d = value,
+            3 => self.e = value,
+            4 => self.set_index(index, self.index(index) & 0x00FF | (value as u16) << 8),
+            5 => self.set_index(index, self.index(index) & 0xFF00 | value as u16),
+            6 => bus.write(address, value),
+            _ => self.a = value,
+        }
+    }
+
+    fn condition(&self, cc: u8) -> bool {
+        let flag = [Z, C, PV, S][(cc >> 1) as usize];
+        (self.f & flag != 0) == (cc & 1 != 0)
+    }
+
+    fn fetch<B: MemoryBus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word<B: MemoryBus>(&mut self, bus: &mut B) -> u16 {
+        u16::from_le_bytes([self.fetch(bus), self.fetch(bus)])
+    }
+
+    // an opcode fetch, which refreshes the lower seven bits of R
+    fn fetch_opcode<B: MemoryBus>(&mut self, bus: &mut B) -> u8 {
+        self.r = self.r & 0x80 | self.r.wrapping_add(1) & 0x7F;
+        self.fetch(bus)
+    }
+
+    fn read_word<B: MemoryBus>(bus: &mut B, address: u16) -> u16 {
+        u16::from_le_bytes([bus.read(address), bus.read(address.wrapping_add(1))])
+    }
+
+    fn write_word<B: MemoryBus>(bus: &mut B, address: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        bus.write(address, low);
+        bus.write(address.wrapping_add(1), high);
+    }
+
+    fn push<B: MemoryBus>(&mut self, bus: &mut B, value: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        Z80::write_word(bus, self.sp, value);
+    }
+
+    fn pop<B: MemoryBus>(&mut self, bus: &mut B) -> u16 {
+        let value = Z80::read_word(bus, self.sp);
+        self.sp = self.sp.wrapping_add(2);
+        value
+    }
+
+    // the address of (HL), or of (IX+d) or (IY+d) after fetching the displacement
+    fn address<B: MemoryBus>(&mut self, bus: &mut B, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.hl(),
+            _ => self.index(index).wrapping_add(self.fetch(bus) as i8 as u16),
+        }
+    }
+
+    /// interrupt accepts a maskable interrupt if interrupts are enabled, as the ULA
+    /// raises one every frame: a RST 0x38 in IM 0 and 1, or a call through the vector
+    /// table at I in IM 2. Returns whether it was accepted.
+    pub fn interrupt<B: MemoryBus>(&mut self, bus: &mut B) -> bool {
+        if !self.iff1 {
+            return false;
+        }
+        self.iff1 = false;
+        self.iff2 = false;
+        self.r = self.r & 0x80 | self.r.wrapping_add(1) & 0x7F;
+        self.push(bus, self.pc);
+        self.pc = match self.im {
+            2 => Z80::read_word(bus, u16::from_be_bytes([self.i, 0xFF])),
+            _ => 0x0038,
+        };
+        true
+    }
+
+    /// step executes one instruction. A HALT with interrupts enabled goes straight on
+    /// to the interrupt it waits for; with them disabled the CPU stays on the HALT.
+    pub fn step<B: MemoryBus>(&mut self, bus: &mut B) {
+        let mut index = Index::Hl;
+        let mut opcode = self.fetch_opcode(bus);
+        while opcode == 0xDD || opcode == 0xFD {
+            index = if opcode == 0xDD { Index::Ix } else { Index::Iy };
+            opcode = self.fetch_opcode(bus);
+        }
+        match opcode {
+            0xCB if index == Index::Hl => {
+                let opcode = self.fetch_opcode(bus);
+                self.execute_cb(bus, opcode, None);
+            }
+            0xCB => {
+                let address = self.address(bus, index);
+                let opcode = self.fetch(bus);
+                self.execute_cb(bus, opcode, Some(address));
+            }
+            0xED => {
+                let opcode = self.fetch_opcode(bus);
+                self.execute_ed(bus, opcode);
+            }
+            _ => self.execute(bus, opcode, index),
+        }
+    }
+
+    fn execute<B: MemoryBus>(&mut self, bus: &mut B, opcode: u8, index: Index) {
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (0, 0) => match y {
+                0 => {}
+                1 => {
+                    let af = u16::from_be_bytes([self.a, self.f]);
+                    [self.a, self.f] = self.af_prime.to_be_bytes();
+                    self.af_prime = af;
+                }
+                2 => {
+                    let offset = self.fetch(bus) as i8;
+                    self.b = self.b.wrapping_sub(1);
+                    if self.b != 0 {
+                        self.pc = self.pc.wrapping_add(offset as u16);
+                    }
+                }
+                _ => {
+                    let offset = self.fetch(bus) as i8;
+                    if y == 3 || self.condition(y - 4) {
+                        self.pc = self.pc.wrapping_add(offset as u16);
+                    }
+                }
+            },
+            (0, 1) if q == 0 => {
+                let value = self.fetch_word(bus);
+                self.set_rp(p, index, value);
+            }
+            (0, 1) => {
+                let result = self.add16(self.index(index), self.rp(p, index));
+                self.set_index(index, result);
+            }
+            (0, 2) => match (p, q) {
+                (0, 0) => bus.write(self.bc(), self.a),
+                (1, 0) => bus.write(self.de(), self.a),
+                (2, 0) => {
+                    let address = self.fetch_word(bus);
+                    Z80::write_word(bus, address, self.index(index));
+                }
+                (3, 0) => {
+                    let address = self.fetch_word(bus);
+                    bus.write(address, self.a);
+                }
+                (0, _) => self.a = bus.read(self.bc()),
+                (1, _) => self.a = bus.read(self.de()),
+                (2, _) => {
+                    let address = self.fetch_word(bus);
+                    let value = Z80::read_word(bus, address);
+                    self.set_index(index, value);
+                }
+                _ => {
+                    let address = self.fetch_word(bus);
+                    self.a = bus.read(address);
+                }
+            },
+            (0, 3) => {
+                let value = self.rp(p, index);
+                self.set_rp(p, index, if q == 0 { value.wrapping_add(1) } else { value.wrapping_sub(1) });
+            }
+            (0, 4 | 5) => {
+                let (index, address) = if y == 6 { (Index::Hl, self.address(bus, index)) } else { (index, 0) };
+                let value = self.reg(bus, y, index, address);
+                let result = if z == 4 { self.inc8(value) } else { self.dec8(value) };
+                self.set_reg(bus, y, index, address, result);
+            }
+            (0, 6) => {
+                let (index, address) = if y == 6 { (Index::Hl, self.address(bus, index)) } else { (index, 0) };
+                let value = self.fetch(bus);
+                self.set_reg(bus, y, index, address, value);
+            }
+            (0, _) => self.execute_accumulator(y),
+            (1, 6) if y == 6 => {
+                if self.iff1 {
+                    self.interrupt(bus);
+                } else {
+                    self.pc = self.pc.wrapping_sub(1);
+                }
+            }
+            (1, _) => {
+                // with (IX+d) on one side, H and L on the other are the real H and L
+                let (index, address) = if y == 6 || z == 6 { (Index::Hl, self.address(bus, index)) } else { (index, 0) };
+                let value = self.reg(bus, z, index, address);
+                self.set_reg(bus, y, index, address, value);
+            }
+            (2, _) => {
+                let (index, address) = if z == 6 { (Index::Hl, self.address(bus, index)) } else { (index, 0) };
+                let value = self.reg(bus, z, index, address);
+                self.alu(y, value);
+            }
+            (_, 0) => {
+                if self.condition(y) {
+                    self.pc = self.pop(bus);
+                }
+            }
+            (_, 1) => match (q, p) {
+                (0, _) => {
+                    let value = self.pop(bus);
+                    self.set_rp2(p, index, value);
+                }
+                (_, 0) => self.pc = self.pop(bus),
+                (_, 1) => {
+                    let (bc, de, hl) = (self.bc(), self.de(), self.hl());
+                    self.set_bc(self.bc_prime);
+                    self.set_de(self.de_prime);
+                    self.set_hl(self.hl_prime);
+                    (self.bc_prime, self.de_prime, self.hl_prime) = (bc, de, hl);
+                }
+                (_, 2) => self.pc = self.index(index),
+                _ => self.sp = self.index(index),
+            },
+            (_, 2) => {
+                let address = self.fetch_word(bus);
+                if self.condition(y) {
+                    self.pc = address;
+                }
+            }
+            (_, 3) => match y {
+                0 => self.pc = self.fetch_word(bus),
+                2 => {
+                    let port = u16::from_be_bytes([self.a, self.fetch(bus)]);
+                    bus.write_io(port, self.a);
+                }
+                3 => {
+                    let port = u16::from_be_bytes([self.a, self.fetch(bus)]);
+                    self.a = bus.read_io(port);
+                }
+                4 => {
+                    let value = Z80::read_word(bus, self.sp);
+                    Z80::write_word(bus, self.sp, self.index(index));
+                    self.set_index(index, value);
+                }
+                5 => {
+                    let de = self.de();
+                    self.set_de(self.hl());
+                    self.set_hl(de);
+                }
+                6 => {
+                    self.iff1 = false;
+                    self.iff2 = false;
+                }
+                _ => {
+                    self.iff1 = true;
+                    self.iff2 = true;
+                }
+            },
+            (_, 4) => {
+                let address = self.fetch_word(bus);
+                if self.condition(y) {
+                    self.push(bus, self.pc);
+                    self.pc = address;
+                }
+            }
+            (_, 5) if q == 0 => self.push(bus, self.rp2(p, index)),
+            (_, 5) => {
+                let address = self.fetch_word(bus);
+                self.push(bus, self.pc);
+                self.pc = address;
+            }
+            (_, 6) => {
+                let value = self.fetch(bus);
+                self.alu(y, value);
+            }
+            _ => {
+                self.push(bus, self.pc);
+                self.pc = (y as u16) * 8;
+            }
+        }
+    }
+
+    // RLCA, RRCA, RLA, RRA, DAA, CPL, SCF and CCF
+    fn execute_accumulator(&mut self, y: u8) {
+        let a = self.a;
+        let carry = self.f & C;
+        let kept = self.f & (S | Z | PV);
+        match y {
+            0 => {
+                self.a = a.rotate_left(1);
+                self.f = kept | self.a & (Y | X | C);
+            }
+            1 => {
+                self.a = a.rotate_right(1);
+                self.f = kept | self.a & (Y | X) | a & C;
+            }
+            2 => {
+                self.a = a << 1 | carry;
+                self.f = kept | self.a & (Y | X) | a >> 7;
+            }
+            3 => {
+                self.a = a >> 1 | carry << 7;
+                self.f = kept | self.a & (Y | X) | a & C;
+            }
+            4 => self.daa(),
+            5 => {
+                self.a = !a;
+                self.f = self.f & (S | Z | PV | C) | self.a & (Y | X) | H | N;
+            }
+            6 => self.f = kept | a & (Y | X) | C,
+            _ => self.f = kept | a & (Y | X) | if carry != 0 { H } else { C },
+        }
+    }
+
+    fn daa(&mut self) {
+        let a = self.a;
+        let mut correction = 0;
+        let mut carry = self.f & C;
+        if self.f & H != 0 || a & 0x0F > 9 {
+            correction |= 0x06;
+        }
+        if carry != 0 || a > 0x99 {
+            correction |= 0x60;
+            carry = C;
+        }
+        let subtract = self.f & N != 0;
+        let half = if subtract { self.f & H != 0 && a & 0x0F < 6 } else { a & 0x0F > 9 };
+        self.a = if subtract { a.wrapping_sub(correction) } else { a.wrapping_add(correction) };
+        self.f = sz53p(self.a) | self.f & N | carry | if half { H } else { 0 };
+    }
+
+    fn alu(&mut self, operation: u8, value: u8) {
+        match operation {
+            0 => self.a = self.add8(value, 0),
+            1 => self.a = self.add8(value, self.f & C),
+            2 => self.a = self.sub8(value, 0),
+            3 => self.a = self.sub8(value, self.f & C),
+            4 => {
+                self.a &= value;
+                self.f = sz53p(self.a) | H;
+            }
+            5 => {
+                self.a ^= value;
+                self.f = sz53p(self.a);
+            }
+            6 => {
+                self.a |= value;
+                self.f = sz53p(self.a);
+            }
+            _ => {
+                self.sub8(value, 0);
+                self.f = self.f & !(Y | X) | value & (Y | X);
+            }
+        }
+    }
+
+    fn add8(&mut self, value: u8, carry: u8) -> u8 {
+        let a = self.a;
+        let sum = a as u16 + value as u16 + carry as u16;
+        let result = sum as u8;
+        let overflow = (a ^ result) & (value ^ result) & 0x80 != 0;
+        self.f = sz53(result) | (a ^ value ^ result) & H | if overflow { PV } else { 0 } | (sum >> 8) as u8;
+        result
+    }
+
+    fn sub8(&mut self, value: u8, carry: u8) -> u8 {
+        let a = self.a;
+        let difference = (a as u16).wrapping_sub(value as u16).wrapping_sub(carry as u16);
+        let result = difference as u8;
+        let overflow = (a ^ value) & (a ^ result) & 0x80 != 0;
+        self.f = sz53(result) | (a ^ value ^ result) & H | if overflow { PV } else { 0 } | N | (difference >> 8) as u8 & C;
+        result
+    }
+
+    fn inc8(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.f = self.f & C | sz53(result) | if value & 0x0F == 0x0F { H } else { 0 } | if value == 0x7F { PV } else { 0 };
+        result
+    }
+
+    fn dec8(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.f = self.f & C | sz53(result) | N | if value & 0x0F == 0 { H } else { 0 } | if value == 0x80 { PV } else { 0 };
+        result
+    }
+
+    fn add16(&mut self, a: u16, b: u16) -> u16 {
+        let sum = a as u32 + b as u32;
+        let result = sum as u16;
+        let half = ((a ^ b ^ result) >> 8) as u8 & H;
+        self.f = self.f & (S | Z | PV) | (result >> 8) as u8 & (Y | X) | half | (sum >> 16) as u8;
+        result
+    }
+
+    fn adc16(&mut self, value: u16) {
+        let hl = self.hl();
+        let sum = hl as u32 + value as u32 + (self.f & C) as u32;
+        let result = sum as u16;
+        let overflow = (hl ^ result) & (value ^ result) & 0x8000 != 0;
+        self.f = (result >> 8) as u8 & (S | Y | X) | if result == 0 { Z } else { 0 } | ((hl ^ value ^ result) >> 8) as u8 & H
+            | if overflow { PV } else { 0 } | (sum >> 16) as u8;
+        self.set_hl(result);
+    }
+
+    fn sbc16(&mut self, value: u16) {
+        let hl = self.hl();
+        let difference = (hl as u32).wrapping_sub(value as u32).wrapping_sub((self.f & C) as u32);
+        let result = difference as u16;
+        let overflow = (hl ^ value) & (hl ^ result) & 0x8000 != 0;
+        self.f = (result >> 8) as u8 & (S | Y | X) | if result == 0 { Z } else { 0 } | ((hl ^ value ^ result) >> 8) as u8 & H
+            | if overflow { PV } else { 0 } | N | (difference >> 16) as u8 & C;
+        self.set_hl(result);
+    }
+
+    // the CB instructions; address is the (IX+d) or (IY+d) of a DDCB or FDCB instruction,
+    // whose result also goes to the register named unless that is (HL)
+    fn execute_cb<B: MemoryBus>(&mut self, bus: &mut B, opcode: u8, address: Option<u16>) {
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let value = match address {
+            Some(address) => bus.read(address),
+            None => self.reg(bus, z, Index::Hl, self.hl()),
+        };
+        let result = match x {
+            0 => self.rotate(y, value),
+            1 => {
+                let bit = value & (1 << y);
+                let undocumented = match address {
+                    Some(address) => (address >> 8) as u8,
+                    None => value,
+                };
+                self.f = self.f & C | H | undocumented & (Y | X) | bit & S | if bit == 0 { Z | PV } else { 0 };
+                return;
+            }
+            2 => value & !(1 << y),
+            _ => value | 1 << y,
+        };
+        match address {
+            Some(address) => {
+                bus.write(address, result);
+                if z != 6 {
+                    self.set_reg(bus, z, Index::Hl, 0, result);
+                }
+            }
+            None => self.set_reg(bus, z, Index::Hl, self.hl(), result),
+        }
+    }
+
+    // RLC, RRC, RL, RR, SLA, SRA, SLL and SRR
+    fn rotate(&mut self, operation: u8, value: u8) -> u8 {
+        let carry = self.f & C;
+        let (result, carry_out) = match operation {
+            0 => (value.rotate_left(1), value >> 7),
+            1 => (value.rotate_right(1), value & 1),
+            2 => (value << 1 | carry, value >> 7),
+            3 => (value >> 1 | carry << 7, value & 1),
+            4 => (value << 1, value >> 7),
+            5 => (value >> 1 | value & 0x80, value & 1),
+            6 => (value << 1 | 1, value >> 7),
+            _ => (value >> 1, value & 1),
+        };
+        self.f = sz53p(result) | carry_out;
+        result
+    }
+
+    fn execute_ed<B: MemoryBus>(&mut self, bus: &mut B, opcode: u8) {
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (1, 0) => {
+                let value = bus.read_io(self.bc());
+                self.f = self.f & C | sz53p(value);
+                if y != 6 {
+                    self.set_reg(bus, y, Index::Hl, 0, value);
+                }
+            }
+            (1, 1) => {
+                let value = if y == 6 { 0 } else { self.reg(bus, y, Index::Hl, 0) };
+                bus.write_io(self.bc(), value);
+            }
+            (1, 2) if q == 0 => self.sbc16(self.rp(p, Index::Hl)),
+            (1, 2) => self.adc16(self.rp(p, Index::Hl)),
+            (1, 3) => {
+                let address = self.fetch_word(bus);
+                if q == 0 {
+                    Z80::write_word(bus, address, self.rp(p, Index::Hl));
+                } else {
+                    let value = Z80::read_word(bus, address);
+                    self.set_rp(p, Index::Hl, value);
+                }
+            }
+            (1, 4) => {
+                let a = self.a;
+                self.a = 0;
+                self.a = self.sub8(a, 0);
+            }
+            (1, 5) => {
+                self.iff1 = self.iff2;
+                self.pc = self.pop(bus);
+            }
+            (1, 6) => self.im = [0, 0, 1, 2][(y & 3) as usize],
+            (1, 7) => match y {
+                0 => self.i = self.a,
+                1 => self.r = self.a,
+                2 | 3 => {
+                    self.a = if y == 2 { self.i } else { self.r };
+                    self.f = self.f & C | sz53(self.a) | if self.iff2 { PV } else { 0 };
+                }
+                4 | 5 => {
+                    let hl = self.hl();
+                    let value = bus.read(hl);
+                    let (a, memory) = if y == 4 {
+                        (self.a & 0xF0 | value & 0x0F, value >> 4 | self.a << 4)
+                    } else {
+                        (self.a & 0xF0 | value >> 4, value << 4 | self.a & 0x0F)
+                    };
+                    self.a = a;
+                    bus.write(hl, memory);
+                    self.f = self.f & C | sz53p(a);
+                }
+                _ => {}
+            },
+            (2, 0..=3) if y >= 4 => self.execute_block(bus, y, z),
+            _ => {}
+        }
+    }
+
+    // LDI, CPI, INI and OUTI, their decrementing forms for y 5 and the repeating ones
+    // for y 6 and 7, which repeat by stepping back over themselves
+    fn execute_block<B: MemoryBus>(&mut self, bus: &mut B, y: u8, z: u8) {
+        let step = if y & 1 == 0 { 1u16 } else { 0xFFFF };
+        let repeat = y >= 6;
+        let hl = self.hl();
+        let again = match z {
+            0 => {
+                let value = bus.read(hl);
+                bus.write(self.de(), value);
+                self.set_de(self.de().wrapping_add(step));
+                self.set_bc(self.bc().wrapping_sub(1));
+                let n = value.wrapping_add(self.a);
+                self.f = self.f & (S | Z | C) | n & X | (n << 4) & Y | if self.bc() != 0 { PV } else { 0 };
+                self.bc() != 0
+            }
+            1 => {
+                let value = bus.read(hl);
+                let result = self.a.wrapping_sub(value);
+                let half = (self.a ^ value ^ result) & H;
+                self.set_bc(self.bc().wrapping_sub(1));
+                let n = result.wrapping_sub(half >> 4);
+                self.f = self.f & C | sz53(result) & (S | Z) | half | N | n & X | (n << 4) & Y
+                    | if self.bc() != 0 { PV } else { 0 };
+                self.bc() != 0 && result != 0
+            }
+            2 => {
+                let value = bus.read_io(self.bc());
+                bus.write(hl, value);
+                self.b = self.b.wrapping_sub(1);
+                self.f = self.f & C | sz53(self.b) | N;
+                self.b != 0
+            }
+            _ => {
+                let value = bus.read(hl);
+                self.b = self.b.wrapping_sub(1);
+                bus.write_io(self.bc(), value);
+                self.f = self.f & C | sz53(self.b) | N;
+                self.b != 0
+            }
+        };
+        self.set_hl(hl.wrapping_add(step));
+        if repeat && again {
+            self.pc = self.pc.wrapping_sub(2);
+        }
+    }
+}
+
+impl Snapshot {
+    /// step executes a number of instructions, as [`Z80::step`] does, against the
+    /// snapshot's memory. Returns an error from set_state if the PC of a 48K snapshot
+    /// cannot be stacked afterwards, or SnaError::InvalidFormat if it could not be
+    /// unstacked to start with.
+    pub fn step(&mut self, count: usize) -> Result<(), SnaError> {
+        let mut cpu = Z80::default();
+        cpu.load_state(self)?;
+        for _ in 0..count {
+            cpu.step(self);
+        }
+        self.set_state(&cpu)
+    }
+
+    /// run_until executes instructions until the PC reaches an address, such as the
+    /// start of a game after its loader or decruncher, and returns how many ran.
+    /// Returns SnaError::NotReached if it has not after limit instructions, leaving the
+    /// snapshot where execution stopped, or the errors step returns.
+    pub fn run_until(&mut self, pc: u16, limit: usize) -> Result<usize, SnaError> {
+        let mut cpu = Z80::default();
+        cpu.load_state(self)?;
+        let mut steps = 0;
+        while cpu.pc != pc && steps < limit {
+            cpu.step(self);
+            steps += 1;
+        }
+        self.set_state(&cpu)?;
+        if cpu.pc != pc {
+            return Err(SnaError::NotReached { pc, steps });
+        }
+        Ok(steps)
+    }
+
+    /// is_halted returns whether the instruction at the PC is a HALT, where the CPU waits
+    /// for an interrupt.
+    pub fn is_halted(&self) -> bool {
+        self.pc().is_some_and(|pc| self.peek(pc) == HALT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // assembles code at 0x8000, one instruction per line
+    fn load(snapshot: &mut Snapshot, code: &[&str]) {
+        code.iter().fold(0x8000, |address, line| snapshot.asm(address, line).expect("Failed to assemble"));
+    }
+
+    // a CPU that has run code at 0x8000 in a blank 48K snapshot
+    fn run(code: &[&str], steps: usize) -> (Z80, Snapshot) {
+        let mut snapshot = Snapshot::new_48k();
+        load(&mut snapshot, code);
+        let mut cpu = Z80 { pc: 0x8000, sp: 0xFF00, ..Z80::default() };
+        for _ in 0..steps {
+            cpu.step(&mut snapshot);
+        }
+        (cpu, snapshot)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let (cpu, _) = run(&["ld a,$7f", "add a,1"], 2);
+        assert_eq!((cpu.a, cpu.f), (0x80, S | H | PV));
+        let (cpu, _) = run(&["ld a,$10", "sub $20"], 2);
+        assert_eq!((cpu.a, cpu.f & (S | C | N)), (0xF0, S | C | N));
+        let (cpu, _) = run(&["ld a,$15", "add a,$27", "daa"], 3);
+        assert_eq!(cpu.a, 0x42, "DAA corrects BCD addition");
+        let (cpu, _) = run(&["ld hl,$ffff", "ld bc,1", "or a", "adc hl,bc"], 4);
+        assert_eq!((cpu.hl(), cpu.f & (Z | C)), (0, Z | C));
+        let (cpu, _) = run(&["ld a,5", "cp 5"], 2);
+        assert_eq!(cpu.f & (Z | N), Z | N);
+        let (cpu, _) = run(&["ld b,$81", "rlc b", "bit 0,b"], 3);
+        assert_eq!((cpu.b, cpu.f & (Z | C)), (0x03, C));
+        let (cpu, _) = run(&["ld ix,$1234", "ld a,ixh", "ld ixl,a"], 3);
+        assert_eq!(cpu.ix, 0x1212, "IXH and IXL are undocumented but used");
+    }
+
+    #[test]
+    fn test_memory_and_flow() {
+        let (cpu, snapshot) = run(&[
+            "ld hl,$9000", "ld (hl),3", "ld de,$a000", "ld bc,4", "ldir",
+            "ld ix,$9ffe", "ld a,(ix+2)", "inc (ix+3)", "call $801c", "halt", "nop",
+            "ld ($b000),a", "ret",
+        ], 17);
+        assert_eq!(snapshot.peek(0xA000), 3, "LDIR copies");
+        assert_eq!(cpu.bc(), 0);
+        assert_eq!(cpu.a, 3);
+        assert_eq!(snapshot.peek(0xA001), 1);
+        assert_eq!(snapshot.peek(0xB000), 3, "The subroutine ran");
+        assert_eq!(snapshot.peek(cpu.pc), HALT, "Halted with interrupts disabled");
+        assert_eq!(cpu.sp, 0xFF00);
+    }
+
+    #[test]
+    fn test_run_until() {
+        // a loop that fills the screen, then a jump to a "game" at 0x9000
+        let mut snapshot = Snapshot::new_48k();
+        load(&mut snapshot, &["ld hl,$4000", "ld de,$4001", "ld bc,$17ff", "ld (hl),$aa", "ldir", "jp $9000"]);
+        snapshot.set_pc(0x8000).unwrap();
+        let sp = { snapshot.header.sp };
+        let steps = snapshot.run_until(0x9000, 100_000).unwrap();
+        assert_eq!(steps, 5 + 0x17FF);
+        assert_eq!(snapshot.pc(), Some(0x9000));
+        assert_eq!({ snapshot.header.sp }, sp, "The PC is stacked where it was");
+        assert_eq!(snapshot.peek(0x57FF), 0xAA);
+
+        assert!(matches!(snapshot.run_until(0x8000, 10), Err(SnaError::NotReached { pc: 0x8000, steps: 10 })));
+        assert_eq!(snapshot.pc(), Some(0x900A), "NOPs run on");
+
+        // EI then HALT takes the interrupt at once
+        let mut snapshot = Snapshot::new_128k();
+        load(&mut snapshot, &["ei", "halt"]);
+        snapshot.set_pc(0x8000).unwrap();
+        assert!(!snapshot.is_halted());
+        snapshot.step(2).unwrap();
+        assert_eq!(snapshot.pc(), Some(0x0038));
+        assert_eq!(snapshot.peek_word(snapshot.header.sp).unwrap(), 0x8002, "HALT returns past itself");
+    }
+
+    #[test]
+    fn test_paging_out() {
+        let mut snapshot = Snapshot::new_128k();
+        load(&mut snapshot, &["ld bc,$7ffd", "ld a,3", "out (c),a", "ld a,$55", "ld ($c000),a"]);
+        snapshot.set_pc(0x8000).unwrap();
+        snapshot.step(5).unwrap();
+        assert_eq!(snapshot.mapping[2], 3);
+        assert_eq!(snapshot.bank_peek(3, 0).unwrap(), 0x55, "The write went to the paged bank");
+    }
+}
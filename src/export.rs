@@ -0,0 +1,194 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::{Addr, BankAddr, RegionLocation, SnaError, Snapshot};
+
+/// A column in a CSV or TSV export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    Address,        // hex address, or bank:offset for banked ranges
+    AddressDec,     // decimal address, or decimal offset in the bank for banked ranges
+    Value,          // hex byte
+    ValueDec,       // decimal byte
+    Char,           // the byte as a printable ASCII character, or '.'
+}
+
+impl Column {
+    /// The columns exported when none are given.
+    pub const DEFAULT: [Column; 5] = [Column::Address, Column::AddressDec, Column::Value, Column::ValueDec, Column::Char];
+
+    fn name(self) -> &'static str {
+        match self {
+            Column::Address => "address",
+            Column::AddressDec => "address_dec",
+            Column::Value => "value",
+            Column::ValueDec => "value_dec",
+            Column::Char => "char",
+        }
+    }
+}
+
+impl Snapshot {
+    /// export_csv returns one comma-separated row per byte of a mapped or banked range,
+    /// after a header row naming the columns. Fields are quoted where CSV requires it.
+    /// Returns SnaError::AddressOverflow if the range runs past the end of memory or of
+    /// the bank, and SnaError::BankOutOfRange if the snapshot has no such bank.
+    pub fn export_csv<L: Into<RegionLocation>>(&self, start: L, len: usize, columns: &[Column]) -> Result<String, SnaError> {
+        self.export_delimited(start.into(), len, columns, ',')
+    }
+
+    /// export_tsv is export_csv with tab-separated fields.
+    pub fn export_tsv<L: Into<RegionLocation>>(&self, start: L, len: usize, columns: &[Column]) -> Result<String, SnaError> {
+        self.export_delimited(start.into(), len, columns, '\t')
+    }
+
+    /// hexdump returns a classic dump of mapped memory, 16 bytes per line, each line
+    /// giving the address, the bytes in hex and the bytes as ASCII with '.' for
+    /// anything unprintable. Memory is read through the current paging.
+    pub fn hexdump(&self, range: Range<u16>) -> String {
+        let bytes: Vec<u8> = range.clone().map(|address| self.peek(address)).collect();
+        hexdump_lines(&bytes, |line| format!("{:04X}", range.start as usize + line * HEXDUMP_WIDTH))
+    }
+
+    /// bank_hexdump is hexdump for a range of offsets within a bank, with lines labelled
+    /// bank:offset. Returns SnaError::BankOutOfRange if the snapshot has no such bank and
+    /// SnaError::AddressOverflow if the range runs past the end of the bank.
+    pub fn bank_hexdump(&self, bank: usize, range: Range<u16>) -> Result<String, SnaError> {
+        self.check_bank(bank)?;
+        if range.end > 0x4000 {
+            return Err(SnaError::AddressOverflow { address: range.start, len: range.len() });
+        }
+        if range.is_empty() {
+            return Ok(String::new());
+        }
+        let bytes = &self.banks[bank][range.start as usize..range.end as usize];
+        Ok(hexdump_lines(bytes, |line| format!("{}:{:04X}", bank, range.start as usize + line * HEXDUMP_WIDTH)))
+    }
+
+    fn export_delimited(&self, start: RegionLocation, len: usize, columns: &[Column], delimiter: char) -> Result<String, SnaError> {
+        let columns = if columns.is_empty() { &Column::DEFAULT[..] } else { columns };
+        let (first, limit) = match start {
+            RegionLocation::Mapped(address) => (address.0 as usize, 0x10000),
+            RegionLocation::Banked(location) => {
+                self.check_bank(location.bank.0)?;
+                (location.offset as usize, 0x4000)
+            }
+        };
+        if first + len > limit {
+            return Err(SnaError::AddressOverflow { address: first as u16, len });
+        }
+
+        let mut out = String::new();
+        let names: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+        out.push_str(&names.join(&delimiter.to_string()));
+        out.push('\n');
+        for position in first..first + len {
+            let (address, offset, value) = match start {
+                RegionLocation::Mapped(_) => {
+                    let address = Addr(position as u16);
+                    (address.to_string(), position, self.peek(address.0))
+                }
+                RegionLocation::Banked(location) => {
+                    let offset = position as u16;
+                    (BankAddr { offset, ..location }.to_string(), position, self.bank_peek(location.bank.0, offset)?)
+                }
+            };
+            for (index, column) in columns.iter().enumerate() {
+                if index > 0 {
+                    out.push(delimiter);
+                }
+                let field = match column {
+                    Column::Address => address.clone(),
+                    Column::AddressDec => offset.to_string(),
+                    Column::Value => format!("0x{:02X}", value),
+                    Column::ValueDec => value.to_string(),
+                    Column::Char => if (0x20..0x7F).contains(&value) { (value as char).to_string() } else { ".".to_string() },
+                };
+                push_field(&mut out, &field, delimiter);
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
+
+const HEXDUMP_WIDTH: usize = 16;
+
+fn hexdump_lines(bytes: &[u8], label: impl Fn(usize) -> String) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(HEXDUMP_WIDTH).enumerate() {
+        let _ = write!(out, "{} ", label(line));
+        for column in 0..HEXDUMP_WIDTH {
+            if column == HEXDUMP_WIDTH / 2 {
+                out.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => { let _ = write!(out, " {:02X}", byte); }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&byte| if (0x20..0x7F).contains(&byte) { byte as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out
+}
+
+// quotes a field containing the delimiter or a quote
+fn push_field(out: &mut String, field: &str, delimiter: char) {
+    if field.contains(delimiter) || field.contains('"') {
+        let _ = write!(out, "\"{}\"", field.replace('"', "\"\""));
+    } else {
+        out.push_str(field);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::{BankId, SnapshotType};
+
+    #[test]
+    fn test_export_csv() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        snapshot.poke(0x8000, b'A').unwrap();
+        snapshot.poke(0x8001, b',').unwrap();
+        snapshot.poke(0x8002, b'"').unwrap();
+        snapshot.poke(0x8003, 0x00).unwrap();
+
+        let csv = snapshot.export_csv(Addr(0x8000), 4, &[]).unwrap();
+        assert_eq!(csv, "address,address_dec,value,value_dec,char\n\
+                         0x8000,32768,0x41,65,A\n\
+                         0x8001,32769,0x2C,44,\",\"\n\
+                         0x8002,32770,0x22,34,\"\"\"\"\n\
+                         0x8003,32771,0x00,0,.\n");
+
+        let tsv = snapshot.export_tsv(BankAddr::new(BankId(2), 0x3FFF).unwrap(), 1, &[Column::Address, Column::Char]).unwrap();
+        assert_eq!(tsv, "address\tchar\n2:0x3FFF\t.\n");
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        for (i, &byte) in b"Hello, world!\x00\x7F\xFFAB".iter().enumerate() {
+            snapshot.poke(0x8000 + i as u16, byte).unwrap();
+        }
+        let dump = snapshot.hexdump(0x8000..0x8012);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "8000  48 65 6C 6C 6F 2C 20 77  6F 72 6C 64 21 00 7F FF  |Hello, world!...|");
+        assert_eq!(lines[1], "8010  41 42                                             |AB|");
+
+        let dump = snapshot.bank_hexdump(2, 0x0000..0x0002).expect("Failed to dump bank");
+        assert!(dump.starts_with("2:0000  48 65 "), "Unexpected dump: {}", dump);
+        assert!(snapshot.bank_hexdump(2, 0x3FF0..0x4001).is_err(), "A range past the bank should fail");
+        assert!(snapshot.bank_hexdump(8, 0..1).is_err(), "A 128K snapshot has no bank 8");
+    }
+
+    #[test]
+    fn test_export_past_end() {
+        let snapshot = fixtures::generate(SnapshotType::Snapshot48, Default::default());
+        assert!(snapshot.export_csv(Addr(0xFFFF), 2, &[]).is_err(), "Export past 0xFFFF should fail");
+        assert!(snapshot.export_csv(BankAddr::new(BankId(5), 0).unwrap(), 1, &[]).is_err(), "A 48K snapshot has no bank 5");
+    }
+}
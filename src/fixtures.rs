@@ -0,0 +1,243 @@
+//! Deterministic snapshots for use in test suites.
+//! Every fixture of the same type and options is byte-for-byte identical, so
+//! downstream tests can assert against known values without shipping
+//! copyrighted .sna files. Each bank is filled with a pattern that
+//! `pattern_byte` reproduces, the registers hold the `FIXTURE_*` values, and
+//! a BASIC program and test screen can optionally be laid over the pattern.
+//! `pathological` produces edge-case files for hardening snapshot loaders.
+
+use crate::{layout, Bank, SnaError, Snapshot, SnapshotExtension, SnapshotHeader, SnapshotType};
+
+/// The program counter of every fixture (on the stack for 48K, in the extension for 128K).
+pub const FIXTURE_PC: u16 = 0x8000;
+/// The stack pointer of every fixture.
+pub const FIXTURE_SP: u16 = 0xFF40;
+/// The value written to port 0x7FFD for 128K fixtures: ROM 1 (48K BASIC) with bank 0 paged in.
+pub const FIXTURE_7FFD: u8 = 0x10;
+
+/// The tokenized BASIC program stored at 0x5CCB when `basic` is requested: `10 PRINT "HELLO"`.
+pub const FIXTURE_BASIC: [u8; 13] = [0x00, 0x0A, 0x09, 0x00, 0xF5, b'"', b'H', b'E', b'L', b'L', b'O', b'"', 0x0D];
+
+const PROG: u16 = 0x5CCB;
+
+/// Options for generating a fixture.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FixtureOptions {
+    pub basic: bool,    // store FIXTURE_BASIC and the system variables pointing at it
+    pub screen: bool,   // draw a test screen of alternating pixel rows and cycling attributes
+}
+
+/// pattern_byte returns the byte a fixture holds at the given bank and offset,
+/// wherever it has not been overwritten by the stack, BASIC program or screen.
+pub fn pattern_byte(bank: usize, offset: u16) -> u8 {
+    (offset as u8) ^ ((offset >> 8) as u8) ^ (bank as u8).wrapping_mul(0x35)
+}
+
+/// fixture_header returns the register values shared by every fixture.
+pub fn fixture_header() -> SnapshotHeader {
+    SnapshotHeader {
+        i: 0x3F,
+        hl_prime: 0x2758,
+        de_prime: 0x0102,
+        bc_prime: 0x0304,
+        af_prime: 0x0506,
+        hl: 0x0708,
+        de: 0x090A,
+        bc: 0x0B0C,
+        iy: 0x5C3A,
+        ix: 0x0D0E,
+        interrupt: 0x04,
+        r: 0x2A,
+        af: 0x0F10,
+        sp: FIXTURE_SP,
+        int_mode: 1,
+        border_color: 2,
+    }
+}
+
+/// generate builds a deterministic snapshot of the given type.
+pub fn generate(snapshot_type: SnapshotType, options: FixtureOptions) -> Snapshot {
+    let bank_count = match snapshot_type {
+        SnapshotType::Snapshot48 => 3,
+        SnapshotType::Snapshot128 => 8,
+    };
+    let mut snapshot = Snapshot {
+        snapshot_type,
+        header: fixture_header(),
+        ..Snapshot::default()
+    };
+    for bank in 0..bank_count {
+        let mut data = Bank::new();
+        for (offset, byte) in data.iter_mut().enumerate() {
+            *byte = pattern_byte(bank, offset as u16);
+        }
+        snapshot.banks.push(data);
+    }
+
+    match snapshot_type {
+        SnapshotType::Snapshot48 => snapshot.mapping = [0, 1, 2],
+        SnapshotType::Snapshot128 => {
+            snapshot.mapping = [5, 2, FIXTURE_7FFD & 0x07];
+            snapshot.extension = Some(SnapshotExtension { pc: FIXTURE_PC, x7ffd: FIXTURE_7FFD, tr_dos: 0 });
+        }
+    }
+    snapshot.remap();
+
+    if snapshot_type == SnapshotType::Snapshot48 {
+        snapshot.poke_word(FIXTURE_SP, FIXTURE_PC).expect("The fixture stack is in RAM");
+    }
+    if options.screen {
+        draw_screen(&mut snapshot).expect("The screen is in RAM");
+    }
+    if options.basic {
+        store_basic(&mut snapshot).expect("The BASIC areas are in RAM");
+    }
+    snapshot
+}
+
+/// generate_bytes builds a deterministic snapshot and returns it in .sna format.
+pub fn generate_bytes(snapshot_type: SnapshotType, options: FixtureOptions) -> Vec<u8> {
+    to_bytes(&generate(snapshot_type, options))
+}
+
+/// An edge-case snapshot file produced by `pathological`.
+pub struct PathologicalCase {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub bin: Vec<u8>,
+}
+
+/// pathological returns a set of unusual but real-world snapshot files for testing loaders:
+/// minimum and maximum file sizes, 0x7FFD with the lock and unused high bits set, SP at 0xFFFF,
+/// IM 2 with I pointing into ROM, and a 128K layout where a bank appears twice.
+pub fn pathological() -> Vec<PathologicalCase> {
+    let mut cases = Vec::new();
+
+    cases.push(PathologicalCase {
+        name: "minimum-size",
+        description: "48K snapshot of exactly 49179 bytes, the smallest valid .sna",
+        bin: generate_bytes(SnapshotType::Snapshot48, FixtureOptions::default()),
+    });
+
+    // paging bank 5 in at 0xC000 makes the file store it twice and carry six further banks
+    let mut snapshot = generate(SnapshotType::Snapshot128, FixtureOptions::default());
+    snapshot.write_0x7ffd(0x15).expect("The fixture is a 128K snapshot");
+    cases.push(PathologicalCase {
+        name: "maximum-size-duplicated-bank",
+        description: "128K snapshot with bank 5 paged at 0xC000, stored twice for 147487 bytes in total",
+        bin: to_bytes(&snapshot),
+    });
+
+    let mut snapshot = generate(SnapshotType::Snapshot128, FixtureOptions::default());
+    snapshot.write_0x7ffd(0xF3).expect("The fixture is a 128K snapshot");
+    cases.push(PathologicalCase {
+        name: "x7ffd-lock-and-high-bits",
+        description: "128K snapshot with 0x7FFD = 0xF3: paging locked and the unused high bits set",
+        bin: to_bytes(&snapshot),
+    });
+
+    let mut snapshot = generate(SnapshotType::Snapshot48, FixtureOptions::default());
+    snapshot.header.sp = 0xFFFF;
+    cases.push(PathologicalCase {
+        name: "sp-at-ffff",
+        description: "48K snapshot with SP = 0xFFFF, so the stacked PC wraps around into ROM",
+        bin: to_bytes(&snapshot),
+    });
+
+    let mut snapshot = generate(SnapshotType::Snapshot48, FixtureOptions::default());
+    snapshot.header.int_mode = 2;
+    snapshot.header.i = 0x00;
+    cases.push(PathologicalCase {
+        name: "im2-vector-in-rom",
+        description: "48K snapshot in interrupt mode 2 with I = 0x00, taking its vector table from ROM",
+        bin: to_bytes(&snapshot),
+    });
+
+    cases
+}
+
+fn to_bytes(snapshot: &Snapshot) -> Vec<u8> {
+    let mut bin = Vec::new();
+    snapshot.write_to(&mut bin).expect("Writing to a Vec cannot fail");
+    bin
+}
+
+// pixel rows alternate between 0xAA and 0x55, attributes cycle through every ink/paper pair
+fn draw_screen(snapshot: &mut Snapshot) -> Result<(), SnaError> {
+    for address in layout::SCREEN {
+        let line = (address >> 8) & 0x07;
+        snapshot.poke(address, if line & 1 == 0 { 0xAA } else { 0x55 })?;
+    }
+    for cell in 0..layout::ATTRIBUTES_LEN {
+        snapshot.poke(layout::ATTRIBUTES_START + cell, (cell % 128) as u8)?;
+    }
+    Ok(())
+}
+
+// the program is followed by an empty variables area and edit line, with the
+// system variables that locate each area pointing at them
+fn store_basic(snapshot: &mut Snapshot) -> Result<(), SnaError> {
+    let vars = PROG + FIXTURE_BASIC.len() as u16;
+    let e_line = vars + 1;
+    let worksp = e_line + 2;
+
+    for (i, &byte) in FIXTURE_BASIC.iter().enumerate() {
+        snapshot.poke(PROG + i as u16, byte)?;
+    }
+    snapshot.poke(vars, 0x80)?;
+    snapshot.poke(e_line, 0x0D)?;
+    snapshot.poke(e_line + 1, 0x80)?;
+
+    snapshot.poke_word(0x5C53, PROG)?;     // PROG
+    snapshot.poke_word(0x5C4B, vars)?;     // VARS
+    snapshot.poke_word(0x5C59, e_line)?;   // E_LINE
+    snapshot.poke_word(0x5C61, worksp)?;   // WORKSP
+    snapshot.poke_word(0x5C63, worksp)?;   // STKBOT
+    snapshot.poke_word(0x5C65, worksp)?;   // STKEND
+    snapshot.poke_word(0x5CB2, 0xFF57)?;   // RAMTOP
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic() {
+        for snapshot_type in [SnapshotType::Snapshot48, SnapshotType::Snapshot128] {
+            let options = FixtureOptions { basic: true, screen: true };
+            let bin = generate_bytes(snapshot_type, options);
+            assert!(bin == generate_bytes(snapshot_type, options), "Fixture for {} is not deterministic", snapshot_type);
+
+            let snapshot = Snapshot::try_from(bin).expect("Failed to parse fixture");
+            assert_eq!(snapshot.snapshot_type, snapshot_type, "Fixture did not load as the requested type");
+            assert_eq!(snapshot.peek_word(0x5C53).unwrap(), PROG, "PROG does not point at the BASIC program");
+            assert_eq!(snapshot.peek(0x4000), 0xAA, "Test screen was not drawn");
+        }
+    }
+
+    #[test]
+    fn test_pattern() {
+        let snapshot = generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        for bank in 0..8 {
+            for offset in [0x0000, 0x1234, 0x3FFF] {
+                assert_eq!(snapshot.bank_peek(bank, offset).unwrap(), pattern_byte(bank, offset), "Pattern mismatch in bank {} at 0x{:04X}", bank, offset);
+            }
+        }
+        assert_eq!({ snapshot.extension.as_ref().unwrap().pc }, FIXTURE_PC);
+
+        let snapshot = generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        assert_eq!(snapshot.peek_word(FIXTURE_SP).unwrap(), FIXTURE_PC, "48K fixture should hold PC on the stack");
+    }
+
+    #[test]
+    fn test_pathological_cases_load() {
+        let cases = pathological();
+        assert_eq!(cases[0].bin.len(), 49179, "Minimum size case has the wrong length");
+        assert_eq!(cases[1].bin.len(), 147487, "Maximum size case has the wrong length");
+        for case in cases {
+            let snapshot = Snapshot::try_from(case.bin).expect("Failed to parse pathological case");
+            assert_eq!(snapshot.banks.len() == 8, snapshot.snapshot_type == SnapshotType::Snapshot128, "Case {} has the wrong number of banks", case.name);
+        }
+    }
+}
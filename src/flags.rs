@@ -0,0 +1,83 @@
+use crate::{Register, Snapshot};
+
+/// The Z80 flags held in F or F', one field per bit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub sign: bool,             // bit 7
+    pub zero: bool,             // bit 6
+    pub y: bool,                // bit 5, undocumented copy of bit 5 of the result
+    pub half_carry: bool,       // bit 4
+    pub x: bool,                // bit 3, undocumented copy of bit 3 of the result
+    pub parity_overflow: bool,  // bit 2
+    pub add_subtract: bool,     // bit 1
+    pub carry: bool,            // bit 0
+}
+
+impl Flags {
+    /// from_byte decodes the flags from the value of F.
+    pub fn from_byte(f: u8) -> Flags {
+        let bit = |n: u8| f & (1 << n) != 0;
+        Flags {
+            sign: bit(7),
+            zero: bit(6),
+            y: bit(5),
+            half_carry: bit(4),
+            x: bit(3),
+            parity_overflow: bit(2),
+            add_subtract: bit(1),
+            carry: bit(0),
+        }
+    }
+
+    /// to_byte encodes the flags as the value of F.
+    pub fn to_byte(self) -> u8 {
+        [self.carry, self.add_subtract, self.parity_overflow, self.x, self.half_carry, self.y, self.zero, self.sign]
+            .iter()
+            .enumerate()
+            .fold(0, |f, (n, &set)| f | (set as u8) << n)
+    }
+}
+
+impl Snapshot {
+    /// flags returns the flags in F.
+    pub fn flags(&self) -> Flags {
+        Flags::from_byte(self.get_reg(Register::F))
+    }
+
+    /// set_flags sets F from the flags.
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.set_reg(Register::F, flags.to_byte());
+    }
+
+    /// alt_flags returns the flags in F'.
+    pub fn alt_flags(&self) -> Flags {
+        Flags::from_byte(self.get_reg(Register::FPrime))
+    }
+
+    /// set_alt_flags sets F' from the flags.
+    pub fn set_alt_flags(&mut self, flags: Flags) {
+        self.set_reg(Register::FPrime, flags.to_byte());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        assert!((0..=255).all(|f| Flags::from_byte(f).to_byte() == f), "Flags should round trip every byte");
+        let flags = Flags::from_byte(0x41);
+        assert!(flags.zero && flags.carry && !flags.sign && !flags.half_carry);
+
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.header.af = 0x1200;
+        snapshot.set_flags(Flags { sign: true, carry: true, ..Flags::default() });
+        assert_eq!({ snapshot.header.af }, 0x1281, "Setting the flags should keep A");
+        let mut alt = snapshot.alt_flags();
+        alt.parity_overflow = true;
+        snapshot.set_alt_flags(alt);
+        assert_eq!({ snapshot.header.af_prime }, 0x0004);
+        assert!(snapshot.flags().sign);
+    }
+}
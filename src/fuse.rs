@@ -0,0 +1,191 @@
+//! Interoperability with the FUSE emulator's debugger.
+//!
+//! FUSE's debugger accepts commands such as `break read 0x5c00` and `set pc 0x8000`,
+//! and can run a file of them at startup. These helpers write and read such files,
+//! so analysis can move between this crate and an interactive FUSE session.
+
+use std::fmt;
+
+use crate::trainer::{parse_number, ParseError};
+use crate::Snapshot;
+
+/// What triggers a breakpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakpointKind {
+    Execute,
+    Read,
+    Write,
+    PortRead,
+    PortWrite,
+}
+
+/// A FUSE debugger breakpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub kind: BreakpointKind,
+    pub address: u16,
+    pub condition: Option<String>,  // a FUSE debugger expression, e.g. "a == 3"
+}
+
+impl Breakpoint {
+    /// new creates an unconditional breakpoint.
+    pub fn new(kind: BreakpointKind, address: u16) -> Breakpoint {
+        Breakpoint { kind, address, condition: None }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    /// Formats the breakpoint as a FUSE debugger command.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            BreakpointKind::Execute => "",
+            BreakpointKind::Read => "read ",
+            BreakpointKind::Write => "write ",
+            BreakpointKind::PortRead => "port read ",
+            BreakpointKind::PortWrite => "port write ",
+        };
+        write!(f, "break {}0x{:04x}", kind, self.address)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+/// to_breakpoint_file formats breakpoints as FUSE debugger commands, one per line.
+pub fn to_breakpoint_file(breakpoints: &[Breakpoint]) -> String {
+    breakpoints.iter().map(|breakpoint| format!("{}\n", breakpoint)).collect()
+}
+
+/// parse_breakpoint_file reads the breakpoints from a file of FUSE debugger commands.
+/// `break`, `br` and `breakpoint` commands are accepted; blank lines, `#` comments
+/// and other commands are skipped.
+pub fn parse_breakpoint_file(text: &str) -> Result<Vec<Breakpoint>, ParseError> {
+    let mut breakpoints = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| ParseError { line: index + 1, message: message.to_string() };
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let (command, condition) = match line.split_once(" if ") {
+            Some((command, condition)) => (command, Some(condition.trim().to_string())),
+            None => (line, None),
+        };
+        let words: Vec<String> = command.split_whitespace().map(str::to_ascii_lowercase).collect();
+        if !matches!(words.first().map(String::as_str), Some("break" | "br" | "breakpoint")) {
+            continue;
+        }
+        let (kind, address) = match &words[1..] {
+            [address] => (BreakpointKind::Execute, address),
+            [kind, address] if kind == "read" => (BreakpointKind::Read, address),
+            [kind, address] if kind == "write" => (BreakpointKind::Write, address),
+            [port, kind, address] if port == "port" && kind == "read" => (BreakpointKind::PortRead, address),
+            [port, kind, address] if port == "port" && kind == "write" => (BreakpointKind::PortWrite, address),
+            _ => return Err(error("Unsupported breakpoint")),
+        };
+        let address = parse_number(address).and_then(|a| u16::try_from(a).ok()).ok_or_else(|| error("Invalid address"))?;
+        breakpoints.push(Breakpoint { kind, address, condition });
+    }
+    Ok(breakpoints)
+}
+
+impl Snapshot {
+    /// fuse_register_dump formats the CPU state as FUSE debugger `set` commands.
+    /// Running the result in FUSE's debugger restores the registers of this snapshot.
+    pub fn fuse_register_dump(&self) -> String {
+        let header = self.header;
+        let iff = (header.interrupt >> 2) & 1;
+        let mut dump = String::new();
+        for (name, value) in [
+            ("af", header.af), ("bc", header.bc), ("de", header.de), ("hl", header.hl),
+            ("af'", header.af_prime), ("bc'", header.bc_prime), ("de'", header.de_prime), ("hl'", header.hl_prime),
+            ("ix", header.ix), ("iy", header.iy), ("sp", header.sp),
+        ] {
+            dump.push_str(&format!("set {} 0x{:04x}\n", name, value));
+        }
+        if let Some(pc) = self.pc() {
+            dump.push_str(&format!("set pc 0x{:04x}\n", pc));
+        }
+        dump.push_str(&format!("set i 0x{:02x}\nset r 0x{:02x}\nset im {}\nset iff1 {}\nset iff2 {}\n", header.i, header.r, header.int_mode, iff, iff));
+        dump
+    }
+
+    /// apply_fuse_register_dump sets the CPU state from FUSE debugger `set` commands,
+    /// such as those written by fuse_register_dump. Other commands are skipped.
+    /// On 48K snapshots the PC is written to the stack, so `set sp` must come first.
+    pub fn apply_fuse_register_dump(&mut self, text: &str) -> Result<(), ParseError> {
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ParseError { line: index + 1, message };
+            let words: Vec<String> = line.split('#').next().unwrap_or_default()
+                .split_whitespace().map(str::to_ascii_lowercase).collect();
+            let (name, value) = match words.as_slice() {
+                [set, name, value] if set == "set" => (name.as_str(), value),
+                _ => continue,
+            };
+            let value = parse_number(value).and_then(|v| u16::try_from(v).ok())
+                .ok_or_else(|| error(format!("Invalid value for {}", name)))?;
+            let byte = || u8::try_from(value).map_err(|_| error(format!("Value for {} does not fit in a byte", name)));
+            let header = &mut self.header;
+            match name {
+                "af" => header.af = value,
+                "bc" => header.bc = value,
+                "de" => header.de = value,
+                "hl" => header.hl = value,
+                "af'" => header.af_prime = value,
+                "bc'" => header.bc_prime = value,
+                "de'" => header.de_prime = value,
+                "hl'" => header.hl_prime = value,
+                "ix" => header.ix = value,
+                "iy" => header.iy = value,
+                "sp" => header.sp = value,
+                "i" => header.i = byte()?,
+                "r" => header.r = byte()?,
+                "im" if value > 2 => return Err(error(format!("Invalid interrupt mode {}", value))),
+                "im" => header.int_mode = value as u8,
+                "iff1" => {}
+                "iff2" => header.interrupt = (header.interrupt & !0x04) | if value != 0 { 0x04 } else { 0 },
+                "pc" => self.set_pc(value)
+                    .map_err(|_| error(format!("Cannot store PC on the stack at 0x{:04X}", { self.header.sp })))?,
+                _ => return Err(error(format!("Unsupported register {}", name))),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SnapshotType;
+    use crate::fixtures;
+
+    #[test]
+    fn test_breakpoints_round_trip() {
+        let mut breakpoints = vec![
+            Breakpoint::new(BreakpointKind::Execute, 0x8000),
+            Breakpoint::new(BreakpointKind::Read, 0x5C00),
+            Breakpoint::new(BreakpointKind::PortWrite, 0x7FFD),
+        ];
+        breakpoints[0].condition = Some("a == 3".to_string());
+        let text = to_breakpoint_file(&breakpoints);
+        assert!(text.starts_with("break 0x8000 if a == 3\nbreak read 0x5c00\n"));
+        assert_eq!(parse_breakpoint_file(&text), Ok(breakpoints));
+
+        let parsed = parse_breakpoint_file("# comment\nbr 32768\nrun\n").expect("Failed to parse");
+        assert_eq!(parsed, [Breakpoint::new(BreakpointKind::Execute, 0x8000)]);
+        assert_eq!(parse_breakpoint_file("break time 10").map_err(|e| e.line), Err(1));
+    }
+
+    #[test]
+    fn test_register_dump_round_trip() {
+        for snapshot_type in [SnapshotType::Snapshot48, SnapshotType::Snapshot128] {
+            let source = fixtures::generate(snapshot_type, Default::default());
+            let dump = source.fuse_register_dump();
+            assert!(dump.contains("set pc 0x8000\n"), "Dump is missing PC: {}", dump);
+
+            let mut target = fixtures::generate(snapshot_type, Default::default());
+            target.header = Default::default();
+            target.header.sp = 0x8000;
+            target.apply_fuse_register_dump(&dump).expect("Failed to apply dump");
+            assert_eq!(target.fuse_register_dump(), dump);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// Guards over ranges of mapped memory, so that tools writing to a snapshot can be
+// told about, or stopped from, writes to regions such as the system variables or
+// the screen. Writes through poke and poke_slice, and everything built on them, are
+// checked, as are the screen's own writes while its bank is paged in; other bank
+// writes are not, as guards are on mapped addresses.
+
+use std::ops::Range;
+
+use crate::{BankAddr, BankId, SnaError, Snapshot};
+
+/// What a guard does with a write into its range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuardAction {
+    Report, // let the write happen and record it
+    Block,  // refuse the write with SnaError::Guarded
+}
+
+/// A named range of mapped memory whose writes are reported or blocked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Guard {
+    pub name: String,
+    pub range: Range<u16>,
+    pub action: GuardAction,
+}
+
+/// A write into a guarded range.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuardHit {
+    pub guard: String,  // the name of the guard
+    pub address: u16,
+    pub value: u8,      // the byte written, or that would have been
+    pub blocked: bool,
+}
+
+pub(crate) type GuardList = Vec<Guard>;
+
+// the addresses and values of the bytes of a write that fall in a guard's range
+fn written<'a>(guard: &'a Guard, address: u16, bytes: &'a [u8]) -> impl Iterator<Item = (usize, u8)> + 'a {
+    bytes.iter().enumerate()
+        .map(move |(offset, &value)| (address as usize + offset, value))
+        .filter(|&(address, _)| guard.range.contains(&(address as u16)))
+}
+
+impl Snapshot {
+    /// guard watches a range of mapped memory, reporting or blocking writes to it.
+    /// A guard with the same name is replaced.
+    pub fn guard(&mut self, name: &str, range: Range<u16>, action: GuardAction) {
+        self.unguard(name);
+        self.guards.push(Guard { name: name.to_string(), range, action });
+    }
+
+    /// unguard removes the guard of a name, returning whether there was one.
+    pub fn unguard(&mut self, name: &str) -> bool {
+        let count = self.guards.len();
+        self.guards.retain(|guard| guard.name != name);
+        self.guards.len() != count
+    }
+
+    /// guards returns the guards in the order they were added.
+    pub fn guards(&self) -> &[Guard] {
+        &self.guards
+    }
+
+    /// take_guard_hits returns the writes into guarded ranges since it was last called,
+    /// in the order they happened, including the first byte of each blocked write.
+    pub fn take_guard_hits(&mut self) -> Vec<GuardHit> {
+        std::mem::take(&mut self.guard_hits)
+    }
+
+    // checks a write of bytes from an address against the guards, recording hits.
+    // Returns SnaError::Guarded for the first blocked byte, and then nothing else is
+    // recorded as nothing is written.
+    pub(crate) fn check_guards(&mut self, address: u16, bytes: &[u8]) -> Result<(), SnaError> {
+        let blocked = self.guards.iter()
+            .filter(|guard| guard.action == GuardAction::Block)
+            .filter_map(|guard| written(guard, address, bytes).next().map(|hit| (guard, hit)))
+            .min_by_key(|(_, (address, _))| *address);
+        if let Some((guard, (address, value))) = blocked {
+            let guard = guard.name.clone();
+            self.guard_hits.push(GuardHit { guard: guard.clone(), address: address as u16, value, blocked: true });
+            return Err(SnaError::Guarded { address: address as u16, guard });
+        }
+        let mut reported: Vec<GuardHit> = self.guards.iter()
+            .flat_map(|guard| written(guard, address, bytes).map(|(address, value)| {
+                GuardHit { guard: guard.name.clone(), address: address as u16, value, blocked: false }
+            }))
+            .collect();
+        reported.sort_by_key(|hit| hit.address);
+        self.guard_hits.extend(reported);
+        Ok(())
+    }
+
+    // returns SnaError::Guarded for the first byte of a write that a guard blocks,
+    // without recording anything, so that several writes can be checked before any
+    pub(crate) fn check_blocked(&self, address: u16, len: usize) -> Result<(), SnaError> {
+        let end = address as usize + len;
+        let blocked = self.guards.iter()
+            .filter(|guard| guard.action == GuardAction::Block)
+            .map(|guard| (guard, (guard.range.start as usize).max(address as usize)))
+            .filter(|&(guard, start)| start < end.min(guard.range.end as usize))
+            .min_by_key(|&(_, start)| start);
+        match blocked {
+            Some((guard, start)) => Err(SnaError::Guarded { address: start as u16, guard: guard.name.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    // checks a write of bytes from an offset into a bank against the guards as a write to
+    // the address the bank is paged in at, if it is paged in at all
+    pub(crate) fn check_bank_guards(&mut self, bank: usize, offset: usize, bytes: &[u8]) -> Result<(), SnaError> {
+        match self.address_of(BankAddr { bank: BankId(bank), offset: offset as u16 }) {
+            Some(address) => self.check_guards(address.0, bytes),
+            None => Ok(()),
+        }
+    }
+
+    // checks single byte writes at offsets into a bank against the guards in the same
+    // way, recording nothing but the blocked byte unless none of them is blocked
+    pub(crate) fn check_bank_writes(&mut self, bank: usize, writes: &[(usize, u8)]) -> Result<(), SnaError> {
+        let Some(start) = self.address_of(BankAddr { bank: BankId(bank), offset: 0 }) else {
+            return Ok(());
+        };
+        let address = |offset: usize| start.0 + offset as u16;
+        if let Some(&(offset, value)) = writes.iter().find(|&&(offset, _)| self.is_blocked(address(offset))) {
+            return self.check_guards(address(offset), &[value]);
+        }
+        for &(offset, value) in writes {
+            self.check_guards(address(offset), &[value])?;
+        }
+        Ok(())
+    }
+
+    // whether an address is in a blocking guard, for writes that cannot report
+    pub(crate) fn is_blocked(&self, address: u16) -> bool {
+        self.guards.iter().any(|guard| guard.action == GuardAction::Block && guard.range.contains(&address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::SCREEN;
+
+    #[test]
+    fn test_guards() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.guard("sysvars", 0x5C00..0x5CC0, GuardAction::Block);
+        snapshot.guard("screen", SCREEN, GuardAction::Report);
+        assert_eq!(snapshot.guards().len(), 2);
+
+        snapshot.poke(0x4000, 0xFF).unwrap();
+        snapshot.load_bin(0x57FF, &[1, 2]).unwrap();
+        let hits = snapshot.take_guard_hits();
+        assert_eq!(hits.iter().map(|hit| (hit.address, hit.value)).collect::<Vec<_>>(), [(0x4000, 0xFF), (0x57FF, 1)]);
+        assert!(snapshot.take_guard_hits().is_empty());
+
+        assert!(matches!(snapshot.poke_word(0x5C3A, 0x1234), Err(SnaError::Guarded { address: 0x5C3A, .. })));
+        assert!(matches!(snapshot.load_bin(0x5BFE, &[1, 2, 3]), Err(SnaError::Guarded { address: 0x5C00, .. })));
+        assert_eq!(snapshot.peek(0x5BFE), 0, "Nothing is written when any byte is blocked");
+        assert!(snapshot.fill(0x5C00..0x5C01, 0).is_err());
+        let hits = snapshot.take_guard_hits();
+        assert_eq!(hits.len(), 3);
+        assert!(hits.iter().all(|hit| hit.blocked && hit.guard == "sysvars"));
+
+        assert!(snapshot.unguard("sysvars"));
+        assert!(!snapshot.unguard("sysvars"));
+        snapshot.poke_word(0x5C3A, 0x1234).unwrap();
+        assert_eq!(snapshot.peek_word(0x5C3A).unwrap(), 0x1234);
+    }
+}
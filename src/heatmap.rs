@@ -0,0 +1,80 @@
+use crate::{BankId, SnaError, Snapshot, MEM_16K};
+
+/// The number of changed bytes in each chunk of one bank.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BankHeatmap {
+    pub bank: BankId,
+    pub granularity: usize,     // bytes per chunk; the last chunk may be shorter
+    pub counts: Vec<u32>,       // changed bytes per chunk, starting at offset 0
+}
+
+impl BankHeatmap {
+    /// changed returns the total number of changed bytes in the bank.
+    pub fn changed(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+}
+
+/// diff_heatmap counts the bytes that differ between two snapshots in each
+/// granularity-byte chunk of every bank, e.g. 256 for 64 chunks per bank.
+/// Returns SnaError::InvalidLength unless granularity is 1 to 16384,
+/// SnaError::TypeMismatch if the snapshots are not of the same type, and
+/// SnaError::BankCountMismatch if they are but have different numbers of banks, as
+/// a Scorpion 256 and a 128K do.
+pub fn diff_heatmap(a: &Snapshot, b: &Snapshot, granularity: usize) -> Result<Vec<BankHeatmap>, SnaError> {
+    if !(1..=MEM_16K).contains(&granularity) {
+        return Err(SnaError::InvalidLength { len: granularity, max: MEM_16K });
+    }
+    if a.snapshot_type != b.snapshot_type {
+        return Err(SnaError::TypeMismatch { expected: a.snapshot_type, found: b.snapshot_type });
+    }
+    if a.banks.len() != b.banks.len() {
+        return Err(SnaError::BankCountMismatch { expected: a.banks.len(), found: b.banks.len() });
+    }
+    Ok(a.banks.iter().zip(&b.banks).enumerate().map(|(bank, (a, b))| BankHeatmap {
+        bank: BankId(bank),
+        granularity,
+        counts: a.chunks(granularity).zip(b.chunks(granularity))
+            .map(|(a, b)| a.iter().zip(b).filter(|(a, b)| a != b).count() as u32)
+            .collect(),
+    }).collect())
+}
+
+impl Snapshot {
+    /// diff_heatmap compares this snapshot with another, see [`diff_heatmap`].
+    pub fn diff_heatmap(&self, other: &Snapshot, granularity: usize) -> Result<Vec<BankHeatmap>, SnaError> {
+        diff_heatmap(self, other, granularity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_diff_heatmap() {
+        let a = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        let mut b = a.clone();
+        b.bank_poke(3, 0x0000, !a.bank_peek(3, 0x0000).unwrap()).unwrap();
+        b.bank_poke(3, 0x00FF, !a.bank_peek(3, 0x00FF).unwrap()).unwrap();
+        b.bank_poke(3, 0x3FFF, !a.bank_peek(3, 0x3FFF).unwrap()).unwrap();
+
+        let heatmap = diff_heatmap(&a, &b, 256).unwrap();
+        assert_eq!(heatmap.len(), 8);
+        assert_eq!(heatmap[3].counts.len(), 64);
+        assert_eq!((heatmap[3].counts[0], heatmap[3].counts[63], heatmap[3].changed()), (2, 1, 3));
+        assert!(heatmap.iter().filter(|bank| bank.bank != BankId(3)).all(|bank| bank.changed() == 0));
+
+        let heatmap = a.diff_heatmap(&b, 5000).unwrap();
+        assert_eq!(heatmap[3].counts, [2, 0, 0, 1], "The last chunk should hold the remainder of the bank");
+
+        let small = fixtures::generate(SnapshotType::Snapshot48, Default::default());
+        assert!(matches!(a.diff_heatmap(&small, 256), Err(SnaError::TypeMismatch { .. })));
+        let mut scorpion = a.clone();
+        scorpion.set_machine(crate::Machine::Scorpion256).unwrap();
+        assert!(matches!(a.diff_heatmap(&scorpion, 256), Err(SnaError::BankCountMismatch { expected: 8, found: 16 })));
+        assert!(matches!(a.diff_heatmap(&b, 0), Err(SnaError::InvalidLength { len: 0, .. })), "A chunk must hold at least a byte");
+    }
+}
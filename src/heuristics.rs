@@ -0,0 +1,98 @@
+use crate::layout::{ATTRIBUTES, RAM_START, SCREEN};
+use crate::{Snapshot, SnapshotType};
+
+const HALT: u8 = 0x76;
+
+impl Snapshot {
+    /// screen_entropy returns the Shannon entropy of the visible screen's bytes,
+    /// in bits per byte: 0 for a blank screen, up to 8 for noise.
+    pub fn screen_entropy(&self) -> f64 {
+        entropy(self.screen_bytes())
+    }
+
+    /// is_loading_screen_only guesses whether the snapshot was taken after a loading
+    /// screen was loaded but before the game itself: the screen has content while
+    /// the memory above it is almost entirely empty.
+    pub fn is_loading_screen_only(&self) -> bool {
+        let used = (0x6000..=0xFFFFu16).filter(|&address| self.peek(address) != 0).count();
+        self.screen_entropy() > 1.0 && used < 0xA000 / 50
+    }
+
+    /// looks_like_crash guesses whether the machine had crashed when the snapshot was
+    /// taken: the stack is unusable, the PC is in screen memory, or the CPU is halted
+    /// with interrupts disabled and so can never continue.
+    pub fn looks_like_crash(&self) -> bool {
+        if self.check_stack().is_err() {
+            return true;
+        }
+        let Some(pc) = self.pc() else {
+            return true;
+        };
+        if (SCREEN.start..ATTRIBUTES.end).contains(&pc) {
+            return true;
+        }
+        let interrupts_enabled = self.header.interrupt & 0x04 != 0;
+        pc >= RAM_START && self.peek(pc) == HALT && !interrupts_enabled
+    }
+
+    /// uses_shadow_screen returns whether a 128K snapshot is displaying, or has drawn
+    /// anything on, the shadow screen in bank 7.
+    pub fn uses_shadow_screen(&self) -> bool {
+        if self.snapshot_type != SnapshotType::Snapshot128 {
+            return false;
+        }
+        self.screen_bank() == 7 || self.banks[7][..(ATTRIBUTES.end - SCREEN.start) as usize].iter().any(|&byte| byte != 0)
+    }
+}
+
+fn entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts.iter().filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+
+    // a 128K fixture with all RAM cleared, optionally redrawing the fixture screen
+    fn cleared(screen: bool) -> Snapshot {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions { screen, ..FixtureOptions::default() });
+        snapshot.banks_mut(|id, bank, _| {
+            let keep = if screen && id.0 == 5 { (ATTRIBUTES.end - SCREEN.start) as usize } else { 0 };
+            bank[keep..].fill(0);
+        });
+        snapshot
+    }
+
+    #[test]
+    fn test_classifiers() {
+        let blank = cleared(false);
+        assert_eq!(blank.screen_entropy(), 0.0);
+        assert!(!blank.is_loading_screen_only() && !blank.looks_like_crash());
+
+        let mut screen = cleared(true);
+        assert!(screen.is_loading_screen_only(), "A screen with empty memory should look like a loading screen");
+        assert!(!screen.uses_shadow_screen());
+        screen.bank_poke(7, 0x100, 0xFF).unwrap();
+        assert!(screen.uses_shadow_screen());
+        let game = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions { screen: true, ..FixtureOptions::default() });
+        assert!(!game.is_loading_screen_only(), "A screen with a full memory should not look like a loading screen");
+
+        let mut halted = cleared(false);
+        halted.poke(fixtures::FIXTURE_PC, HALT).unwrap();
+        halted.header.interrupt = 0;
+        assert!(halted.looks_like_crash(), "DI; HALT should look like a crash");
+        halted.header.interrupt = 0x04;
+        assert!(!halted.looks_like_crash(), "HALT with interrupts enabled is waiting for a frame");
+    }
+}
@@ -0,0 +1,133 @@
+use crate::layout::PRINTER_BUFFER_START;
+use crate::{Addr, FreeSource, SnaError, Snapshot, SnapshotType};
+
+const JP: u8 = 0xC3;
+const NOP: u8 = 0x00;
+
+/// A hook injected into a snapshot, recording what it replaced so it can be removed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Hook {
+    pub at: Addr,               // where the jump to the stub was written
+    pub original: Vec<u8>,      // the displaced instruction bytes
+    pub stub: Addr,             // where the stub was placed
+    pub stub_len: usize,
+}
+
+impl Snapshot {
+    /// inject_hook makes the code at `at` run `payload` first. The `displaced` bytes
+    /// at `at`, which must be at least 3 and cover whole instructions, are replaced by
+    /// a `JP` to a stub that runs the payload, then the displaced instructions, then
+    /// jumps back to the instruction after them. The stub is placed in free memory
+    /// (see [`Snapshot::memory_usage`]) that is never paged out.
+    ///
+    /// The payload must fall through at its end rather than return. Displaced
+    /// instructions must not be relative jumps or otherwise depend on their address.
+    /// Returns SnaError::NoFreeMemory if there is nowhere to put the stub, and the
+    /// errors of [`Snapshot::inject_hook_at`].
+    pub fn inject_hook(&mut self, at: Addr, displaced: usize, payload: &[u8]) -> Result<Hook, SnaError> {
+        let stub_len = payload.len() + displaced + 3;
+        let stub = self.memory_usage().free.iter()
+            .filter(|region| region.source != FreeSource::BasicSpare && region.len >= stub_len)
+            .filter_map(|region| self.address_of(region.start))
+            .find(|stub| {
+                let limit = match self.snapshot_type {
+                    SnapshotType::Snapshot48 => 0x10000,
+                    SnapshotType::Snapshot128 => 0xC000,
+                };
+                stub.0 >= PRINTER_BUFFER_START && stub.0 as usize + stub_len <= limit
+                    && (stub.0 as usize + stub_len <= at.0 as usize || stub.0 as usize >= at.0 as usize + displaced)
+            });
+        match stub {
+            Some(stub) => self.inject_hook_at(at, displaced, payload, stub),
+            None => Err(SnaError::NoFreeMemory { len: stub_len }),
+        }
+    }
+
+    /// inject_hook_at is inject_hook with the stub placed at a given address.
+    /// Returns SnaError::DisplacedTooShort if fewer than 3 bytes are displaced,
+    /// SnaError::RomWrite if the hook or stub is in ROM, SnaError::AddressOverflow if
+    /// the stub or the address to resume at runs past 0xFFFF, and SnaError::Guarded
+    /// if either is blocked by a guard.
+    pub fn inject_hook_at(&mut self, at: Addr, displaced: usize, payload: &[u8], stub: Addr) -> Result<Hook, SnaError> {
+        if displaced < 3 {
+            return Err(SnaError::DisplacedTooShort { len: displaced, min: 3 });
+        }
+        if self.is_rom(at.0) {
+            return Err(SnaError::RomWrite(at.0));
+        }
+        // the stub jumps back to the address after the displaced bytes, so it must exist
+        if at.0 as usize + displaced >= 0x10000 {
+            return Err(SnaError::AddressOverflow { address: at.0, len: displaced + 1 });
+        }
+        let stub_len = payload.len() + displaced + 3;
+        if self.is_rom(stub.0) {
+            return Err(SnaError::RomWrite(stub.0));
+        }
+        if stub.0 as usize + stub_len > 0x10000 {
+            return Err(SnaError::AddressOverflow { address: stub.0, len: stub_len });
+        }
+
+        let original: Vec<u8> = (0..displaced as u16).map(|i| self.peek(at.0 + i)).collect();
+        let resume = at.0 + displaced as u16;
+
+        let mut code = payload.to_vec();
+        code.extend_from_slice(&original);
+        code.push(JP);
+        code.extend_from_slice(&resume.to_le_bytes());
+        let mut jump = vec![JP];
+        jump.extend_from_slice(&stub.0.to_le_bytes());
+        jump.resize(displaced, NOP);
+
+        self.check_write(stub.0, code.len())?;
+        self.check_write(at.0, jump.len())?;
+        self.grouped(|snapshot| {
+            snapshot.poke_slice(stub.0, &code)?;
+            snapshot.poke_slice(at.0, &jump)
+        })?;
+        Ok(Hook { at, original, stub, stub_len })
+    }
+
+    /// remove_hook restores the displaced bytes and clears the stub to zeros.
+    pub fn remove_hook(&mut self, hook: &Hook) -> Result<(), SnaError> {
+        self.check_write(hook.at.0, hook.original.len())?;
+        self.check_write(hook.stub.0, hook.stub_len)?;
+        self.grouped(|snapshot| {
+            snapshot.poke_slice(hook.at.0, &hook.original)?;
+            snapshot.poke_slice(hook.stub.0, &vec![0; hook.stub_len])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn test_inject_hook() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        for address in 0x9000..0x9400 {
+            snapshot.poke(address, 0).unwrap();
+        }
+        snapshot.poke_word(0x5C65, 0).unwrap(); // no STKEND, so no BASIC spare memory
+        // LD A,1 : LD B,2 : RET
+        for (i, &byte) in [0x3E, 0x01, 0x06, 0x02, 0xC9].iter().enumerate() {
+            snapshot.poke(0x8000 + i as u16, byte).unwrap();
+        }
+        let before = snapshot.clone();
+
+        // INC (HL)
+        let hook = snapshot.inject_hook(Addr(0x8000), 4, &[0x34]).expect("Failed to inject hook");
+        assert_eq!(hook.stub, Addr(0x9000));
+        assert_eq!((0..5).map(|i| snapshot.peek(0x8000 + i)).collect::<Vec<_>>(), [JP, 0x00, 0x90, NOP, 0xC9]);
+        assert_eq!((0..8).map(|i| snapshot.peek(0x9000 + i)).collect::<Vec<_>>(), [0x34, 0x3E, 0x01, 0x06, 0x02, JP, 0x04, 0x80]);
+
+        snapshot.remove_hook(&hook).expect("Failed to remove hook");
+        assert!((0x4000..=0xFFFF).all(|address| snapshot.peek(address) == before.peek(address)), "Removing the hook should restore memory");
+
+        assert!(matches!(snapshot.inject_hook(Addr(0x8000), 2, &[]), Err(SnaError::DisplacedTooShort { len: 2, min: 3 })), "A JP does not fit in 2 bytes");
+        assert!(matches!(snapshot.inject_hook(Addr(0x8000), 3, &[0; 0x400]), Err(SnaError::NoFreeMemory { len: 0x406 })), "There is no room for a 1K stub");
+        assert!(matches!(snapshot.inject_hook_at(Addr(0x3FFE), 3, &[], Addr(0x9000)), Err(SnaError::RomWrite(0x3FFE))));
+        assert!(matches!(snapshot.inject_hook_at(Addr(0xFFFD), 3, &[], Addr(0x9000)), Err(SnaError::AddressOverflow { address: 0xFFFD, len: 4 })), "There is no address to resume at after 0xFFFF");
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use crate::trainer::ParseError;
+use crate::{SnaError, Snapshot};
+
+const RECORD_LEN: usize = 16;
+
+fn record(kind: u8, address: u16, data: &[u8]) -> String {
+    let mut line = format!(":{:02X}{:04X}{:02X}", data.len(), address, kind);
+    let mut sum = (data.len() as u8).wrapping_add((address >> 8) as u8).wrapping_add(address as u8).wrapping_add(kind);
+    for &byte in data {
+        let _ = write!(line, "{:02X}", byte);
+        sum = sum.wrapping_add(byte);
+    }
+    let _ = writeln!(line, "{:02X}", sum.wrapping_neg());
+    line
+}
+
+impl Snapshot {
+    /// export_ihex returns a range of mapped memory as Intel HEX data records of up to
+    /// 16 bytes at their load addresses, followed by an end-of-file record.
+    pub fn export_ihex(&self, range: RangeInclusive<u16>) -> String {
+        let mut hex = String::new();
+        let bytes: Vec<u8> = range.clone().map(|address| self.peek(address)).collect();
+        for (index, chunk) in bytes.chunks(RECORD_LEN).enumerate() {
+            hex.push_str(&record(0x00, range.start() + (index * RECORD_LEN) as u16, chunk));
+        }
+        hex.push_str(&record(0x01, 0, &[]));
+        hex
+    }
+
+    /// import_ihex writes the data records of Intel HEX text into mapped memory,
+    /// returning the number of bytes written. Every record is checked before anything
+    /// is written, so on error the snapshot is unchanged. Start address records are
+    /// ignored, and extended address records must select the first 64K.
+    pub fn import_ihex(&mut self, text: &str) -> Result<usize, ParseError> {
+        let mut writes = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| ParseError { line: index + 1, message: message.to_string() };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let digits = line.strip_prefix(':').ok_or_else(|| error("Record does not start with ':'"))?;
+            if !digits.is_ascii() {
+                return Err(error("Record holds a non-hex digit"));
+            }
+            if digits.len() % 2 != 0 || digits.len() < 10 {
+                return Err(error("Record is too short"));
+            }
+            let bytes = (0..digits.len()).step_by(2)
+                .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| error("Record holds a non-hex digit"))?;
+            let len = bytes[0] as usize;
+            if bytes.len() != len + 5 {
+                return Err(error("Record length does not match its byte count"));
+            }
+            if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+                return Err(error("Record checksum is wrong"));
+            }
+            let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+            let data = &bytes[4..4 + len];
+            match bytes[3] {
+                0x00 => {
+                    if self.is_rom(address) || address as usize + len > 0x10000 {
+                        return Err(error("Record data is outside RAM"));
+                    }
+                    writes.push((index + 1, address, data.to_vec()));
+                }
+                0x01 => break,
+                0x02 | 0x04 if data.iter().any(|&byte| byte != 0) => return Err(error("Extended address is beyond 64K")),
+                0x02..=0x05 => {}
+                _ => return Err(error("Unknown record type")),
+            }
+        }
+
+        let failed = |line: usize| move |e: SnaError| ParseError { line, message: e.to_string() };
+        for (line, address, data) in &writes {
+            self.check_write(*address, data.len()).map_err(failed(*line))?;
+        }
+        self.grouped(|snapshot| writes.into_iter().try_fold(0, |written, (line, address, data)| {
+            snapshot.poke_slice(address, &data).map_err(failed(line))?;
+            Ok(written + data.len())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures;
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_ihex_round_trip() {
+        let source = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        let hex = source.export_ihex(0x8000..=0x8012);
+        let lines: Vec<&str> = hex.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(":10800000") && lines[1].starts_with(":03801000"));
+        assert_eq!(lines[2], ":00000001FF");
+
+        let mut target = fixtures::generate(SnapshotType::Snapshot48, Default::default());
+        assert_eq!(target.import_ihex(&hex), Ok(19));
+        assert!((0x8000..=0x8012).all(|address| target.peek(address) == source.peek(address)));
+
+        assert_eq!(target.import_ihex(":0300300002337A1E\n").map_err(|e| e.message), Err("Record data is outside RAM".to_string()));
+        assert_eq!(target.import_ihex(":0180000000FF\n").map_err(|e| e.line), Err(1), "Bad checksum should fail");
+        assert_eq!(target.import_ihex(":0é000000000").map_err(|e| e.message), Err("Record holds a non-hex digit".to_string()));
+
+        target.guard("code", 0x8010..0x8011, crate::GuardAction::Block);
+        target.poke(0x8000, 0).unwrap();
+        assert_eq!(target.import_ihex(&hex).map_err(|e| e.line), Err(2), "The guarded record fails");
+        assert_eq!(target.peek(0x8000), 0, "Nothing is written when a record is blocked");
+    }
+}
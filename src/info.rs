@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::layout::{ATTRIBUTES_LEN, SCREEN_LEN};
+use crate::screen::fnv1a;
+use crate::{SnaError, SnapshotHeader, SnapshotType, MEM_16K, MEM_48K};
+
+const HEADER_SIZE: usize = 27;
+const EXTENSION_SIZE: usize = 4;
+
+/// The layout of a .sna file, told apart by its size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnaVariant {
+    Sna48,              // 49179 bytes
+    Sna128,             // 131103 bytes, paged bank 0, 1, 3, 4, 6 or 7
+    Sna128Duplicated,   // 147487 bytes, paged bank 2 or 5 stored twice
+    Sna256,             // 262175 bytes, Sna128 followed by Scorpion banks 8 to 15
+    Sna256Duplicated,   // 278559 bytes, Sna128Duplicated followed by Scorpion banks 8 to 15
+}
+
+/// A quick look at a snapshot file, read without loading its memory.
+#[derive(Clone, Copy)]
+pub struct SnapshotInfo {
+    pub snapshot_type: SnapshotType,
+    pub variant: SnaVariant,
+    pub header: SnapshotHeader,
+    pub pc: Option<u16>,        // None for a 48K snapshot whose stack is not in RAM
+    pub x7ffd: Option<u8>,      // 128K snapshots only
+    pub border: u8,
+    pub screen_hash: u64,       // the same value as Snapshot::screen_hash
+}
+
+impl SnapshotHeader {
+    /// from_bytes reads a header from the 27 byte little-endian .sna layout.
+    pub fn from_bytes(bytes: &[u8; 27]) -> SnapshotHeader {
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        SnapshotHeader {
+            i: bytes[0],
+            hl_prime: word(1),
+            de_prime: word(3),
+            bc_prime: word(5),
+            af_prime: word(7),
+            hl: word(9),
+            de: word(11),
+            bc: word(13),
+            iy: word(15),
+            ix: word(17),
+            interrupt: bytes[19],
+            r: bytes[20],
+            af: word(21),
+            sp: word(23),
+            int_mode: bytes[25],
+            border_color: bytes[26],
+        }
+    }
+}
+
+impl SnapshotInfo {
+    /// probe reads only the header, the PC and the visible screen of a .sna file,
+    /// which is much cheaper than loading the whole snapshot when scanning large
+    /// collections. Returns SnaError::InvalidSize if the file size is not a .sna size.
+    pub fn probe<P: AsRef<Path>>(path: P) -> Result<SnapshotInfo, SnaError> {
+        SnapshotInfo::probe_reader(File::open(path)?)
+    }
+
+    /// probe_reader is probe for any seekable reader holding a .sna file.
+    pub fn probe_reader<R: Read + Seek>(mut reader: R) -> Result<SnapshotInfo, SnaError> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        let (snapshot_type, variant) = match len {
+            49179 => (SnapshotType::Snapshot48, SnaVariant::Sna48),
+            131103 => (SnapshotType::Snapshot128, SnaVariant::Sna128),
+            147487 => (SnapshotType::Snapshot128, SnaVariant::Sna128Duplicated),
+            262175 => (SnapshotType::Snapshot128, SnaVariant::Sna256),
+            278559 => (SnapshotType::Snapshot128, SnaVariant::Sna256Duplicated),
+            _ => return Err(SnaError::InvalidSize(len as usize)),
+        };
+
+        let mut bytes = [0u8; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut bytes)?;
+        let header = SnapshotHeader::from_bytes(&bytes);
+
+        // offset in the file of a mapped address in the first 48K of memory
+        let ram = |address: u16| (HEADER_SIZE + address as usize - 0x4000) as u64;
+        let (pc, x7ffd, screen) = match snapshot_type {
+            SnapshotType::Snapshot48 => {
+                let sp = { header.sp };
+                let pc = if (0x4000..0xFFFF).contains(&sp) {
+                    let mut word = [0u8; 2];
+                    reader.seek(SeekFrom::Start(ram(sp)))?;
+                    reader.read_exact(&mut word)?;
+                    Some(u16::from_le_bytes(word))
+                } else {
+                    None
+                };
+                (pc, None, ram(0x4000))
+            }
+            SnapshotType::Snapshot128 => {
+                let mut extension = [0u8; EXTENSION_SIZE];
+                reader.seek(SeekFrom::Start((HEADER_SIZE + MEM_48K) as u64))?;
+                reader.read_exact(&mut extension)?;
+                let x7ffd = extension[2];
+                let paged = (x7ffd & 0x07) as usize;
+                let screen = if x7ffd & 0x08 == 0 {
+                    ram(0x4000)
+                } else if paged == 7 {
+                    ram(0xC000)
+                } else {
+                    // bank 7 is among the remaining banks, stored in ascending order without the paged bank
+                    let index = [0, 1, 3, 4, 6].iter().filter(|&&bank| bank != paged).count();
+                    (HEADER_SIZE + MEM_48K + EXTENSION_SIZE + index * MEM_16K) as u64
+                };
+                (Some(u16::from_le_bytes([extension[0], extension[1]])), Some(x7ffd), screen)
+            }
+        };
+
+        let mut screen_bytes = vec![0u8; (SCREEN_LEN + ATTRIBUTES_LEN) as usize];
+        reader.seek(SeekFrom::Start(screen))?;
+        reader.read_exact(&mut screen_bytes)?;
+
+        Ok(SnapshotInfo {
+            snapshot_type,
+            variant,
+            header,
+            pc,
+            x7ffd,
+            border: header.border_color,
+            screen_hash: fnv1a(&screen_bytes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Snapshot;
+
+    #[test]
+    fn test_probe_matches_full_parse() {
+        for name in ["48k.sna", "128k.sna"] {
+            let info = SnapshotInfo::probe(name).expect("Failed to probe snapshot");
+            let snapshot = Snapshot::try_from(std::fs::read(name).expect("Failed to read snapshot file")).expect("Failed to parse snapshot");
+            assert_eq!(info.snapshot_type, snapshot.snapshot_type);
+            assert_eq!(info.header.to_bytes(), snapshot.header.to_bytes());
+            assert_eq!(info.screen_hash, snapshot.screen_hash(), "Screen hash differs for {}", name);
+            assert_eq!(info.border, snapshot.header.border_color);
+        }
+        assert_eq!(SnapshotInfo::probe("48k.sna").map(|info| info.variant).ok(), Some(SnaVariant::Sna48));
+        assert!(matches!(SnapshotInfo::probe_reader(std::io::Cursor::new(vec![0u8; 100])), Err(SnaError::InvalidSize(100))));
+    }
+
+    #[test]
+    fn test_probe_shadow_screen() {
+        for paged in [0, 5, 7] {
+            let mut snapshot = crate::fixtures::generate(SnapshotType::Snapshot128, Default::default());
+            snapshot.write_0x7ffd(0x08 | paged).unwrap();
+            let mut bin = Vec::new();
+            snapshot.write_to(&mut bin).expect("Failed to write snapshot");
+            let info = SnapshotInfo::probe_reader(std::io::Cursor::new(bin)).expect("Failed to probe snapshot");
+            assert_eq!(info.screen_hash, snapshot.screen_hash(), "Shadow screen hash differs with bank {} paged", paged);
+            assert_eq!(info.pc, Some(crate::fixtures::FIXTURE_PC));
+            assert_eq!(info.variant, if paged == 5 { SnaVariant::Sna128Duplicated } else { SnaVariant::Sna128 });
+        }
+    }
+}
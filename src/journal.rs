@@ -0,0 +1,504 @@
+// An undo/redo journal of the changes made to a snapshot, for editors that let the
+// user experiment. Writes to memory through poke, poke_slice, bank_poke, import_bank
+// and the screen editing methods, and changes of paging through the paging ports, are
+// recorded once it is started. Edits made of several writes, such as patches, hooks
+// and set_sysvars, are recorded as one change, as are the writes made through
+// banks_mut. Writes through IndexMut or straight to the banks field are not, as the
+// journal cannot see them. Each change is stamped with
+// the time it was made and can be labelled, so a session can be exported as JSON to be
+// audited or replayed onto another copy of the snapshot.
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::json::Value;
+use crate::{Bank, SnaError, Snapshot, MEM_16K};
+
+/// Bytes written to a bank by one change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BankEdit {
+    pub bank: usize,
+    pub offset: u16,    // the offset within the bank of the first byte
+    pub old: Vec<u8>,   // the bytes before the write
+    pub new: Vec<u8>,   // the bytes written
+}
+
+/// A change recorded in the journal, undone and redone as a whole.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Memory(Vec<BankEdit>),                              // one write, split by the banks it touched
+    Paging { old: (u8, Option<u8>), new: (u8, Option<u8>) }, // 0x7FFD and 0x1FFD before and after
+    Group(Vec<Change>),                                 // the writes and paging of one edit, in order
+}
+
+// The journal is shared between clones, as banks are, and copied by the first of them
+// to record a change, so cloning an edited snapshot stays cheap.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Journal {
+    done: Vec<Change>,              // changes in the order they were made
+    stamps: Vec<Stamp>,             // when each change in done was made, and its label
+    undone: Vec<(Change, Stamp)>,   // changes undone, the most recent last, until another is made
+}
+
+// when a change was made, in milliseconds since the Unix epoch, and what it was for
+#[derive(Clone, Debug, Default)]
+struct Stamp {
+    time: u64,
+    label: Option<String>,
+}
+
+impl Stamp {
+    fn now() -> Stamp {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        Stamp { time, label: None }
+    }
+}
+
+impl Snapshot {
+    /// start_journal starts recording changes so they can be undone, discarding any
+    /// recorded before.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Arc::default());
+    }
+
+    /// stop_journal stops recording changes and discards those recorded.
+    pub fn stop_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// is_journaling returns whether changes are being recorded.
+    pub fn is_journaling(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// changes returns the changes that can be undone, oldest first. It is empty if the
+    /// journal has not been started.
+    pub fn changes(&self) -> &[Change] {
+        self.journal.as_ref().map_or(&[], |journal| &journal.done)
+    }
+
+    /// undo reverts the most recent change, returning false if there is none. Memory is
+    /// restored whether or not it is paged in, and guards are not consulted.
+    pub fn undo(&mut self) -> bool {
+        let Some(journal) = self.journal.as_mut().filter(|journal| !journal.done.is_empty()) else {
+            return false;
+        };
+        let journal = Arc::make_mut(journal);
+        let (change, stamp) = (journal.done.pop(), journal.stamps.pop());
+        let entry = (change.expect("The journal is not empty"), stamp.unwrap_or_default());
+        self.apply(&entry.0, true);
+        Arc::make_mut(self.journal.as_mut().expect("The journal was just used")).undone.push(entry);
+        true
+    }
+
+    /// redo makes the most recently undone change again, returning false if there is
+    /// none. Making any other change discards the changes that could be redone.
+    pub fn redo(&mut self) -> bool {
+        let Some((change, stamp)) = self.journal.as_mut().and_then(|journal| Arc::make_mut(journal).undone.pop()) else {
+            return false;
+        };
+        self.apply(&change, false);
+        let journal = Arc::make_mut(self.journal.as_mut().expect("The journal was just used"));
+        journal.done.push(change);
+        journal.stamps.push(stamp);
+        true
+    }
+
+    /// label_change labels the most recent change, such as "infinite lives", so that it
+    /// can be told apart in an exported journal. Returns false if there is no change to
+    /// label.
+    pub fn label_change(&mut self, label: &str) -> bool {
+        match self.journal.as_mut().filter(|journal| !journal.stamps.is_empty()) {
+            Some(journal) => {
+                let stamp = Arc::make_mut(journal).stamps.last_mut().expect("The journal is not empty");
+                stamp.label = Some(label.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// export_journal returns the changes that can be undone as JSON, oldest first, each
+    /// with the time it was made in milliseconds since the Unix epoch and its label if it
+    /// has one. Memory changes list the bytes before and after each write as hex, so the
+    /// session can be audited, or replayed onto another copy of the snapshot with
+    /// replay_journal.
+    pub fn export_journal(&self) -> String {
+        let stamps = self.journal.as_ref().map_or(&[][..], |journal| &journal.stamps);
+        let changes = self.changes().iter().zip(stamps).map(|(change, stamp)| {
+            let mut members = vec![("time".to_string(), Value::Number(stamp.time as f64))];
+            if let Some(label) = &stamp.label {
+                members.push(("label".to_string(), Value::String(label.clone())));
+            }
+            members.push(("change".to_string(), change_to_json(change)));
+            Value::Object(members)
+        }).collect();
+        Value::Object(vec![("changes".to_string(), Value::Array(changes))]).to_string()
+    }
+
+    /// replay_journal makes the changes in JSON written by export_journal, returning the
+    /// number made. Memory changes write their new bytes whatever the memory holds, and
+    /// paging changes write the new port values even if paging is locked. Every change is
+    /// checked before any is made, so on error the snapshot is unchanged. If the journal
+    /// is started, each change is recorded with its label. Returns
+    /// SnaError::InvalidFormat if the JSON is not an exported journal,
+    /// SnaError::BankOutOfRange if a change writes a bank the snapshot lacks, or
+    /// SnaError::Not128K if it changes the paging of a 48K snapshot.
+    pub fn replay_journal(&mut self, json: &str) -> Result<usize, SnaError> {
+        let invalid = || SnaError::InvalidFormat("Invalid journal".to_string());
+        let value = Value::parse(json).ok_or_else(invalid)?;
+        let entries = value.get("changes").and_then(Value::as_array).ok_or_else(invalid)?;
+        let changes = entries.iter().map(|entry| {
+            let change = change_from_json(entry.get("change").ok_or_else(invalid)?).ok_or_else(invalid)?;
+            self.check_change(&change)?;
+            Ok((change, entry.get("label").and_then(Value::as_str)))
+        }).collect::<Result<Vec<_>, SnaError>>()?;
+
+        for (change, label) in &changes {
+            self.grouped(|snapshot| snapshot.replay(change));
+            if let Some(label) = label {
+                self.label_change(label);
+            }
+        }
+        Ok(changes.len())
+    }
+
+    // checks that a replayed change fits the snapshot
+    fn check_change(&self, change: &Change) -> Result<(), SnaError> {
+        match change {
+            Change::Memory(edits) => edits.iter().try_for_each(|edit| {
+                self.check_bank(edit.bank)?;
+                match edit.offset as usize + edit.new.len() {
+                    end if end > MEM_16K => Err(SnaError::InvalidLength { len: end, max: MEM_16K }),
+                    _ => Ok(()),
+                }
+            }),
+            Change::Paging { .. } if self.extension.is_none() => Err(SnaError::Not128K),
+            Change::Paging { .. } => Ok(()),
+            Change::Group(changes) => changes.iter().try_for_each(|change| self.check_change(change)),
+        }
+    }
+
+    // makes a change checked by check_change, recording it as new changes are
+    fn replay(&mut self, change: &Change) {
+        match change {
+            Change::Memory(edits) => {
+                let started: Vec<BankEdit> = edits.iter()
+                    .filter_map(|edit| self.start_edit(edit.bank, edit.offset as usize..edit.offset as usize + edit.new.len()))
+                    .collect();
+                for edit in edits {
+                    let start = edit.offset as usize;
+                    self.banks[edit.bank][start..start + edit.new.len()].copy_from_slice(&edit.new);
+                }
+                self.finish_edits(started);
+            }
+            Change::Paging { new, .. } => self.set_paging(new.0, new.1),
+            Change::Group(changes) => changes.iter().for_each(|change| self.replay(change)),
+        }
+    }
+
+    // starts recording a write to a range of a bank by taking the bytes it will
+    // replace, or returns None if the journal has not been started
+    #[inline]
+    pub(crate) fn start_edit(&self, bank: usize, range: Range<usize>) -> Option<BankEdit> {
+        self.journal.as_ref()?;
+        Some(BankEdit { bank, offset: range.start as u16, old: self.banks[bank][range].to_vec(), new: Vec::new() })
+    }
+
+    // starts recording the bytes of a bank that differ from a copy of it taken before
+    // it was written, from the first changed byte to the last, or returns None if none
+    // have changed
+    pub(crate) fn bank_edit(&self, bank: usize, old: &Bank) -> Option<BankEdit> {
+        let new = &self.banks[bank];
+        let first = old.iter().zip(new.iter()).position(|(old, new)| old != new)?;
+        let last = old.iter().zip(new.iter()).rposition(|(old, new)| old != new)?;
+        Some(BankEdit { bank, offset: first as u16, old: old[first..=last].to_vec(), new: Vec::new() })
+    }
+
+    // finishes recording the writes begun with start_edit as one change, once they
+    // have been made
+    #[inline]
+    pub(crate) fn finish_edits<I: IntoIterator<Item = BankEdit>>(&mut self, edits: I) {
+        let edits: Vec<BankEdit> = edits.into_iter().map(|mut edit| {
+            let start = edit.offset as usize;
+            edit.new = self.banks[edit.bank][start..start + edit.old.len()].to_vec();
+            edit
+        }).collect();
+        if !edits.is_empty() {
+            self.record(Change::Memory(edits));
+        }
+    }
+
+    // runs an edit made of several writes, recording them as one change so that the
+    // edit is undone in one step
+    pub(crate) fn grouped<R>(&mut self, edit: impl FnOnce(&mut Snapshot) -> R) -> R {
+        let start = self.changes().len();
+        let result = edit(self);
+        if let Some(journal) = self.journal.as_mut().filter(|journal| journal.done.len() > start + 1) {
+            let journal = Arc::make_mut(journal);
+            let changes = journal.done.split_off(start);
+            journal.stamps.truncate(start + 1);
+            let change = if changes.iter().all(|change| matches!(change, Change::Memory(_))) {
+                Change::Memory(changes.into_iter().flat_map(|change| match change {
+                    Change::Memory(edits) => edits,
+                    _ => unreachable!("Only memory changes are merged"),
+                }).collect())
+            } else {
+                Change::Group(changes)
+            };
+            journal.done.push(change);
+        }
+        result
+    }
+
+    // replaces the banks with shared copies of others, recording the banks that change,
+    // or discarding the changes recorded so far if the number of banks changes
+    pub(crate) fn replace_banks(&mut self, banks: &[Bank]) {
+        if self.banks.len() != banks.len() {
+            if self.journal.is_some() {
+                self.start_journal();
+            }
+        } else {
+            let edits: Vec<BankEdit> = (0..banks.len())
+                .filter(|&bank| self.banks[bank] != banks[bank])
+                .filter_map(|bank| self.start_edit(bank, 0..MEM_16K).map(|edit| BankEdit { new: banks[bank].to_vec(), ..edit }))
+                .collect();
+            if !edits.is_empty() {
+                self.record(Change::Memory(edits));
+            }
+        }
+        self.banks = banks.to_vec();
+    }
+
+    // changes the paging ports' registers and remaps memory, recording the change
+    pub(crate) fn set_paging(&mut self, x7ffd: u8, x1ffd: Option<u8>) {
+        let old = self.paging_registers();
+        if old != (x7ffd, x1ffd) {
+            self.record(Change::Paging { old, new: (x7ffd, x1ffd) });
+        }
+        self.apply_paging((x7ffd, x1ffd));
+    }
+
+    // the values last written to 0x7FFD and 0x1FFD
+    pub(crate) fn paging_registers(&self) -> (u8, Option<u8>) {
+        (self.extension.as_ref().map_or(0, |extension| extension.x7ffd), self.x1ffd)
+    }
+
+    fn apply_paging(&mut self, (x7ffd, x1ffd): (u8, Option<u8>)) {
+        if let Some(extension) = self.extension.as_mut() {
+            extension.x7ffd = x7ffd;
+        }
+        self.x1ffd = x1ffd;
+        self.mapping[2] = self.paged_bank();
+        self.remap();
+    }
+
+    fn record(&mut self, change: Change) {
+        if let Some(journal) = self.journal.as_mut() {
+            let journal = Arc::make_mut(journal);
+            journal.done.push(change);
+            journal.stamps.push(Stamp::now());
+            journal.undone.clear();
+        }
+    }
+
+    // puts back the state before a change, or after it
+    fn apply(&mut self, change: &Change, undo: bool) {
+        match change {
+            Change::Memory(edits) if undo => {
+                for edit in edits.iter().rev() {
+                    let start = edit.offset as usize;
+                    self.banks[edit.bank][start..start + edit.old.len()].copy_from_slice(&edit.old);
+                }
+            }
+            Change::Memory(edits) => {
+                for edit in edits {
+                    let start = edit.offset as usize;
+                    self.banks[edit.bank][start..start + edit.new.len()].copy_from_slice(&edit.new);
+                }
+            }
+            Change::Paging { old, new } => self.apply_paging(if undo { *old } else { *new }),
+            Change::Group(changes) if undo => changes.iter().rev().for_each(|change| self.apply(change, true)),
+            Change::Group(changes) => changes.iter().for_each(|change| self.apply(change, false)),
+        }
+    }
+}
+
+fn change_to_json(change: &Change) -> Value {
+    let member = |key: &str, value: Value| (key.to_string(), value);
+    let registers = |(x7ffd, x1ffd): (u8, Option<u8>)| Value::Object(vec![
+        member("x7ffd", Value::Number(x7ffd as f64)),
+        member("x1ffd", x1ffd.map_or(Value::Null, |x1ffd| Value::Number(x1ffd as f64))),
+    ]);
+    match change {
+        Change::Memory(edits) => Value::Object(vec![
+            member("type", Value::String("memory".to_string())),
+            member("edits", Value::Array(edits.iter().map(|edit| Value::Object(vec![
+                member("bank", Value::Number(edit.bank as f64)),
+                member("offset", Value::Number(edit.offset as f64)),
+                member("old", Value::String(to_hex(&edit.old))),
+                member("new", Value::String(to_hex(&edit.new))),
+            ])).collect())),
+        ]),
+        Change::Paging { old, new } => Value::Object(vec![
+            member("type", Value::String("paging".to_string())),
+            member("old", registers(*old)),
+            member("new", registers(*new)),
+        ]),
+        Change::Group(changes) => Value::Object(vec![
+            member("type", Value::String("group".to_string())),
+            member("changes", Value::Array(changes.iter().map(change_to_json).collect())),
+        ]),
+    }
+}
+
+fn change_from_json(value: &Value) -> Option<Change> {
+    let number = |value: &Value, key: &str, max: f64| {
+        value.get(key)?.as_f64().filter(|n| n.fract() == 0.0 && (0.0..=max).contains(n))
+    };
+    let registers = |value: Option<&Value>| {
+        let value = value?;
+        let x1ffd = match value.get("x1ffd")? {
+            Value::Null => None,
+            _ => Some(number(value, "x1ffd", 255.0)? as u8),
+        };
+        Some((number(value, "x7ffd", 255.0)? as u8, x1ffd))
+    };
+    match value.get("type")?.as_str()? {
+        "memory" => value.get("edits")?.as_array()?.iter().map(|edit| {
+            let (old, new) = (from_hex(edit.get("old")?.as_str()?)?, from_hex(edit.get("new")?.as_str()?)?);
+            Some(BankEdit { bank: number(edit, "bank", usize::MAX as f64)? as usize, offset: number(edit, "offset", 0x3FFF as f64)? as u16, old, new })
+        }).collect::<Option<Vec<_>>>().map(Change::Memory),
+        "paging" => Some(Change::Paging { old: registers(value.get("old"))?, new: registers(value.get("new"))? }),
+        "group" => value.get("changes")?.as_array()?.iter().map(change_from_json).collect::<Option<Vec<_>>>().map(Change::Group),
+        _ => None,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Attribute;
+
+    #[test]
+    fn test_journal() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.poke(0x8000, 0x11).unwrap();
+        assert!(snapshot.changes().is_empty() && !snapshot.undo(), "Nothing is recorded until the journal is started");
+
+        snapshot.start_journal();
+        snapshot.poke(0x8000, 0x22).unwrap();
+        snapshot.poke_slice(0xBFFF, &[0x33, 0x44]).unwrap();
+        snapshot.write_0x7ffd(0x03).unwrap();
+        snapshot.bank_poke(0, 0x0000, 0x55).unwrap();
+        assert!(snapshot.poke(0x0000, 0x66).is_err());
+        assert_eq!(snapshot.changes().len(), 4, "Failed writes are not recorded");
+        assert_eq!(snapshot.changes()[1], Change::Memory(vec![
+            BankEdit { bank: 2, offset: 0x3FFF, old: vec![0], new: vec![0x33] },
+            BankEdit { bank: 0, offset: 0x0000, old: vec![0], new: vec![0x44] },
+        ]), "A write across banks is one change");
+
+        assert!(snapshot.undo() && snapshot.undo());
+        assert_eq!((snapshot.mapping[2], snapshot.bank_peek(0, 0).unwrap()), (0, 0x44), "Paging and the bank write are undone");
+        assert!(snapshot.undo());
+        assert_eq!((snapshot.peek(0xBFFF), snapshot.peek(0xC000)), (0, 0));
+        assert!(snapshot.redo());
+        assert_eq!((snapshot.peek(0xBFFF), snapshot.peek(0xC000)), (0x33, 0x44));
+
+        snapshot.set_attr(0, 0, Attribute::from_byte(0x38)).unwrap();
+        assert!(!snapshot.redo(), "A new change discards the changes that could be redone");
+        assert!(snapshot.undo() && snapshot.undo() && snapshot.undo());
+        assert_eq!(snapshot.peek(0x8000), 0x11);
+        assert!(!snapshot.undo());
+        assert_eq!(snapshot.changes().len(), 0);
+
+        snapshot.poke(0x8000, 0x22).unwrap();
+        let mut variant = snapshot.clone();
+        assert!(Arc::ptr_eq(snapshot.journal.as_ref().unwrap(), variant.journal.as_ref().unwrap()), "Clones share the journal");
+        variant.undo();
+        assert_eq!((snapshot.changes().len(), variant.changes().len()), (1, 0), "Undoing in a clone leaves the original alone");
+        assert_eq!((snapshot.peek(0x8000), variant.peek(0x8000)), (0x22, 0x11));
+
+        snapshot.stop_journal();
+        assert!(!snapshot.is_journaling() && !snapshot.redo());
+    }
+
+    #[test]
+    fn test_grouped_edits() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.start_journal();
+        crate::patch!(snapshot, 0x8000 => [1, 2, 3, 4], 0x9000 => w 0x1234).unwrap();
+        let mut sysvars = snapshot.sysvars();
+        sysvars.udg = 0xFF58;
+        snapshot.set_sysvars(&sysvars).unwrap();
+        let hook = snapshot.inject_hook_at(crate::Addr(0x8000), 3, &[0x34], crate::Addr(0xA000)).unwrap();
+        snapshot.remove_hook(&hook).unwrap();
+        assert_eq!(snapshot.changes().len(), 4, "Each edit is one change");
+
+        assert!(snapshot.undo() && snapshot.undo() && snapshot.undo());
+        assert_eq!((snapshot.peek(0x8000), snapshot.peek_word(0x9000).unwrap()), (1, 0x1234));
+        assert!(snapshot.undo());
+        assert_eq!((snapshot.peek(0x8000), snapshot.peek_word(0x9000).unwrap()), (0, 0), "The whole patch is undone");
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.start_journal();
+        snapshot.grouped(|snapshot| {
+            snapshot.write_0x7ffd(0x01).unwrap();
+            snapshot.poke(0xC000, 0x11).unwrap();
+        });
+        assert!(matches!(snapshot.changes(), [Change::Group(changes)] if changes.len() == 2));
+        assert!(snapshot.undo());
+        assert_eq!((snapshot.mapping[2], snapshot.bank_peek(1, 0).unwrap()), (0, 0), "Paging and memory are undone together");
+        assert!(snapshot.redo());
+        assert_eq!(snapshot.peek(0xC000), 0x11);
+    }
+
+    #[test]
+    fn test_export_journal() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.start_journal();
+        snapshot.poke(0x8000, 0x22).unwrap();
+        assert!(snapshot.label_change("lives"));
+        snapshot.load_bin(0xBFFF, &[0x33, 0x44]).unwrap();
+        snapshot.write_0x7ffd(0x03).unwrap();
+        snapshot.grouped(|snapshot| {
+            snapshot.write_0x7ffd(0x01).unwrap();
+            snapshot.poke(0xC000, 0x55).unwrap();
+        });
+        let json = snapshot.export_journal();
+        assert!(json.contains(r#""label":"lives","change":{"type":"memory","edits":[{"bank":2,"offset":0,"old":"00","new":"22"}]}"#), "{}", json);
+        assert!(json.contains(r#"{"type":"paging","old":{"x7ffd":0,"x1ffd":null},"new":{"x7ffd":3,"x1ffd":null}}"#), "{}", json);
+
+        let mut copy = Snapshot::new_128k();
+        copy.start_journal();
+        assert_eq!(copy.replay_journal(&json).unwrap(), 4);
+        assert!((0..8).all(|bank| copy.banks[bank] == snapshot.banks[bank]), "Replayed memory differs");
+        assert_eq!(copy.mapping[2], 1);
+        assert_eq!(copy.export_journal().matches(r#""label":"lives""#).count(), 1, "Labels are replayed");
+        assert!(copy.undo() && copy.undo());
+        assert_eq!(copy.mapping[2], 0);
+
+        assert!(snapshot.undo());
+        assert!(!snapshot.export_journal().contains(r#""type":"group""#), "Undone changes are not exported");
+        assert!(snapshot.redo());
+        assert_eq!(snapshot.export_journal(), json, "Redone changes keep their time and label");
+
+        let mut small = Snapshot::new_48k();
+        assert!(matches!(small.replay_journal(&json), Err(SnaError::Not128K)));
+        assert_eq!(small.peek(0x8000), 0, "Nothing is replayed when a change does not fit");
+        assert!(matches!(small.replay_journal("{}"), Err(SnaError::InvalidFormat(_))));
+        assert!(!small.label_change("nothing"), "There is no change to label");
+    }
+}
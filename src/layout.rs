@@ -0,0 +1,146 @@
+//! Memory layout constants and queries for the machines a snapshot can describe,
+//! so code working with snapshots does not need to hard-code addresses.
+
+use std::ops::Range;
+
+use crate::{BankId, Machine, Snapshot, SnapshotType};
+
+pub use crate::{MEM_16K, MEM_1K, MEM_48K};
+
+/// Start of the ROM.
+pub const ROM_START: u16 = 0x0000;
+/// Start of RAM, the first address stored in a snapshot.
+pub const RAM_START: u16 = 0x4000;
+/// Start of the display file (pixel data).
+pub const SCREEN_START: u16 = 0x4000;
+/// Length of the display file in bytes.
+pub const SCREEN_LEN: u16 = 0x1800;
+/// Start of the attribute file.
+pub const ATTRIBUTES_START: u16 = 0x5800;
+/// Length of the attribute file in bytes.
+pub const ATTRIBUTES_LEN: u16 = 0x0300;
+/// Start of the printer buffer.
+pub const PRINTER_BUFFER_START: u16 = 0x5B00;
+/// Start of the system variables.
+pub const SYSVARS_START: u16 = 0x5C00;
+/// End (exclusive) of the system variables of the 48K ROM.
+pub const SYSVARS_END: u16 = 0x5CB6;
+/// Size of a memory window, the unit that banks are paged in.
+pub const WINDOW_SIZE: u16 = 0x4000;
+
+/// Address range of the display file.
+pub const SCREEN: Range<u16> = SCREEN_START..SCREEN_START + SCREEN_LEN;
+/// Address range of the attribute file.
+pub const ATTRIBUTES: Range<u16> = ATTRIBUTES_START..ATTRIBUTES_START + ATTRIBUTES_LEN;
+/// Address range of the printer buffer.
+pub const PRINTER_BUFFER: Range<u16> = PRINTER_BUFFER_START..SYSVARS_START;
+/// Address range of the system variables.
+pub const SYSVARS: Range<u16> = SYSVARS_START..SYSVARS_END;
+
+/// The memory layout of a snapshot: the machine it is for, how many banks it has and
+/// which bank is visible in each 16K window. Window 0 holds the ROM, which snapshots do
+/// not store, except when +2A/+3 special paging maps RAM there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryLayout {
+    pub machine: Machine,
+    pub bank_count: usize,
+    pub windows: [Option<BankId>; 4],
+}
+
+impl MemoryLayout {
+    /// for_machine returns the layout of the given machine freshly reset.
+    pub fn for_machine(machine: Machine) -> MemoryLayout {
+        let windows = match machine.snapshot_type() {
+            SnapshotType::Snapshot48 => [None, Some(BankId(0)), Some(BankId(1)), Some(BankId(2))],
+            SnapshotType::Snapshot128 => [None, Some(BankId(5)), Some(BankId(2)), Some(BankId(0))],
+        };
+        MemoryLayout { machine, bank_count: machine.bank_count(), windows }
+    }
+
+    /// for_type returns the layout of a freshly reset 48K or 128K of the given type.
+    pub fn for_type(snapshot_type: SnapshotType) -> MemoryLayout {
+        match snapshot_type {
+            SnapshotType::Snapshot48 => MemoryLayout::for_machine(Machine::Spectrum48),
+            SnapshotType::Snapshot128 => MemoryLayout::for_machine(Machine::Spectrum128),
+        }
+    }
+
+    /// window_range returns the address range of a window, 0 to 3.
+    pub fn window_range(window: usize) -> Range<u32> {
+        let start = window as u32 * WINDOW_SIZE as u32;
+        start..start + WINDOW_SIZE as u32
+    }
+
+    /// bank_at returns the bank visible at an address, or None for ROM.
+    pub fn bank_at(&self, address: u16) -> Option<BankId> {
+        self.windows[(address / WINDOW_SIZE) as usize]
+    }
+
+    /// is_rom returns whether the address is in ROM.
+    pub fn is_rom(&self, address: u16) -> bool {
+        self.bank_at(address).is_none()
+    }
+
+    /// is_screen returns whether the address is in the display or attribute file.
+    pub fn is_screen(&self, address: u16) -> bool {
+        (SCREEN_START..ATTRIBUTES.end).contains(&address)
+    }
+
+    /// is_contended returns whether the bank is contended on this machine, see
+    /// [`Machine::is_contended`].
+    pub fn is_contended(&self, bank: BankId) -> bool {
+        self.machine.is_contended(bank.0)
+    }
+
+    /// screen_banks returns the banks that can be displayed as the screen.
+    pub fn screen_banks(&self) -> &'static [BankId] {
+        match self.machine.snapshot_type() {
+            SnapshotType::Snapshot48 => &[BankId(0)],
+            SnapshotType::Snapshot128 => &[BankId(5), BankId(7)],
+        }
+    }
+}
+
+impl Snapshot {
+    /// layout returns the snapshot's memory layout with its current paging.
+    pub fn layout(&self) -> MemoryLayout {
+        let mut layout = MemoryLayout::for_machine(self.machine());
+        for (window, bank) in self.mapping.iter().enumerate() {
+            layout.windows[window + 1] = Some(BankId(*bank as usize));
+        }
+        if let Some(banks) = self.special_paging() {
+            layout.windows = banks.map(|bank| Some(BankId(bank as usize)));
+        }
+        layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let file = std::fs::File::open("128k.sna").expect("Failed to open snapshot file");
+        let snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        let layout = snapshot.layout();
+        assert_eq!(layout.bank_count, 8);
+        assert!(layout.is_rom(0x3FFF) && !layout.is_rom(RAM_START));
+        assert_eq!(layout.bank_at(0x4000), Some(BankId(5)));
+        assert_eq!(layout.bank_at(0xC000), Some(BankId(snapshot.mapping[2] as usize)));
+        assert!(layout.is_screen(0x5AFF) && !layout.is_screen(PRINTER_BUFFER_START));
+        assert_eq!(MemoryLayout::window_range(3), 0xC000..0x10000);
+
+        let layout = MemoryLayout::for_type(SnapshotType::Snapshot48);
+        assert_eq!(layout.bank_at(0xFFFF), Some(BankId(2)));
+        assert!(layout.is_contended(BankId(0)) && !layout.is_contended(BankId(1)));
+
+        let mut snapshot = Snapshot::new_plus3();
+        assert!(snapshot.layout().is_contended(BankId(4)) && !snapshot.layout().is_contended(BankId(1)), "The +3 contends banks 4 to 7");
+        snapshot.set_machine(Machine::Scorpion256).unwrap();
+        let layout = snapshot.layout();
+        assert_eq!(layout.bank_count, 16);
+        assert!(!layout.is_contended(BankId(5)), "The Scorpion has no contended memory");
+        assert_eq!(MemoryLayout::for_machine(Machine::Pentagon128).bank_count, 8);
+    }
+}
@@ -5,21 +5,120 @@
 // THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 // https://opensource.org/license/mit
 
+//! This module provides functionality to handle ZX Spectrum snapshots.
+//! It includes structures to represent the snapshot header, extension,
+//! and the snapshot itself. The snapshots can be created from binary data
+//! or from a file. The module also provides methods to peek and poke
+//! memory addresses within the snapshot, allowing for reading and writing
+//! of memory values as needed.
+
 use std::io::Read;
 use std::fs::File;
-
-/// This module provides functionality to handle ZX Spectrum snapshots.
-/// It includes structures to represent the snapshot header, extension,
-/// and the snapshot itself. The snapshots can be created from binary data
-/// or from a file. The module also provides methods to peek and poke
-/// memory addresses within the snapshot, allowing for reading and writing
-/// of memory values as needed.
-
-const MEM_1K: usize = 1024;
-const MEM_16K: usize = MEM_1K * 16;
-const MEM_48K: usize = MEM_1K * 48;
-
-#[derive(PartialEq,Debug)]
+use std::sync::Arc;
+
+mod access;
+mod addr;
+mod asm;
+mod bank;
+pub mod basic;
+mod builder;
+mod bus;
+pub mod charset;
+mod cpu;
+mod digest;
+pub mod disasm;
+mod error;
+#[cfg(feature = "exec")]
+pub mod exec;
+mod export;
+pub mod fixtures;
+mod flags;
+pub mod fuse;
+mod guard;
+mod json;
+pub mod layout;
+mod heatmap;
+mod heuristics;
+mod hook;
+mod ihex;
+mod info;
+mod journal;
+mod loader;
+mod machine;
+mod metadata;
+mod normalize;
+#[cfg(feature = "online")]
+pub mod online;
+mod paging;
+mod patch;
+mod peripherals;
+pub mod pok;
+mod raw;
+mod reader;
+mod regions;
+mod registers;
+mod rom;
+mod scan;
+mod search;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod screen;
+mod shared;
+mod slab;
+mod stack;
+mod summary;
+mod sysvars;
+pub mod trainer;
+mod transplant;
+pub mod tzx;
+mod usage;
+mod view;
+pub mod watch;
+mod writer;
+mod z80;
+pub mod zxfloat;
+pub use addr::{Addr, BankAddr};
+pub use asm::assemble;
+pub use bank::{Bank, BankId, BankInfo};
+pub use builder::{BuildError, SnapshotBuilder};
+pub use bus::MemoryBus;
+pub use cpu::CpuState;
+pub use error::SnaError;
+pub use export::Column;
+pub use flags::Flags;
+pub use guard::{Guard, GuardAction, GuardHit};
+pub use heatmap::{diff_heatmap, BankHeatmap};
+pub use hook::Hook;
+pub use info::{SnaVariant, SnapshotInfo};
+pub use journal::{BankEdit, Change};
+pub use loader::SnapshotLoader;
+pub use layout::MemoryLayout;
+pub use machine::Machine;
+pub use metadata::Metadata;
+pub use normalize::Repair;
+pub use paging::{PagingPort, PagingState, PagingWrite};
+pub use patch::Patch;
+pub use peripherals::{AyState, InputState, JoystickType, PeripheralState};
+pub use regions::{Region, RegionLocation, RegionValue, ValueType};
+pub use registers::{Register, RegisterPair};
+pub use search::{Pattern, PatternError};
+pub use shared::SharedSnapshot;
+pub use stack::SaveOptions;
+pub use sysvars::{SystemVariables, SYSVARS_LEN};
+pub use usage::{FreeRegion, FreeSource, MemoryUsage, MIN_ZERO_RUN};
+pub use view::PokeView;
+
+/// One kilobyte.
+pub const MEM_1K: usize = 1024;
+/// The size of a memory bank.
+pub const MEM_16K: usize = MEM_1K * 16;
+/// The size of the RAM of a 48K machine.
+pub const MEM_48K: usize = MEM_1K * 48;
+/// The size of the Z80 address space.
+pub const MEM_64K: usize = MEM_1K * 64;
+
+#[derive(PartialEq,Debug,Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SnapshotType {
     Snapshot48,
     Snapshot128,
@@ -34,6 +133,8 @@ pub enum SnapshotType {
 /// the snapshot was taken.
 /// The fields are represented in little-endian format, which is the
 /// standard for ZX Spectrum snapshots.
+#[derive(Clone,Copy,Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C,packed)]
 pub struct SnapshotHeader{
     pub i: u8,
@@ -54,32 +155,11 @@ pub struct SnapshotHeader{
     pub border_color: u8,
 }
 
-impl Default for SnapshotHeader {
-    fn default() -> Self {
-        SnapshotHeader {
-            i: 0,
-            hl_prime: 0,
-            de_prime: 0,
-            bc_prime: 0,
-            af_prime: 0,
-            hl: 0,
-            de: 0,
-            bc: 0,
-            iy: 0,
-            ix: 0,
-            interrupt: 0,
-            r: 0,
-            af: 0,
-            sp: 0,
-            int_mode: 0,
-            border_color: 0,
-        }
-    }
-}
-
 /// Represents an optional extension for the snapshot.
 /// This struct contains additional fields for the ZX Spectrum 128 snapshot.
 /// It includes the program counter, the 7FFD register, and the TR-DOS state
+#[derive(Clone,Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C,packed)]
 pub struct SnapshotExtension {
     pub pc: u16,
@@ -90,13 +170,32 @@ pub struct SnapshotExtension {
 /// Represents a snapshot of a ZX Spectrum state.
 /// This struct contains the snapshot type, header, optional extension,
 /// and a pointer to the memory block representing the snapshot.
+/// Cloning a snapshot is cheap: banks and the undo journal are shared between
+/// clones and only copied when first written to, so hundreds of variants of a
+/// snapshot can be kept in memory for comparison.
+#[derive(Clone)]
 #[repr(C)]
 pub struct Snapshot{
     pub snapshot_type: SnapshotType,            // type of snapshot (48K or 128K)
     pub header: SnapshotHeader,                 // snapshot header containing CPU state
     pub extension: Option<SnapshotExtension>,   // optional extension for ZX Spectrum 128 snapshots
-    pub banks: Vec<Vec<u8>>,                    // banks of memory
-    pub mapping: [u8; 3],
+    pub banks: Vec<Bank>,                       // banks of memory (copy-on-write)
+    pub ay: Option<AyState>,                    // optional AY-3-8912 sound chip state for 128K machines
+    pub input: Option<InputState>,              // optional keyboard matrix and joystick state
+    pub metadata: Option<Metadata>,             // optional provenance, saved in a sidecar file
+    mapping: [u8; 3],                           // banks mapped into 0x4000, 0x8000 and 0xC000 by port 0x7FFD (change via write_0x7ffd)
+    x1ffd: Option<u8>,                          // last value written to port 0x1FFD on a +2A/+3, which .sna files cannot hold
+    windows: [usize; 4],                        // bank index per 16K window of the address space, cached from mapping
+    regions: regions::RegionMap,                // named regions of game state
+    trainers: Vec<trainer::Trainer>,            // trainers applied with apply_trainer, saved in the sidecar
+    attachments: peripherals::AttachmentMap,    // serialized third-party state by chunk id
+    guards: guard::GuardList,                   // ranges of mapped memory whose writes are reported or blocked
+    guard_hits: Vec<GuardHit>,                  // writes into guarded ranges not yet taken
+    journal: Option<Arc<journal::Journal>>,     // changes that can be undone, once start_journal is called
+    roms: Vec<Bank>,                            // attached ROM images, 16K each (see attach_rom)
+    rom: usize,                                 // the ROM paged in at 0x0000, cached from the paging state
+    all_ram: bool,                              // whether special paging maps RAM at 0x0000, cached from x1ffd
+    model: Option<Machine>,                     // the model set with set_machine, otherwise inferred
 }
 
 impl Default for Snapshot {
@@ -106,96 +205,173 @@ impl Default for Snapshot {
             header: SnapshotHeader::default(),
             extension: None,
             banks: Vec::new(),
+            ay: None,
+            input: None,
+            metadata: None,
             mapping: [0u8; 3],
+            x1ffd: None,
+            windows: [0usize; 4],
+            regions: regions::RegionMap::new(),
+            trainers: Vec::new(),
+            attachments: peripherals::AttachmentMap::new(),
+            guards: guard::GuardList::new(),
+            guard_hits: Vec::new(),
+            journal: None,
+            roms: Vec::new(),
+            rom: 0,
+            all_ram: false,
+            model: None,
         }
     }
 }
 
 impl Snapshot {
+    /// is_rom returns whether an address is in ROM as the snapshot is currently paged,
+    /// so that writes to it fail: below 0x4000, unless +2A/+3 special paging has mapped
+    /// RAM there.
+    #[inline]
+    pub fn is_rom(&self, address: u16) -> bool {
+        address < 0x4000 && !self.all_ram
+    }
+
     /// poke writes a byte to the memory MAPPED to the given address.
-    /// If the address is less than 0x4000, it panics with an error message.
-    /// The address is expected to be in the range of 0x4000 to 0xFFFF.
-    pub fn poke(&mut self, address: u16, value: u8) {
-        if address < 0x4000 {
-            panic!("Attempted to poke at address < 0x4000, which is invalid.");
+    /// Returns SnaError::RomWrite if the address is less than 0x4000, unless +2A/+3
+    /// special paging has mapped RAM there.
+    #[inline]
+    pub fn poke(&mut self, address: u16, value: u8) -> Result<(), SnaError> {
+        if self.is_rom(address) {
+            return Err(SnaError::RomWrite(address));
+        }
+        if !self.guards.is_empty() {
+            self.check_guards(address, &[value])?;
         }
 
-        let bank_index = ((address >> 14) & 0x03 ) - 1;
-        self.banks[self.mapping[bank_index as usize] as usize][(address & 0x3FFF) as usize] = value;
+        let bank = self.windows[(address >> 14) as usize];
+        let offset = (address & 0x3FFF) as usize;
+        let edit = self.start_edit(bank, offset..offset + 1);
+        self.banks[bank].write(address, value);
+        self.finish_edits(edit);
+        Ok(())
     }
 
     /// peek reads a byte from the memory MAPPED to the given address.
-    /// If the address is less than 0x4000, it returns 0x
+    /// If the address is less than 0x4000, it reads the ROM paged in from an attached
+    /// ROM image, or returns 0xFF if none is attached, unless +2A/+3 special paging has
+    /// mapped RAM there.
+    #[inline]
     pub fn peek(&self, address: u16) -> u8 {
-        if address < 0x4000 {
-            return 0xFF;
+        if self.is_rom(address) {
+            return self.roms.get(self.rom).map_or(0xFF, |rom| rom.read(address));
         }
 
-        let bank_index = ((address >> 14) & 0x03 ) - 1;
-        self.banks[self.mapping[bank_index as usize] as usize][(address & 0x3FFF) as usize]
+        self.banks[self.windows[(address >> 14) as usize]].read(address)
     }
 
-    /// peek reads a byte from the memory MAPPED to the given address.
-    /// If the address is less than 0x4000, it returns 0x
-	pub fn peek_word(&self, address:u16) -> u16 {
-		if address == 0xFFFF {
-			panic!("Attempted to peek16 at address 0xFFFF, which is invalid.");
-		}
-		(self.peek(address) as u16) | ((self.peek(address+1) as u16) << 8)
-	}
+    /// peek_word reads a little-endian 16-bit value from the memory MAPPED to the given address.
+    /// Returns SnaError::AddressOverflow if the address is 0xFFFF.
+    pub fn peek_word(&self, address: u16) -> Result<u16, SnaError> {
+        if address == 0xFFFF {
+            return Err(SnaError::AddressOverflow { address, len: 2 });
+        }
+        Ok((self.peek(address) as u16) | ((self.peek(address + 1) as u16) << 8))
+    }
 
     /// poke_word writes a 16-bit value to the memory MAPPED to the given address.
-    /// If the address is 0xFFFF, it panics with an error message.
-    /// This is a little-endian write operation.
-    pub fn poke_word(&mut self, address:u16, value:u16) {
+    /// This is a little-endian write operation. Returns SnaError::AddressOverflow if
+    /// the address is 0xFFFF, or SnaError::RomWrite if either byte is in ROM, in which
+    /// case nothing is written.
+    pub fn poke_word(&mut self, address: u16, value: u16) -> Result<(), SnaError> {
         if address == 0xFFFF {
-            panic!("Attempted to poke16 at address 0xFFFF, which is invalid.");
+            return Err(SnaError::AddressOverflow { address, len: 2 });
+        }
+        if self.is_rom(address) {
+            return Err(SnaError::RomWrite(address));
         }
-        self.poke(address, (value & 0xFF) as u8);
-        self.poke(address + 1, ((value >> 8) & 0xFF) as u8);
+        self.poke_slice(address, &value.to_le_bytes())
     }
 
-    /// changes the bank that is mapped into 0xC000-0xCFFF when using peek (or the future poke) functions.
-    pub fn write_0x7ffd(&mut self, value: u8) {
-        if self.snapshot_type != SnapshotType::Snapshot128 {
-            panic!("Attempted to write to 0x7ffd on a 48K snapshot, which is invalid.");
+    /// write_0x7ffd writes to the 128K paging port as an OUT would: bits 0-2 choose the
+    /// bank mapped into 0xC000-0xFFFF, bit 3 the screen shown, bit 4 the ROM and bit 5
+    /// locks paging, after which writes are ignored until the machine is reset (see
+    /// set_paging_state). Returns SnaError::Not128K for 48K snapshots.
+    pub fn write_0x7ffd(&mut self, value: u8) -> Result<(), SnaError> {
+        let x7ffd = match (self.snapshot_type, self.extension.as_ref()) {
+            (SnapshotType::Snapshot128, Some(extension)) => extension.x7ffd,
+            _ => return Err(SnaError::Not128K),
+        };
+        if x7ffd & paging::LOCK != 0 {
+            return Ok(());
         }
-        self.extension.as_mut().expect("Extension is None").x7ffd = value;
-        self.mapping[2] = value & 0x07; // update the mapping based on the new value
+        self.set_paging(value, self.x1ffd); // updates the mapping based on the new value
+        Ok(())
     }
 
-    /// bank_peek reads a byte from the specified bank at the given address.
-    /// The bank index should be within the range of available banks.
-    /// The address is masked to ensure it is within the valid range for the bank.
-    /// If the bank index is out of bounds, it panics with an error message.
-    pub fn bank_peek(&self, bank: usize, address: u16) -> u8 {
+    /// remap refreshes the cached window table from the current mapping.
+    /// It must be called whenever the mapping changes so that peek and poke
+    /// resolve a window with a single lookup.
+    fn remap(&mut self) {
+        self.windows = [0, self.mapping[0] as usize, self.mapping[1] as usize, self.mapping[2] as usize];
+        let special = self.special_paging();
+        if let Some(banks) = special {
+            self.windows = banks.map(|bank| bank as usize);
+        }
+        self.all_ram = special.is_some();
+        self.rom = self.paged_rom();
+    }
+
+    /// compress_inactive_banks LZ4 compresses every bank that is not currently mapped
+    /// into the address space, returning the number of banks compressed.
+    /// Compressed banks are decompressed transparently the next time they are accessed,
+    /// so this can be called again once analysis of a snapshot has moved on.
+    #[cfg(feature = "compression")]
+    pub fn compress_inactive_banks(&mut self) -> usize {
+        let windows = self.layout().windows;
+        let mut count = 0;
+        for (index, bank) in self.banks.iter_mut().enumerate() {
+            if !windows.contains(&Some(BankId(index))) && !bank.is_compressed() {
+                bank.compress();
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// check_bank returns SnaError::BankOutOfRange if the snapshot has no such bank.
+    pub(crate) fn check_bank(&self, bank: usize) -> Result<(), SnaError> {
         if bank >= self.banks.len() {
-            panic!("Bank index out of bounds");
+            return Err(SnaError::BankOutOfRange { bank, banks: self.banks.len() });
         }
-        self.banks[bank][(address & 0x3FFF) as usize]
+        Ok(())
+    }
+
+    /// bank_peek reads a byte from the specified bank at the given address.
+    /// The address is masked to ensure it is within the valid range for the bank.
+    /// Returns SnaError::BankOutOfRange if the bank index is out of bounds.
+    pub fn bank_peek(&self, bank: usize, address: u16) -> Result<u8, SnaError> {
+        self.check_bank(bank)?;
+        Ok(self.banks[bank].read(address))
     }
 
     /// bank_poke writes a byte to the specified bank at the given address.
-    /// The bank index should be within the range of available banks.
     /// The address is masked to ensure it is within the valid range for the bank.
-    /// If the bank index is out of bounds, it panics with an error message.
+    /// Returns SnaError::BankOutOfRange if the bank index is out of bounds.
     /// This function is used to modify the contents of a specific bank in the snapshot.
-    pub fn bank_poke(&mut self, bank: usize, address: u16, value: u8) {
-        if bank >= self.banks.len() {
-            panic!("Bank index out of bounds");
-        }
-        self.banks[bank][(address & 0x3FFF) as usize] = value;
+    pub fn bank_poke(&mut self, bank: usize, address: u16, value: u8) -> Result<(), SnaError> {
+        self.check_bank(bank)?;
+        let offset = (address & 0x3FFF) as usize;
+        let edit = self.start_edit(bank, offset..offset + 1);
+        self.banks[bank].write(address, value);
+        self.finish_edits(edit);
+        Ok(())
     }
 
     /// bank_poke_word writes a 16-bit value to the specified bank at the given address.
-    /// The bank index should be within the range of available banks.
     /// Given this is a banked poke, for pokes at 0x3FFF it will poke into the next bank
     /// or wrap around to address 0 of the same bank depending on the wrap parameter.
-    pub fn bank_poke_word(&mut self, mut bank: usize, mut address: u16, value: u16, wrap: bool) {
-        if bank >= self.banks.len() {
-            panic!("Bank index out of bounds");
-        }
-        self.bank_poke(bank, address, (value & 0xFF) as u8);
+    /// Returns SnaError::BankOutOfRange if either byte falls outside the banks.
+    pub fn bank_poke_word(&mut self, mut bank: usize, mut address: u16, value: u16, wrap: bool) -> Result<(), SnaError> {
+        self.check_bank(bank)?;
+        let low = address;
         if address == 0xFFFF {
             address = 0;
             if !wrap {
@@ -204,19 +380,20 @@ impl Snapshot {
         } else {
             address += 1;
         }
+        self.check_bank(bank)?;
 
-        self.bank_poke(bank, address, ((value >> 8) & 0xFF) as u8);
+        self.grouped(|snapshot| {
+            snapshot.bank_poke(bank, low, (value & 0xFF) as u8)?;
+            snapshot.bank_poke(bank, address, ((value >> 8) & 0xFF) as u8)
+        })
     }
 
     /// bank_peek_word reads a 16-bit value from the specified bank at the given address.
-    /// The bank index should be within the range of available banks.
     /// Given this is a banked peek, for peeks at 0x3FFF it will peek into the next bank
     /// or wrap around to address 0 of the same bank depending on the wrap parameter.
-    pub fn bank_peek_word(&mut self, mut bank: usize, mut address: u16, wrap: bool) -> u16 {
-        if bank >= self.banks.len() {
-            panic!("Bank index out of bounds");
-        }
-        let low = self.bank_peek(bank, address);
+    /// Returns SnaError::BankOutOfRange if either byte falls outside the banks.
+    pub fn bank_peek_word(&self, mut bank: usize, mut address: u16, wrap: bool) -> Result<u16, SnaError> {
+        let low = self.bank_peek(bank, address)?;
         if address == 0xFFFF {
             address = 0;
             if !wrap {
@@ -226,32 +403,32 @@ impl Snapshot {
             address += 1;
         }
 
-        let high = self.bank_peek(bank, address);
-        (high as u16) << 8 as u16 | low as u16
+        let high = self.bank_peek(bank, address)?;
+        Ok((high as u16) << 8 | low as u16)
     }
 
     /// checksum calculates the checksum for a specific bank.
     /// It sums up all the bytes in the specified bank and returns the result as a u16.
-    /// The checksum is calculated by iterating through each byte in the bank,
-    /// adding it to a running total, and wrapping the sum to prevent overflow.
-    /// If the bank index is out of bounds, it panics with an error message.
-    #[allow(dead_code)]
-    fn checksum(&self, bank:usize) -> u16 {
-        if bank >= self.banks.len() {
-            panic!("Bank index out of bounds");
-        }
-        let mut sum: u16 = 0;
-        for byte in &self.banks[bank] {
-            sum = sum.wrapping_add(*byte as u16);
-        }
-        sum
+    /// The bytes are summed with SIMD instructions and the total is truncated to 16 bits,
+    /// which gives the same result as a byte-at-a-time wrapping sum.
+    /// Returns SnaError::BankOutOfRange if the snapshot has no such bank.
+    pub fn checksum(&self, bank:usize) -> Result<u16, SnaError> {
+        self.check_bank(bank)?;
+        Ok(scan::sum_bytes(&self.banks[bank]) as u16)
+    }
+
+    /// memory_checksum sums every byte of every bank, paged in or not, as a u16. It is
+    /// the wrapping sum of the checksums of the banks, so two snapshots with different
+    /// values certainly differ in memory.
+    pub fn memory_checksum(&self) -> u16 {
+        self.banks.iter().fold(0u32, |sum, bank| sum.wrapping_add(scan::sum_bytes(bank))) as u16
     }
 }
 
 
 
 impl TryFrom<File> for Snapshot {
-    type Error = std::io::Error;
+    type Error = SnaError;
 
     /// Creates a new `Snapshot` from a file.
     /// It reads the binary data from the file and initializes the snapshot.
@@ -259,14 +436,13 @@ impl TryFrom<File> for Snapshot {
     fn try_from(mut file: File) -> Result<Self, Self::Error> {
         let mut bin = Vec::new();
         file.read_to_end(&mut bin)?;
-        let snapshot = Snapshot::try_from(bin).expect("Failed to create snapshot from binary data");
-        Ok(snapshot)
+        Snapshot::try_from(bin)
     }
 }
 
 
 impl TryFrom<Vec<u8>> for Snapshot {
-    type Error = std::string::FromUtf8Error;
+    type Error = SnaError;
 
     /// Creates a new `Snapshot` from a binary slice.
     /// It initializes the snapshot based on the binary data provided.
@@ -282,12 +458,60 @@ impl TryFrom<Vec<u8>> for Snapshot {
     /// * `bin` - A byte slice containing the binary data of the snapshot.
     ///
     /// # Returns
-    /// A `Snapshot` instance initialized with the data from the binary slice.
+    /// A `Snapshot` instance initialized with the data from the binary slice,
+    /// or SnaError::InvalidSize if the data is not the size of a .sna file.
     fn try_from(bin: Vec<u8>) -> Result<Self, Self::Error> {
+        Snapshot::try_from(bin.as_slice())
+    }
+}
+
+impl TryFrom<&[u8]> for Snapshot {
+    type Error = SnaError;
+
+    /// Creates a new `Snapshot` from borrowed .sna data, such as a memory-mapped file
+    /// or an `include_bytes!` array, copying only the memory into the banks.
+    /// Returns SnaError::InvalidSize if the data is not the size of a .sna file.
+    fn try_from(bin: &[u8]) -> Result<Self, Self::Error> {
+        check_size(bin)?;
+        Ok(Snapshot::parse(bin, Bank::new))
+    }
+}
+
+/// check_size returns SnaError::InvalidSize unless bin is the size of a 48K, 128K or
+/// Scorpion 256 .sna file. A 128K file holds the bank paged at 0xC000 only once, so
+/// its size depends on whether that bank is also 5 or 2, which are stored anyway.
+pub(crate) fn check_size(bin: &[u8]) -> Result<(), SnaError> {
+    const HEADER_SIZE: usize = std::mem::size_of::<SnapshotHeader>();
+    const EXTENDED_SIZE: usize = HEADER_SIZE + MEM_48K + std::mem::size_of::<SnapshotExtension>();
+    let len = bin.len();
+    if len == HEADER_SIZE + MEM_48K {
+        return Ok(());
+    }
+    if len < EXTENDED_SIZE {
+        return Err(SnaError::InvalidSize(len));
+    }
+    let remaining = match bin[HEADER_SIZE + MEM_48K + 2] & 0x07 {
+        2 | 5 => 6,
+        _ => 5,
+    };
+    let expected = EXTENDED_SIZE + remaining * MEM_16K;
+    if len == expected || len == expected + 8 * MEM_16K {
+        Ok(())
+    } else {
+        Err(SnaError::InvalidSize(len))
+    }
+}
+
+impl Snapshot {
+    /// parse builds a snapshot from binary .sna data.
+    /// Banks are obtained from new_bank, allowing callers such as `SnapshotLoader` to
+    /// reuse memory across many snapshots. Every bank obtained is completely overwritten
+    /// with data from the file. The data must have passed check_size.
+    pub(crate) fn parse<F: FnMut() -> Bank>(bin: &[u8], mut new_bank: F) -> Snapshot {
         const HEADER_SIZE: usize = std::mem::size_of::<SnapshotHeader>();
         let mut mapping: [u8; 3] = [0, 1, 2];  // assume 48k mapping (for now)
 
-        let mut banks: Vec<Vec<u8>> = Vec::new();
+        let mut banks: Vec<Bank> = Vec::new();
 
         let mut extension = None;
         let mut snapshot_type = SnapshotType::Snapshot48;
@@ -302,14 +526,14 @@ impl TryFrom<Vec<u8>> for Snapshot {
             });
 
             // allocate 128K in 8 memory banks
-            banks.push(vec![0u8; MEM_16K]); // bank 0
-            banks.push(vec![0u8; MEM_16K]); // bank 1
-            banks.push(vec![0u8; MEM_16K]); // bank 2
-            banks.push(vec![0u8; MEM_16K]); // bank 3
-            banks.push(vec![0u8; MEM_16K]); // bank 4
-            banks.push(vec![0u8; MEM_16K]); // bank 5
-            banks.push(vec![0u8; MEM_16K]); // bank 6
-            banks.push(vec![0u8; MEM_16K]); // bank 7
+            banks.push(new_bank()); // bank 0
+            banks.push(new_bank()); // bank 1
+            banks.push(new_bank()); // bank 2
+            banks.push(new_bank()); // bank 3
+            banks.push(new_bank()); // bank 4
+            banks.push(new_bank()); // bank 5
+            banks.push(new_bank()); // bank 6
+            banks.push(new_bank()); // bank 7
 
             mapping[0] = 5; // bank 0
             mapping[1] = 2; // bank 1
@@ -329,19 +553,26 @@ impl TryFrom<Vec<u8>> for Snapshot {
                 banks[bank][0..MEM_16K].copy_from_slice(&bin[index..index + MEM_16K]);
                 index += MEM_16K;
             }
+
+            // a Scorpion 256 follows with banks 8 to 15 in order
+            for memory in bin[index..].chunks_exact(MEM_16K) {
+                let mut bank = new_bank();
+                bank[0..MEM_16K].copy_from_slice(memory);
+                banks.push(bank);
+            }
         }
         else{
             // allocate 48K in 3 memory banks
-            banks.push(vec![0u8; MEM_16K]);
-            banks.push(vec![0u8; MEM_16K]);
-            banks.push(vec![0u8; MEM_16K]);
+            banks.push(new_bank());
+            banks.push(new_bank());
+            banks.push(new_bank());
 
             banks[0][0..MEM_16K].copy_from_slice(&bin[HEADER_SIZE..HEADER_SIZE + MEM_16K]);
             banks[1][0..MEM_16K].copy_from_slice(&bin[HEADER_SIZE + MEM_16K..HEADER_SIZE + (2 * MEM_16K)]);
             banks[2][0..MEM_16K].copy_from_slice(&bin[HEADER_SIZE + (2 * MEM_16K)..HEADER_SIZE + (3 * MEM_16K)]);
         }
 
-        Ok(Snapshot {
+        let mut snapshot = Snapshot {
             header : SnapshotHeader {
                 i: bin[0],
                 hl_prime: u16::from_le_bytes([bin[1], bin[2]]),
@@ -363,8 +594,25 @@ impl TryFrom<Vec<u8>> for Snapshot {
             snapshot_type,
             extension,
             banks,
-            mapping
-        })
+            ay: None,
+            input: None,
+            metadata: None,
+            mapping,
+            x1ffd: None,
+            windows: [0usize; 4],
+            regions: regions::RegionMap::new(),
+            trainers: Vec::new(),
+            attachments: peripherals::AttachmentMap::new(),
+            guards: guard::GuardList::new(),
+            guard_hits: Vec::new(),
+            journal: None,
+            roms: Vec::new(),
+            rom: 0,
+            all_ram: false,
+            model: None,
+        };
+        snapshot.remap();
+        snapshot
     }
 }
 
@@ -380,9 +628,9 @@ mod tests {
         let expected: [u16; 3] = [59066, 0, 11458];  // assume 48k mapping (for now)
         let file = File::open("48k.sna").expect("Failed to open snapshot file");
         let snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
-        for bank in 0..3 {
-            let checksum = snapshot.checksum(bank);
-            assert_eq!(checksum, expected[bank], "Checksum for bank {} is incorrect expected {}, got {}", bank, expected[bank], checksum);
+        for (bank, &expected) in expected.iter().enumerate() {
+            let checksum = snapshot.checksum(bank).unwrap();
+            assert_eq!(checksum, expected, "Checksum for bank {} is incorrect expected {}, got {}", bank, expected, checksum);
         }
     }
 
@@ -394,9 +642,9 @@ mod tests {
         let file = File::open("128k.sna").expect("Failed to open snapshot file");
         let snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
         assert_eq!(snapshot.snapshot_type, SnapshotType::Snapshot128, "Snapshot type is not Snapshot128");
-        for bank in 0..=7 {
-            let checksum = snapshot.checksum(bank);
-            assert_eq!(checksum, expected[bank], "Checksum for bank {} is incorrect expected {}, got {}", bank, expected[bank], checksum);
+        for (bank, &expected) in expected.iter().enumerate() {
+            let checksum = snapshot.checksum(bank).unwrap();
+            assert_eq!(checksum, expected, "Checksum for bank {} is incorrect expected {}, got {}", bank, expected, checksum);
         }
     }
 
@@ -409,10 +657,10 @@ mod tests {
         let file = File::open("128k.sna").expect("Failed to open snapshot file");
         let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
         assert_eq!(snapshot.snapshot_type, SnapshotType::Snapshot128, "Snapshot type is not Snapshot128");
-        for bank in 0..=7 {
-            snapshot.write_0x7ffd(bank as u8);
-            let checksum = snapshot.checksum(bank);
-            assert_eq!(checksum, expected[bank], "Checksum for bank {} is incorrect expected {}, got {}", bank, expected[bank], checksum);
+        for (bank, &expected) in expected.iter().enumerate() {
+            snapshot.write_0x7ffd(bank as u8).unwrap();
+            let checksum = snapshot.checksum(bank).unwrap();
+            assert_eq!(checksum, expected, "Checksum for bank {} is incorrect expected {}, got {}", bank, expected, checksum);
             let mapped_checksum = {
                 let mut sum: u16 = 0;
                 for i in 0xC000..=0xFFFF {
@@ -424,6 +672,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_errors() {
+        let file = File::open("48k.sna").expect("Failed to open snapshot file");
+        let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        assert!(matches!(snapshot.poke(0x3FFF, 0), Err(SnaError::RomWrite(0x3FFF))));
+        assert!(matches!(snapshot.peek_word(0xFFFF), Err(SnaError::AddressOverflow { address: 0xFFFF, len: 2 })));
+        let before = snapshot.peek(0x4000);
+        assert!(matches!(snapshot.poke_word(0x3FFF, 0xAA55), Err(SnaError::RomWrite(0x3FFF))));
+        assert_eq!(snapshot.peek(0x4000), before, "A failed poke_word should not write its second byte");
+        assert!(matches!(snapshot.write_0x7ffd(0x07), Err(SnaError::Not128K)));
+        assert!(matches!(snapshot.bank_peek(3, 0), Err(SnaError::BankOutOfRange { bank: 3, banks: 3 })));
+        assert!(matches!(Snapshot::try_from(vec![0u8; 100]), Err(SnaError::InvalidSize(100))));
+    }
+
+    #[test]
+    fn test_try_from_slice() {
+        let bin = std::fs::read("128k.sna").expect("Failed to read snapshot file");
+        let borrowed = Snapshot::try_from(&bin[..]).expect("Failed to parse snapshot");
+        let owned = Snapshot::try_from(bin.clone()).expect("Failed to parse snapshot");
+        assert!((0..8).all(|bank| borrowed.checksum(bank).unwrap() == owned.checksum(bank).unwrap()), "Borrowed and owned parses differ");
+        assert!(matches!(Snapshot::try_from(&bin[1..]), Err(SnaError::InvalidSize(131102))));
+    }
+
+    // the size of a 128K file must match the bank paged at 0xC000, which is stored
+    // only once, so a paged bank of 2 or 5 needs six more banks and any other five
+    #[test]
+    fn test_size_from_paged_bank() {
+        let mut bin = std::fs::read("128k.sna").expect("Failed to read snapshot file");
+        assert_eq!(bin.len(), 131103);
+        bin[49181] = 0x02;
+        assert!(matches!(Snapshot::try_from(&bin[..]), Err(SnaError::InvalidSize(131103))));
+        assert!(matches!(Snapshot::from_reader(&bin[..]), Err(SnaError::InvalidSize(_))));
+
+        bin.resize(147487, 0);
+        assert!(Snapshot::try_from(&bin[..]).is_ok(), "A paged bank of 2 needs six more banks");
+        bin[49181] = 0x00;
+        assert!(matches!(Snapshot::try_from(&bin[..]), Err(SnaError::InvalidSize(147487))));
+        assert!(matches!(Snapshot::from_reader(&bin[..]), Err(SnaError::InvalidSize(_))));
+    }
+
     #[test]
     fn test_bank_peek() {
         let mut rng = rand::rng();
@@ -433,16 +721,16 @@ mod tests {
         assert_eq!(snapshot.snapshot_type, SnapshotType::Snapshot128, "Snapshot type is not Snapshot128");
         for bank in 0..=7 {
             let mut mapped_checksum: u16 = 0;
-            snapshot.write_0x7ffd(bank as u8);
+            snapshot.write_0x7ffd(bank as u8).unwrap();
             for i in 0xC000..=0xFFFF {
                 let random_number: u8 = rng.random();
-                snapshot.poke(i, random_number);
+                snapshot.poke(i, random_number).unwrap();
                 mapped_checksum = mapped_checksum.wrapping_add(random_number as u16);
             }
 
             let mut bank_checksum: u16 = 0;
             for i in 0..=0x3FFF {
-                let value = snapshot.bank_peek(bank, i);
+                let value = snapshot.bank_peek(bank, i).unwrap();
                 bank_checksum = bank_checksum.wrapping_add(value as u16);
             }
 
@@ -461,12 +749,12 @@ mod tests {
             let mut bank_checksum: u16 = 0;
             for i in 0..=0x3FFF {
                 let random_number: u8 = rng.random();
-                snapshot.bank_poke(bank, i, random_number);
+                snapshot.bank_poke(bank, i, random_number).unwrap();
                 bank_checksum = bank_checksum.wrapping_add(random_number as u16);
             }
 
             let mut mapped_checksum: u16 = 0;
-            snapshot.write_0x7ffd(bank as u8);
+            snapshot.write_0x7ffd(bank as u8).unwrap();
             for i in 0xC000..=0xFFFF {
                 let value = snapshot.peek(i);
                 mapped_checksum = mapped_checksum.wrapping_add(value as u16);
@@ -475,4 +763,44 @@ mod tests {
             assert_eq!(bank_checksum, mapped_checksum, "Banked checksum for bank {} is incorrect expected {}, got {}", bank, mapped_checksum, bank_checksum);
         }
     }
+
+    // clones share their banks until one of them is written to, at which point
+    // only the written bank is copied.
+    #[test]
+    fn test_clone_copy_on_write() {
+        let file = File::open("128k.sna").expect("Failed to open snapshot file");
+        let snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        let original = snapshot.peek(0x8000);
+
+        let mut variant = snapshot.clone();
+        assert!(variant.banks.iter().all(|bank| bank.is_shared()), "Cloned snapshot should share all banks");
+
+        variant.poke(0x8000, original.wrapping_add(1)).unwrap();
+        assert_eq!(snapshot.peek(0x8000), original, "Original snapshot was modified by a poke to its clone");
+        assert_eq!(variant.peek(0x8000), original.wrapping_add(1), "Clone did not receive the poke");
+        for bank in 0..=7 {
+            assert_eq!(variant.banks[bank].is_shared(), bank != 2, "Unexpected sharing state for bank {}", bank);
+        }
+    }
+
+    // inactive banks compress and still read back the same contents
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compress_inactive_banks() {
+        let file = File::open("128k.sna").expect("Failed to open snapshot file");
+        let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        let expected: Vec<u16> = (0..8).map(|bank| snapshot.checksum(bank).unwrap()).collect();
+
+        assert_eq!(snapshot.compress_inactive_banks(), 5, "Expected the 5 unmapped banks to be compressed");
+        for (bank, &expected) in expected.iter().enumerate() {
+            assert_eq!(snapshot.banks[bank].is_compressed(), !snapshot.mapping.contains(&(bank as u8)), "Unexpected compression state for bank {}", bank);
+            assert_eq!(snapshot.checksum(bank).unwrap(), expected, "Checksum for compressed bank {} is incorrect", bank);
+        }
+
+        // special paging maps banks 4, 7, 6 and 3, leaving 5 and 2 unmapped
+        let mut snapshot = Snapshot::new_plus3();
+        snapshot.write_0x1ffd(0x07).unwrap();
+        assert_eq!(snapshot.compress_inactive_banks(), 4);
+        assert!((0..8).all(|bank| snapshot.banks[bank].is_compressed() == [0, 1, 2, 5].contains(&bank)), "Only banks 0, 1, 2 and 5 are unmapped");
+    }
 }
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::slab::Slab;
+use crate::{check_size, Bank, SnaError, Snapshot};
+
+/// A reusable loader for batch processing many snapshots.
+/// The loader keeps its file read buffer between calls to `load`, and banks
+/// handed back through `recycle` are reused for the next snapshot instead of
+/// being freed and allocated again. A long-running service can allocate its
+/// banks up front with `with_capacity`, as one slab of memory, so it settles at a
+/// fixed allocation instead of allocating and freeing 48K-128K for every file it
+/// indexes and fragmenting the heap.
+#[derive(Default)]
+pub struct SnapshotLoader {
+    buffer: Vec<u8>,
+    pool: Vec<Bank>,
+    slab: Option<Slab>,
+}
+
+impl SnapshotLoader {
+    /// Creates a new loader with an empty buffer and bank pool.
+    pub fn new() -> Self {
+        SnapshotLoader::default()
+    }
+
+    /// Creates a new loader whose snapshots take their banks from a slab, a single
+    /// allocation holding the given number of banks, e.g. 64 for eight 128K snapshots
+    /// in use at once. A bank of the slab goes back to it when the snapshot holding it
+    /// is dropped or recycled, and a shared bank written to is copied into a free bank
+    /// of the slab while there is one. Only when the slab is full are banks allocated
+    /// one by one. The slab is freed once the loader and every bank from it are gone.
+    pub fn with_capacity(banks: usize) -> Self {
+        SnapshotLoader {
+            buffer: Vec::new(),
+            pool: Vec::new(),
+            slab: Some(Slab::new(banks)),
+        }
+    }
+
+    /// load reads the snapshot file at the given path using the loader's buffer
+    /// and builds a snapshot from pooled banks where available.
+    /// Returns SnaError::InvalidSize if the file is not the size of a .sna file.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<Snapshot, SnaError> {
+        let mut file = File::open(path)?;
+        self.buffer.clear();
+        file.read_to_end(&mut self.buffer)?;
+        check_size(&self.buffer)?;
+        Ok(Snapshot::parse(&self.buffer, || take_bank(&mut self.pool, &self.slab)))
+    }
+
+    /// load_bin builds a snapshot from binary data using pooled banks where available.
+    /// Returns SnaError::InvalidSize if the data is not the size of a .sna file.
+    pub fn load_bin(&mut self, bin: &[u8]) -> Result<Snapshot, SnaError> {
+        check_size(bin)?;
+        Ok(Snapshot::parse(bin, || take_bank(&mut self.pool, &self.slab)))
+    }
+
+    /// recycle returns the banks of a snapshot that is no longer needed to the pool.
+    /// Banks still shared with a clone of the snapshot are dropped instead, as
+    /// reusing them would force a copy on the first write anyway.
+    pub fn recycle(&mut self, snapshot: Snapshot) {
+        self.pool.extend(snapshot.banks.into_iter().filter(|bank| !bank.is_shared()));
+    }
+
+    /// Returns the number of banks waiting in the pool or the slab to be reused.
+    pub fn pooled_banks(&self) -> usize {
+        self.pool.len() + self.slab.as_ref().map_or(0, Slab::free_slots)
+    }
+}
+
+// take_bank returns a bank from the pool, or else a free bank of the slab, or else a new one
+fn take_bank(pool: &mut Vec<Bank>, slab: &Option<Slab>) -> Bank {
+    pool.pop()
+        .or_else(|| slab.as_ref()?.take().map(Bank::from_memory))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MEM_16K;
+
+    #[test]
+    fn test_reuse_banks() {
+        let mut loader = SnapshotLoader::new();
+        let snapshot = loader.load("128k.sna").expect("Failed to load snapshot");
+        let expected: Vec<u16> = (0..8).map(|bank| snapshot.checksum(bank).unwrap()).collect();
+        loader.recycle(snapshot);
+        assert_eq!(loader.pooled_banks(), 8, "All 8 banks should have been returned to the pool");
+
+        let snapshot = loader.load("48k.sna").expect("Failed to load snapshot");
+        assert_eq!(loader.pooled_banks(), 5, "A 48K snapshot should take 3 banks from the pool");
+        assert_eq!([snapshot.checksum(0).unwrap(), snapshot.checksum(1).unwrap(), snapshot.checksum(2).unwrap()], [59066, 0, 11458], "48K snapshot loaded into pooled banks is incorrect");
+        loader.recycle(snapshot);
+
+        let snapshot = loader.load("128k.sna").expect("Failed to load snapshot");
+        let checksums: Vec<u16> = (0..8).map(|bank| snapshot.checksum(bank).unwrap()).collect();
+        assert_eq!(checksums, expected, "128K snapshot loaded into pooled banks is incorrect");
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut loader = SnapshotLoader::with_capacity(16);
+        let first = loader.load("128k.sna").expect("Failed to load snapshot");
+        let second = loader.load("48k.sna").expect("Failed to load snapshot");
+        assert_eq!(loader.pooled_banks(), 5, "Both snapshots should be loaded into pooled banks");
+        assert_eq!(second.checksum(0).unwrap(), 59066, "48K snapshot loaded into pooled banks is incorrect");
+
+        assert!(first.banks.iter().chain(&second.banks).all(Bank::in_slab), "Every bank should come from the slab");
+        let start = first.banks.iter().chain(&second.banks).map(|bank| bank.as_ptr() as usize).min().unwrap();
+        let end = first.banks.iter().chain(&second.banks).map(|bank| bank.as_ptr() as usize).max().unwrap();
+        assert!(end - start < 16 * (MEM_16K + 64), "The banks should share one allocation");
+
+        let mut clone = second.clone();
+        clone.poke(0x8000, 0xFF).unwrap();
+        assert!(clone.banks[1].in_slab() && loader.pooled_banks() == 4, "A copy on write should come from the slab");
+        drop(clone);
+        loader.recycle(first);
+        drop(second);
+        assert_eq!(loader.pooled_banks(), 16, "Recycled and dropped banks should return to the slab");
+
+        let mut loader = SnapshotLoader::with_capacity(4);
+        let snapshot = loader.load("128k.sna").expect("Failed to load snapshot");
+        assert_eq!(snapshot.banks.iter().filter(|bank| bank.in_slab()).count(), 4, "Banks past the slab are allocated one by one");
+        assert_eq!(snapshot.checksum(5).unwrap(), 46342);
+    }
+
+    #[test]
+    fn test_shared_banks_not_pooled() {
+        let mut loader = SnapshotLoader::new();
+        let snapshot = loader.load("48k.sna").expect("Failed to load snapshot");
+        let clone = snapshot.clone();
+        loader.recycle(snapshot);
+        assert_eq!(loader.pooled_banks(), 0, "Banks shared with a clone should not be pooled");
+        assert_eq!(clone.checksum(0).unwrap(), 59066, "Clone was affected by recycling the original");
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let mut loader = SnapshotLoader::new();
+        assert!(matches!(loader.load_bin(&[0; 100]), Err(SnaError::InvalidSize(100))));
+        let bin = std::fs::read("48k.sna").expect("Failed to read snapshot file");
+        assert!(matches!(loader.load_bin(&bin[..bin.len() - 1]), Err(SnaError::InvalidSize(49178))), "A truncated file is rejected");
+        assert!(loader.load_bin(&bin).is_ok());
+        assert!(matches!(loader.load("Cargo.toml"), Err(SnaError::InvalidSize(_))));
+        assert!(matches!(loader.load("missing.sna"), Err(SnaError::Io(_))));
+    }
+}
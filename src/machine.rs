@@ -0,0 +1,176 @@
+use std::fmt;
+
+use crate::{Bank, SnaError, Snapshot, SnapshotType};
+
+// the system variable holding the last byte of RAM, and its value on a 16K machine
+const P_RAMT: u16 = 0x5CB4;
+const LAST_16K_ADDRESS: u16 = 0x7FFF;
+
+/// The model of Spectrum a snapshot is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Machine {
+    Spectrum16,     // a 48K .sna with only the first 16K of RAM in use
+    Spectrum48,
+    Spectrum128,
+    SpectrumPlus2,  // the grey +2, which pages as the 128K does
+    SpectrumPlus2A, // the +2A, with port 0x1FFD
+    SpectrumPlus3,  // the +3, which pages as the +2A does
+    Pentagon128,    // 128K paging without contended memory
+    Scorpion256,    // 16 banks, the top 8 paged by bit 4 of port 0x1FFD
+}
+
+impl Machine {
+    /// snapshot_type returns the .sna layout used for the machine.
+    pub fn snapshot_type(self) -> SnapshotType {
+        match self {
+            Machine::Spectrum16 | Machine::Spectrum48 => SnapshotType::Snapshot48,
+            _ => SnapshotType::Snapshot128,
+        }
+    }
+
+    /// bank_count returns the number of 16K RAM banks a snapshot of the machine holds.
+    /// A 16K snapshot holds 3, as the .sna format always stores 48K.
+    pub fn bank_count(self) -> usize {
+        match self {
+            Machine::Spectrum16 | Machine::Spectrum48 => 3,
+            Machine::Scorpion256 => 16,
+            _ => 8,
+        }
+    }
+
+    /// has_0x1ffd returns whether the machine has a second paging port at 0x1FFD.
+    pub fn has_0x1ffd(self) -> bool {
+        matches!(self, Machine::SpectrumPlus2A | Machine::SpectrumPlus3 | Machine::Scorpion256)
+    }
+
+    /// is_contended returns whether the ULA contends access to a RAM bank: bank 0 of a 48K
+    /// snapshot, the odd banks of the 128K and +2, and banks 4 to 7 of the +2A and +3. The
+    /// Pentagon and Scorpion have no contended memory.
+    pub fn is_contended(self, bank: usize) -> bool {
+        match self {
+            Machine::Spectrum16 | Machine::Spectrum48 => bank == 0,
+            Machine::Spectrum128 | Machine::SpectrumPlus2 => bank & 1 == 1,
+            Machine::SpectrumPlus2A | Machine::SpectrumPlus3 => (4..8).contains(&bank),
+            Machine::Pentagon128 | Machine::Scorpion256 => false,
+        }
+    }
+
+    /// has_plus3_paging returns whether port 0x1FFD works as on the +2A/+3, with special
+    /// paging and four ROMs.
+    pub fn has_plus3_paging(self) -> bool {
+        matches!(self, Machine::SpectrumPlus2A | Machine::SpectrumPlus3)
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Machine::Spectrum16 => write!(f, "ZX Spectrum 16K"),
+            Machine::Spectrum48 => write!(f, "ZX Spectrum 48K"),
+            Machine::Spectrum128 => write!(f, "ZX Spectrum 128K"),
+            Machine::SpectrumPlus2 => write!(f, "ZX Spectrum +2"),
+            Machine::SpectrumPlus2A => write!(f, "ZX Spectrum +2A"),
+            Machine::SpectrumPlus3 => write!(f, "ZX Spectrum +3"),
+            Machine::Pentagon128 => write!(f, "Pentagon 128"),
+            Machine::Scorpion256 => write!(f, "Scorpion ZS 256"),
+        }
+    }
+}
+
+impl Snapshot {
+    /// machine returns the model the snapshot is for. That is the one set with
+    /// set_machine or read from a .Z80 header if there is one, otherwise a guess:
+    ///
+    /// - a 48K snapshot is a 16K if P_RAMT, which the ROM sets to the last byte of RAM,
+    ///   is 0x7FFF, and a 48K otherwise;
+    /// - a 128K snapshot is a Scorpion 256 if it has 16 banks, a +3 if it has a 0x1FFD
+    ///   value, a Pentagon 128 if it was taken with the TR-DOS ROM paged in, as the
+    ///   Pentagon has a Beta disk interface built in, and a 128K otherwise.
+    ///
+    /// A +2 or +2A cannot be told apart from a 128K or +3 by its .sna file.
+    pub fn machine(&self) -> Machine {
+        if let Some(machine) = self.model {
+            return machine;
+        }
+        match self.snapshot_type {
+            SnapshotType::Snapshot48 if self.p_ramt() == Some(LAST_16K_ADDRESS) => Machine::Spectrum16,
+            SnapshotType::Snapshot48 => Machine::Spectrum48,
+            _ if self.banks.len() == Machine::Scorpion256.bank_count() => Machine::Scorpion256,
+            _ if self.x1ffd.is_some() => Machine::SpectrumPlus3,
+            _ if self.extension.as_ref().is_some_and(|extension| extension.tr_dos != 0) => Machine::Pentagon128,
+            _ => Machine::Spectrum128,
+        }
+    }
+
+    // P_RAMT read straight from the bank at 0x4000, as machine() is used while the
+    // paging is being worked out
+    fn p_ramt(&self) -> Option<u16> {
+        let bank = self.banks.get(self.mapping[0] as usize)?;
+        Some(u16::from_le_bytes([bank.read(P_RAMT), bank.read(P_RAMT + 1)]))
+    }
+
+    /// set_machine records the model the snapshot is for, which decides how the paging
+    /// ports behave. Moving to a machine with more banks adds zeroed banks, and port
+    /// 0x1FFD is reset to 0 on machines that have it and removed from those that do not.
+    /// Returns SnaError::TypeMismatch if the machine uses the other .sna layout, or
+    /// SnaError::BankOutOfRange if it has fewer banks than the snapshot holds.
+    pub fn set_machine(&mut self, machine: Machine) -> Result<(), SnaError> {
+        if machine.snapshot_type() != self.snapshot_type {
+            return Err(SnaError::TypeMismatch { expected: machine.snapshot_type(), found: self.snapshot_type });
+        }
+        if self.banks.len() > machine.bank_count() {
+            return Err(SnaError::BankOutOfRange { bank: self.banks.len() - 1, banks: machine.bank_count() });
+        }
+        self.banks.resize_with(machine.bank_count(), Bank::new);
+        self.x1ffd = machine.has_0x1ffd().then_some(0x00);
+        self.model = Some(machine);
+        self.remap();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_machine() {
+        let mut snapshot = Snapshot::new_128k();
+        assert_eq!(snapshot.machine(), Machine::Spectrum128);
+        snapshot.set_machine(Machine::Pentagon128).unwrap();
+        assert_eq!(snapshot.machine(), Machine::Pentagon128);
+        assert_eq!(snapshot.x1ffd, None);
+
+        snapshot.set_machine(Machine::Scorpion256).unwrap();
+        assert_eq!(snapshot.banks.len(), 16);
+        assert_eq!(snapshot.x1ffd, Some(0));
+        assert!(matches!(snapshot.set_machine(Machine::Spectrum128), Err(SnaError::BankOutOfRange { bank: 15, banks: 8 })));
+        assert!(matches!(snapshot.set_machine(Machine::Spectrum48), Err(SnaError::TypeMismatch { .. })));
+
+        let mut bin = Vec::new();
+        snapshot.write_to(&mut bin).expect("Failed to write snapshot");
+        assert_eq!(bin.len(), 262175);
+        assert_eq!(Snapshot::from_reader(&bin[..]).unwrap().machine(), Machine::Scorpion256);
+        assert!(Snapshot::from_reader(&bin[..bin.len() - 1]).is_err());
+        assert_eq!(Snapshot::try_from(bin).unwrap().machine(), Machine::Scorpion256, "16 banks make a Scorpion");
+    }
+
+    #[test]
+    fn test_machine_heuristics() {
+        let mut snapshot = Snapshot::new_48k();
+        assert_eq!(snapshot.machine(), Machine::Spectrum48);
+        snapshot.poke_word(0x5CB4, 0x7FFF).unwrap();
+        assert_eq!(snapshot.machine(), Machine::Spectrum16, "P_RAMT ends RAM at 0x7FFF");
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.extension.as_mut().unwrap().tr_dos = 1;
+        assert_eq!(snapshot.machine(), Machine::Pentagon128, "TR-DOS is paged in");
+        snapshot.x1ffd = Some(0);
+        assert_eq!(snapshot.machine(), Machine::SpectrumPlus3);
+        snapshot.set_machine(Machine::SpectrumPlus2A).unwrap();
+        snapshot.write_0x1ffd(0x01).unwrap();
+        assert!(snapshot.poke(0x0000, 1).is_ok(), "The +2A has special paging");
+        assert_eq!(Machine::SpectrumPlus2.to_string(), "ZX Spectrum +2");
+    }
+}
@@ -0,0 +1,223 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::json::Value;
+use crate::regions::Region;
+use crate::trainer::Trainer;
+use crate::{SnaError, Snapshot};
+
+/// Provenance information for a snapshot.
+/// The .sna format has nowhere to store it, so it is saved to a JSON sidecar
+/// file next to the snapshot (see [`Snapshot::save_with_sidecar`]).
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    pub creator: Option<String>,   // the tool that created or last edited the snapshot
+    pub timestamp: Option<u64>,    // seconds since the Unix epoch
+    pub notes: String,             // free-text notes
+    pub tags: Vec<String>,         // free-form tags for archives
+}
+
+impl Metadata {
+    /// new creates metadata naming the creator tool, stamped with the current time.
+    pub fn new(creator: &str) -> Metadata {
+        Metadata {
+            creator: Some(creator.to_string()),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()),
+            ..Metadata::default()
+        }
+    }
+
+    /// to_json serializes the metadata as a JSON object.
+    pub fn to_json(&self) -> String {
+        Value::Object(self.members()).to_string()
+    }
+
+    // the members of the JSON object to_json writes
+    fn members(&self) -> Vec<(String, Value)> {
+        let mut members = Vec::new();
+        if let Some(creator) = &self.creator {
+            members.push(("creator".to_string(), Value::String(creator.clone())));
+        }
+        if let Some(timestamp) = self.timestamp {
+            members.push(("timestamp".to_string(), Value::Number(timestamp as f64)));
+        }
+        members.push(("notes".to_string(), Value::String(self.notes.clone())));
+        members.push(("tags".to_string(), Value::Array(self.tags.iter().cloned().map(Value::String).collect())));
+        members
+    }
+
+    /// from_json parses metadata written by to_json. Unknown members are ignored.
+    /// Returns SnaError::InvalidFormat if the text is not metadata.
+    pub fn from_json(text: &str) -> Result<Metadata, SnaError> {
+        let invalid = || SnaError::InvalidFormat("Invalid metadata sidecar".to_string());
+        let value = Value::parse(text).ok_or_else(invalid)?;
+        if !matches!(value, Value::Object(_)) {
+            return Err(invalid());
+        }
+        let tags = match value.get("tags") {
+            Some(tags) => tags.as_array().ok_or_else(invalid)?
+                .iter()
+                .map(|tag| tag.as_str().map(str::to_string).ok_or_else(invalid))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(Metadata {
+            creator: value.get("creator").and_then(Value::as_str).map(str::to_string),
+            timestamp: value.get("timestamp").and_then(Value::as_f64).map(|t| t as u64),
+            notes: value.get("notes").and_then(Value::as_str).unwrap_or_default().to_string(),
+            tags,
+        })
+    }
+
+    /// sidecar_path returns the path of the sidecar file for a snapshot file, e.g. game.sna.json.
+    pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+        let mut name = path.as_ref().as_os_str().to_owned();
+        name.push(".json");
+        PathBuf::from(name)
+    }
+}
+
+impl Snapshot {
+    /// save_with_sidecar saves the snapshot and, if it has metadata, named regions or
+    /// applied trainers, writes them to the sidecar file next to it. A stale sidecar is
+    /// removed when the snapshot has none of them.
+    pub fn save_with_sidecar<P: AsRef<Path>>(&self, path: P) -> Result<(), SnaError> {
+        self.save(&path)?;
+        let sidecar = Metadata::sidecar_path(&path);
+        if self.metadata.is_none() && self.regions.is_empty() && self.trainers.is_empty() {
+            return match std::fs::remove_file(sidecar) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        let mut members = self.metadata.as_ref().map(Metadata::members).unwrap_or_default();
+        if !self.regions.is_empty() {
+            let regions = self.regions.iter().map(|(name, region)| region.to_json(name)).collect();
+            members.push(("regions".to_string(), Value::Array(regions)));
+        }
+        if !self.trainers.is_empty() {
+            members.push(("trainers".to_string(), Value::Array(self.trainers.iter().map(Trainer::to_json).collect())));
+        }
+        Ok(std::fs::write(sidecar, Value::Object(members).to_string())?)
+    }
+
+    /// load_sidecar reads the metadata, named regions and applied trainers in the sidecar
+    /// for the snapshot file at the given path, if there is one. Regions it names replace
+    /// those of the same name, and its trainers follow those already applied. Returns
+    /// whether a sidecar was found, or SnaError::InvalidFormat if it is not valid.
+    pub fn load_sidecar<P: AsRef<Path>>(&mut self, path: P) -> Result<bool, SnaError> {
+        match std::fs::read_to_string(Metadata::sidecar_path(path)) {
+            Ok(text) => {
+                let metadata = Metadata::from_json(&text)?;
+                let value = Value::parse(&text).expect("from_json has parsed the sidecar");
+                let regions = match value.get("regions") {
+                    Some(regions) => regions.as_array()
+                        .ok_or_else(|| SnaError::InvalidFormat("Invalid metadata sidecar".to_string()))?
+                        .iter()
+                        .map(Region::from_json)
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None => Vec::new(),
+                };
+                let trainers = match value.get("trainers") {
+                    Some(trainers) => trainers.as_array()
+                        .ok_or_else(|| SnaError::InvalidFormat("Invalid metadata sidecar".to_string()))?
+                        .iter()
+                        .map(Trainer::from_json)
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None => Vec::new(),
+                };
+                // to_json always writes notes, so a sidecar without them holds only regions
+                if value.get("notes").is_some() {
+                    self.metadata = Some(metadata);
+                }
+                self.regions.extend(regions);
+                self.trainers.extend(trainers);
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BankAddr, BankId, ValueType};
+
+    #[test]
+    fn test_sidecar_round_trip() {
+        let dir = std::env::temp_dir().join(format!("lib-zx-sna-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+        let path = dir.join("game.sna");
+
+        let mut snapshot = Snapshot::try_from(std::fs::read("48k.sna").expect("Failed to read snapshot file")).expect("Failed to parse snapshot");
+        let mut metadata = Metadata::new("test \"suite\"");
+        metadata.notes = "Infinite lives\napplied".to_string();
+        metadata.tags = vec!["cheat".to_string(), "title".to_string()];
+        snapshot.metadata = Some(metadata.clone());
+        snapshot.save_with_sidecar(&path).expect("Failed to save snapshot");
+        assert!(Metadata::sidecar_path(&path).ends_with("game.sna.json"));
+
+        let mut loaded = Snapshot::try_from(std::fs::read(&path).expect("Failed to read saved snapshot")).expect("Failed to parse snapshot");
+        assert!(loaded.load_sidecar(&path).expect("Failed to load sidecar"));
+        assert_eq!(loaded.metadata, Some(metadata));
+
+        loaded.metadata = None;
+        loaded.save_with_sidecar(&path).expect("Failed to save snapshot");
+        assert!(!loaded.load_sidecar(&path).expect("Failed to check sidecar"), "Stale sidecar should be removed");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_sidecar_regions() {
+        let dir = std::env::temp_dir().join(format!("lib-zx-sna-regions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+        let path = dir.join("game.sna");
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.define_region("lives", 0x8000, 1, ValueType::U8).unwrap();
+        snapshot.define_region("score", BankAddr::new(BankId(4), 0x0100).unwrap(), 3, ValueType::Bcd).unwrap();
+        snapshot.save_with_sidecar(&path).expect("Failed to save snapshot");
+
+        let mut loaded = Snapshot::new_128k();
+        assert!(loaded.load_sidecar(&path).expect("Failed to load sidecar"));
+        assert_eq!(loaded.regions().collect::<Vec<_>>(), snapshot.regions().collect::<Vec<_>>());
+        assert_eq!(loaded.metadata, None, "A sidecar of regions alone has no metadata");
+
+        snapshot.metadata = Some(Metadata::new("test"));
+        snapshot.save_with_sidecar(&path).expect("Failed to save snapshot");
+        assert!(loaded.load_sidecar(&path).expect("Failed to load sidecar"));
+        assert_eq!((&loaded.metadata, loaded.regions().count()), (&snapshot.metadata, 2));
+
+        std::fs::write(Metadata::sidecar_path(&path), r#"{"regions": [{"name": "lives", "address": 65535, "len": 2, "type": "U16"}]}"#).unwrap();
+        assert!(matches!(Snapshot::new_48k().load_sidecar(&path), Err(SnaError::AddressOverflow { address: 0xFFFF, len: 2 })), "Regions are checked as define_region does");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_sidecar_trainers() {
+        let dir = std::env::temp_dir().join(format!("lib-zx-sna-trainers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create temp dir");
+        let path = dir.join("game.sna");
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.poke(0x8000, 3).unwrap();
+        let trainer = Trainer::parse_script("Lives", "POKE 32768,0\nPOKE 4:49152,201").unwrap();
+        snapshot.apply_trainer(&trainer).unwrap();
+        assert_eq!(snapshot.applied_trainers()[0].pokes[0].original, 3, "The replaced value should be recorded");
+        snapshot.save_with_sidecar(&path).expect("Failed to save snapshot");
+
+        let mut loaded = Snapshot::new_128k();
+        assert!(loaded.load_sidecar(&path).expect("Failed to load sidecar"));
+        assert_eq!(loaded.applied_trainers(), snapshot.applied_trainers());
+        assert_eq!(loaded.applied_trainers()[0].pokes[1].bank, Some(4));
+        assert_eq!(loaded.metadata, None, "A sidecar of trainers alone has no metadata");
+
+        std::fs::write(Metadata::sidecar_path(&path), r#"{"trainers": [{"name": "Lives", "pokes": [{"address": 32768, "value": 256, "original": 0}]}]}"#).unwrap();
+        assert!(matches!(Snapshot::new_48k().load_sidecar(&path), Err(SnaError::InvalidFormat(_))), "A poke value must be a byte");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+
+use crate::Snapshot;
+
+/// A change made to a header value by `Snapshot::normalize`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Repair {
+    pub field: &'static str,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: 0x{:02X} -> 0x{:02X}", self.field, self.old, self.new)
+    }
+}
+
+impl Snapshot {
+    /// normalize repairs header values that strict emulators reject, returning what was changed.
+    /// The border colour is masked to 0-7, an interrupt mode above 2 becomes IM 1 (the mode the
+    /// ROM runs in), and the unused high bits of 0x7FFD are cleared on 128K snapshots.
+    /// Values that are already valid are left untouched, so calling it twice changes nothing.
+    /// The repair of 0x7FFD is journaled as a paging change, so it can be undone.
+    pub fn normalize(&mut self) -> Vec<Repair> {
+        let mut repairs = Vec::new();
+
+        let border = self.header.border_color;
+        if border > 7 {
+            self.header.border_color = border & 0x07;
+            repairs.push(Repair { field: "border_color", old: border, new: border & 0x07 });
+        }
+
+        let int_mode = self.header.int_mode;
+        if int_mode > 2 {
+            self.header.int_mode = 1;
+            repairs.push(Repair { field: "int_mode", old: int_mode, new: 1 });
+        }
+
+        // set_paging rather than write_0x7ffd, which ignores writes once paging is locked
+        if let Some(x7ffd) = self.extension.as_ref().map(|extension| extension.x7ffd).filter(|x7ffd| x7ffd & 0xC0 != 0) {
+            self.set_paging(x7ffd & 0x3F, self.x1ffd);
+            repairs.push(Repair { field: "x7ffd", old: x7ffd, new: x7ffd & 0x3F });
+        }
+
+        repairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_normalize() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        snapshot.header.border_color = 0x0A;
+        snapshot.header.int_mode = 5;
+        snapshot.write_0x7ffd(0xD3).unwrap();
+
+        let repairs = snapshot.normalize();
+        assert_eq!(repairs, vec![
+            Repair { field: "border_color", old: 0x0A, new: 0x02 },
+            Repair { field: "int_mode", old: 5, new: 1 },
+            Repair { field: "x7ffd", old: 0xD3, new: 0x13 },
+        ]);
+        assert_eq!(snapshot.mapping[2], 3, "Normalizing 0x7FFD should not change the paged bank");
+        assert!(snapshot.normalize().is_empty(), "A normalized snapshot should need no further repairs");
+
+        snapshot.start_journal();
+        snapshot.set_paging(0xF3, None);
+        snapshot.normalize();
+        assert_eq!(snapshot.paging_registers(), (0x33, None));
+        assert!(snapshot.undo());
+        assert_eq!(snapshot.paging_registers(), (0xF3, None), "The repair of 0x7FFD is undone");
+    }
+}
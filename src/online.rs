@@ -0,0 +1,148 @@
+//! Looking up snapshots in ZXDB, the database of ZX Spectrum software, by file hash.
+//!
+//! Only compiled with the `online` feature. All network access goes through the
+//! [`Transport`] trait, implemented over the caller's own HTTP client (or a cache,
+//! or a fake in tests), so the crate itself never touches the network.
+
+use std::io;
+
+use md5::{Digest, Md5};
+
+use crate::json::Value;
+use crate::{Metadata, Snapshot};
+
+/// Fetches a URL. Returns None if the server has no such resource (HTTP 404).
+/// Implement it over an HTTP client such as ureq or reqwest.
+pub trait Transport {
+    fn get(&self, url: &str) -> io::Result<Option<String>>;
+}
+
+/// What ZXDB knows about a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ZxdbEntry {
+    pub id: Option<String>,
+    pub title: String,
+    pub publisher: Option<String>,
+    pub year: Option<u16>,
+}
+
+impl ZxdbEntry {
+    /// apply_to records the entry in snapshot metadata: the title, publisher and
+    /// year are added to the notes and the ZXDB id as a `zxdb:<id>` tag.
+    pub fn apply_to(&self, metadata: &mut Metadata) {
+        let mut line = self.title.clone();
+        match (&self.publisher, self.year) {
+            (Some(publisher), Some(year)) => line.push_str(&format!(" ({}, {})", publisher, year)),
+            (Some(publisher), None) => line.push_str(&format!(" ({})", publisher)),
+            (None, Some(year)) => line.push_str(&format!(" ({})", year)),
+            (None, None) => {}
+        }
+        if !metadata.notes.is_empty() {
+            metadata.notes.push('\n');
+        }
+        metadata.notes.push_str(&line);
+        if let Some(id) = &self.id {
+            metadata.tags.push(format!("zxdb:{}", id));
+        }
+    }
+
+    // reads the fields from a ZXInfo-style JSON response
+    fn from_json(text: &str) -> io::Result<ZxdbEntry> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid ZXDB response");
+        let value = Value::parse(text).ok_or_else(invalid)?;
+        let text_or_number = |value: &Value| match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(format!("{}", n)),
+            _ => None,
+        };
+        let publisher = value.get("publisher").and_then(Value::as_str).map(str::to_string)
+            .or_else(|| value.get("publishers")?.as_array()?.first()?.get("name")?.as_str().map(str::to_string));
+        let year = value.get("year").or_else(|| value.get("originalYearOfRelease"))
+            .and_then(text_or_number).and_then(|year| year.parse().ok());
+        Ok(ZxdbEntry {
+            id: value.get("entry_id").or_else(|| value.get("id")).and_then(text_or_number),
+            title: value.get("title").and_then(Value::as_str).ok_or_else(invalid)?.to_string(),
+            publisher,
+            year,
+        })
+    }
+}
+
+/// A client for a ZXDB mirror or API that looks files up by MD5, as
+/// `<base_url>/filecheck/<md5>`.
+pub struct ZxdbClient<T: Transport> {
+    pub base_url: String,
+    transport: T,
+}
+
+impl<T: Transport> ZxdbClient<T> {
+    /// new creates a client for the mirror at base_url that fetches through the transport.
+    pub fn new(base_url: &str, transport: T) -> Self {
+        ZxdbClient { base_url: base_url.trim_end_matches('/').to_string(), transport }
+    }
+
+    /// lookup_md5 finds the entry for a file with the given MD5, as lowercase hex.
+    pub fn lookup_md5(&self, md5: &str) -> io::Result<Option<ZxdbEntry>> {
+        match self.transport.get(&format!("{}/filecheck/{}", self.base_url, md5))? {
+            Some(body) => ZxdbEntry::from_json(&body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// lookup_file finds the entry for a file's contents.
+    pub fn lookup_file(&self, bin: &[u8]) -> io::Result<Option<ZxdbEntry>> {
+        self.lookup_md5(&md5_hex(bin))
+    }
+
+    /// lookup_snapshot finds the entry for a snapshot as it would be saved in .sna format.
+    /// This only matches if the snapshot is unchanged from a .sna file known to ZXDB.
+    pub fn lookup_snapshot(&self, snapshot: &Snapshot) -> io::Result<Option<ZxdbEntry>> {
+        let mut bin = Vec::new();
+        snapshot.write_to(&mut bin)?;
+        self.lookup_file(&bin)
+    }
+}
+
+/// md5_hex returns the MD5 digest of the data as lowercase hex, the key ZXDB files are indexed by.
+pub fn md5_hex(data: &[u8]) -> String {
+    Md5::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct FakeTransport {
+        requests: RefCell<Vec<String>>,
+    }
+
+    impl Transport for FakeTransport {
+        fn get(&self, url: &str) -> io::Result<Option<String>> {
+            self.requests.borrow_mut().push(url.to_string());
+            Ok(url.ends_with("/900150983cd24fb0d6963f7d28e17f72").then(|| {
+                r#"{"entry_id":"0003012","title":"Manic Miner","publishers":[{"name":"Bug-Byte"}],"originalYearOfRelease":1983}"#.to_string()
+            }))
+        }
+    }
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"The quick brown fox jumps over the lazy dog"), "9e107d9d372bb6826bd81d3542a419d6");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let client = ZxdbClient::new("http://mirror.local/api/", FakeTransport { requests: RefCell::new(Vec::new()) });
+        let entry = client.lookup_file(b"abc").expect("Lookup failed").expect("Entry not found");
+        assert_eq!(entry, ZxdbEntry { id: Some("0003012".to_string()), title: "Manic Miner".to_string(), publisher: Some("Bug-Byte".to_string()), year: Some(1983) });
+        assert_eq!(client.lookup_file(b"abcd").expect("Lookup failed"), None);
+        assert_eq!(client.transport.requests.borrow()[0], "http://mirror.local/api/filecheck/900150983cd24fb0d6963f7d28e17f72");
+
+        let mut metadata = Metadata::default();
+        entry.apply_to(&mut metadata);
+        assert_eq!(metadata.notes, "Manic Miner (Bug-Byte, 1983)");
+        assert_eq!(metadata.tags, ["zxdb:0003012"]);
+    }
+}
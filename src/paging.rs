@@ -0,0 +1,295 @@
+use crate::{BankAddr, BankId, Machine, SnaError, Snapshot, SnapshotType};
+
+// how far after LD BC,port the OUT (C),r may appear
+const OUT_WINDOW: usize = 16;
+
+// the bit of 0x7FFD that locks paging until reset
+pub(crate) const LOCK: u8 = 0x20;
+
+// the banks at 0x0000, 0x4000, 0x8000 and 0xC000 in each +2A/+3 special paging
+// configuration, chosen by bits 1-2 of 0x1FFD
+const SPECIAL_PAGING: [[u8; 4]; 4] = [[0, 1, 2, 3], [4, 5, 6, 7], [4, 5, 6, 3], [4, 7, 6, 3]];
+
+/// The 128K paging state, one field per part of the value last written to port 0x7FFD.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PagingState {
+    pub ram_bank: u8,           // bits 0-2, the bank mapped into 0xC000-0xFFFF
+    pub shadow_screen: bool,    // bit 3, show the screen in bank 7 rather than bank 5
+    pub rom: u8,                // bit 4, 0 for the 128K editor ROM and 1 for 48K BASIC
+    pub locked: bool,           // bit 5, ignore further writes until reset
+}
+
+impl PagingState {
+    /// from_byte decodes a value written to port 0x7FFD. Bits 6 and 7 are unused.
+    pub fn from_byte(x7ffd: u8) -> PagingState {
+        PagingState {
+            ram_bank: x7ffd & 0x07,
+            shadow_screen: x7ffd & 0x08 != 0,
+            rom: (x7ffd >> 4) & 1,
+            locked: x7ffd & LOCK != 0,
+        }
+    }
+
+    /// to_byte encodes the state as a value for port 0x7FFD. Only the low bits of
+    /// ram_bank and rom are used.
+    pub fn to_byte(self) -> u8 {
+        (self.locked as u8) << 5 | (self.rom & 1) << 4 | (self.shadow_screen as u8) << 3 | (self.ram_bank & 0x07)
+    }
+}
+
+/// Which paging port an instruction sequence writes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PagingPort {
+    Port7ffd,   // 128K memory paging
+    Port1ffd,   // +2A/+3 extended paging
+}
+
+/// A code sequence that writes to a paging port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PagingWrite {
+    pub port: PagingPort,
+    pub location: BankAddr,     // the start of the sequence
+}
+
+impl Snapshot {
+    /// mapping returns the banks mapped into 0x4000, 0x8000 and 0xC000 by the paging
+    /// ports. It is changed through write_0x7ffd, write_0x1ffd and set_paging_state,
+    /// which keep the memory peek and poke see in step with it.
+    pub fn mapping(&self) -> [u8; 3] {
+        self.mapping
+    }
+
+    /// x1ffd returns the value last written to port 0x1FFD, or None on machines without
+    /// the port. It is changed through write_0x1ffd.
+    pub fn x1ffd(&self) -> Option<u8> {
+        self.x1ffd
+    }
+
+    /// paging_state returns the decoded 128K paging state.
+    /// Returns SnaError::Not128K for 48K snapshots.
+    pub fn paging_state(&self) -> Result<PagingState, SnaError> {
+        match (self.snapshot_type, self.extension.as_ref()) {
+            (SnapshotType::Snapshot128, Some(extension)) => Ok(PagingState::from_byte(extension.x7ffd)),
+            _ => Err(SnaError::Not128K),
+        }
+    }
+
+    /// set_paging_state changes the 128K paging as write_0x7ffd does, but even when
+    /// paging is locked, as a debugger can. Returns SnaError::Not128K for 48K snapshots.
+    pub fn set_paging_state(&mut self, state: PagingState) -> Result<(), SnaError> {
+        self.paging_state()?;
+        self.set_paging(state.to_byte(), self.x1ffd);
+        Ok(())
+    }
+
+    /// write_0x1ffd writes to the second paging port of a +2A/+3 or Scorpion 256 as an
+    /// OUT would. Writes are ignored once bit 5 of 0x7FFD has locked paging.
+    ///
+    /// On a +2A/+3, with bit 0 set special paging maps RAM over the whole address space
+    /// in one of four bank configurations chosen by bits 1-2: 0-1-2-3, 4-5-6-7, 4-5-6-3
+    /// or 4-7-6-3. With it clear, 0x7FFD paging applies and bit 2 is the high bit of the
+    /// ROM number, so four ROMs can be selected.
+    ///
+    /// On a Scorpion 256, bit 0 maps bank 0 over the ROM, bit 1 selects ROM 2 whatever
+    /// 0x7FFD says, and bit 4 adds 8 to the bank 0x7FFD pages in at 0xC000.
+    ///
+    /// Returns SnaError::NotPlus3 for machines without the port.
+    pub fn write_0x1ffd(&mut self, value: u8) -> Result<(), SnaError> {
+        let locked = self.extension.as_ref().is_some_and(|extension| extension.x7ffd & LOCK != 0);
+        match self.x1ffd {
+            Some(_) if locked => {}
+            Some(_) => self.set_paging(self.paging_registers().0, Some(value)),
+            None => return Err(SnaError::NotPlus3),
+        }
+        Ok(())
+    }
+
+    // the bank the paging ports select for 0xC000
+    pub(crate) fn paged_bank(&self) -> u8 {
+        let x7ffd = self.extension.as_ref().map_or(0, |extension| extension.x7ffd);
+        match (self.machine(), self.x1ffd) {
+            (Machine::Scorpion256, Some(x1ffd)) => (x1ffd >> 1) & 0x08 | x7ffd & 0x07,
+            _ => x7ffd & 0x07,
+        }
+    }
+
+    // the banks mapped from 0x0000 by special paging, or None if it is not in use
+    pub(crate) fn special_paging(&self) -> Option<[u8; 4]> {
+        match (self.machine(), self.x1ffd) {
+            (machine, Some(x1ffd)) if machine.has_plus3_paging() && x1ffd & 1 != 0 => Some(SPECIAL_PAGING[((x1ffd >> 1) & 0x03) as usize]),
+            (Machine::Scorpion256, Some(x1ffd)) if x1ffd & 1 != 0 => Some([0, self.mapping[0], self.mapping[1], self.mapping[2]]),
+            _ => None,
+        }
+    }
+
+    /// find_paging_writes scans every bank for code that writes to the 128K paging
+    /// ports: `LD BC,$7FFD` (or `$1FFD`) followed shortly by an `OUT (C),r`, and
+    /// `LD A,$7F` (or `$1F`) followed by `OUT ($FD),A`.
+    /// A snapshot with no matches almost certainly never pages memory, so it can be
+    /// safely converted to 48K. Matches are a strong hint, not proof, that it does.
+    pub fn find_paging_writes(&self) -> Vec<PagingWrite> {
+        let mut writes = Vec::new();
+        for (id, data, _) in self.banks() {
+            for offset in 0..data.len() {
+                if let Some(port) = paging_write_at(data, offset) {
+                    writes.push(PagingWrite { port, location: BankAddr { bank: id, offset: offset as u16 } });
+                }
+            }
+        }
+        writes
+    }
+
+    /// pages_memory returns whether any code in the snapshot appears to write to a paging port.
+    pub fn pages_memory(&self) -> bool {
+        self.banks().any(|(_, data, _)| (0..data.len()).any(|offset| paging_write_at(data, offset).is_some()))
+    }
+
+    /// paging_banks returns the banks that contain paging code.
+    pub fn paging_banks(&self) -> Vec<BankId> {
+        let mut banks: Vec<BankId> = self.find_paging_writes().iter().map(|write| write.location.bank).collect();
+        banks.dedup();
+        banks
+    }
+}
+
+fn port(high: u8) -> Option<PagingPort> {
+    match high {
+        0x7F => Some(PagingPort::Port7ffd),
+        0x1F => Some(PagingPort::Port1ffd),
+        _ => None,
+    }
+}
+
+fn paging_write_at(data: &[u8], offset: usize) -> Option<PagingPort> {
+    match data[offset..] {
+        // LD BC,$xxFD ... OUT (C),r
+        [0x01, 0xFD, high, ref rest @ ..] => {
+            let port = port(high)?;
+            let rest = &rest[..rest.len().min(OUT_WINDOW)];
+            rest.windows(2).any(|pair| pair[0] == 0xED && matches!(pair[1], 0x41 | 0x49 | 0x51 | 0x59 | 0x61 | 0x69 | 0x79))
+                .then_some(port)
+        }
+        // LD A,$xx : OUT ($FD),A
+        [0x3E, high, 0xD3, 0xFD, ..] => port(high),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn test_find_paging_writes() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        snapshot.banks_mut(|_, bank, _| bank.fill(0));
+        assert!(!snapshot.pages_memory());
+
+        // LD BC,$7FFD : LD A,$17 : OUT (C),A
+        for (offset, byte) in [0x01, 0xFD, 0x7F, 0x3E, 0x17, 0xED, 0x79].into_iter().enumerate() {
+            snapshot.bank_poke(2, 0x100 + offset as u16, byte).unwrap();
+        }
+        // LD A,$1F : OUT ($FD),A
+        for (offset, byte) in [0x3E, 0x1F, 0xD3, 0xFD].into_iter().enumerate() {
+            snapshot.bank_poke(6, 0x200 + offset as u16, byte).unwrap();
+        }
+        // LD BC,$7FFD with no OUT nearby
+        snapshot.bank_poke(3, 0, 0x01).unwrap();
+        snapshot.bank_poke(3, 1, 0xFD).unwrap();
+        snapshot.bank_poke(3, 2, 0x7F).unwrap();
+
+        assert!(snapshot.pages_memory());
+        assert_eq!(snapshot.find_paging_writes(), [
+            PagingWrite { port: PagingPort::Port7ffd, location: BankAddr::new(BankId(2), 0x100).unwrap() },
+            PagingWrite { port: PagingPort::Port1ffd, location: BankAddr::new(BankId(6), 0x200).unwrap() },
+        ]);
+        assert_eq!(snapshot.paging_banks(), [BankId(2), BankId(6)]);
+    }
+
+    #[test]
+    fn test_paging_state() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.write_0x7ffd(0x1B).unwrap();
+        let state = snapshot.paging_state().unwrap();
+        assert_eq!(state, PagingState { ram_bank: 3, shadow_screen: true, rom: 1, locked: false });
+        assert_eq!(state.to_byte(), 0x1B);
+        assert_eq!(PagingState::from_byte(0xC0), PagingState::default(), "Bits 6 and 7 are unused");
+        assert_eq!(snapshot.screen_bank(), 7);
+
+        snapshot.write_0x7ffd(0x24).unwrap();
+        assert_eq!(snapshot.mapping()[2], 4);
+        snapshot.write_0x7ffd(0x01).unwrap();
+        assert_eq!(snapshot.paging_state().unwrap(), PagingState { ram_bank: 4, shadow_screen: false, rom: 0, locked: true }, "Writes are ignored once locked");
+        assert_eq!(snapshot.mapping()[2], 4);
+
+        snapshot.set_paging_state(PagingState { ram_bank: 1, ..PagingState::default() }).unwrap();
+        assert_eq!(snapshot.mapping(), [5, 2, 1]);
+        assert!(!snapshot.paging_state().unwrap().locked);
+
+        let mut snapshot = Snapshot::new_48k();
+        assert!(matches!(snapshot.paging_state(), Err(SnaError::Not128K)));
+        assert!(matches!(snapshot.set_paging_state(PagingState::default()), Err(SnaError::Not128K)));
+    }
+
+    #[test]
+    fn test_write_0x1ffd() {
+        let mut snapshot = Snapshot::new_plus3();
+        for bank in 0..8 {
+            snapshot.bank_poke(bank, 0, bank as u8).unwrap();
+        }
+        let windows = |snapshot: &Snapshot| [0x0000, 0x4000, 0x8000, 0xC000].map(|address| snapshot.peek(address));
+        snapshot.write_0x1ffd(0x01).unwrap();
+        assert_eq!(windows(&snapshot), [0, 1, 2, 3]);
+        snapshot.write_0x1ffd(0x07).unwrap();
+        assert_eq!(windows(&snapshot), [4, 7, 6, 3]);
+        snapshot.poke(0x0001, 0x42).unwrap();
+        assert_eq!(snapshot.bank_peek(4, 1).unwrap(), 0x42, "Special paging maps RAM at 0x0000");
+        assert!(snapshot.layout().bank_at(0x0000).is_some());
+
+        snapshot.write_0x7ffd(0x06).unwrap();
+        assert_eq!(windows(&snapshot)[3], 3, "0x7FFD does not page while special paging is on");
+        snapshot.write_0x1ffd(0x00).unwrap();
+        assert_eq!(windows(&snapshot)[1..], [5, 2, 6]);
+        assert!(snapshot.poke(0x0001, 0).is_err(), "ROM is back at 0x0000");
+
+        snapshot.attach_rom(&(0..4u8).flat_map(|rom| [rom; crate::MEM_16K]).collect::<Vec<_>>()).unwrap();
+        snapshot.write_0x1ffd(0x04).unwrap();
+        snapshot.write_0x7ffd(0x10).unwrap();
+        assert_eq!(snapshot.active_rom(), Some(3), "Bit 2 of 0x1FFD and bit 4 of 0x7FFD select ROM 3");
+
+        snapshot.write_0x7ffd(0x20).unwrap();
+        snapshot.write_0x1ffd(0x01).unwrap();
+        assert_eq!(snapshot.x1ffd(), Some(0x04), "Locking 0x7FFD locks 0x1FFD too");
+
+        assert!(matches!(Snapshot::new_128k().write_0x1ffd(0x01), Err(SnaError::NotPlus3)));
+    }
+
+    #[test]
+    fn test_scorpion_paging() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.set_machine(Machine::Scorpion256).unwrap();
+        snapshot.bank_poke(11, 0, 0x11).unwrap();
+        snapshot.bank_poke(0, 0, 0x42).unwrap();
+        snapshot.write_0x7ffd(0x03).unwrap();
+        snapshot.write_0x1ffd(0x10).unwrap();
+        assert_eq!(snapshot.mapping()[2], 11);
+        assert_eq!(snapshot.peek(0xC000), 0x11, "Bit 4 of 0x1FFD pages in bank 8 + 3");
+        snapshot.write_0x7ffd(0x04).unwrap();
+        assert_eq!(snapshot.mapping()[2], 12, "The extra bit is kept across 0x7FFD writes");
+
+        snapshot.write_0x1ffd(0x01).unwrap();
+        assert_eq!(snapshot.mapping()[2], 4);
+        assert_eq!(snapshot.peek(0x0000), 0x42, "Bit 0 of 0x1FFD maps bank 0 over the ROM");
+        snapshot.write_0x1ffd(0x02).unwrap();
+        snapshot.attach_rom(&(0..4u8).flat_map(|rom| [rom; crate::MEM_16K]).collect::<Vec<_>>()).unwrap();
+        assert_eq!(snapshot.active_rom(), Some(2), "Bit 1 of 0x1FFD selects ROM 2");
+
+        snapshot.write_0x1ffd(0x10).unwrap();
+        let mut bin = Vec::new();
+        snapshot.write_to(&mut bin).expect("Failed to write snapshot");
+        let loaded = Snapshot::try_from(&bin[..]).unwrap();
+        assert_eq!(loaded.bank_peek(11, 0).unwrap(), 0x11, "Bank 11 is stored among banks 8 to 15");
+        assert_eq!(loaded.mapping()[2], 4, "The file only records 0x7FFD");
+    }
+}
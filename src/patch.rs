@@ -0,0 +1,100 @@
+use crate::{SnaError, Snapshot};
+
+/// A set of writes to mapped memory, applied all together or not at all.
+/// Usually built with the [`patch!`](crate::patch!) macro.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Patch {
+    pub writes: Vec<(u16, Vec<u8>)>,   // start address and bytes, applied in order
+}
+
+impl Patch {
+    /// new creates an empty patch.
+    pub fn new() -> Patch {
+        Patch::default()
+    }
+
+    /// bytes adds a write of bytes starting at an address.
+    pub fn bytes(&mut self, address: u16, bytes: &[u8]) -> &mut Patch {
+        self.writes.push((address, bytes.to_vec()));
+        self
+    }
+
+    /// word adds a write of a little-endian word.
+    pub fn word(&mut self, address: u16, value: u16) -> &mut Patch {
+        self.bytes(address, &value.to_le_bytes())
+    }
+
+    /// validate checks that every write lies in RAM as the snapshot is paged, without
+    /// writing anything. Returns SnaError::RomWrite if a write is in ROM, or
+    /// SnaError::AddressOverflow if it runs past 0xFFFF.
+    pub fn validate(&self, snapshot: &Snapshot) -> Result<(), SnaError> {
+        for (address, bytes) in &self.writes {
+            if snapshot.is_rom(*address) {
+                return Err(SnaError::RomWrite(*address));
+            }
+            if *address as usize + bytes.len() > 0x10000 {
+                return Err(SnaError::AddressOverflow { address: *address, len: bytes.len() });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// apply_patch validates every write in the patch and then applies them all.
+    /// If any write is invalid, or blocked by a guard (SnaError::Guarded), the snapshot
+    /// is left unchanged.
+    pub fn apply_patch(&mut self, patch: &Patch) -> Result<(), SnaError> {
+        patch.validate(self)?;
+        for (address, bytes) in &patch.writes {
+            self.check_write(*address, bytes.len())?;
+        }
+        self.grouped(|snapshot| patch.writes.iter().try_for_each(|(address, bytes)| snapshot.poke_slice(*address, bytes)))
+    }
+}
+
+/// Applies byte and word writes to a snapshot as one validated patch, returning
+/// `Result<(), SnaError>`. Nothing is written unless every write is valid.
+/// Bytes are given as a list and words with a `w` prefix, e.g.
+/// `patch!(snapshot, 0x8000 => [0x3E, 5, 0xC9], 0x9000 => w 0x1234)`.
+#[macro_export]
+macro_rules! patch {
+    (@write $patch:ident;) => {};
+    (@write $patch:ident; $address:expr => w $value:expr $(, $($rest:tt)*)?) => {
+        $patch.word($address, $value);
+        $crate::patch!(@write $patch; $($($rest)*)?);
+    };
+    (@write $patch:ident; $address:expr => [$($byte:expr),* $(,)?] $(, $($rest:tt)*)?) => {
+        $patch.bytes($address, &[$($byte),*]);
+        $crate::patch!(@write $patch; $($($rest)*)?);
+    };
+    ($snapshot:expr, $($writes:tt)+) => {{
+        let mut patch = $crate::Patch::new();
+        $crate::patch!(@write patch; $($writes)+);
+        $snapshot.apply_patch(&patch)
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures;
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_patch_macro() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, Default::default());
+        crate::patch!(snapshot, 0x8000 => [0x3E, 5, 0xC9], 0x9000 => w 0x1234,).expect("Failed to apply patch");
+        assert_eq!([snapshot.peek(0x8000), snapshot.peek(0x8001), snapshot.peek(0x8002)], [0x3E, 5, 0xC9]);
+        assert_eq!(snapshot.peek_word(0x9000).ok(), Some(0x1234));
+
+        let before = snapshot.clone();
+        let result = crate::patch!(snapshot, 0x8000 => [0], 0x3FFF => [0xFF]);
+        assert!(matches!(result, Err(crate::SnaError::RomWrite(0x3FFF))), "A write to ROM should fail");
+        assert_eq!(snapshot.peek(0x8000), before.peek(0x8000), "A failed patch should not write anything");
+        assert!(matches!(crate::patch!(snapshot, 0xFFFF => w 0), Err(crate::SnaError::AddressOverflow { address: 0xFFFF, len: 2 })), "A word at 0xFFFF runs past the end of memory");
+
+        snapshot.guard("lives", 0x8001..0x8002, crate::GuardAction::Block);
+        assert!(matches!(crate::patch!(snapshot, 0x9000 => [1], 0x8000 => [0, 0, 0]), Err(crate::SnaError::Guarded { .. })), "A guarded write should fail");
+        assert_eq!((snapshot.peek(0x9000), snapshot.peek(0x8000)), (0x34, 0x3E), "A blocked patch should not write anything");
+    }
+}
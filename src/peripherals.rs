@@ -0,0 +1,206 @@
+// Optional hardware state beyond the CPU and memory. The .sna format cannot
+// carry any of it, so it is only present when set by the caller or loaded from
+// a richer format.
+
+use std::collections::BTreeMap;
+
+use crate::{SnaError, Snapshot};
+
+/// The state of the AY-3-8912 sound chip found in 128K machines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AyState {
+    pub registers: [u8; 16],
+    pub selected: u8,   // register last selected through port 0xFFFD
+}
+
+impl AyState {
+    /// write_0xfffd selects the register that following writes to port 0xBFFD will change.
+    pub fn write_0xfffd(&mut self, register: u8) {
+        self.selected = register & 0x0F;
+    }
+
+    /// write_0xbffd writes a value to the currently selected register.
+    pub fn write_0xbffd(&mut self, value: u8) {
+        self.registers[(self.selected & 0x0F) as usize] = value;
+    }
+
+    /// read_0xfffd reads the currently selected register.
+    pub fn read_0xfffd(&self) -> u8 {
+        self.registers[(self.selected & 0x0F) as usize]
+    }
+}
+
+/// The joystick interfaces a snapshot may record.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JoystickType {
+    #[default]
+    None,
+    Kempston,
+    Sinclair1,
+    Sinclair2,
+    Cursor,
+}
+
+/// The state of the keyboard matrix and joystick.
+/// Each of the 8 keyboard half-rows holds 5 active-low key bits, as read from port 0xFE
+/// with address lines A8-A15 selecting the rows; 0x1F means no key in the row is down.
+/// The Kempston byte is active-high as read from port 0x1F.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputState {
+    pub keyboard: [u8; 8],
+    pub joystick: JoystickType,
+    pub kempston: u8,
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        InputState { keyboard: [0x1F; 8], joystick: JoystickType::None, kempston: 0 }
+    }
+}
+
+impl InputState {
+    /// set_key presses or releases a key, given its half-row (0 for CAPS SHIFT-V through
+    /// 7 for SPACE-B, in address line order) and bit (0-4).
+    /// Returns SnaError::InvalidLength if the row or bit is not on the keyboard matrix.
+    pub fn set_key(&mut self, row: usize, bit: u8, pressed: bool) -> Result<(), SnaError> {
+        if row > 7 {
+            return Err(SnaError::InvalidLength { len: row, max: 7 });
+        }
+        if bit > 4 {
+            return Err(SnaError::InvalidLength { len: bit as usize, max: 4 });
+        }
+        if pressed {
+            self.keyboard[row] &= !(1 << bit);
+        } else {
+            self.keyboard[row] |= 1 << bit;
+        }
+        Ok(())
+    }
+
+    /// read_0xfe returns the key bits seen by an IN from port 0xFE with the given high address byte.
+    /// Every half-row whose address line is low is combined, as on the real machine.
+    /// Bits 5-7 are returned set.
+    pub fn read_0xfe(&self, high: u8) -> u8 {
+        let keys = (0..8).filter(|row| high & (1 << row) == 0)
+            .fold(0x1F, |keys, row| keys & self.keyboard[row]);
+        keys | 0xE0
+    }
+
+    /// Returns true if any key is held down.
+    pub fn any_key_down(&self) -> bool {
+        self.keyboard.iter().any(|&row| row & 0x1F != 0x1F)
+    }
+}
+
+/// Custom state that third parties can attach to a snapshot.
+/// Attachments are stored serialized, keyed by a four character chunk id in the
+/// style of SZX chunks, so that state the crate knows nothing about is carried
+/// through clones and conversions unchanged.
+pub trait PeripheralState: Sized {
+    /// The chunk id the state is stored under. It must be unique to the state type.
+    const CHUNK_ID: [u8; 4];
+
+    /// Serializes the state.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Deserializes the state, returning None if the bytes are not valid.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Snapshot {
+    /// attach_state stores custom state on the snapshot, replacing any earlier state of the same type.
+    pub fn attach_state<T: PeripheralState>(&mut self, state: &T) {
+        self.attachments.insert(T::CHUNK_ID, state.to_bytes());
+    }
+
+    /// state returns the custom state of the given type, if attached and valid.
+    pub fn state<T: PeripheralState>(&self) -> Option<T> {
+        self.attachments.get(&T::CHUNK_ID).and_then(|bytes| T::from_bytes(bytes))
+    }
+
+    /// detach_state removes the custom state of the given type, returning it if attached and valid.
+    pub fn detach_state<T: PeripheralState>(&mut self) -> Option<T> {
+        self.attachments.remove(&T::CHUNK_ID).and_then(|bytes| T::from_bytes(&bytes))
+    }
+
+    /// attach_raw stores serialized state under a chunk id without knowing its type,
+    /// for loaders carrying through chunks they do not understand.
+    pub fn attach_raw(&mut self, chunk_id: [u8; 4], bytes: Vec<u8>) {
+        self.attachments.insert(chunk_id, bytes);
+    }
+
+    /// Iterates over every attachment as its chunk id and serialized bytes, in chunk id order.
+    pub fn attachments(&self) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+        self.attachments.iter().map(|(id, bytes)| (*id, bytes.as_slice()))
+    }
+}
+
+pub(crate) type AttachmentMap = BTreeMap<[u8; 4], Vec<u8>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ay_ports() {
+        let mut ay = AyState::default();
+        ay.write_0xfffd(0x17);
+        ay.write_0xbffd(0x3F);
+        assert_eq!(ay.selected, 7, "Register select should be masked to 0-15");
+        assert_eq!(ay.registers[7], 0x3F);
+        assert_eq!(ay.read_0xfffd(), 0x3F);
+    }
+
+    #[test]
+    fn test_keyboard_matrix() {
+        let mut input = InputState::default();
+        assert!(!input.any_key_down());
+        input.set_key(7, 0, true).unwrap();   // SPACE
+        input.set_key(0, 1, true).unwrap();   // Z
+        assert_eq!(input.read_0xfe(0x7F), 0xFE, "SPACE should read on half-row 0x7FFE");
+        assert_eq!(input.read_0xfe(0xFE), 0xFD, "Z should read on half-row 0xFEFE");
+        assert_eq!(input.read_0xfe(0x00), 0xFC, "Reading all rows should combine them");
+        input.set_key(7, 0, false).unwrap();
+        assert!(matches!(input.set_key(8, 0, true), Err(SnaError::InvalidLength { len: 8, max: 7 })));
+        assert!(matches!(input.set_key(0, 5, true), Err(SnaError::InvalidLength { len: 5, max: 4 })));
+        assert_eq!(input.read_0xfe(0x7F), 0xFF, "Released key should read high");
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Multiface {
+        paged: bool,
+    }
+
+    impl PeripheralState for Multiface {
+        const CHUNK_ID: [u8; 4] = *b"MFCE";
+
+        fn to_bytes(&self) -> Vec<u8> {
+            vec![self.paged as u8]
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Option<Self> {
+            match bytes {
+                [paged] => Some(Multiface { paged: *paged != 0 }),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_attachments() {
+        let mut snapshot = crate::fixtures::generate(crate::SnapshotType::Snapshot48, Default::default());
+        assert_eq!(snapshot.state::<Multiface>(), None);
+
+        snapshot.attach_state(&Multiface { paged: true });
+        snapshot.attach_raw(*b"ZZZZ", vec![1, 2, 3]);
+        let clone = snapshot.clone();
+        assert_eq!(clone.state::<Multiface>(), Some(Multiface { paged: true }), "Attachment did not survive a clone");
+        assert_eq!(clone.attachments().map(|(id, _)| id).collect::<Vec<_>>(), [*b"MFCE", *b"ZZZZ"]);
+
+        assert_eq!(snapshot.detach_state::<Multiface>(), Some(Multiface { paged: true }));
+        assert_eq!(snapshot.state::<Multiface>(), None, "Detached state should be gone");
+    }
+}
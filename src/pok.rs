@@ -0,0 +1,309 @@
+//! Reading and writing .POK trainer files, as distributed by World of Spectrum.
+//!
+//! A .POK file is a list of trainers, each an `N` line with its name followed by
+//! `M` lines for its pokes, the last of which is a `Z` line. The file ends with `Y`.
+//! Each poke line gives a bank, an address, a value and the original value. Bank 8
+//! (or any bank with bit 3 set) means the poke is not tied to a 128K bank, and a value
+//! of 256 means the user is asked for the value, as for "number of lives" trainers.
+//!
+//! Pokes found by searching or diffing can be recorded as trainers and written back out
+//! with [`save`].
+
+use std::path::Path;
+
+use crate::trainer::{to_pok, Poke, ParseError, Trainer};
+use crate::{SnaError, Snapshot, SnapshotType};
+
+/// The value of a .POK poke.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PokValue {
+    Byte(u8),
+    Ask,        // written as 256, the user chooses the value
+}
+
+/// A poke as written in a .POK file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PokEntry {
+    pub bank: Option<u8>,
+    pub address: u16,
+    pub value: PokValue,
+    pub original: u8,
+}
+
+/// A named trainer read from a .POK file.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PokTrainer {
+    pub name: String,
+    pub entries: Vec<PokEntry>,
+}
+
+impl PokTrainer {
+    /// asks returns whether any poke takes its value from the user.
+    pub fn asks(&self) -> bool {
+        self.entries.iter().any(|entry| entry.value == PokValue::Ask)
+    }
+
+    /// resolve turns the entries into a trainer, using the answer for every poke whose
+    /// value the user is asked for. The answer is ignored if no poke asks.
+    pub fn resolve(&self, answer: u8) -> Trainer {
+        let pokes = self.entries.iter().map(|entry| Poke {
+            bank: entry.bank,
+            address: entry.address,
+            value: match entry.value {
+                PokValue::Byte(value) => value,
+                PokValue::Ask => answer,
+            },
+            original: entry.original,
+        }).collect();
+        Trainer { name: self.name.clone(), pokes }
+    }
+}
+
+/// parse reads the trainers in a .POK file. Blank lines are ignored, as is anything
+/// after the `Y` line.
+pub fn parse(text: &str) -> Result<Vec<PokTrainer>, ParseError> {
+    let mut trainers: Vec<PokTrainer> = Vec::new();
+    let mut open = false;  // whether the last trainer is still waiting for its Z line
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| ParseError { line: index + 1, message };
+        let line = line.trim_end();
+        let Some(marker) = line.chars().next() else { continue };
+        match marker {
+            'N' => {
+                if open {
+                    return Err(error("trainer started before the last one ended with Z".to_string()));
+                }
+                trainers.push(PokTrainer { name: line[1..].trim().to_string(), entries: Vec::new() });
+                open = true;
+            }
+            'M' | 'Z' => {
+                let trainer = trainers.last_mut().filter(|_| open)
+                    .ok_or_else(|| error(format!("poke outside a trainer in '{}'", line)))?;
+                let fields: Vec<u32> = line[1..].split_whitespace()
+                    .map(|field| field.parse().map_err(|_| error(format!("invalid number '{}'", field))))
+                    .collect::<Result<_, _>>()?;
+                let [bank, address, value, original] = fields[..] else {
+                    return Err(error(format!("expected bank, address, value and original in '{}'", line)));
+                };
+                let address = u16::try_from(address).map_err(|_| error(format!("invalid address {}", address)))?;
+                let value = match value {
+                    256 => PokValue::Ask,
+                    value => PokValue::Byte(u8::try_from(value).map_err(|_| error(format!("invalid value {}", value)))?),
+                };
+                let original = u8::try_from(original).map_err(|_| error(format!("invalid original value {}", original)))?;
+                let bank = if bank & 0x08 != 0 { None } else { Some((bank & 0x07) as u8) };
+                trainer.entries.push(PokEntry { bank, address, value, original });
+                open = marker == 'M';
+            }
+            'Y' => break,
+            _ => return Err(error(format!("unexpected line '{}'", line))),
+        }
+    }
+    if open {
+        return Err(ParseError { line: text.lines().count(), message: "last trainer does not end with Z".to_string() });
+    }
+    Ok(trainers)
+}
+
+/// load reads a .POK file. Parse errors are returned as SnaError::InvalidFormat.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<PokTrainer>, SnaError> {
+    let text = std::fs::read_to_string(path)?;
+    parse(&text).map_err(|e| SnaError::InvalidFormat(e.to_string()))
+}
+
+/// save writes trainers as a .POK file, see [`to_pok`](crate::trainer::to_pok).
+pub fn save<P: AsRef<Path>>(path: P, trainers: &[Trainer]) -> Result<(), SnaError> {
+    Ok(std::fs::write(path, to_pok(trainers)?)?)
+}
+
+/// Pokes a snapshot and remembers each poke, with the value it replaced, so that the
+/// pokes can be saved as a trainer. Created by [`Snapshot::record_pokes`].
+pub struct PokeRecorder<'a> {
+    snapshot: &'a mut Snapshot,
+    pokes: Vec<Poke>,
+}
+
+impl PokeRecorder<'_> {
+    /// poke writes a byte to mapped memory and records it. On 128K snapshots pokes at or
+    /// above 0xC000 are recorded against the bank paged in there.
+    pub fn poke(&mut self, address: u16, value: u8) -> Result<(), SnaError> {
+        let original = self.snapshot.peek(address);
+        self.snapshot.poke(address, value)?;
+        let bank = (address >= 0xC000 && self.snapshot.snapshot_type == SnapshotType::Snapshot128).then_some(self.snapshot.mapping[2]);
+        self.record(Poke { bank, address, value, original });
+        Ok(())
+    }
+
+    /// bank_poke writes a byte at an offset within a bank and records it. Banks that .POK
+    /// files cannot name, such as banks 5 and 2 of a 128K snapshot or any bank of a 48K
+    /// one, are recorded at the address they are always mapped to.
+    pub fn bank_poke(&mut self, bank: usize, offset: u16, value: u8) -> Result<(), SnaError> {
+        let original = self.snapshot.bank_peek(bank, offset)?;
+        self.snapshot.bank_poke(bank, offset, value)?;
+        self.record(bank_location(self.snapshot.snapshot_type, bank, offset, value, original));
+        Ok(())
+    }
+
+    /// pokes returns the pokes recorded so far, one per address.
+    pub fn pokes(&self) -> &[Poke] {
+        &self.pokes
+    }
+
+    /// into_trainer returns the recorded pokes as a named trainer.
+    pub fn into_trainer(self, name: &str) -> Trainer {
+        Trainer { name: name.to_string(), pokes: self.pokes }
+    }
+
+    // a second poke to an address keeps the original from the first
+    fn record(&mut self, poke: Poke) {
+        match self.pokes.iter_mut().find(|recorded| recorded.bank == poke.bank && recorded.address == poke.address) {
+            Some(recorded) => recorded.value = poke.value,
+            None => self.pokes.push(poke),
+        }
+    }
+}
+
+fn bank_location(snapshot_type: SnapshotType, bank: usize, offset: u16, value: u8, original: u8) -> Poke {
+    let offset = offset & 0x3FFF;
+    match (snapshot_type, bank) {
+        (SnapshotType::Snapshot48, _) => Poke { bank: None, address: 0x4000 * (bank as u16 + 1) + offset, value, original },
+        (SnapshotType::Snapshot128, 5) => Poke { bank: None, address: 0x4000 + offset, value, original },
+        (SnapshotType::Snapshot128, 2) => Poke { bank: None, address: 0x8000 + offset, value, original },
+        (SnapshotType::Snapshot128, _) => Poke { bank: Some(bank as u8), address: 0xC000 + offset, value, original },
+    }
+}
+
+impl Trainer {
+    /// from_diff makes a trainer of every byte that differs between two snapshots of the
+    /// same game, with the values in `after` as the pokes and those in `before` as the
+    /// originals. Every bank is compared, whether or not it is paged in.
+    /// Returns SnaError::TypeMismatch if the snapshots are not of the same type.
+    pub fn from_diff(name: &str, before: &Snapshot, after: &Snapshot) -> Result<Trainer, SnaError> {
+        if before.snapshot_type != after.snapshot_type {
+            return Err(SnaError::TypeMismatch { expected: before.snapshot_type, found: after.snapshot_type });
+        }
+        let mut pokes = Vec::new();
+        for (bank, (old, new)) in before.banks.iter().zip(&after.banks).enumerate() {
+            for (offset, (&original, &value)) in old.iter().zip(new.iter()).enumerate() {
+                if original != value {
+                    pokes.push(bank_location(before.snapshot_type, bank, offset as u16, value, original));
+                }
+            }
+        }
+        pokes.sort_by_key(|poke| (poke.address, poke.bank));
+        Ok(Trainer { name: name.to_string(), pokes })
+    }
+}
+
+impl Snapshot {
+    /// record_pokes returns a recorder whose pokes are written to this snapshot and
+    /// remembered, to be saved as a trainer once a cheat has been found.
+    pub fn record_pokes(&mut self) -> PokeRecorder<'_> {
+        PokeRecorder { snapshot: self, pokes: Vec::new() }
+    }
+
+    /// plan_pok is a dry run of apply_pok: it returns the pokes that would be performed,
+    /// each with the value currently in memory as its original, without writing anything.
+    /// Returns an error if any poke is into ROM or a bank the snapshot does not have.
+    pub fn plan_pok(&self, trainer: &PokTrainer, answer: u8) -> Result<Vec<Poke>, SnaError> {
+        let trainer = trainer.resolve(answer);
+        trainer.pokes.iter().map(|poke| {
+            let original = match poke.bank {
+                Some(bank) if poke.address >= 0xC000 && self.snapshot_type == SnapshotType::Snapshot128 => {
+                    self.bank_peek(bank as usize, poke.address)?
+                }
+                _ => {
+                    self.check_write(poke.address, 1)?;
+                    self.peek(poke.address)
+                }
+            };
+            Ok(Poke { original, ..*poke })
+        }).collect()
+    }
+
+    /// apply_pok performs the pokes of a .POK trainer, using the answer for any value the
+    /// user is asked for, and returns them with the values they replaced. Nothing is
+    /// written if any poke would fail.
+    pub fn apply_pok(&mut self, trainer: &PokTrainer, answer: u8) -> Result<Vec<Poke>, SnaError> {
+        let pokes = self.plan_pok(trainer, answer)?;
+        self.apply_trainer(&Trainer { name: trainer.name.clone(), pokes: pokes.clone() })?;
+        Ok(pokes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POK: &str = "NInfinite lives\nM  8 35899   0  53\nZ  4 49152 201  58\nNStarting lives\nZ  8 36000 256   3\nY\n";
+
+    #[test]
+    fn test_parse() {
+        let trainers = parse(POK).expect("Failed to parse .POK");
+        assert_eq!(trainers.len(), 2);
+        assert_eq!(trainers[0].name, "Infinite lives");
+        assert_eq!(trainers[0].entries, [
+            PokEntry { bank: None, address: 35899, value: PokValue::Byte(0), original: 53 },
+            PokEntry { bank: Some(4), address: 49152, value: PokValue::Byte(201), original: 58 },
+        ]);
+        assert!(!trainers[0].asks());
+        assert_eq!(trainers[1].entries[0].value, PokValue::Ask);
+        assert!(trainers[1].asks());
+
+        assert_eq!(parse("NBad\nM 8 35899 257 0\nY\n").unwrap_err().line, 2, "257 is not a value");
+        assert!(parse("NOpen\nM 8 35899 0 0\nY\n").is_err(), "The trainer never ends with Z");
+        assert!(parse("Z 8 35899 0 0\nY\n").is_err(), "The poke has no trainer");
+        assert_eq!(parse(&crate::trainer::to_pok(&[trainers[0].resolve(0)]).unwrap()).unwrap()[0], trainers[0], "to_pok output reads back");
+    }
+
+    #[test]
+    fn test_apply_pok() {
+        let trainers = parse(POK).expect("Failed to parse .POK");
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.poke(36000, 3).unwrap();
+
+        let planned = snapshot.plan_pok(&trainers[1], 9).unwrap();
+        assert_eq!(planned, [Poke { bank: None, address: 36000, value: 9, original: 3 }]);
+        assert_eq!(snapshot.peek(36000), 3, "A dry run should not write");
+        assert_eq!(snapshot.apply_pok(&trainers[1], 9).unwrap(), planned);
+        assert_eq!(snapshot.peek(36000), 9);
+
+        snapshot.apply_pok(&trainers[0], 0).unwrap();
+        assert_eq!(snapshot.peek(35899), 0);
+        assert_eq!(snapshot.bank_peek(4, 0).unwrap(), 201, "Banked poke was not written to bank 4");
+
+        let rom = PokTrainer { name: "ROM".to_string(), entries: vec![
+            PokEntry { bank: None, address: 40000, value: PokValue::Byte(1), original: 0 },
+            PokEntry { bank: None, address: 100, value: PokValue::Byte(1), original: 0 },
+        ] };
+        assert!(matches!(snapshot.apply_pok(&rom, 0), Err(SnaError::RomWrite(100))));
+        assert_eq!(snapshot.peek(40000), 0, "A failed trainer should write nothing");
+    }
+
+    #[test]
+    fn test_record_pokes() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.poke(35899, 53).unwrap();
+        let before = snapshot.clone();
+
+        let mut recorder = snapshot.record_pokes();
+        recorder.poke(35899, 1).unwrap();
+        recorder.poke(35899, 0).unwrap();
+        recorder.bank_poke(4, 0, 201).unwrap();
+        assert!(recorder.poke(100, 0).is_err());
+        let lives = recorder.into_trainer("Infinite lives");
+        assert_eq!(lives.pokes, [
+            Poke { bank: None, address: 35899, value: 0, original: 53 },
+            Poke { bank: Some(4), address: 49152, value: 201, original: 0 },
+        ]);
+
+        let diff = Trainer::from_diff("Infinite lives", &before, &snapshot).unwrap();
+        assert_eq!(diff, lives);
+        assert!(Trainer::from_diff("Mismatch", &Snapshot::new_48k(), &snapshot).is_err());
+
+        let time = Trainer { name: "Infinite time".to_string(), pokes: vec![Poke { bank: None, address: 40000, value: 0, original: 9 }] };
+        let empty = Trainer { name: "Nothing".to_string(), pokes: Vec::new() };
+        let trainers = parse(&to_pok(&[lives.clone(), empty, time.clone()]).unwrap()).expect("Failed to parse generated .POK");
+        assert_eq!(trainers.iter().map(|trainer| trainer.resolve(0)).collect::<Vec<_>>(), [lives, time], "Empty trainers are left out");
+    }
+}
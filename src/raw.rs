@@ -0,0 +1,120 @@
+// Raw binaries: code and data copied into and out of a snapshot's memory with no
+// header, as assemblers produce them and as ripped graphics and music are kept.
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{Bank, SnaError, Snapshot, MEM_16K};
+
+impl Snapshot {
+    /// load_bin copies a binary into the memory MAPPED from an address, across bank
+    /// boundaries, as the way to inject an assembled routine. Returns
+    /// SnaError::AddressOverflow if it would run past 0xFFFF, or SnaError::RomWrite if
+    /// it starts in ROM, in which case nothing is written.
+    pub fn load_bin(&mut self, address: u16, data: &[u8]) -> Result<(), SnaError> {
+        self.poke_slice(address, data)
+    }
+
+    /// load_bin_file is load_bin for a binary in a file, such as an assembler's output.
+    pub fn load_bin_file<P: AsRef<Path>>(&mut self, address: u16, path: P) -> io::Result<()> {
+        Ok(self.load_bin(address, &fs::read(path)?)?)
+    }
+
+    /// save_bin returns the bytes of a range of MAPPED memory, read through the current
+    /// paging, for ripping graphics, music or code into a raw file.
+    pub fn save_bin(&self, range: Range<u16>) -> Vec<u8> {
+        range.map(|address| self.peek(address)).collect()
+    }
+
+    /// save_bin_file is save_bin into a file.
+    pub fn save_bin_file<P: AsRef<Path>>(&self, path: P, range: Range<u16>) -> io::Result<()> {
+        fs::write(path, self.save_bin(range))
+    }
+
+    /// bank_save_bin is save_bin for a range of offsets within a bank, whether or not it
+    /// is paged in. Returns SnaError::BankOutOfRange if the snapshot has no such bank and
+    /// SnaError::AddressOverflow if the range runs past the end of the bank.
+    pub fn bank_save_bin(&self, bank: usize, range: Range<u16>) -> Result<Vec<u8>, SnaError> {
+        self.check_bank(bank)?;
+        if range.end > 0x4000 {
+            return Err(SnaError::AddressOverflow { address: range.start, len: range.len() });
+        }
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(self.banks[bank][range.start as usize..range.end as usize].to_vec())
+    }
+
+    /// export_bank returns the 16K of a bank, to be edited in another tool or swapped
+    /// into another snapshot with import_bank. Returns SnaError::BankOutOfRange if the
+    /// snapshot has no such bank.
+    pub fn export_bank(&self, bank: usize) -> Result<Vec<u8>, SnaError> {
+        self.check_bank(bank)?;
+        Ok(self.banks[bank].to_vec())
+    }
+
+    /// import_bank replaces the contents of a bank, paged in or not. Returns
+    /// SnaError::BankOutOfRange if the snapshot has no such bank.
+    pub fn import_bank(&mut self, bank: usize, data: &[u8; MEM_16K]) -> Result<(), SnaError> {
+        self.check_bank(bank)?;
+        let edit = self.start_edit(bank, 0..MEM_16K);
+        self.banks[bank] = Bank::from_storage(*data);
+        self.finish_edits(edit);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_bin() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.load_bin(0xBFFE, &[1, 2, 3, 4]).unwrap();
+        assert_eq!((snapshot.bank_peek(2, 0x3FFF).unwrap(), snapshot.bank_peek(0, 0x0000).unwrap()), (2, 3), "The binary crosses into bank 0");
+        snapshot.load_bin(0xFFFF, &[5]).unwrap();
+        assert_eq!(snapshot.peek(0xFFFF), 5);
+
+        assert!(matches!(snapshot.load_bin(0xFFFF, &[6, 7]), Err(SnaError::AddressOverflow { address: 0xFFFF, len: 2 })));
+        assert!(matches!(snapshot.load_bin(0x3FFF, &[6, 7]), Err(SnaError::RomWrite(0x3FFF))));
+        assert_eq!((snapshot.peek(0xFFFF), snapshot.peek(0x4000)), (5, 0), "Nothing is written on failure");
+        assert!(snapshot.load_bin(0x0000, &[]).is_ok());
+
+        let mut snapshot = Snapshot::new_plus3();
+        snapshot.write_0x1ffd(0x01).unwrap();
+        snapshot.load_bin(0x0000, &[0xF3]).unwrap();
+        assert_eq!(snapshot.peek(0x0000), 0xF3, "Special paging maps RAM at 0x0000");
+    }
+
+    #[test]
+    fn test_save_bin() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.load_bin(0xBFFE, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(snapshot.save_bin(0xBFFE..0xC002), [1, 2, 3, 4]);
+        assert_eq!(snapshot.save_bin(0x3FFF..0x4001), [0xFF, 0x00], "ROM reads as 0xFF");
+        assert!(snapshot.save_bin(0x9000..0x9000).is_empty());
+
+        snapshot.write_0x7ffd(0x01).unwrap();
+        assert_eq!(snapshot.bank_save_bin(0, 0x0000..0x0002).unwrap(), [3, 4], "Bank 0 is no longer paged in");
+        assert!(snapshot.bank_save_bin(0, 0x3FFF..0x4001).is_err());
+        assert!(matches!(snapshot.bank_save_bin(8, 0..1), Err(SnaError::BankOutOfRange { bank: 8, banks: 8 })));
+    }
+
+    #[test]
+    fn test_import_bank() {
+        let mut source = Snapshot::new_128k();
+        source.bank_poke(4, 0x1234, 0x56).unwrap();
+        let bank = source.export_bank(4).unwrap();
+        assert_eq!((bank.len(), bank[0x1234]), (MEM_16K, 0x56));
+        assert!(source.export_bank(8).is_err());
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.write_0x7ffd(0x04).unwrap();
+        snapshot.import_bank(4, &bank.try_into().unwrap()).unwrap();
+        assert_eq!(snapshot.peek(0xD234), 0x56, "The paged bank is replaced");
+        assert!(matches!(snapshot.import_bank(9, &[0; MEM_16K]), Err(SnaError::BankOutOfRange { bank: 9, banks: 8 })));
+    }
+}
@@ -0,0 +1,135 @@
+use std::io::{self, Read};
+
+use crate::{Bank, SnaError, Snapshot, SnapshotExtension, SnapshotHeader, SnapshotType};
+
+const HEADER_SIZE: usize = 27;
+const EXTENSION_SIZE: usize = 4;
+
+// reads until the buffer is full or the reader ends, returning the number of bytes read
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// fills the buffer completely, counting bytes read so a short read can report the file size
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8], total: &mut usize) -> Result<(), SnaError> {
+    let n = read_up_to(reader, buf)?;
+    *total += n;
+    if n < buf.len() {
+        return Err(SnaError::InvalidSize(*total));
+    }
+    Ok(())
+}
+
+impl Snapshot {
+    /// from_reader streams a snapshot in .sna format from any reader, such as a
+    /// network stream, an entry in a zip archive or stdin. Memory is read straight
+    /// into the banks without buffering the whole file first, and the snapshot type
+    /// is decided by whether anything follows the first 48K.
+    /// Returns SnaError::InvalidSize if the stream is not the size of a .sna file.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Snapshot, SnaError> {
+        let mut total = 0;
+        let mut header = [0u8; HEADER_SIZE];
+        fill(&mut reader, &mut header, &mut total)?;
+
+        let mut lower: Vec<Bank> = vec![Bank::default(), Bank::default(), Bank::default()];
+        for bank in &mut lower {
+            fill(&mut reader, bank, &mut total)?;
+        }
+
+        let mut extension = [0u8; EXTENSION_SIZE];
+        let n = read_up_to(&mut reader, &mut extension)?;
+        total += n;
+        let mut snapshot = Snapshot { header: SnapshotHeader::from_bytes(&header), ..Snapshot::default() };
+        match n {
+            0 => {
+                snapshot.banks = lower;
+                snapshot.mapping = [0, 1, 2];
+            }
+            EXTENSION_SIZE => {
+                let x7ffd = extension[2];
+                let paged = (x7ffd & 0x07) as usize;
+                let mut banks: Vec<Bank> = vec![Bank::default(); 8];
+                for (bank, memory) in [5, 2, paged].into_iter().zip(lower) {
+                    banks[bank] = memory;
+                }
+                for bank in [0, 1, 3, 4, 6, 7].into_iter().filter(|&bank| bank != paged) {
+                    fill(&mut reader, &mut banks[bank], &mut total)?;
+                }
+                // a Scorpion 256 follows with banks 8 to 15, or nothing at all
+                let mut extra = Bank::default();
+                let n = read_up_to(&mut reader, &mut extra)?;
+                total += n;
+                if n > 0 {
+                    if n < extra.len() {
+                        return Err(SnaError::InvalidSize(total));
+                    }
+                    banks.push(extra);
+                    for _ in 9..16 {
+                        let mut bank = Bank::default();
+                        fill(&mut reader, &mut bank, &mut total)?;
+                        banks.push(bank);
+                    }
+                }
+                snapshot.snapshot_type = SnapshotType::Snapshot128;
+                snapshot.extension = Some(SnapshotExtension {
+                    pc: u16::from_le_bytes([extension[0], extension[1]]),
+                    x7ffd,
+                    tr_dos: extension[3],
+                });
+                snapshot.banks = banks;
+                snapshot.mapping = [5, 2, paged as u8];
+            }
+            _ => return Err(SnaError::InvalidSize(total)),
+        }
+
+        let trailing = io::copy(&mut reader, &mut io::sink())?;
+        if trailing > 0 {
+            return Err(SnaError::InvalidSize(total + trailing as usize));
+        }
+        snapshot.remap();
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn test_from_reader() {
+        for name in ["48k.sna", "128k.sna"] {
+            let bin = std::fs::read(name).expect("Failed to read snapshot file");
+            let streamed = Snapshot::from_reader(std::fs::File::open(name).expect("Failed to open snapshot file")).expect("Failed to stream snapshot");
+            let parsed = Snapshot::try_from(&bin[..]).expect("Failed to parse snapshot");
+            assert_eq!(streamed.snapshot_type, parsed.snapshot_type);
+            assert_eq!(streamed.mapping, parsed.mapping);
+            assert!((0..streamed.banks.len()).all(|bank| streamed.checksum(bank).unwrap() == parsed.checksum(bank).unwrap()), "Banks differ for {}", name);
+
+            let short = Snapshot::from_reader(&bin[..bin.len() - 1]);
+            assert!(matches!(short, Err(SnaError::InvalidSize(len)) if len == bin.len() - 1), "A short stream should fail");
+        }
+
+        // bank 5 paged at 0xC000 is stored twice
+        let bin = fixtures::generate_bytes(SnapshotType::Snapshot128, Default::default());
+        let mut snapshot = Snapshot::try_from(&bin[..]).expect("Failed to parse fixture");
+        snapshot.write_0x7ffd(0x15).unwrap();
+        let mut bin = Vec::new();
+        snapshot.write_to(&mut bin).expect("Failed to write snapshot");
+        let streamed = Snapshot::from_reader(&bin[..]).expect("Failed to stream snapshot");
+        assert_eq!(streamed.mapping, [5, 2, 5]);
+        assert!((0..8).all(|bank| streamed.checksum(bank).unwrap() == snapshot.checksum(bank).unwrap()));
+
+        bin.push(0);
+        assert!(matches!(Snapshot::from_reader(&bin[..]), Err(SnaError::InvalidSize(147488))));
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+
+use crate::json::Value;
+use crate::{Addr, BankAddr, BankId, SnaError, Snapshot};
+
+/// Where a named region lives: at a mapped address, or at a fixed location in a bank.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RegionLocation {
+    Mapped(Addr),
+    Banked(BankAddr),
+}
+
+impl From<Addr> for RegionLocation {
+    fn from(address: Addr) -> Self {
+        RegionLocation::Mapped(address)
+    }
+}
+
+impl From<u16> for RegionLocation {
+    fn from(address: u16) -> Self {
+        RegionLocation::Mapped(Addr(address))
+    }
+}
+
+impl From<BankAddr> for RegionLocation {
+    fn from(location: BankAddr) -> Self {
+        RegionLocation::Banked(location)
+    }
+}
+
+/// How the bytes of a named region are interpreted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueType {
+    U8,
+    U16,            // little-endian
+    U32,            // little-endian
+    U16Be,          // big-endian
+    U32Be,          // big-endian
+    I8,
+    I16,            // little-endian
+    Bcd,            // packed BCD, most significant byte first
+    BcdLe,          // packed BCD, least significant byte first
+    AsciiDigits,    // ASCII digits, most significant first, optionally padded with leading spaces
+    ScreenDigits,   // digits drawn in the ROM font, one character cell per digit, starting at a display file address
+    Bytes,
+}
+
+impl ValueType {
+    const ALL: [ValueType; 12] = [
+        ValueType::U8, ValueType::U16, ValueType::U32, ValueType::U16Be, ValueType::U32Be, ValueType::I8,
+        ValueType::I16, ValueType::Bcd, ValueType::BcdLe, ValueType::AsciiDigits, ValueType::ScreenDigits, ValueType::Bytes,
+    ];
+
+    /// Returns the fixed length of the type, or None for types whose length is given by the region.
+    fn fixed_len(self) -> Option<usize> {
+        match self {
+            ValueType::U8 | ValueType::I8 => Some(1),
+            ValueType::U16 | ValueType::U16Be | ValueType::I16 => Some(2),
+            ValueType::U32 | ValueType::U32Be => Some(4),
+            ValueType::Bcd | ValueType::BcdLe | ValueType::AsciiDigits | ValueType::ScreenDigits | ValueType::Bytes => None,
+        }
+    }
+
+    /// Returns the longest region the type can decode into an i64.
+    fn max_len(self) -> usize {
+        match self {
+            ValueType::Bcd | ValueType::BcdLe => 9,
+            ValueType::AsciiDigits | ValueType::ScreenDigits => 18,
+            _ => usize::MAX,
+        }
+    }
+
+    /// Returns the offsets of each byte of a region of the given length, relative to its start.
+    /// Screen digits take eight pixel lines per character cell, each 256 bytes apart in the display file.
+    /// The region must have been checked by Region::new, so that every offset fits in a u16.
+    fn offsets(self, len: usize) -> Vec<u16> {
+        match self {
+            ValueType::ScreenDigits => (0..len as u16)
+                .flat_map(|cell| (0..8u16).map(move |line| cell + line * 256))
+                .collect(),
+            _ => (0..len).map(|offset| offset as u16).collect(),
+        }
+    }
+
+    /// Returns the offset of the last byte of a non-empty region of the given length.
+    fn last_offset(self, len: usize) -> usize {
+        match self {
+            ValueType::ScreenDigits => len - 1 + 7 * 256,
+            _ => len - 1,
+        }
+    }
+}
+
+/// The decoded value of a named region.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RegionValue {
+    Number(i64),
+    Bytes(Vec<u8>),
+}
+
+/// A named region of memory holding a piece of game state.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Region {
+    pub location: RegionLocation,
+    pub len: usize,
+    pub value_type: ValueType,
+}
+
+impl Region {
+    // checks that a region is not empty, has a length that suits its type, and ends
+    // within memory or its bank, as every region read or written must
+    fn new(location: RegionLocation, len: usize, value_type: ValueType) -> Result<Region, SnaError> {
+        if len > value_type.max_len() {
+            return Err(SnaError::InvalidLength { len, max: value_type.max_len() });
+        }
+        if len == 0 || value_type.fixed_len().is_some_and(|fixed| fixed != len) {
+            return Err(SnaError::InvalidFormat(format!("A length of {} does not suit {:?}", len, value_type)));
+        }
+        let last = value_type.last_offset(len);
+        let (start, limit) = match location {
+            RegionLocation::Mapped(address) => (address.0, 0xFFFF),
+            RegionLocation::Banked(location) => (location.offset, 0x3FFF),
+        };
+        if start as usize + last > limit {
+            return Err(SnaError::AddressOverflow { address: start, len: last + 1 });
+        }
+        Ok(Region { location, len, value_type })
+    }
+
+    // the sidecar form of a named region, with its type by variant name
+    pub(crate) fn to_json(&self, name: &str) -> Value {
+        let mut members = vec![("name".to_string(), Value::String(name.to_string()))];
+        match self.location {
+            RegionLocation::Mapped(address) => members.push(("address".to_string(), Value::Number(address.0 as f64))),
+            RegionLocation::Banked(location) => {
+                members.push(("bank".to_string(), Value::Number(location.bank.0 as f64)));
+                members.push(("offset".to_string(), Value::Number(location.offset as f64)));
+            }
+        }
+        members.push(("len".to_string(), Value::Number(self.len as f64)));
+        members.push(("type".to_string(), Value::String(format!("{:?}", self.value_type))));
+        Value::Object(members)
+    }
+
+    // reads a named region written by to_json, checking it as define_region does
+    pub(crate) fn from_json(value: &Value) -> Result<(String, Region), SnaError> {
+        let invalid = || SnaError::InvalidFormat("Invalid region in sidecar".to_string());
+        let number = |key: &str, max: usize| match value.get(key).and_then(Value::as_f64) {
+            Some(n) if n >= 0.0 && n <= max as f64 && n.fract() == 0.0 => Ok(n as usize),
+            _ => Err(invalid()),
+        };
+        let name = value.get("name").and_then(Value::as_str).ok_or_else(invalid)?;
+        let type_name = value.get("type").and_then(Value::as_str).ok_or_else(invalid)?;
+        let value_type = ValueType::ALL.into_iter().find(|t| format!("{:?}", t) == type_name).ok_or_else(invalid)?;
+        let location = match value.get("address") {
+            Some(_) => RegionLocation::Mapped(Addr(number("address", 0xFFFF)? as u16)),
+            None => RegionLocation::Banked(BankAddr { bank: BankId(number("bank", usize::MAX)?), offset: number("offset", 0x3FFF)? as u16 }),
+        };
+        Ok((name.to_string(), Region::new(location, number("len", usize::MAX)?, value_type)?))
+    }
+}
+
+// the digits 0-9 from the Spectrum ROM character set
+const ROM_DIGITS: [[u8; 8]; 10] = [
+    [0x00, 0x3C, 0x46, 0x4A, 0x52, 0x62, 0x3C, 0x00],
+    [0x00, 0x18, 0x28, 0x08, 0x08, 0x08, 0x3E, 0x00],
+    [0x00, 0x3C, 0x42, 0x02, 0x3C, 0x40, 0x7E, 0x00],
+    [0x00, 0x3C, 0x42, 0x0C, 0x02, 0x42, 0x3C, 0x00],
+    [0x00, 0x08, 0x18, 0x28, 0x48, 0x7E, 0x08, 0x00],
+    [0x00, 0x7E, 0x40, 0x7C, 0x02, 0x42, 0x3C, 0x00],
+    [0x00, 0x3C, 0x40, 0x7C, 0x42, 0x42, 0x3C, 0x00],
+    [0x00, 0x7E, 0x02, 0x04, 0x08, 0x10, 0x10, 0x00],
+    [0x00, 0x3C, 0x42, 0x3C, 0x42, 0x42, 0x3C, 0x00],
+    [0x00, 0x3C, 0x42, 0x42, 0x3E, 0x02, 0x3C, 0x00],
+];
+
+/// decode interprets the bytes of a region as a value of the given type.
+/// Returns None if the bytes are not a valid encoding, such as a BCD nibble above 9
+/// or a screen cell that does not match any ROM digit.
+fn decode(value_type: ValueType, bytes: &[u8]) -> Option<RegionValue> {
+    let value = match value_type {
+        ValueType::U8 => bytes[0] as i64,
+        ValueType::I8 => bytes[0] as i8 as i64,
+        ValueType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+        ValueType::U16Be => u16::from_be_bytes([bytes[0], bytes[1]]) as i64,
+        ValueType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+        ValueType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+        ValueType::U32Be => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+        ValueType::Bcd => decimal(bcd_digits(bytes.iter().copied()))?,
+        ValueType::BcdLe => decimal(bcd_digits(bytes.iter().rev().copied()))?,
+        ValueType::AsciiDigits => {
+            let digits = bytes.iter().skip_while(|&&byte| byte == b' ');
+            decimal(digits.map(|&byte| byte.is_ascii_digit().then(|| byte - b'0')))?
+        }
+        ValueType::ScreenDigits => {
+            decimal(bytes.chunks(8).map(|cell| ROM_DIGITS.iter().position(|glyph| glyph == cell).map(|digit| digit as u8)))?
+        }
+        ValueType::Bytes => return Some(RegionValue::Bytes(bytes.to_vec())),
+    };
+    Some(RegionValue::Number(value))
+}
+
+// combines decimal digits, most significant first, failing on any invalid digit
+fn decimal<I: Iterator<Item = Option<u8>>>(mut digits: I) -> Option<i64> {
+    digits.try_fold(0i64, |value, digit| digit.map(|digit| value * 10 + digit as i64))
+}
+
+// splits packed BCD bytes into their digits, marking nibbles above 9 as invalid
+fn bcd_digits<I: Iterator<Item = u8>>(bytes: I) -> impl Iterator<Item = Option<u8>> {
+    bytes.flat_map(|byte| [byte >> 4, byte & 0x0F]).map(|digit| (digit <= 9).then_some(digit))
+}
+
+/// encode converts a value into the bytes of a region of the given type and length.
+/// Numbers are truncated to fit; decimal encodings keep the least significant digits.
+/// Returns SnaError::InvalidFormat if the value does not suit the type.
+fn encode(value_type: ValueType, len: usize, value: RegionValue) -> Result<Vec<u8>, SnaError> {
+    let number = match (value_type, value) {
+        (ValueType::Bytes, RegionValue::Bytes(bytes)) if bytes.len() == len => return Ok(bytes),
+        (value_type, RegionValue::Number(number)) if value_type != ValueType::Bytes => number,
+        (value_type, value) => {
+            return Err(SnaError::InvalidFormat(format!("Cannot write {:?} to a region of type {:?} and length {}", value, value_type, len)));
+        }
+    };
+    let digits = |count: usize| -> Vec<u8> {
+        let mut remaining = number.unsigned_abs();
+        let mut digits = vec![0u8; count];
+        for digit in digits.iter_mut().rev() {
+            *digit = (remaining % 10) as u8;
+            remaining /= 10;
+        }
+        digits
+    };
+
+    Ok(match value_type {
+        ValueType::U8 | ValueType::I8 | ValueType::U16 | ValueType::I16 | ValueType::U32 => number.to_le_bytes()[..len].to_vec(),
+        ValueType::U16Be | ValueType::U32Be => number.to_be_bytes()[8 - len..].to_vec(),
+        ValueType::Bcd => digits(len * 2).chunks(2).map(|pair| (pair[0] << 4) | pair[1]).collect(),
+        ValueType::BcdLe => digits(len * 2).chunks(2).rev().map(|pair| (pair[0] << 4) | pair[1]).collect(),
+        ValueType::AsciiDigits => digits(len).iter().map(|digit| b'0' + digit).collect(),
+        ValueType::ScreenDigits => digits(len).iter().flat_map(|&digit| ROM_DIGITS[digit as usize]).collect(),
+        ValueType::Bytes => unreachable!("Byte values are returned above"),
+    })
+}
+
+impl Snapshot {
+    /// define_region names a region of memory so that it can be read and written by name.
+    /// For fixed-size types the length must match the size of the type. For decimal types it
+    /// is the number of bytes (BCD) or digits (ASCII and screen digits).
+    /// Defining a name again replaces the earlier definition.
+    /// Returns SnaError::InvalidFormat if the region is empty or its length does not suit its
+    /// type, SnaError::InvalidLength if it is too long for a decimal type, and
+    /// SnaError::AddressOverflow if it runs past 0xFFFF or past the end of its bank.
+    pub fn define_region<L: Into<RegionLocation>>(&mut self, name: &str, location: L, len: usize, value_type: ValueType) -> Result<(), SnaError> {
+        let region = Region::new(location.into(), len, value_type)?;
+        self.regions.insert(name.to_string(), region);
+        Ok(())
+    }
+
+    /// Removes a named region, returning its definition if it existed.
+    pub fn remove_region(&mut self, name: &str) -> Option<Region> {
+        self.regions.remove(name)
+    }
+
+    /// Returns the definition of a named region.
+    pub fn region(&self, name: &str) -> Option<&Region> {
+        self.regions.get(name)
+    }
+
+    /// Iterates over the defined regions in name order.
+    pub fn regions(&self) -> impl Iterator<Item = (&str, &Region)> {
+        self.regions.iter().map(|(name, region)| (name.as_str(), region))
+    }
+
+    /// peek_named reads the value of a named region.
+    /// Returns None if no region has that name, its bank does not exist, or the region
+    /// does not hold a valid encoding.
+    pub fn peek_named(&self, name: &str) -> Option<RegionValue> {
+        let region = self.regions.get(name)?;
+        decode(region.value_type, &self.region_bytes(region).ok()?)
+    }
+
+    /// poke_named writes a value to a named region, returning false if no region has that name.
+    /// Numbers are truncated to the size of the region; byte values must match its length.
+    /// Returns an error, writing nothing, if the region is in ROM, its bank does not exist,
+    /// or the value does not suit the region's type.
+    pub fn poke_named(&mut self, name: &str, value: RegionValue) -> Result<bool, SnaError> {
+        let Some(region) = self.regions.get(name).cloned() else {
+            return Ok(false);
+        };
+        let bytes = encode(region.value_type, region.len, value)?;
+        self.write_region_bytes(&region, &bytes)?;
+        Ok(true)
+    }
+
+    /// peek_value decodes a value of the given type directly from memory, without naming the region.
+    /// Returns the errors define_region does for a region of that location, length and type,
+    /// SnaError::BankOutOfRange if its bank does not exist, and SnaError::InvalidFormat if the
+    /// bytes are not a valid encoding.
+    pub fn peek_value<L: Into<RegionLocation>>(&self, location: L, len: usize, value_type: ValueType) -> Result<RegionValue, SnaError> {
+        let region = Region::new(location.into(), len, value_type)?;
+        decode(value_type, &self.region_bytes(&region)?)
+            .ok_or_else(|| SnaError::InvalidFormat(format!("The bytes are not a valid {:?} value", value_type)))
+    }
+
+    /// poke_value encodes a value of the given type directly into memory, without naming the region.
+    /// Returns the errors define_region does for a region of that location, length and type, and
+    /// an error, writing nothing, if the location is in ROM, its bank does not exist, or the
+    /// value does not suit the type.
+    pub fn poke_value<L: Into<RegionLocation>>(&mut self, location: L, len: usize, value_type: ValueType, value: RegionValue) -> Result<(), SnaError> {
+        let region = Region::new(location.into(), len, value_type)?;
+        self.write_region_bytes(&region, &encode(value_type, len, value)?)
+    }
+
+    fn region_bytes(&self, region: &Region) -> Result<Vec<u8>, SnaError> {
+        region.value_type.offsets(region.len).into_iter().map(|i| match region.location {
+            RegionLocation::Mapped(address) => Ok(self.peek(address.0 + i)),
+            RegionLocation::Banked(location) => self.bank_peek(location.bank.0, location.offset + i),
+        }).collect()
+    }
+
+    fn write_region_bytes(&mut self, region: &Region, bytes: &[u8]) -> Result<(), SnaError> {
+        let offsets = region.value_type.offsets(region.len);
+        match region.location {
+            RegionLocation::Mapped(address) => {
+                for &i in &offsets {
+                    self.check_write(address.0 + i, 1)?;
+                }
+            }
+            RegionLocation::Banked(location) => self.check_bank(location.bank.0)?,
+        }
+        self.grouped(|snapshot| offsets.into_iter().zip(bytes).try_for_each(|(i, &byte)| match region.location {
+            RegionLocation::Mapped(address) => snapshot.poke(address.0 + i, byte),
+            RegionLocation::Banked(location) => snapshot.bank_poke(location.bank.0, location.offset + i, byte),
+        }))
+    }
+}
+
+pub(crate) type RegionMap = BTreeMap<String, Region>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_named_regions() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        snapshot.define_region("lives", 0x8000, 1, ValueType::U8).unwrap();
+        snapshot.define_region("score", BankAddr::new(BankId(4), 0x0100).unwrap(), 3, ValueType::Bcd).unwrap();
+        snapshot.define_region("energy", Addr(0x8002), 2, ValueType::I16).unwrap();
+
+        assert!(snapshot.poke_named("lives", RegionValue::Number(5)).unwrap());
+        assert!(snapshot.poke_named("score", RegionValue::Number(12345)).unwrap());
+        assert!(snapshot.poke_named("energy", RegionValue::Number(-3)).unwrap());
+        assert!(!snapshot.poke_named("ammo", RegionValue::Number(1)).unwrap(), "Undefined region should not be written");
+
+        assert_eq!(snapshot.peek_named("lives"), Some(RegionValue::Number(5)));
+        assert_eq!(snapshot.peek_named("score"), Some(RegionValue::Number(12345)));
+        assert_eq!(snapshot.peek_named("energy"), Some(RegionValue::Number(-3)));
+        assert_eq!(snapshot.bank_peek(4, 0x0100).unwrap(), 0x01, "Banked region was not written to its bank");
+        assert_eq!(snapshot.regions().map(|(name, _)| name).collect::<Vec<_>>(), ["energy", "lives", "score"]);
+    }
+
+    #[test]
+    fn test_invalid_regions() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        assert!(matches!(snapshot.define_region("lives", 0x8000, 2, ValueType::U8), Err(SnaError::InvalidFormat(_))));
+        assert!(matches!(snapshot.define_region("score", 0x8000, 10, ValueType::Bcd), Err(SnaError::InvalidLength { len: 10, max: 9 })));
+        assert!(matches!(snapshot.define_region("end", BankAddr::new(BankId(0), 0x3FFF).unwrap(), 2, ValueType::U16), Err(SnaError::AddressOverflow { .. })));
+        assert!(matches!(snapshot.define_region("all", 0x4000, 0x10001, ValueType::Bytes), Err(SnaError::AddressOverflow { address: 0x4000, len: 0x10001 })), "A length above 0xFFFF must not wrap");
+        assert!(matches!(snapshot.peek_value(0x4000, 0x20000, ValueType::Bytes), Err(SnaError::AddressOverflow { .. })));
+        assert!(snapshot.region("lives").is_none(), "Invalid regions are not defined");
+
+        assert!(matches!(snapshot.peek_value(Addr(0xFFFF), 4, ValueType::U32), Err(SnaError::AddressOverflow { address: 0xFFFF, len: 4 })));
+        assert!(matches!(snapshot.peek_value(0x8000, 0, ValueType::U8), Err(SnaError::InvalidFormat(_))));
+        let before = snapshot.peek(0x8000);
+        assert!(matches!(snapshot.poke_value(0x8000, 1, ValueType::U8, RegionValue::Bytes(vec![1])), Err(SnaError::InvalidFormat(_))));
+        assert!(matches!(snapshot.poke_value(0xFFFF, 2, ValueType::U16, RegionValue::Number(1)), Err(SnaError::AddressOverflow { .. })));
+        assert_eq!(snapshot.peek(0x8000), before, "Nothing is written for an invalid value");
+
+        snapshot.define_region("lives", 0x8000, 1, ValueType::U8).unwrap();
+        assert!(matches!(snapshot.poke_named("lives", RegionValue::Bytes(vec![1])), Err(SnaError::InvalidFormat(_))));
+        snapshot.poke(0x9000, 0xAA).unwrap();
+        assert!(matches!(snapshot.peek_value(0x9000, 1, ValueType::Bcd), Err(SnaError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_game_number_encodings() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        let cases = [
+            (ValueType::U16Be, 2, 0x1234, vec![0x12, 0x34]),
+            (ValueType::U32Be, 4, 0x01020304, vec![0x01, 0x02, 0x03, 0x04]),
+            (ValueType::BcdLe, 3, 12345, vec![0x45, 0x23, 0x01]),
+            (ValueType::AsciiDigits, 6, 4250, b"004250".to_vec()),
+        ];
+        for (value_type, len, number, bytes) in cases {
+            snapshot.poke_value(0x9000, len, value_type, RegionValue::Number(number)).unwrap();
+            assert_eq!((0..len as u16).map(|i| snapshot.peek(0x9000 + i)).collect::<Vec<_>>(), bytes, "Incorrect encoding for {:?}", value_type);
+            assert_eq!(snapshot.peek_value(0x9000, len, value_type).unwrap(), RegionValue::Number(number), "Incorrect decoding for {:?}", value_type);
+        }
+
+        for (i, &byte) in b"  950".iter().enumerate() {
+            snapshot.poke(0x9000 + i as u16, byte).unwrap();
+        }
+        assert_eq!(snapshot.peek_value(0x9000, 5, ValueType::AsciiDigits).unwrap(), RegionValue::Number(950), "Leading spaces should be ignored");
+    }
+
+    #[test]
+    fn test_screen_digits() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        snapshot.define_region("score", 0x4005, 4, ValueType::ScreenDigits).unwrap();
+        snapshot.poke_named("score", RegionValue::Number(1980)).unwrap();
+        assert_eq!(snapshot.peek(0x4105), 0x18, "Second pixel line of '1' should be in the next display file line");
+        assert_eq!(snapshot.peek_named("score"), Some(RegionValue::Number(1980)));
+
+        snapshot.poke(0x4105, 0xFF).unwrap();
+        assert_eq!(snapshot.peek_named("score"), None, "Unrecognised glyph should not decode");
+    }
+}
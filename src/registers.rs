@@ -0,0 +1,281 @@
+use std::fmt;
+
+use crate::{SnaError, Snapshot};
+
+/// An 8-bit Z80 register, including the halves of the register pairs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Register {
+    A, F, B, C, D, E, H, L,
+    APrime, FPrime, BPrime, CPrime, DPrime, EPrime, HPrime, LPrime,
+    I, R,
+    Ixh, Ixl, Iyh, Iyl,
+}
+
+/// A 16-bit Z80 register pair. The PC is not included, as where it is kept depends
+/// on the snapshot type; see [`Snapshot::pc`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RegisterPair {
+    AF, BC, DE, HL,
+    AFPrime, BCPrime, DEPrime, HLPrime,
+    IX, IY, SP,
+}
+
+impl Register {
+    /// Every register, in the order a debugger usually lists them.
+    pub const ALL: [Register; 22] = [
+        Register::A, Register::F, Register::B, Register::C, Register::D, Register::E, Register::H, Register::L,
+        Register::APrime, Register::FPrime, Register::BPrime, Register::CPrime,
+        Register::DPrime, Register::EPrime, Register::HPrime, Register::LPrime,
+        Register::I, Register::R,
+        Register::Ixh, Register::Ixl, Register::Iyh, Register::Iyl,
+    ];
+
+    /// name returns the register's assembler name, e.g. `A'` or `IXH`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Register::A => "A",
+            Register::F => "F",
+            Register::B => "B",
+            Register::C => "C",
+            Register::D => "D",
+            Register::E => "E",
+            Register::H => "H",
+            Register::L => "L",
+            Register::APrime => "A'",
+            Register::FPrime => "F'",
+            Register::BPrime => "B'",
+            Register::CPrime => "C'",
+            Register::DPrime => "D'",
+            Register::EPrime => "E'",
+            Register::HPrime => "H'",
+            Register::LPrime => "L'",
+            Register::I => "I",
+            Register::R => "R",
+            Register::Ixh => "IXH",
+            Register::Ixl => "IXL",
+            Register::Iyh => "IYH",
+            Register::Iyl => "IYL",
+        }
+    }
+
+    // the pair holding the register and whether it is the high byte, or None for I and R
+    fn half(self) -> Option<(RegisterPair, bool)> {
+        Some(match self {
+            Register::A => (RegisterPair::AF, true),
+            Register::F => (RegisterPair::AF, false),
+            Register::B => (RegisterPair::BC, true),
+            Register::C => (RegisterPair::BC, false),
+            Register::D => (RegisterPair::DE, true),
+            Register::E => (RegisterPair::DE, false),
+            Register::H => (RegisterPair::HL, true),
+            Register::L => (RegisterPair::HL, false),
+            Register::APrime => (RegisterPair::AFPrime, true),
+            Register::FPrime => (RegisterPair::AFPrime, false),
+            Register::BPrime => (RegisterPair::BCPrime, true),
+            Register::CPrime => (RegisterPair::BCPrime, false),
+            Register::DPrime => (RegisterPair::DEPrime, true),
+            Register::EPrime => (RegisterPair::DEPrime, false),
+            Register::HPrime => (RegisterPair::HLPrime, true),
+            Register::LPrime => (RegisterPair::HLPrime, false),
+            Register::Ixh => (RegisterPair::IX, true),
+            Register::Ixl => (RegisterPair::IX, false),
+            Register::Iyh => (RegisterPair::IY, true),
+            Register::Iyl => (RegisterPair::IY, false),
+            Register::I | Register::R => return None,
+        })
+    }
+}
+
+impl RegisterPair {
+    /// Every register pair, in the order a debugger usually lists them.
+    pub const ALL: [RegisterPair; 11] = [
+        RegisterPair::AF, RegisterPair::BC, RegisterPair::DE, RegisterPair::HL,
+        RegisterPair::AFPrime, RegisterPair::BCPrime, RegisterPair::DEPrime, RegisterPair::HLPrime,
+        RegisterPair::IX, RegisterPair::IY, RegisterPair::SP,
+    ];
+
+    /// name returns the register pair's assembler name, e.g. `HL'`.
+    pub fn name(self) -> &'static str {
+        match self {
+            RegisterPair::AF => "AF",
+            RegisterPair::BC => "BC",
+            RegisterPair::DE => "DE",
+            RegisterPair::HL => "HL",
+            RegisterPair::AFPrime => "AF'",
+            RegisterPair::BCPrime => "BC'",
+            RegisterPair::DEPrime => "DE'",
+            RegisterPair::HLPrime => "HL'",
+            RegisterPair::IX => "IX",
+            RegisterPair::IY => "IY",
+            RegisterPair::SP => "SP",
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl fmt::Display for RegisterPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl Snapshot {
+    /// get_reg returns the value of an 8-bit register.
+    pub fn get_reg(&self, register: Register) -> u8 {
+        match register.half() {
+            Some((pair, true)) => (self.get_pair(pair) >> 8) as u8,
+            Some((pair, false)) => self.get_pair(pair) as u8,
+            None if register == Register::I => self.header.i,
+            None => self.header.r,
+        }
+    }
+
+    /// set_reg sets the value of an 8-bit register, leaving the other half of its pair unchanged.
+    pub fn set_reg(&mut self, register: Register, value: u8) {
+        match register.half() {
+            Some((pair, true)) => self.set_pair(pair, (self.get_pair(pair) & 0x00FF) | (value as u16) << 8),
+            Some((pair, false)) => self.set_pair(pair, (self.get_pair(pair) & 0xFF00) | value as u16),
+            None if register == Register::I => self.header.i = value,
+            None => self.header.r = value,
+        }
+    }
+
+    /// get_pair returns the value of a register pair.
+    pub fn get_pair(&self, pair: RegisterPair) -> u16 {
+        let header = self.header;
+        match pair {
+            RegisterPair::AF => header.af,
+            RegisterPair::BC => header.bc,
+            RegisterPair::DE => header.de,
+            RegisterPair::HL => header.hl,
+            RegisterPair::AFPrime => header.af_prime,
+            RegisterPair::BCPrime => header.bc_prime,
+            RegisterPair::DEPrime => header.de_prime,
+            RegisterPair::HLPrime => header.hl_prime,
+            RegisterPair::IX => header.ix,
+            RegisterPair::IY => header.iy,
+            RegisterPair::SP => header.sp,
+        }
+    }
+
+    /// set_pair sets the value of a register pair. Setting SP of a 48K snapshot moves
+    /// where its PC is read from; see [`Snapshot::pc`].
+    pub fn set_pair(&mut self, pair: RegisterPair, value: u16) {
+        let header = &mut self.header;
+        match pair {
+            RegisterPair::AF => header.af = value,
+            RegisterPair::BC => header.bc = value,
+            RegisterPair::DE => header.de = value,
+            RegisterPair::HL => header.hl = value,
+            RegisterPair::AFPrime => header.af_prime = value,
+            RegisterPair::BCPrime => header.bc_prime = value,
+            RegisterPair::DEPrime => header.de_prime = value,
+            RegisterPair::HLPrime => header.hl_prime = value,
+            RegisterPair::IX => header.ix = value,
+            RegisterPair::IY => header.iy = value,
+            RegisterPair::SP => header.sp = value,
+        }
+    }
+
+    /// pc returns where execution resumes when the snapshot is loaded. 128K snapshots
+    /// keep the PC in the extension; 48K snapshots keep it on the stack at SP, from
+    /// where the loader pops it with RETN. Returns None if SP of a 48K snapshot is
+    /// not in RAM.
+    pub fn pc(&self) -> Option<u16> {
+        match &self.extension {
+            Some(extension) => Some(extension.pc),
+            None => {
+                let sp = { self.header.sp };
+                (0x4000..0xFFFF).contains(&sp).then(|| self.peek_word(sp).ok()).flatten()
+            }
+        }
+    }
+
+    /// set_pc sets where execution resumes. For 48K snapshots the stacked PC at SP is
+    /// overwritten, leaving SP unchanged. Returns SnaError::RomWrite or
+    /// SnaError::AddressOverflow if SP of a 48K snapshot is not in RAM.
+    pub fn set_pc(&mut self, pc: u16) -> Result<(), SnaError> {
+        match self.extension.as_mut() {
+            Some(extension) => extension.pc = pc,
+            None => self.poke_word(self.header.sp, pc)?,
+        }
+        Ok(())
+    }
+
+    /// push_pc stores a PC the way a 48K snapshot expects, moving SP down two bytes and
+    /// writing the PC there, as if the CPU had been interrupted at it. 128K snapshots
+    /// just have their PC set. Nothing is changed if the PC cannot be pushed into RAM.
+    pub fn push_pc(&mut self, pc: u16) -> Result<(), SnaError> {
+        if self.extension.is_some() {
+            return self.set_pc(pc);
+        }
+        let sp = { self.header.sp }.wrapping_sub(2);
+        self.poke_word(sp, pc)?;
+        self.header.sp = sp;
+        Ok(())
+    }
+
+    /// cpu_sp returns the SP the CPU has once the snapshot is running. For 48K snapshots
+    /// this is two above SP, after the stacked PC has been popped.
+    pub fn cpu_sp(&self) -> u16 {
+        match self.extension {
+            Some(_) => self.header.sp,
+            None => { self.header.sp }.wrapping_add(2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FIXTURE_PC, FIXTURE_SP};
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_registers() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.set_pair(RegisterPair::HL, 0x1234);
+        snapshot.set_reg(Register::Ixh, 0xAB);
+        snapshot.set_reg(Register::FPrime, 0xCD);
+        snapshot.set_reg(Register::R, 0x7F);
+        assert_eq!((snapshot.get_reg(Register::H), snapshot.get_reg(Register::L)), (0x12, 0x34));
+        assert_eq!(({ snapshot.header.ix }, { snapshot.header.af_prime }), (0xAB00, 0x00CD));
+        assert_eq!(snapshot.get_reg(Register::R), 0x7F);
+        assert_eq!(snapshot.get_pair(RegisterPair::SP), { snapshot.header.sp });
+
+        for register in Register::ALL {
+            snapshot.set_reg(register, 0x5A);
+            assert_eq!(snapshot.get_reg(register), 0x5A, "{} did not round trip", register);
+        }
+        assert!(RegisterPair::ALL[..10].iter().all(|&pair| snapshot.get_pair(pair) == 0x5A5A), "Every pair but SP is made of registers");
+    }
+
+    #[test]
+    fn test_pc() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, Default::default());
+        assert_eq!(snapshot.pc(), Some(FIXTURE_PC));
+        assert_eq!(snapshot.cpu_sp(), FIXTURE_SP + 2);
+        snapshot.set_pc(0x9000).unwrap();
+        assert_eq!((snapshot.pc(), { snapshot.header.sp }), (Some(0x9000), FIXTURE_SP));
+
+        snapshot.push_pc(0xA000).unwrap();
+        assert_eq!((snapshot.pc(), { snapshot.header.sp }), (Some(0xA000), FIXTURE_SP - 2));
+        assert_eq!(snapshot.peek_word(FIXTURE_SP).ok(), Some(0x9000), "Pushing should keep the word above");
+
+        snapshot.header.sp = 0x4000;
+        assert!(snapshot.push_pc(0).is_err(), "The PC cannot be pushed into ROM");
+        assert_eq!({ snapshot.header.sp }, 0x4000);
+        snapshot.header.sp = 0x1000;
+        assert_eq!(snapshot.pc(), None);
+        assert!(snapshot.set_pc(0).is_err());
+
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        snapshot.set_pc(0x1234).unwrap();
+        assert_eq!((snapshot.pc(), snapshot.cpu_sp()), (Some(0x1234), FIXTURE_SP));
+    }
+}
@@ -0,0 +1,100 @@
+// ROM images attached to a snapshot. A .sna file holds only RAM, so without one
+// everything below 0x4000 reads as 0xFF; with one, peeks and the disassembler can
+// follow code into ROM routines. ROMs are tool state and are never saved.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{Bank, Machine, SnaError, Snapshot, SnapshotType, MEM_16K};
+
+impl Snapshot {
+    /// attach_rom attaches a ROM image: 16K for a 48K machine, 32K for a 128K machine
+    /// (the 128K editor ROM then 48K BASIC) or 64K for a +2A/+3 (ROMs 0 to 3). Reads
+    /// below 0x4000 then come from the ROM the paging selects. On a 128K snapshot that
+    /// is the one chosen by bit 4 of 0x7FFD and, on a +2A/+3 or Scorpion, by port 0x1FFD,
+    /// falling back to ROM 0 if the image has no such ROM. On a 48K snapshot it is the
+    /// last ROM of the image, which is 48K BASIC. Any ROM image already attached is
+    /// replaced. Returns SnaError::InvalidFormat for any other length.
+    pub fn attach_rom(&mut self, image: &[u8]) -> Result<(), SnaError> {
+        if !matches!(image.len(), 0x4000 | 0x8000 | 0x10000) {
+            return Err(SnaError::InvalidFormat(format!("A ROM image must be 16K, 32K or 64K, not {} bytes", image.len())));
+        }
+        self.roms = image.chunks(MEM_16K).map(Bank::from_slice).collect::<Result<_, _>>()?;
+        self.rom = self.paged_rom();
+        Ok(())
+    }
+
+    /// attach_rom_file is attach_rom for a ROM image in a file, such as `128.rom`.
+    /// Returns SnaError::Io if the file cannot be read.
+    pub fn attach_rom_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SnaError> {
+        self.attach_rom(&fs::read(path)?)
+    }
+
+    /// detach_rom removes any attached ROM image, so reads below 0x4000 return 0xFF again.
+    pub fn detach_rom(&mut self) {
+        self.roms.clear();
+    }
+
+    /// rom_count returns the number of 16K ROMs attached, 0 if there is no ROM image.
+    pub fn rom_count(&self) -> usize {
+        self.roms.len()
+    }
+
+    /// active_rom returns which attached ROM is paged in at 0x0000, or None if there is
+    /// no ROM image.
+    pub fn active_rom(&self) -> Option<usize> {
+        (!self.roms.is_empty()).then_some(self.rom)
+    }
+
+    // the index of the ROM the paging selects, falling back to ROM 0
+    pub(crate) fn paged_rom(&self) -> usize {
+        let selected = match (self.snapshot_type, self.extension.as_ref()) {
+            (SnapshotType::Snapshot128, Some(extension)) => match (self.machine(), self.x1ffd.unwrap_or(0)) {
+                (Machine::Scorpion256, x1ffd) if x1ffd & 0x02 != 0 => 2,
+                (machine, x1ffd) if machine.has_plus3_paging() => ((x1ffd >> 1) & 2 | (extension.x7ffd >> 4) & 1) as usize,
+                _ => ((extension.x7ffd >> 4) & 1) as usize,
+            },
+            _ => self.roms.len().saturating_sub(1),
+        };
+        if selected < self.roms.len() { selected } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a ROM image whose every byte is the number of its 16K ROM
+    fn image(roms: usize) -> Vec<u8> {
+        (0..roms).flat_map(|rom| vec![rom as u8; MEM_16K]).collect()
+    }
+
+    #[test]
+    fn test_attach_rom() {
+        let mut snapshot = Snapshot::new_48k();
+        assert_eq!(snapshot.peek(0x0038), 0xFF);
+        assert_eq!(snapshot.active_rom(), None);
+        snapshot.attach_rom(&image(1)).unwrap();
+        assert_eq!(snapshot.peek(0x0038), 0);
+        assert_eq!(snapshot.disassemble_one(0x0000).bytes, [0x00], "The disassembler reads the ROM");
+        assert!(snapshot.poke(0x0038, 1).is_err(), "The ROM is still read only");
+        snapshot.attach_rom(&image(2)).unwrap();
+        assert_eq!(snapshot.peek(0x3FFF), 1, "A 48K snapshot runs 48K BASIC, the last ROM");
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.attach_rom(&image(4)).unwrap();
+        assert_eq!(snapshot.rom_count(), 4);
+        assert_eq!(snapshot.peek(0x0000), 0, "Bit 4 of 0x7FFD is clear");
+        snapshot.write_0x7ffd(0x10).unwrap();
+        assert_eq!(snapshot.active_rom(), Some(1));
+        assert_eq!(snapshot.peek(0x0000), 1);
+        snapshot.attach_rom(&image(1)).unwrap();
+        assert_eq!(snapshot.active_rom(), Some(0), "There is no ROM 1 to select");
+
+        assert!(matches!(snapshot.attach_rom(&[0; 0x6000]), Err(SnaError::InvalidFormat(_))));
+        assert!(snapshot.attach_rom(&[]).is_err());
+        assert!(matches!(snapshot.attach_rom_file("missing.rom"), Err(SnaError::Io(_))));
+        snapshot.detach_rom();
+        assert_eq!(snapshot.peek(0x0000), 0xFF);
+    }
+}
@@ -0,0 +1,123 @@
+// Fast paths for bulk operations over memory banks.
+// These work on a vector register or a machine word of bytes at a time,
+// falling back to byte-at-a-time only for the remainder.
+// Run `cargo bench` to compare them with the byte-at-a-time loops.
+
+#[cfg(target_arch = "x86_64")]
+const SSE_WIDTH: usize = 16;
+const WORD: usize = std::mem::size_of::<usize>();
+const ONES: usize = usize::MAX / 0xFF;  // 0x01 in every byte
+const HIGHS: usize = ONES << 7;         // 0x80 in every byte
+
+/// sum_bytes adds up every byte in the slice.
+/// On x86-64 sixteen bytes at a time are summed with the SSE2 PSADBW instruction,
+/// which adds the absolute differences from zero into two 64-bit lanes. SSE2 is part
+/// of the x86-64 baseline, so no runtime detection is needed.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn sum_bytes(data: &[u8]) -> u32 {
+    use std::arch::x86_64::{__m128i, _mm_add_epi64, _mm_loadu_si128, _mm_sad_epu8, _mm_setzero_si128, _mm_storeu_si128};
+
+    let chunks = data.chunks_exact(SSE_WIDTH);
+    let remainder = chunks.remainder();
+    let mut lanes = [0u64; 2];
+    // SAFETY: SSE2 is always available on x86-64, and the loads and store are
+    // unaligned ones of exactly 16 bytes within the chunk and the lanes array.
+    unsafe {
+        let zero = _mm_setzero_si128();
+        let mut sums = _mm_setzero_si128();
+        for chunk in chunks {
+            let bytes = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            sums = _mm_add_epi64(sums, _mm_sad_epu8(bytes, zero));
+        }
+        _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, sums);
+    }
+    let sum = lanes[0].wrapping_add(lanes[1]) as u32;
+    remainder.iter().fold(sum, |sum, &byte| sum.wrapping_add(byte as u32))
+}
+
+/// sum_bytes adds up every byte in the slice. The compiler vectorises this loop, which
+/// is faster than splitting it into lanes by hand.
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) fn sum_bytes(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |sum, &byte| sum.wrapping_add(byte as u32))
+}
+
+/// find_byte returns the offset of the first occurrence of a byte in the slice.
+/// A word of bytes is tested at a time, XORing it with the byte repeated so that a
+/// match becomes a zero byte, which the classic has-zero-byte test finds without
+/// looking at the bytes one by one.
+pub(crate) fn find_byte(data: &[u8], byte: u8) -> Option<usize> {
+    let repeated = ONES * byte as usize;
+    let mut offset = 0;
+    for chunk in data.chunks_exact(WORD) {
+        let word = usize::from_ne_bytes(chunk.try_into().expect("Chunks are a word long")) ^ repeated;
+        if word.wrapping_sub(ONES) & !word & HIGHS != 0 {
+            break;
+        }
+        offset += WORD;
+    }
+    data[offset..].iter().position(|&found| found == byte).map(|index| offset + index)
+}
+
+/// find_all returns the offset of every occurrence of the needle in the data, including
+/// overlapping ones. Candidates are found with find_byte on the first byte of the
+/// needle and only then compared in full. An empty needle matches nothing.
+pub(crate) fn find_all(data: &[u8], needle: &[u8]) -> Vec<usize> {
+    let mut found = Vec::new();
+    let Some((&first, rest)) = needle.split_first() else {
+        return found;
+    };
+    let mut start = 0;
+    while start + needle.len() <= data.len() {
+        let Some(index) = find_byte(&data[start..=data.len() - needle.len()], first) else {
+            break;
+        };
+        let candidate = start + index;
+        if &data[candidate + 1..candidate + needle.len()] == rest {
+            found.push(candidate);
+        }
+        start = candidate + 1;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn naive_sum(data: &[u8]) -> u32 {
+        let mut sum: u32 = 0;
+        for byte in data {
+            sum = sum.wrapping_add(*byte as u32);
+        }
+        sum
+    }
+
+    #[test]
+    fn test_sum_bytes() {
+        let mut rng = rand::rng();
+        for len in [0, 1, 31, 32, 33, 1000, 16384] {
+            let data: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+            assert_eq!(sum_bytes(&data), naive_sum(&data), "Sum of {} bytes is incorrect", len);
+        }
+    }
+
+    #[test]
+    fn test_find_all() {
+        let mut rng = rand::rng();
+        // few distinct values so that there are plenty of overlapping matches
+        let data: Vec<u8> = (0..1000).map(|_| rng.random_range(0..3)).collect();
+        for needle in [&[0u8][..], &[1, 2], &[2, 2, 2], &[0, 1, 2, 0]] {
+            let naive: Vec<usize> = data.windows(needle.len()).enumerate()
+                .filter(|(_, window)| window == &needle)
+                .map(|(offset, _)| offset)
+                .collect();
+            assert_eq!(find_all(&data, needle), naive, "Matches of {:?} are incorrect", needle);
+        }
+        assert!(find_all(&data, &[]).is_empty());
+        assert!(find_all(&[1, 2], &[1, 2, 3]).is_empty());
+        assert_eq!(find_byte(&[0; 17], 0xFF), None);
+        assert_eq!(find_byte(&[0x80; 17], 0x80), Some(0));
+    }
+}
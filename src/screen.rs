@@ -0,0 +1,877 @@
+//! Decoding the Spectrum's display file and attributes into images.
+//!
+//! The screen is 256x192 pixels in 32x24 character cells. Each cell has one attribute
+//! byte: bits 0-2 are the INK colour, bits 3-5 the PAPER colour, bit 6 BRIGHT and
+//! bit 7 FLASH. Pixel rows are interleaved in memory in thirds of the screen.
+
+use std::fmt::Write;
+
+use crate::layout::{ATTRIBUTES, ATTRIBUTES_LEN, ATTRIBUTES_START, SCREEN, SCREEN_LEN, SCREEN_START};
+use crate::{charset, SnaError, Snapshot, SnapshotType};
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// Width of the screen in pixels.
+pub const WIDTH: usize = 256;
+/// Height of the screen in pixels.
+pub const HEIGHT: usize = 192;
+/// Size of a .SCR file: the display file followed by the attributes.
+pub const SCR_LEN: usize = (SCREEN_LEN + ATTRIBUTES_LEN) as usize;
+
+/// The 16 Spectrum colours as RGBA: black, blue, red, magenta, green, cyan, yellow
+/// and white, then the same with BRIGHT. Black is the same either way.
+pub const PALETTE: [[u8; 4]; 16] = [
+    [0x00, 0x00, 0x00, 0xFF], [0x00, 0x00, 0xD7, 0xFF], [0xD7, 0x00, 0x00, 0xFF], [0xD7, 0x00, 0xD7, 0xFF],
+    [0x00, 0xD7, 0x00, 0xFF], [0x00, 0xD7, 0xD7, 0xFF], [0xD7, 0xD7, 0x00, 0xFF], [0xD7, 0xD7, 0xD7, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF], [0x00, 0x00, 0xFF, 0xFF], [0xFF, 0x00, 0x00, 0xFF], [0xFF, 0x00, 0xFF, 0xFF],
+    [0x00, 0xFF, 0x00, 0xFF], [0x00, 0xFF, 0xFF, 0xFF], [0xFF, 0xFF, 0x00, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF],
+];
+
+/// Width of the screen in character cells, each with one attribute.
+pub const COLUMNS: usize = 32;
+/// Height of the screen in character cells.
+pub const ROWS: usize = 24;
+
+/// The Spectrum ROM character set, from 0x3D00 in the 48K ROM: eight bytes, top row
+/// first, for each of the characters from space (0x20) to © (0x7F).
+pub const ROM_FONT: [u8; 768] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,   // space
+    0x00, 0x10, 0x10, 0x10, 0x10, 0x00, 0x10, 0x00,   // !
+    0x00, 0x24, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00,   // "
+    0x00, 0x24, 0x7E, 0x24, 0x24, 0x7E, 0x24, 0x00,   // #
+    0x00, 0x08, 0x3E, 0x28, 0x3E, 0x0A, 0x3E, 0x08,   // $
+    0x00, 0x62, 0x64, 0x08, 0x10, 0x26, 0x46, 0x00,   // %
+    0x00, 0x10, 0x28, 0x10, 0x2A, 0x44, 0x3A, 0x00,   // &
+    0x00, 0x08, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00,   // '
+    0x00, 0x04, 0x08, 0x08, 0x08, 0x08, 0x04, 0x00,   // (
+    0x00, 0x20, 0x10, 0x10, 0x10, 0x10, 0x20, 0x00,   // )
+    0x00, 0x00, 0x14, 0x08, 0x3E, 0x08, 0x14, 0x00,   // *
+    0x00, 0x00, 0x08, 0x08, 0x3E, 0x08, 0x08, 0x00,   // +
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x08, 0x10,   // ,
+    0x00, 0x00, 0x00, 0x00, 0x3E, 0x00, 0x00, 0x00,   // -
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00,   // .
+    0x00, 0x00, 0x02, 0x04, 0x08, 0x10, 0x20, 0x00,   // /
+    0x00, 0x3C, 0x46, 0x4A, 0x52, 0x62, 0x3C, 0x00,   // 0
+    0x00, 0x18, 0x28, 0x08, 0x08, 0x08, 0x3E, 0x00,   // 1
+    0x00, 0x3C, 0x42, 0x02, 0x3C, 0x40, 0x7E, 0x00,   // 2
+    0x00, 0x3C, 0x42, 0x0C, 0x02, 0x42, 0x3C, 0x00,   // 3
+    0x00, 0x08, 0x18, 0x28, 0x48, 0x7E, 0x08, 0x00,   // 4
+    0x00, 0x7E, 0x40, 0x7C, 0x02, 0x42, 0x3C, 0x00,   // 5
+    0x00, 0x3C, 0x40, 0x7C, 0x42, 0x42, 0x3C, 0x00,   // 6
+    0x00, 0x7E, 0x02, 0x04, 0x08, 0x10, 0x10, 0x00,   // 7
+    0x00, 0x3C, 0x42, 0x3C, 0x42, 0x42, 0x3C, 0x00,   // 8
+    0x00, 0x3C, 0x42, 0x42, 0x3E, 0x02, 0x3C, 0x00,   // 9
+    0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x10, 0x00,   // :
+    0x00, 0x00, 0x10, 0x00, 0x00, 0x10, 0x10, 0x20,   // ;
+    0x00, 0x00, 0x04, 0x08, 0x10, 0x08, 0x04, 0x00,   // <
+    0x00, 0x00, 0x00, 0x3E, 0x00, 0x3E, 0x00, 0x00,   // =
+    0x00, 0x00, 0x10, 0x08, 0x04, 0x08, 0x10, 0x00,   // >
+    0x00, 0x3C, 0x42, 0x04, 0x08, 0x00, 0x08, 0x00,   // ?
+    0x00, 0x3C, 0x4A, 0x56, 0x5E, 0x40, 0x3C, 0x00,   // @
+    0x00, 0x3C, 0x42, 0x42, 0x7E, 0x42, 0x42, 0x00,   // A
+    0x00, 0x7C, 0x42, 0x7C, 0x42, 0x42, 0x7C, 0x00,   // B
+    0x00, 0x3C, 0x42, 0x40, 0x40, 0x42, 0x3C, 0x00,   // C
+    0x00, 0x78, 0x44, 0x42, 0x42, 0x44, 0x78, 0x00,   // D
+    0x00, 0x7E, 0x40, 0x7C, 0x40, 0x40, 0x7E, 0x00,   // E
+    0x00, 0x7E, 0x40, 0x7C, 0x40, 0x40, 0x40, 0x00,   // F
+    0x00, 0x3C, 0x42, 0x40, 0x4E, 0x42, 0x3C, 0x00,   // G
+    0x00, 0x42, 0x42, 0x7E, 0x42, 0x42, 0x42, 0x00,   // H
+    0x00, 0x3E, 0x08, 0x08, 0x08, 0x08, 0x3E, 0x00,   // I
+    0x00, 0x02, 0x02, 0x02, 0x42, 0x42, 0x3C, 0x00,   // J
+    0x00, 0x44, 0x48, 0x70, 0x48, 0x44, 0x42, 0x00,   // K
+    0x00, 0x40, 0x40, 0x40, 0x40, 0x40, 0x7E, 0x00,   // L
+    0x00, 0x42, 0x66, 0x5A, 0x42, 0x42, 0x42, 0x00,   // M
+    0x00, 0x42, 0x62, 0x52, 0x4A, 0x46, 0x42, 0x00,   // N
+    0x00, 0x3C, 0x42, 0x42, 0x42, 0x42, 0x3C, 0x00,   // O
+    0x00, 0x7C, 0x42, 0x42, 0x7C, 0x40, 0x40, 0x00,   // P
+    0x00, 0x3C, 0x42, 0x42, 0x52, 0x4A, 0x3C, 0x00,   // Q
+    0x00, 0x7C, 0x42, 0x42, 0x7C, 0x44, 0x42, 0x00,   // R
+    0x00, 0x3C, 0x40, 0x3C, 0x02, 0x42, 0x3C, 0x00,   // S
+    0x00, 0xFE, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00,   // T
+    0x00, 0x42, 0x42, 0x42, 0x42, 0x42, 0x3C, 0x00,   // U
+    0x00, 0x42, 0x42, 0x42, 0x42, 0x24, 0x18, 0x00,   // V
+    0x00, 0x42, 0x42, 0x42, 0x42, 0x5A, 0x24, 0x00,   // W
+    0x00, 0x42, 0x24, 0x18, 0x18, 0x24, 0x42, 0x00,   // X
+    0x00, 0x82, 0x44, 0x28, 0x10, 0x10, 0x10, 0x00,   // Y
+    0x00, 0x7E, 0x04, 0x08, 0x10, 0x20, 0x7E, 0x00,   // Z
+    0x00, 0x0E, 0x08, 0x08, 0x08, 0x08, 0x0E, 0x00,   // [
+    0x00, 0x00, 0x40, 0x20, 0x10, 0x08, 0x04, 0x00,   // \
+    0x00, 0x70, 0x10, 0x10, 0x10, 0x10, 0x70, 0x00,   // ]
+    0x00, 0x10, 0x38, 0x54, 0x10, 0x10, 0x10, 0x00,   // ↑
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF,   // _
+    0x00, 0x1C, 0x22, 0x78, 0x20, 0x20, 0x7E, 0x00,   // £
+    0x00, 0x00, 0x38, 0x04, 0x3C, 0x44, 0x3C, 0x00,   // a
+    0x00, 0x20, 0x20, 0x3C, 0x22, 0x22, 0x3C, 0x00,   // b
+    0x00, 0x00, 0x1C, 0x20, 0x20, 0x20, 0x1C, 0x00,   // c
+    0x00, 0x04, 0x04, 0x3C, 0x44, 0x44, 0x3C, 0x00,   // d
+    0x00, 0x00, 0x38, 0x44, 0x78, 0x40, 0x3C, 0x00,   // e
+    0x00, 0x0C, 0x10, 0x18, 0x10, 0x10, 0x10, 0x00,   // f
+    0x00, 0x00, 0x3C, 0x44, 0x44, 0x3C, 0x04, 0x38,   // g
+    0x00, 0x40, 0x40, 0x78, 0x44, 0x44, 0x44, 0x00,   // h
+    0x00, 0x10, 0x00, 0x30, 0x10, 0x10, 0x38, 0x00,   // i
+    0x00, 0x04, 0x00, 0x04, 0x04, 0x04, 0x24, 0x18,   // j
+    0x00, 0x20, 0x28, 0x30, 0x30, 0x28, 0x24, 0x00,   // k
+    0x00, 0x10, 0x10, 0x10, 0x10, 0x10, 0x0C, 0x00,   // l
+    0x00, 0x00, 0x68, 0x54, 0x54, 0x54, 0x54, 0x00,   // m
+    0x00, 0x00, 0x78, 0x44, 0x44, 0x44, 0x44, 0x00,   // n
+    0x00, 0x00, 0x38, 0x44, 0x44, 0x44, 0x38, 0x00,   // o
+    0x00, 0x00, 0x78, 0x44, 0x44, 0x78, 0x40, 0x40,   // p
+    0x00, 0x00, 0x3C, 0x44, 0x44, 0x3C, 0x04, 0x06,   // q
+    0x00, 0x00, 0x1C, 0x20, 0x20, 0x20, 0x20, 0x00,   // r
+    0x00, 0x00, 0x38, 0x40, 0x38, 0x04, 0x78, 0x00,   // s
+    0x00, 0x10, 0x38, 0x10, 0x10, 0x10, 0x0C, 0x00,   // t
+    0x00, 0x00, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00,   // u
+    0x00, 0x00, 0x44, 0x44, 0x28, 0x28, 0x10, 0x00,   // v
+    0x00, 0x00, 0x44, 0x54, 0x54, 0x54, 0x28, 0x00,   // w
+    0x00, 0x00, 0x44, 0x28, 0x10, 0x28, 0x44, 0x00,   // x
+    0x00, 0x00, 0x44, 0x44, 0x44, 0x3C, 0x04, 0x38,   // y
+    0x00, 0x00, 0x7C, 0x08, 0x10, 0x20, 0x7C, 0x00,   // z
+    0x00, 0x0E, 0x08, 0x30, 0x08, 0x08, 0x0E, 0x00,   // {
+    0x00, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x00,   // |
+    0x00, 0x70, 0x10, 0x0C, 0x10, 0x10, 0x70, 0x00,   // }
+    0x00, 0x14, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00,   // ~
+    0x3C, 0x42, 0x99, 0xA1, 0xA1, 0x99, 0x42, 0x3C,   // ©
+];
+
+/// The colours of a character cell, one field per part of its attribute byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Attribute {
+    pub ink: u8,        // bits 0-2, the colour of set pixels
+    pub paper: u8,      // bits 3-5, the colour of clear pixels
+    pub bright: bool,   // bit 6
+    pub flash: bool,    // bit 7, swap ink and paper every 16 frames
+}
+
+impl Attribute {
+    /// from_byte decodes an attribute byte.
+    pub fn from_byte(attribute: u8) -> Attribute {
+        Attribute {
+            ink: attribute & 0x07,
+            paper: (attribute >> 3) & 0x07,
+            bright: attribute & 0x40 != 0,
+            flash: attribute & 0x80 != 0,
+        }
+    }
+
+    /// to_byte encodes the attribute. Only the low three bits of ink and paper are used.
+    pub fn to_byte(self) -> u8 {
+        (self.flash as u8) << 7 | (self.bright as u8) << 6 | (self.paper & 0x07) << 3 | (self.ink & 0x07)
+    }
+}
+
+// the border drawn around the screen when RenderOptions::border is set
+const BORDER_X: usize = 32;
+const BORDER_Y: usize = 24;
+
+/// Options controlling how the screen is rendered as an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub scale: usize,   // pixels per Spectrum pixel in each direction, e.g. 2 or 3; 0 is treated as 1
+    pub border: bool,   // frame the screen with 32 pixels of border colour at the sides and 24 above and below
+    pub flash: bool,    // draw the second FLASH frame, with ink and paper swapped in flashing cells
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions { scale: 1, border: false, flash: false }
+    }
+}
+
+/// An RGBA image, row by row from the top left.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// A rendered screen kept up to date by [`Snapshot::render_screen_incremental`], which
+/// redraws only the character cells that have changed since it last drew them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScreenImage {
+    pub image: Image,
+    options: RenderOptions,
+    drawn: Vec<u8>,     // the screen bytes the image shows, empty until it is first drawn
+    border: u8,         // the border colour the image shows
+}
+
+impl ScreenImage {
+    /// new returns a screen image rendered with the options. The image is empty until
+    /// it is first passed to render_screen_incremental.
+    pub fn new(options: RenderOptions) -> ScreenImage {
+        let image = Image { width: 0, height: 0, rgba: Vec::new() };
+        ScreenImage { image, options, drawn: Vec::new(), border: 0 }
+    }
+
+    // draw_cell renders character cell (col, row) of the screen into the image
+    fn draw_cell(&mut self, screen: &[u8], col: usize, row: usize) {
+        let (border_x, border_y) = if self.options.border { (BORDER_X, BORDER_Y) } else { (0, 0) };
+        let scale = self.options.scale.max(1);
+        for y in row * 8..row * 8 + 8 {
+            for x in col * 8..col * 8 + 8 {
+                let colour = PALETTE[colour_at(screen, x, y, self.options.flash)];
+                for line in 0..scale {
+                    let start = (((border_y + y) * scale + line) * self.image.width + (border_x + x) * scale) * 4;
+                    for pixel in self.image.rgba[start..start + scale * 4].chunks_exact_mut(4) {
+                        pixel.copy_from_slice(&colour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// pixel_offset returns the offset into the display file of the byte holding pixel
+/// (x, y), whose bit 7 - (x % 8) is the pixel.
+pub(crate) fn pixel_offset(x: usize, y: usize) -> usize {
+    ((y & 0xC0) << 5) | ((y & 0x07) << 8) | ((y & 0x38) << 2) | (x >> 3)
+}
+
+/// pixel_address returns the address of the display file byte holding pixel (x, y),
+/// whose bit 7 - (x % 8) is the pixel. Rows are interleaved: y is made up of the
+/// third of the screen in bits 6-7, the character row in bits 3-5 and the pixel row
+/// within the character in bits 0-2, and the address holds them in the order third,
+/// pixel row, character row. Returns SnaError::OffScreen unless x < 256 and y < 192.
+pub fn pixel_address(x: usize, y: usize) -> Result<u16, SnaError> {
+    check_pixel(x, y)?;
+    Ok(SCREEN_START + pixel_offset(x, y) as u16)
+}
+
+/// pixel_coordinates is the inverse of pixel_address: it returns the coordinates of the
+/// leftmost of the eight pixels held by a display file byte, or None if the address is
+/// not in the display file.
+pub fn pixel_coordinates(address: u16) -> Option<(usize, usize)> {
+    if !SCREEN.contains(&address) {
+        return None;
+    }
+    let offset = (address - SCREEN_START) as usize;
+    let y = ((offset >> 5) & 0xC0) | ((offset >> 8) & 0x07) | ((offset >> 2) & 0x38);
+    Some(((offset & 0x1F) * 8, y))
+}
+
+/// attr_address returns the address of the attribute of character cell (col, row).
+/// Returns SnaError::OffScreen unless col < 32 and row < 24.
+pub fn attr_address(col: usize, row: usize) -> Result<u16, SnaError> {
+    check_cell(col, row)?;
+    Ok(SCREEN_START + attr_offset(col, row) as u16)
+}
+
+/// attr_coordinates is the inverse of attr_address: it returns the cell whose attribute
+/// is at an address, or None if the address is not in the attribute file.
+pub fn attr_coordinates(address: u16) -> Option<(usize, usize)> {
+    if !ATTRIBUTES.contains(&address) {
+        return None;
+    }
+    let offset = (address - ATTRIBUTES_START) as usize;
+    Some((offset % COLUMNS, offset / COLUMNS))
+}
+
+// the offset into the screen of the attribute of cell (col, row)
+fn attr_offset(col: usize, row: usize) -> usize {
+    SCREEN_LEN as usize + row * COLUMNS + col
+}
+
+fn check_pixel(x: usize, y: usize) -> Result<(), SnaError> {
+    if x >= WIDTH || y >= HEIGHT {
+        return Err(SnaError::OffScreen { x, y });
+    }
+    Ok(())
+}
+
+fn check_cell(col: usize, row: usize) -> Result<(), SnaError> {
+    if col >= COLUMNS || row >= ROWS {
+        return Err(SnaError::OffScreen { x: col, y: row });
+    }
+    Ok(())
+}
+
+/// decode converts a screen of 6912 bytes, the display file followed by the attributes
+/// as in a .SCR file, into 256x192 RGBA pixels, row by row from the top left.
+/// Flashing cells are drawn as they appear in the first frame, see [`decode_frame`].
+/// Returns SnaError::InvalidFormat if there are fewer than 6912 bytes.
+pub fn decode(screen: &[u8]) -> Result<Vec<u8>, SnaError> {
+    decode_frame(screen, false)
+}
+
+/// decode_frame is decode for either of the two frames the ULA alternates between
+/// every 16 frames: with `flash` set, ink and paper are swapped in cells with FLASH.
+/// Returns SnaError::InvalidFormat if there are fewer than 6912 bytes.
+pub fn decode_frame(screen: &[u8], flash: bool) -> Result<Vec<u8>, SnaError> {
+    if screen.len() < SCR_LEN {
+        return Err(SnaError::InvalidFormat(format!("{} bytes is too short for a screen", screen.len())));
+    }
+    Ok(frame(screen, flash))
+}
+
+// decode_frame for a screen known to be at least 6912 bytes long
+fn frame(screen: &[u8], flash: bool) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(WIDTH * HEIGHT * 4);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            rgba.extend_from_slice(&PALETTE[colour_at(screen, x, y, flash)]);
+        }
+    }
+    rgba
+}
+
+// the index into PALETTE of pixel (x, y)
+fn colour_at(screen: &[u8], x: usize, y: usize, flash: bool) -> usize {
+    let attribute = screen[SCREEN_LEN as usize + (y / 8) * COLUMNS + x / 8];
+    let bright = (attribute & 0x40) >> 3;
+    let inverted = flash && attribute & 0x80 != 0;
+    let on = (screen[pixel_offset(x, y)] & (0x80 >> (x % 8)) != 0) != inverted;
+    let colour = if on { attribute & 0x07 } else { (attribute >> 3) & 0x07 };
+    (colour | bright) as usize
+}
+
+// the ANSI colour number of each Spectrum colour, whose bits are blue, red and green
+// where ANSI's are red, green and blue
+const ANSI: [u8; 8] = [0, 4, 1, 5, 2, 6, 3, 7];
+
+impl Snapshot {
+    /// screen_bank returns the bank the ULA is displaying: bank 0 on 48K
+    /// snapshots, and bank 5 or 7 on 128K snapshots depending on bit 3 of 0x7FFD.
+    pub(crate) fn screen_bank(&self) -> usize {
+        match (self.snapshot_type, self.extension.as_ref()) {
+            (SnapshotType::Snapshot128, Some(extension)) if extension.x7ffd & 0x08 != 0 => 7,
+            (SnapshotType::Snapshot128, _) => 5,
+            (SnapshotType::Snapshot48, _) => 0,
+        }
+    }
+
+    /// screen_bytes returns the display file and attributes of the visible screen, 6912 bytes.
+    pub(crate) fn screen_bytes(&self) -> &[u8] {
+        &self.banks[self.screen_bank()][..SCR_LEN]
+    }
+
+    /// screen_hash returns a stable 64-bit FNV-1a hash of the visible screen's
+    /// display file and attributes. Snapshots showing the same screen have the
+    /// same hash, whatever the rest of their memory holds.
+    pub fn screen_hash(&self) -> u64 {
+        fnv1a(self.screen_bytes())
+    }
+
+    /// screen_rgba decodes the visible screen into 256x192 RGBA pixels, see [`decode`].
+    /// On 128K snapshots this is the normal or shadow screen depending on bit 3 of 0x7FFD.
+    pub fn screen_rgba(&self) -> Vec<u8> {
+        frame(self.screen_bytes(), false)
+    }
+
+    /// shadow_screen_rgba decodes the shadow screen in bank 7, whether or not it is
+    /// displayed. Returns SnaError::Not128K for 48K snapshots.
+    pub fn shadow_screen_rgba(&self) -> Result<Vec<u8>, SnaError> {
+        if self.snapshot_type != SnapshotType::Snapshot128 {
+            return Err(SnaError::Not128K);
+        }
+        Ok(frame(&self.banks[7][..SCR_LEN], false))
+    }
+
+    /// get_pixel returns whether the pixel at (x, y) of the visible screen is set, that
+    /// is drawn in the ink colour. Returns SnaError::OffScreen unless x < 256 and y < 192.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Result<bool, SnaError> {
+        check_pixel(x, y)?;
+        Ok(self.banks[self.screen_bank()][pixel_offset(x, y)] & (0x80 >> (x % 8)) != 0)
+    }
+
+    /// set_pixel sets or clears the pixel at (x, y) of the visible screen.
+    /// Returns SnaError::OffScreen unless x < 256 and y < 192, and SnaError::Guarded
+    /// if a guard blocks the write where the screen is paged in.
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) -> Result<(), SnaError> {
+        check_pixel(x, y)?;
+        let bank = self.screen_bank();
+        let offset = pixel_offset(x, y);
+        let mut byte = self.banks[bank][offset];
+        if on {
+            byte |= 0x80 >> (x % 8);
+        } else {
+            byte &= !(0x80 >> (x % 8));
+        }
+        self.check_bank_guards(bank, offset, &[byte])?;
+        let edit = self.start_edit(bank, offset..offset + 1);
+        self.banks[bank][offset] = byte;
+        self.finish_edits(edit);
+        Ok(())
+    }
+
+    /// get_attr returns the attribute of the character cell at (col, row) of the visible
+    /// screen. Returns SnaError::OffScreen unless col < 32 and row < 24.
+    pub fn get_attr(&self, col: usize, row: usize) -> Result<Attribute, SnaError> {
+        check_cell(col, row)?;
+        Ok(Attribute::from_byte(self.banks[self.screen_bank()][attr_offset(col, row)]))
+    }
+
+    /// set_attr sets the attribute of the character cell at (col, row) of the visible
+    /// screen. Returns SnaError::OffScreen unless col < 32 and row < 24, and
+    /// SnaError::Guarded if a guard blocks the write where the screen is paged in.
+    pub fn set_attr(&mut self, col: usize, row: usize, attribute: Attribute) -> Result<(), SnaError> {
+        check_cell(col, row)?;
+        let bank = self.screen_bank();
+        let offset = attr_offset(col, row);
+        self.check_bank_guards(bank, offset, &[attribute.to_byte()])?;
+        let edit = self.start_edit(bank, offset..offset + 1);
+        self.banks[bank][offset] = attribute.to_byte();
+        self.finish_edits(edit);
+        Ok(())
+    }
+
+    /// print_at draws text into the visible screen in the ROM font, starting at character
+    /// cell (col, row) and continuing on the next row at the right edge as PRINT does,
+    /// and gives each cell the attribute. Block graphics are drawn as the ROM draws them
+    /// and user defined graphics from the address in UDG. Nothing is drawn unless all of
+    /// the text fits: returns SnaError::InvalidFormat if it has characters outside the
+    /// Spectrum character set, SnaError::OffScreen with the first cell past the bottom
+    /// right if it runs off the screen, and SnaError::Guarded if a guard blocks any of
+    /// the writes where the screen is paged in.
+    pub fn print_at(&mut self, col: usize, row: usize, text: &str, attribute: Attribute) -> Result<(), SnaError> {
+        self.print_at_with_font(col, row, text, attribute, &ROM_FONT)
+    }
+
+    /// print_at_with_font is print_at with a 768-byte font in the ROM's layout, such as
+    /// a game's own character set, used in place of the ROM font.
+    pub fn print_at_with_font(&mut self, col: usize, row: usize, text: &str, attribute: Attribute, font: &[u8; 768]) -> Result<(), SnaError> {
+        check_cell(col, row)?;
+        let codes = charset::encode(text)?;
+        let start = row * COLUMNS + col;
+        if start + codes.len() > COLUMNS * ROWS {
+            return Err(SnaError::OffScreen { x: 0, y: ROWS });
+        }
+        let udg = self.sysvars().udg;
+        let glyphs: Vec<[u8; 8]> = codes.iter().map(|&code| match code {
+            0x20..=0x7F => {
+                let offset = (code - 0x20) as usize * 8;
+                font[offset..offset + 8].try_into().expect("A glyph is 8 bytes")
+            }
+            // each quarter of a block graphic is four pixels square
+            0x80..=0x8F => {
+                let half = |bits: u8| (if bits & 2 != 0 { 0xF0 } else { 0 }) | (if bits & 1 != 0 { 0x0F } else { 0 });
+                let (top, bottom) = (half(code), half(code >> 2));
+                [top, top, top, top, bottom, bottom, bottom, bottom]
+            }
+            _ => std::array::from_fn(|line| self.peek(udg.wrapping_add((code - charset::FIRST_UDG) as u16 * 8 + line as u16))),
+        }).collect();
+
+        let writes: Vec<(usize, u8)> = (start..).zip(glyphs).flat_map(|(cell, glyph)| {
+            let (col, row) = (cell % COLUMNS, cell / COLUMNS);
+            let lines = glyph.into_iter().enumerate().map(move |(line, byte)| (pixel_offset(col * 8, row * 8 + line), byte));
+            lines.chain([(attr_offset(col, row), attribute.to_byte())])
+        }).collect();
+        let bank = self.screen_bank();
+        self.check_bank_writes(bank, &writes)?;
+        let edit = self.start_edit(bank, 0..SCR_LEN);
+        for (offset, byte) in writes {
+            self.banks[bank][offset] = byte;
+        }
+        self.finish_edits(edit);
+        Ok(())
+    }
+
+    /// uses_flash returns whether any cell of the visible screen has FLASH set, in which
+    /// case a single rendered frame does not show everything the screen does.
+    pub fn uses_flash(&self) -> bool {
+        self.screen_bytes()[SCREEN_LEN as usize..].iter().any(|&attribute| attribute & 0x80 != 0)
+    }
+
+    /// render draws the visible screen as an image, scaled and framed by the border
+    /// colour as the options ask.
+    pub fn render(&self, options: RenderOptions) -> Image {
+        let screen = frame(self.screen_bytes(), options.flash);
+        let (border_x, border_y) = if options.border { (BORDER_X, BORDER_Y) } else { (0, 0) };
+        let border = PALETTE[(self.header.border_color & 0x07) as usize];
+        let scale = options.scale.max(1);
+        let (width, height) = (WIDTH + 2 * border_x, HEIGHT + 2 * border_y);
+
+        let mut rgba = Vec::with_capacity(width * height * scale * scale * 4);
+        for y in 0..height {
+            let row: Vec<u8> = (0..width).flat_map(|x| {
+                let on_screen = (border_x..border_x + WIDTH).contains(&x) && (border_y..border_y + HEIGHT).contains(&y);
+                let pixel = if on_screen { &screen[((y - border_y) * WIDTH + x - border_x) * 4..][..4] } else { &border[..] };
+                pixel.repeat(scale)
+            }).collect();
+            for _ in 0..scale {
+                rgba.extend_from_slice(&row);
+            }
+        }
+        Image { width: width * scale, height: height * scale, rgba }
+    }
+
+    /// render_ansi draws the visible screen for a terminal, as 96 lines of 256 upper
+    /// half block characters each coloured with the pixel above as the foreground and
+    /// the pixel below as the background. The 16 standard ANSI colours are used, the
+    /// bright ones for BRIGHT, so the terminal's palette decides the exact shades.
+    pub fn render_ansi(&self) -> String {
+        let screen = self.screen_bytes();
+        let mut out = String::new();
+        for y in (0..HEIGHT).step_by(2) {
+            let mut last = None;
+            for x in 0..WIDTH {
+                let colours = (colour_at(screen, x, y, false), colour_at(screen, x, y + 1, false));
+                if last != Some(colours) {
+                    let code = |colour: usize, base: u8| ANSI[colour & 0x07] + if colour & 0x08 != 0 { base + 60 } else { base };
+                    let _ = write!(out, "\x1b[{};{}m", code(colours.0, 30), code(colours.1, 40));
+                    last = Some(colours);
+                }
+                out.push('\u{2580}');
+            }
+            out.push_str("\x1b[0m\n");
+        }
+        out
+    }
+
+    /// render_flash_frames renders both frames of the visible screen, the normal one and
+    /// the one with flashing cells inverted, whatever the flash option says. The ULA
+    /// shows each for 16 frames, 0.32 seconds.
+    pub fn render_flash_frames(&self, options: RenderOptions) -> [Image; 2] {
+        [self.render(RenderOptions { flash: false, ..options }), self.render(RenderOptions { flash: true, ..options })]
+    }
+
+    /// render_screen_incremental brings a screen image up to date with the visible screen,
+    /// for viewers that redraw it after every few pokes. Only the character cells whose
+    /// pixels or attribute differ from those last drawn are rendered again, and they are
+    /// returned as (col, row) from the top left. The whole image is drawn, and every cell
+    /// returned, the first time and when the border it shows changes colour.
+    pub fn render_screen_incremental(&self, image: &mut ScreenImage) -> Vec<(usize, usize)> {
+        let screen = self.screen_bytes();
+        let border = self.header.border_color & 0x07;
+        let cells = (0..ROWS).flat_map(|row| (0..COLUMNS).map(move |col| (col, row)));
+        if image.drawn.is_empty() || (image.options.border && image.border != border) {
+            image.image = self.render(image.options);
+            image.drawn = screen.to_vec();
+            image.border = border;
+            return cells.collect();
+        }
+
+        let changed = |&(col, row): &(usize, usize)| {
+            let mut offsets = (0..8).map(|line| pixel_offset(col * 8, row * 8 + line)).chain([attr_offset(col, row)]);
+            offsets.any(|offset| screen[offset] != image.drawn[offset])
+        };
+        let dirty: Vec<_> = cells.filter(changed).collect();
+        for &(col, row) in &dirty {
+            image.draw_cell(screen, col, row);
+        }
+        image.drawn.copy_from_slice(screen);
+        dirty
+    }
+
+    /// screenshot_png encodes the visible screen, rendered with the options, as a PNG file.
+    #[cfg(feature = "image")]
+    pub fn screenshot_png(&self, options: RenderOptions) -> Result<Vec<u8>, SnaError> {
+        let image = self.render(options);
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, image.width as u32, image.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+        writer.write_image_data(&image.rgba).map_err(std::io::Error::other)?;
+        writer.finish().map_err(std::io::Error::other)?;
+        Ok(png)
+    }
+
+    /// export_scr returns the visible screen as the 6912 bytes of a .SCR file.
+    pub fn export_scr(&self) -> Vec<u8> {
+        self.screen_bytes().to_vec()
+    }
+
+    /// import_scr writes a .SCR file into the visible screen, which on 128K snapshots
+    /// is the normal or shadow screen depending on bit 3 of 0x7FFD.
+    /// Returns SnaError::InvalidFormat if the data is not 6912 bytes long, and
+    /// SnaError::Guarded if a guard blocks the write where the screen is paged in.
+    pub fn import_scr(&mut self, scr: &[u8]) -> Result<(), SnaError> {
+        let bank = self.screen_bank();
+        self.import_scr_into(bank, scr)
+    }
+
+    /// export_shadow_scr returns the shadow screen in bank 7 as a .SCR file, whether or
+    /// not it is displayed. Returns SnaError::Not128K for 48K snapshots.
+    pub fn export_shadow_scr(&self) -> Result<Vec<u8>, SnaError> {
+        if self.snapshot_type != SnapshotType::Snapshot128 {
+            return Err(SnaError::Not128K);
+        }
+        Ok(self.banks[7][..SCR_LEN].to_vec())
+    }
+
+    /// import_shadow_scr writes a .SCR file into the shadow screen in bank 7, whether
+    /// or not it is displayed. Returns SnaError::Not128K for 48K snapshots,
+    /// SnaError::InvalidFormat if the data is not 6912 bytes long, and
+    /// SnaError::Guarded if a guard blocks the write where bank 7 is paged in.
+    pub fn import_shadow_scr(&mut self, scr: &[u8]) -> Result<(), SnaError> {
+        if self.snapshot_type != SnapshotType::Snapshot128 {
+            return Err(SnaError::Not128K);
+        }
+        self.import_scr_into(7, scr)
+    }
+
+    fn import_scr_into(&mut self, bank: usize, scr: &[u8]) -> Result<(), SnaError> {
+        if scr.len() != SCR_LEN {
+            return Err(SnaError::InvalidFormat(format!("{} bytes is not the size of a .SCR file", scr.len())));
+        }
+        self.check_bank_guards(bank, 0, scr)?;
+        let edit = self.start_edit(bank, 0..SCR_LEN);
+        self.banks[bank][..SCR_LEN].copy_from_slice(scr);
+        self.finish_edits(edit);
+        Ok(())
+    }
+}
+
+/// fnv1a returns the 64-bit FNV-1a hash of the bytes.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+    use crate::GuardAction;
+
+    #[test]
+    fn test_screen_hash() {
+        let options = FixtureOptions { screen: true, ..FixtureOptions::default() };
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, options);
+        let hash = snapshot.screen_hash();
+        assert_eq!(hash, fixtures::generate(SnapshotType::Snapshot48, options).screen_hash(), "Same screen should hash the same");
+
+        snapshot.poke(0x5B00, 0xFF).unwrap();
+        assert_eq!(snapshot.screen_hash(), hash, "Memory outside the screen should not affect the hash");
+        snapshot.poke(0x5AFF, 0xFF).unwrap();
+        assert_ne!(snapshot.screen_hash(), hash, "A changed attribute should change the hash");
+
+        snapshot.write_0x7ffd(0x08).unwrap();
+        assert_eq!(snapshot.screen_bank(), 7);
+        assert_ne!(snapshot.screen_hash(), hash, "The shadow screen should be hashed when it is displayed");
+    }
+
+    #[test]
+    fn test_screen_rgba() {
+        assert_eq!(PALETTE[2], [0xD7, 0x00, 0x00, 0xFF], "Red");
+        assert_eq!(PALETTE[13], [0x00, 0xFF, 0xFF, 0xFF], "Bright cyan");
+        assert_eq!(PALETTE[8], PALETTE[0], "Bright black is black");
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.poke(0x5800, 0x42).unwrap();          // BRIGHT, PAPER 0, INK 2
+        snapshot.poke(0x4000, 0x80).unwrap();          // the top left pixel
+        snapshot.poke(0x4100 + 31, 0x01).unwrap();     // the last pixel of the second row
+        snapshot.poke(0x5800 + 31, 0x38).unwrap();     // PAPER 7, INK 0
+        let rgba = snapshot.screen_rgba();
+        assert_eq!(rgba.len(), WIDTH * HEIGHT * 4);
+        let pixel = |rgba: &[u8], x: usize, y: usize| -> [u8; 4] { rgba[(y * WIDTH + x) * 4..][..4].try_into().unwrap() };
+        assert_eq!(pixel(&rgba, 0, 0), PALETTE[10], "Bright red ink");
+        assert_eq!(pixel(&rgba, 1, 0), PALETTE[8], "Bright black paper");
+        assert_eq!(pixel(&rgba, 255, 1), PALETTE[0], "Black ink in the interleaved second row");
+        assert_eq!(pixel(&rgba, 254, 1), PALETTE[7], "White paper");
+
+        assert!(snapshot.shadow_screen_rgba().unwrap().chunks(4).all(|pixel| pixel == PALETTE[0]), "The shadow screen is blank");
+        snapshot.write_0x7ffd(0x08).unwrap();
+        assert_eq!(snapshot.screen_rgba(), snapshot.shadow_screen_rgba().unwrap());
+        assert!(matches!(Snapshot::new_48k().shadow_screen_rgba(), Err(SnaError::Not128K)));
+
+        assert_eq!(decode(snapshot.screen_bytes()).unwrap(), snapshot.screen_rgba());
+        assert!(matches!(decode(&snapshot.screen_bytes()[1..]), Err(SnaError::InvalidFormat(_))), "A short screen is an error");
+        assert!(matches!(decode_frame(&[], true), Err(SnaError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_scr() {
+        let scr: Vec<u8> = (0..SCR_LEN).map(|offset| offset as u8).collect();
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.import_scr(&scr).unwrap();
+        assert_eq!(snapshot.peek(0x5AFF), scr[SCR_LEN - 1]);
+        assert_eq!(snapshot.export_scr(), scr);
+        assert!(snapshot.export_shadow_scr().unwrap().iter().all(|&byte| byte == 0), "The shadow screen was written");
+
+        snapshot.write_0x7ffd(0x08).unwrap();
+        snapshot.import_scr(&vec![0xFF; SCR_LEN]).unwrap();
+        assert_eq!(snapshot.export_shadow_scr().unwrap(), vec![0xFF; SCR_LEN], "The displayed shadow screen was not written");
+        snapshot.import_shadow_scr(&scr).unwrap();
+        assert_eq!(snapshot.export_scr(), scr);
+
+        assert!(matches!(snapshot.import_scr(&scr[1..]), Err(SnaError::InvalidFormat(_))));
+        assert!(matches!(Snapshot::new_48k().import_shadow_scr(&scr), Err(SnaError::Not128K)));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.header.border_color = 1;
+        snapshot.poke(0x5800, 0x0A).unwrap();          // PAPER 1, INK 2
+        snapshot.poke(0x4000, 0x80).unwrap();
+
+        let image = snapshot.render(RenderOptions::default());
+        assert_eq!((image.width, image.height), (WIDTH, HEIGHT));
+        assert_eq!(image.rgba, snapshot.screen_rgba());
+
+        let image = snapshot.render(RenderOptions { scale: 2, border: true, flash: false });
+        assert_eq!((image.width, image.height), (640, 480));
+        let pixel = |x: usize, y: usize| &image.rgba[(y * image.width + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), PALETTE[1], "Blue border");
+        assert_eq!(pixel(64, 48), PALETTE[2], "Red ink at the top left of the screen");
+        assert_eq!(pixel(65, 49), PALETTE[2], "Pixels are doubled in both directions");
+        assert_eq!(pixel(66, 48), PALETTE[1], "Blue paper");
+    }
+
+    #[test]
+    fn test_render_screen_incremental() {
+        let mut snapshot = Snapshot::new_48k();
+        let options = RenderOptions { scale: 2, border: true, flash: true };
+        let mut image = ScreenImage::new(options);
+        assert_eq!(snapshot.render_screen_incremental(&mut image).len(), COLUMNS * ROWS, "The first render draws every cell");
+        assert_eq!(image.image, snapshot.render(options));
+
+        snapshot.poke(0x4000, 0x81).unwrap();
+        snapshot.set_attr(5, 3, Attribute { ink: 2, paper: 6, bright: true, flash: true }).unwrap();
+        snapshot.poke(0x5B00, 0xFF).unwrap();
+        assert_eq!(snapshot.render_screen_incremental(&mut image), [(0, 0), (5, 3)]);
+        assert_eq!(image.image, snapshot.render(options), "Redrawn cells should match a full render");
+        assert!(snapshot.render_screen_incremental(&mut image).is_empty(), "Nothing has changed since the last render");
+
+        snapshot.header.border_color = 2;
+        assert_eq!(snapshot.render_screen_incremental(&mut image).len(), COLUMNS * ROWS, "A new border colour redraws everything");
+        assert_eq!(image.image, snapshot.render(options));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_screenshot_png() {
+        let snapshot = Snapshot::new_48k();
+        let png = snapshot.screenshot_png(RenderOptions { scale: 3, ..RenderOptions::default() }).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let decoder = png::Decoder::new(std::io::Cursor::new(png));
+        let reader = decoder.read_info().expect("Failed to read PNG");
+        assert_eq!((reader.info().width, reader.info().height), (768, 576));
+    }
+
+    #[test]
+    fn test_pixels_and_attributes() {
+        let red_on_yellow = Attribute { ink: 2, paper: 6, bright: true, flash: false };
+        assert_eq!(red_on_yellow.to_byte(), 0x72);
+        assert_eq!(Attribute::from_byte(0xB1), Attribute { ink: 1, paper: 6, bright: false, flash: true });
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.set_pixel(9, 65, true).unwrap();
+        assert_eq!(snapshot.peek(0x4901), 0x40, "Row 65 is the second pixel row of the ninth character row");
+        assert!(snapshot.get_pixel(9, 65).unwrap());
+        assert!(!snapshot.get_pixel(8, 65).unwrap());
+        snapshot.set_pixel(9, 65, false).unwrap();
+        assert_eq!(snapshot.peek(0x4901), 0x00);
+
+        snapshot.set_attr(31, 23, red_on_yellow).unwrap();
+        assert_eq!(snapshot.peek(0x5AFF), 0x72);
+        assert_eq!(snapshot.get_attr(31, 23).unwrap(), red_on_yellow);
+
+        snapshot.write_0x7ffd(0x08).unwrap();
+        snapshot.set_pixel(0, 0, true).unwrap();
+        assert_eq!(snapshot.bank_peek(7, 0).unwrap(), 0x80, "Pixels are drawn on the displayed shadow screen");
+        assert_eq!(snapshot.peek(0x4000), 0x00);
+
+        assert!(matches!(snapshot.get_pixel(0, 192), Err(SnaError::OffScreen { x: 0, y: 192 })));
+        assert!(matches!(snapshot.set_attr(32, 0, red_on_yellow), Err(SnaError::OffScreen { x: 32, y: 0 })));
+    }
+
+    #[test]
+    fn test_screen_guards() {
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.guard("screen", 0x4000..0x5B00, GuardAction::Block);
+        let attribute = Attribute { ink: 7, paper: 1, bright: false, flash: false };
+        assert!(matches!(snapshot.set_pixel(9, 65, true), Err(SnaError::Guarded { address: 0x4901, .. })));
+        assert!(matches!(snapshot.set_attr(31, 23, attribute), Err(SnaError::Guarded { address: 0x5AFF, .. })));
+        assert!(matches!(snapshot.print_at(0, 0, "HI", attribute), Err(SnaError::Guarded { .. })));
+        assert!(matches!(snapshot.import_scr(&[0xFF; SCR_LEN]), Err(SnaError::Guarded { address: 0x4000, .. })));
+        assert!(snapshot.export_scr().iter().all(|&byte| byte == 0), "Nothing is written to a blocked screen");
+        assert_eq!(snapshot.take_guard_hits().len(), 4);
+
+        snapshot.guard("shadow", 0xC000..0xD800, GuardAction::Block);
+        snapshot.import_shadow_scr(&[0; SCR_LEN]).unwrap();
+        snapshot.write_0x7ffd(7).unwrap();
+        assert!(snapshot.import_shadow_scr(&[0xFF; SCR_LEN]).is_err(), "Bank 7 is guarded once it is paged in at 0xC000");
+        snapshot.guard("shadow", 0xC000..0xD800, GuardAction::Report);
+        snapshot.import_shadow_scr(&[0xFF; SCR_LEN]).unwrap();
+        assert_eq!(snapshot.take_guard_hits().len(), 1 + 0x1800, "The write is reported at 0xC000");
+    }
+
+    #[test]
+    fn test_screen_addresses() {
+        assert_eq!(pixel_address(0, 0).unwrap(), 0x4000);
+        assert_eq!(pixel_address(9, 65).unwrap(), 0x4901);
+        assert_eq!(pixel_address(255, 191).unwrap(), 0x57FF);
+        assert_eq!(attr_address(0, 0).unwrap(), 0x5800);
+        assert_eq!(attr_address(31, 23).unwrap(), 0x5AFF);
+        assert!(matches!(pixel_address(256, 0), Err(SnaError::OffScreen { x: 256, y: 0 })));
+        assert!(attr_address(0, 24).is_err());
+
+        for y in 0..HEIGHT {
+            for x in (0..WIDTH).step_by(8) {
+                assert_eq!(pixel_coordinates(pixel_address(x, y).unwrap()), Some((x, y)), "Pixel ({}, {}) does not round trip", x, y);
+            }
+        }
+        assert_eq!(pixel_coordinates(0x4901), Some((8, 65)), "The leftmost pixel of the byte");
+        assert_eq!(attr_coordinates(0x5AFF), Some((31, 23)));
+        assert_eq!(pixel_coordinates(0x5800), None);
+        assert_eq!(attr_coordinates(0x57FF), None);
+        assert_eq!(attr_coordinates(0x5B00), None);
+    }
+
+    #[test]
+    fn test_flash_frames() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.set_attr(0, 0, Attribute { ink: 2, paper: 5, bright: false, flash: false }).unwrap();
+        snapshot.set_attr(1, 0, Attribute { ink: 2, paper: 5, bright: false, flash: true }).unwrap();
+        assert!(snapshot.uses_flash());
+
+        let [normal, inverted] = snapshot.render_flash_frames(RenderOptions::default());
+        let pixel = |image: &Image, x: usize| image.rgba[x * 4..][..4].to_vec();
+        assert_eq!(pixel(&normal, 0), PALETTE[5]);
+        assert_eq!(pixel(&inverted, 0), PALETTE[5], "Cells without FLASH do not change");
+        assert_eq!(pixel(&normal, 8), PALETTE[5]);
+        assert_eq!(pixel(&inverted, 8), PALETTE[2], "Flashing paper is drawn in the ink colour");
+        assert_eq!(inverted, snapshot.render(RenderOptions { flash: true, ..RenderOptions::default() }));
+
+        snapshot.set_attr(1, 0, Attribute::default()).unwrap();
+        assert!(!snapshot.uses_flash());
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.set_attr(0, 0, Attribute { ink: 2, paper: 1, bright: true, flash: false }).unwrap();
+        snapshot.set_pixel(0, 0, true).unwrap();
+        let ansi = snapshot.render_ansi();
+        assert_eq!(ansi.lines().count(), HEIGHT / 2);
+        let first = ansi.lines().next().unwrap();
+        assert!(first.starts_with("\x1b[91;104m\u{2580}\x1b[94;104m\u{2580}"), "Bright red over bright blue, then bright blue paper");
+        assert_eq!(first.matches('\u{2580}').count(), WIDTH);
+        assert!(first.contains("\x1b[30;40m") && first.ends_with("\u{2580}\x1b[0m"));
+        assert_eq!(first.matches("\x1b[").count(), 4, "Unchanged colours are not repeated");
+    }
+
+    #[test]
+    fn test_print_at() {
+        let mut snapshot = Snapshot::new_48k();
+        let attribute = Attribute { ink: 7, paper: 1, bright: true, flash: false };
+        let glyph = |snapshot: &Snapshot, col: usize, row: usize| -> Vec<u8> {
+            (0..8).map(|line| snapshot.peek(pixel_address(col * 8, row * 8 + line).unwrap())).collect()
+        };
+        snapshot.print_at(30, 0, "0A£", attribute).unwrap();
+        assert_eq!(glyph(&snapshot, 30, 0), [0x00, 0x3C, 0x46, 0x4A, 0x52, 0x62, 0x3C, 0x00]);
+        assert_eq!(glyph(&snapshot, 31, 0), ROM_FONT[0x21 * 8..0x22 * 8]);
+        assert_eq!(glyph(&snapshot, 0, 1), ROM_FONT[0x40 * 8..0x41 * 8], "Text continues on the next row");
+        assert_eq!(snapshot.get_attr(0, 1).unwrap(), attribute);
+        assert_eq!(snapshot.get_attr(1, 1).unwrap(), Attribute::default());
+
+        let mut sysvars = snapshot.sysvars();
+        sysvars.udg = 0xFF58;
+        snapshot.set_sysvars(&sysvars).unwrap();
+        snapshot.poke(0xFF58 + 8 + 3, 0x81).unwrap();
+        snapshot.print_at(0, 23, "▚Ⓑ", attribute).unwrap();
+        assert_eq!(glyph(&snapshot, 0, 23), [0xF0, 0xF0, 0xF0, 0xF0, 0x0F, 0x0F, 0x0F, 0x0F]);
+        assert_eq!(glyph(&snapshot, 1, 23), [0, 0, 0, 0x81, 0, 0, 0, 0], "UDG B is read from UDG");
+
+        let font = [0xAA; 768];
+        snapshot.print_at_with_font(5, 5, "x", attribute, &font).unwrap();
+        assert_eq!(glyph(&snapshot, 5, 5), [0xAA; 8]);
+
+        assert!(matches!(snapshot.print_at(31, 23, "AB", attribute), Err(SnaError::OffScreen { x: 0, y: 24 })));
+        assert_eq!(glyph(&snapshot, 31, 23), [0; 8], "Nothing is drawn unless it all fits");
+        assert!(matches!(snapshot.print_at(32, 0, "A", attribute), Err(SnaError::OffScreen { .. })));
+        assert!(matches!(snapshot.print_at(0, 0, "é", attribute), Err(SnaError::InvalidFormat(_))));
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+use crate::{charset, scan, BankAddr, BankId, SnaError, Snapshot};
+
+/// An error from parsing a search pattern.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PatternError {
+    pub message: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// A byte sequence to search for in which some bits of some bytes do not matter.
+/// A byte of memory matches when it equals the pattern byte in every bit set in the mask.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<u8>,
+    mask: Vec<u8>,
+}
+
+impl Pattern {
+    /// new creates a pattern that matches the bytes exactly.
+    pub fn new(bytes: &[u8]) -> Pattern {
+        Pattern { bytes: bytes.to_vec(), mask: vec![0xFF; bytes.len()] }
+    }
+
+    /// with_mask creates a pattern that only compares the bits set in the mask, so a
+    /// mask byte of 0x00 matches anything. Returns an error if the lengths differ.
+    pub fn with_mask(bytes: &[u8], mask: &[u8]) -> Result<Pattern, PatternError> {
+        if bytes.len() != mask.len() {
+            return Err(PatternError { message: format!("{} bytes but {} mask bytes", bytes.len(), mask.len()) });
+        }
+        let bytes = bytes.iter().zip(mask).map(|(byte, mask)| byte & mask).collect();
+        Ok(Pattern { bytes, mask: mask.to_vec() })
+    }
+
+    /// parse reads a pattern written as hex bytes separated by spaces, with `??` for a
+    /// byte that can be anything, such as `3E ?? 32 ?? ??` for `LD A,n : LD (nn),A`.
+    /// A single `?` digit, as in `C?`, leaves just that nibble unchecked.
+    pub fn parse(text: &str) -> Result<Pattern, PatternError> {
+        let mut bytes = Vec::new();
+        let mut mask = Vec::new();
+        for token in text.split_whitespace() {
+            let digits: Vec<char> = token.chars().collect();
+            if digits.len() != 2 {
+                return Err(PatternError { message: format!("'{}' is not a hex byte or ??", token) });
+            }
+            let (mut byte, mut bits) = (0u8, 0u8);
+            for digit in digits {
+                let (value, nibble_mask) = match digit {
+                    '?' => (0, 0x0),
+                    _ => match digit.to_digit(16) {
+                        Some(value) => (value as u8, 0xF),
+                        None => return Err(PatternError { message: format!("'{}' is not a hex byte or ??", token) }),
+                    },
+                };
+                byte = byte << 4 | value;
+                bits = bits << 4 | nibble_mask;
+            }
+            bytes.push(byte);
+            mask.push(bits);
+        }
+        Ok(Pattern { bytes, mask })
+    }
+
+    /// len returns the number of bytes the pattern spans, wildcards included.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// is_empty returns whether the pattern has no bytes. An empty pattern matches nothing.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// matches returns whether the pattern matches the start of the data.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.len() && self.bytes.iter().zip(&self.mask).zip(data).all(|((byte, mask), data)| data & mask == *byte)
+    }
+
+    // patterns without wildcards take the fast path of scanning for their first byte
+    fn positions(&self, data: &[u8]) -> Vec<usize> {
+        if self.mask.iter().all(|&mask| mask == 0xFF) {
+            return scan::find_all(data, &self.bytes);
+        }
+        data.windows(self.len().max(1))
+            .enumerate()
+            .filter(|(_, window)| !self.is_empty() && self.matches(window))
+            .map(|(offset, _)| offset)
+            .collect()
+    }
+}
+
+impl From<&[u8]> for Pattern {
+    fn from(bytes: &[u8]) -> Self {
+        Pattern::new(bytes)
+    }
+}
+
+impl Snapshot {
+    /// find returns the address of every occurrence of a byte sequence in the mapped RAM
+    /// from 0x4000 to 0xFFFF, such as the `DEC A : LD (nn),A` of a lives counter.
+    /// Matches may overlap and may span the boundary between two banks. An empty
+    /// pattern matches nothing.
+    pub fn find(&self, pattern: &[u8]) -> Vec<u16> {
+        self.find_pattern(&Pattern::new(pattern), ..)
+    }
+
+    /// find_in_bank returns the offset of every occurrence of a byte sequence within a
+    /// bank, whether or not it is paged in. Returns SnaError::BankOutOfRange if the
+    /// snapshot has no such bank.
+    pub fn find_in_bank(&self, bank: usize, pattern: &[u8]) -> Result<Vec<u16>, SnaError> {
+        let found = self.find_pattern_in_banks(&Pattern::new(pattern), &[BankId(bank)])?;
+        Ok(found.into_iter().map(|location| location.offset).collect())
+    }
+
+    /// find_pattern is find for a masked pattern, restricted to a range of mapped
+    /// addresses. Only matches that lie wholly within the range are returned, and
+    /// addresses below 0x4000 are never searched as the ROM is not part of a snapshot.
+    pub fn find_pattern<R: RangeBounds<u16>>(&self, pattern: &Pattern, range: R) -> Vec<u16> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start as usize,
+            Bound::Excluded(&start) => start as usize + 1,
+            Bound::Unbounded => 0,
+        }.max(0x4000);
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end as usize + 1,
+            Bound::Excluded(&end) => end as usize,
+            Bound::Unbounded => 0x10000,
+        };
+        if start >= end {
+            return Vec::new();
+        }
+        let memory: Vec<u8> = (start..end).map(|address| self.peek(address as u16)).collect();
+        pattern.positions(&memory).into_iter().map(|offset| (start + offset) as u16).collect()
+    }
+
+    /// find_strings returns the address and text of every run of at least min_len
+    /// printable characters, 0x20 to 0x7F, in the mapped RAM from 0x4000 to 0xFFFF, such
+    /// as menus, messages and copy-protection prompts. A character with bit 7 set ends
+    /// a run and is included without it, as the ROM and many games mark the last
+    /// character of a message that way. A min_len of zero is taken as one.
+    pub fn find_strings(&self, min_len: usize) -> Vec<(u16, String)> {
+        let printable = |byte: u8| (0x20..=0x7F).contains(&byte);
+        let mut found = Vec::new();
+        let mut text = String::new();
+        let mut start = 0x4000;
+        for address in 0x4000..=0x10000u32 {
+            let byte = if address <= 0xFFFF { Some(self.peek(address as u16)) } else { None };
+            let (ends, code) = match byte {
+                Some(byte) if printable(byte) => (false, Some(byte)),
+                Some(byte) if !text.is_empty() && printable(byte & 0x7F) => (true, Some(byte & 0x7F)),
+                _ => (true, None),
+            };
+            if let Some(code) = code {
+                if text.is_empty() {
+                    start = address as u16;
+                }
+                text.extend(charset::to_char(code));
+            }
+            if ends {
+                if text.chars().count() >= min_len.max(1) {
+                    found.push((start, std::mem::take(&mut text)));
+                } else {
+                    text.clear();
+                }
+            }
+        }
+        found
+    }
+
+    /// find_pattern_in_banks searches each of the given banks in turn, whether or not
+    /// they are paged in, returning matches in bank order. Matches do not span banks.
+    /// Returns SnaError::BankOutOfRange if the snapshot lacks any of the banks.
+    pub fn find_pattern_in_banks(&self, pattern: &Pattern, banks: &[BankId]) -> Result<Vec<BankAddr>, SnaError> {
+        for bank in banks {
+            self.check_bank(bank.0)?;
+        }
+        Ok(banks.iter()
+            .flat_map(|&bank| pattern.positions(&self.banks[bank.0]).into_iter().map(move |offset| BankAddr { bank, offset: offset as u16 }))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let mut snapshot = Snapshot::new_128k();
+        // DEC A : LD ($8000),A across the boundary between banks 2 and 0
+        for (offset, byte) in [0x3D, 0x32, 0x00, 0x80].into_iter().enumerate() {
+            snapshot.poke(0xBFFE + offset as u16, byte).unwrap();
+        }
+        snapshot.poke(0x6000, 0x3D).unwrap();
+        assert_eq!(snapshot.find(&[0x3D, 0x32, 0x00, 0x80]), [0xBFFE]);
+        assert_eq!(snapshot.find(&[0x3D]), [0x6000, 0xBFFE]);
+        assert!(snapshot.find(&[]).is_empty());
+
+        snapshot.bank_poke(7, 0x3FFE, 0xAA).unwrap();
+        snapshot.bank_poke(7, 0x3FFF, 0xAA).unwrap();
+        assert!(snapshot.find(&[0xAA]).is_empty(), "Bank 7 is not paged in");
+        assert_eq!(snapshot.find_in_bank(7, &[0xAA, 0xAA]).unwrap(), [0x3FFE]);
+        assert_eq!(snapshot.find_in_bank(0, &[0x00, 0x80]).unwrap(), [0]);
+        assert!(matches!(snapshot.find_in_bank(8, &[0]), Err(SnaError::BankOutOfRange { bank: 8, banks: 8 })));
+    }
+
+    #[test]
+    fn test_find_pattern() {
+        let pattern = Pattern::parse("3e ?? 32 ?? ??").unwrap();
+        assert_eq!(pattern, Pattern::with_mask(&[0x3E, 0x00, 0x32, 0x00, 0x00], &[0xFF, 0x00, 0xFF, 0x00, 0x00]).unwrap());
+        assert_eq!(Pattern::parse("C? 1?").unwrap(), Pattern::with_mask(&[0xC0, 0x10], &[0xF0, 0xF0]).unwrap());
+        assert!(Pattern::parse("3E 1").is_err());
+        assert!(Pattern::parse("3E GG").is_err());
+        assert!(Pattern::with_mask(&[0x3E], &[]).is_err());
+
+        let mut snapshot = Snapshot::new_128k();
+        // LD A,3 : LD ($9000),A in two versions of a game
+        for (address, bytes) in [(0x8000, [0x3E, 0x03, 0x32, 0x00, 0x90]), (0xFFFB, [0x3E, 0x05, 0x32, 0x10, 0x90])] {
+            for (offset, byte) in bytes.into_iter().enumerate() {
+                snapshot.poke(address + offset as u16, byte).unwrap();
+            }
+        }
+        assert_eq!(snapshot.find_pattern(&pattern, ..), [0x8000, 0xFFFB]);
+        assert_eq!(snapshot.find_pattern(&pattern, 0x8001..), [0xFFFB]);
+        assert_eq!(snapshot.find_pattern(&pattern, ..0xFFFF), [0x8000], "The second match runs past the range");
+        assert_eq!(snapshot.find_pattern(&pattern, 0xFFFB..=0xFFFF), [0xFFFB]);
+
+        snapshot.bank_poke(3, 0x100, 0x3E).unwrap();
+        snapshot.bank_poke(3, 0x102, 0x32).unwrap();
+        let found = snapshot.find_pattern_in_banks(&pattern, &[BankId(3), BankId(2)]).unwrap();
+        assert_eq!(found, [BankAddr::new(BankId(3), 0x100).unwrap(), BankAddr::new(BankId(2), 0).unwrap()]);
+        assert!(snapshot.find_pattern_in_banks(&pattern, &[BankId(9)]).is_err());
+    }
+
+    #[test]
+    fn test_find_strings() {
+        let mut snapshot = Snapshot::new_48k();
+        let mut store = |address: u16, bytes: &[u8]| {
+            for (offset, &byte) in bytes.iter().enumerate() {
+                snapshot.poke(address + offset as u16, byte).unwrap();
+            }
+        };
+        store(0x8000, b"PRESS ANY KEY");
+        store(0x9000, &[b'G', b'A', b'M', b'E', b' ', b'O', b'V', b'E', b'R' | 0x80, b'H', b'I', b'`', b'5', b'0']);
+        store(0xA000, b"OK");
+        store(0xFFFD, b"END");
+        let found = snapshot.find_strings(4);
+        assert_eq!(found, [
+            (0x8000, "PRESS ANY KEY".to_string()),
+            (0x9000, "GAME OVER".to_string()),
+            (0x9009, "HI£50".to_string()),
+        ]);
+        assert!(snapshot.find_strings(3).contains(&(0xFFFD, "END".to_string())), "A run may end at 0xFFFF");
+        assert!(snapshot.find_strings(0).contains(&(0xA000, "OK".to_string())));
+    }
+}
@@ -0,0 +1,153 @@
+// Serialize and Deserialize for banks and snapshots, behind the `serde` feature.
+// Banks are written as byte arrays, which compact formats such as CBOR store as
+// raw bytes. Named regions and attachments are tool state rather than machine
+// state, so they are not serialized.
+
+use std::fmt;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::{AyState, Bank, InputState, Machine, Metadata, Snapshot, SnapshotExtension, SnapshotHeader, SnapshotType, MEM_16K};
+
+impl Serialize for Bank {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self)
+    }
+}
+
+struct BankVisitor;
+
+impl<'de> Visitor<'de> for BankVisitor {
+    type Value = Bank;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes of bank memory", MEM_16K)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Bank, E> {
+        Bank::from_slice(bytes).map_err(|_| E::invalid_length(bytes.len(), &self))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bank, A::Error> {
+        let mut bytes = Vec::with_capacity(MEM_16K);
+        while let Some(byte) = seq.next_element::<u8>()? {
+            if bytes.len() == MEM_16K {
+                return Err(de::Error::invalid_length(MEM_16K + 1, &self));
+            }
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bank {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Bank, D::Error> {
+        deserializer.deserialize_bytes(BankVisitor)
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    snapshot_type: SnapshotType,
+    header: SnapshotHeader,
+    extension: Option<SnapshotExtension>,
+    mapping: [u8; 3],
+    x1ffd: Option<u8>,
+    model: Option<Machine>,
+    banks: &'a [Bank],
+    ay: Option<AyState>,
+    input: Option<InputState>,
+    metadata: &'a Option<Metadata>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotData {
+    snapshot_type: SnapshotType,
+    header: SnapshotHeader,
+    extension: Option<SnapshotExtension>,
+    mapping: [u8; 3],
+    #[serde(default)]
+    x1ffd: Option<u8>,
+    #[serde(default)]
+    model: Option<Machine>,
+    banks: Vec<Bank>,
+    ay: Option<AyState>,
+    input: Option<InputState>,
+    metadata: Option<Metadata>,
+}
+
+impl Serialize for Snapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SnapshotRef {
+            snapshot_type: self.snapshot_type,
+            header: self.header,
+            extension: self.extension,
+            mapping: self.mapping,
+            x1ffd: self.x1ffd,
+            model: self.model,
+            banks: &self.banks,
+            ay: self.ay,
+            input: self.input,
+            metadata: &self.metadata,
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Snapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Snapshot, D::Error> {
+        let data = SnapshotData::deserialize(deserializer)?;
+        if data.model.is_some_and(|machine| machine.snapshot_type() != data.snapshot_type) {
+            return Err(de::Error::custom("the machine does not use the snapshot type"));
+        }
+        let bank_count = match (data.snapshot_type, data.model) {
+            (_, Some(machine)) => machine.bank_count(),
+            (SnapshotType::Snapshot48, None) => Machine::Spectrum48.bank_count(),
+            (SnapshotType::Snapshot128, None) if data.banks.len() == Machine::Scorpion256.bank_count() => Machine::Scorpion256.bank_count(),
+            (SnapshotType::Snapshot128, None) => Machine::Spectrum128.bank_count(),
+        };
+        if data.banks.len() != bank_count {
+            return Err(de::Error::custom(format!("a {} snapshot has {} banks, not {}", data.snapshot_type, bank_count, data.banks.len())));
+        }
+        if data.mapping.iter().any(|&bank| bank as usize >= bank_count) {
+            return Err(de::Error::custom("mapping refers to a bank the snapshot does not have"));
+        }
+        if (data.snapshot_type == SnapshotType::Snapshot128) != data.extension.is_some() {
+            return Err(de::Error::custom("only 128K snapshots have an extension"));
+        }
+
+        let mut snapshot = Snapshot {
+            snapshot_type: data.snapshot_type,
+            header: data.header,
+            extension: data.extension,
+            banks: data.banks,
+            ay: data.ay,
+            input: data.input,
+            metadata: data.metadata,
+            mapping: data.mapping,
+            x1ffd: data.x1ffd,
+            model: data.model,
+            ..Snapshot::default()
+        };
+        snapshot.remap();
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::value::{BytesDeserializer, Error};
+
+    fn assert_serde<T: Serialize + for<'de> Deserialize<'de>>() {}
+
+    #[test]
+    fn test_bank_bytes() {
+        assert_serde::<Snapshot>();
+        let bytes = vec![0x5A; MEM_16K];
+        let bank = Bank::deserialize(BytesDeserializer::<Error>::new(&bytes)).expect("Failed to deserialize bank");
+        assert_eq!(&bank[..], &bytes[..]);
+        assert!(Bank::deserialize(BytesDeserializer::<Error>::new(&bytes[1..])).is_err(), "A bank must be 16K");
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::Snapshot;
+
+/// A snapshot shared between threads, e.g. a GUI thread rendering the screen
+/// while a worker searches memory. Any number of readers can hold the snapshot
+/// at once; writers take it exclusively. Cloning a SharedSnapshot shares the
+/// same snapshot rather than copying it.
+#[derive(Clone, Default)]
+pub struct SharedSnapshot {
+    inner: Arc<RwLock<Snapshot>>,
+}
+
+impl SharedSnapshot {
+    /// new wraps a snapshot for sharing.
+    pub fn new(snapshot: Snapshot) -> SharedSnapshot {
+        SharedSnapshot { inner: Arc::new(RwLock::new(snapshot)) }
+    }
+
+    /// read locks the snapshot for reading, blocking while a writer holds it.
+    /// A poisoned lock is recovered, since a snapshot cannot be left half-updated
+    /// by a panicking reader.
+    pub fn read(&self) -> RwLockReadGuard<'_, Snapshot> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// write locks the snapshot for writing, blocking while any reader or writer holds it.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Snapshot> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// with_read runs a closure with the snapshot locked for reading.
+    pub fn with_read<R>(&self, f: impl FnOnce(&Snapshot) -> R) -> R {
+        f(&self.read())
+    }
+
+    /// with_write runs a closure with the snapshot locked for writing.
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut Snapshot) -> R) -> R {
+        f(&mut self.write())
+    }
+
+    /// snapshot returns a copy of the current snapshot. Banks are shared copy-on-write,
+    /// so this is cheap and lets long-running work proceed without holding the lock.
+    pub fn snapshot(&self) -> Snapshot {
+        self.read().clone()
+    }
+
+    /// into_inner returns the snapshot if this is the last handle to it, or the handle back otherwise.
+    pub fn into_inner(self) -> Result<Snapshot, SharedSnapshot> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(lock) => Ok(lock.into_inner().unwrap_or_else(|e| e.into_inner())),
+            Err(inner) => Err(SharedSnapshot { inner }),
+        }
+    }
+}
+
+impl From<Snapshot> for SharedSnapshot {
+    fn from(snapshot: Snapshot) -> Self {
+        SharedSnapshot::new(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_across_threads() {
+        let file = std::fs::File::open("48k.sna").expect("Failed to open snapshot file");
+        let shared = SharedSnapshot::new(Snapshot::try_from(file).expect("Failed to parse snapshot"));
+
+        let readers: Vec<_> = (0..4).map(|_| {
+            let shared = shared.clone();
+            std::thread::spawn(move || shared.with_read(|snapshot| snapshot.screen_hash()))
+        }).collect();
+        shared.with_write(|snapshot| snapshot.poke(0x8000, 0x42).unwrap());
+        for reader in readers {
+            reader.join().expect("Reader thread panicked");
+        }
+
+        let copy = shared.snapshot();
+        shared.write().poke(0x8000, 0x43).unwrap();
+        assert_eq!(copy.peek(0x8000), 0x42, "Copy should not see later writes");
+        assert_eq!(shared.read().peek(0x8000), 0x43);
+
+        let other = shared.clone();
+        let Err(shared) = shared.into_inner() else { panic!("Another handle still exists") };
+        drop(other);
+        assert_eq!(shared.into_inner().ok().map(|s| s.peek(0x8000)), Some(0x43));
+    }
+}
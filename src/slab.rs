@@ -0,0 +1,225 @@
+// Reference counted bank memory, allocated either on its own or as a slot in a slab,
+// one allocation holding many banks. SnapshotLoader::with_capacity loads snapshots
+// into slab slots so that long-running services reuse one block of memory instead of
+// allocating and freeing 16K at a time and fragmenting the heap.
+//
+// Memory behaves like an Arc<[u8; MEM_16K]>: clones share the bytes, and make_mut
+// copies them first if they are shared. When the last reference to a slot goes, the
+// slot returns to its slab's free list, and the slab itself is freed once neither a
+// Slab handle nor any slot in use is left.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::MEM_16K;
+
+struct Slot {
+    refs: AtomicUsize,
+    slab: *const SlabInner,     // the slab holding the slot, or null if allocated on its own
+    data: UnsafeCell<[u8; MEM_16K]>,
+}
+
+struct SlabInner {
+    slots: Box<[Slot]>,
+    free: Mutex<Vec<usize>>,    // indexes of the slots not in use
+    users: AtomicUsize,         // Slab handles and slots in use, the slab is freed at 0
+}
+
+/// The memory of one bank, shared between clones until it is written.
+pub(crate) struct Memory {
+    slot: NonNull<Slot>,
+}
+
+// SAFETY: the bytes are only written through make_mut, which needs the only reference,
+// and the counts are atomic, as in Arc
+unsafe impl Send for Memory {}
+unsafe impl Sync for Memory {}
+
+impl Memory {
+    /// new allocates memory on its own holding the bytes.
+    pub(crate) fn new(data: [u8; MEM_16K]) -> Memory {
+        let slot = Box::new(Slot { refs: AtomicUsize::new(1), slab: ptr::null(), data: UnsafeCell::new(data) });
+        Memory { slot: NonNull::from(Box::leak(slot)) }
+    }
+
+    fn slot(&self) -> &Slot {
+        // SAFETY: the slot stays allocated while this reference to it exists
+        unsafe { self.slot.as_ref() }
+    }
+
+    /// is_shared returns whether another clone refers to the same bytes.
+    pub(crate) fn is_shared(&self) -> bool {
+        self.slot().refs.load(Ordering::Acquire) > 1
+    }
+
+    /// in_slab returns whether the memory is a slot of a slab.
+    #[cfg(test)]
+    pub(crate) fn in_slab(&self) -> bool {
+        !self.slot().slab.is_null()
+    }
+
+    /// make_mut returns the bytes for writing, first copying them to memory of their
+    /// own if they are shared, taken from the same slab when it has a free slot.
+    pub(crate) fn make_mut(&mut self) -> &mut [u8; MEM_16K] {
+        if self.is_shared() {
+            let slab = self.slot().slab;
+            // SAFETY: the slab is kept alive by this slot being in use
+            let copy = (!slab.is_null()).then(|| unsafe { take(slab) }).flatten();
+            let copy = copy.unwrap_or_else(|| Memory::new([0; MEM_16K]));
+            // SAFETY: the copy is not shared with anything yet
+            unsafe { *copy.slot().data.get() = **self };
+            *self = copy;
+        }
+        // SAFETY: this is the only reference to the slot and it is borrowed mutably
+        unsafe { &mut *self.slot().data.get() }
+    }
+}
+
+impl Clone for Memory {
+    fn clone(&self) -> Memory {
+        self.slot().refs.fetch_add(1, Ordering::Relaxed);
+        Memory { slot: self.slot }
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        if self.slot().refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        let slab = self.slot().slab;
+        if slab.is_null() {
+            // SAFETY: the slot was allocated on its own by Memory::new and this was the
+            // last reference to it
+            drop(unsafe { Box::from_raw(self.slot.as_ptr()) });
+            return;
+        }
+        // SAFETY: the slab is kept alive by this slot until it is released here
+        unsafe {
+            let index = self.slot.as_ptr().offset_from((*slab).slots.as_ptr()) as usize;
+            (*slab).free.lock().unwrap_or_else(|e| e.into_inner()).push(index);
+            release(slab);
+        }
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u8; MEM_16K];
+
+    fn deref(&self) -> &[u8; MEM_16K] {
+        // SAFETY: the bytes are only written through make_mut, which needs &mut self
+        unsafe { &*self.slot().data.get() }
+    }
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A slab of bank slots allocated together. Slots taken from it are returned when
+/// their memory is dropped, and the allocation lasts until the handle and every slot
+/// are gone.
+pub(crate) struct Slab {
+    inner: NonNull<SlabInner>,
+}
+
+// SAFETY: the free list is behind a mutex and the count is atomic
+unsafe impl Send for Slab {}
+unsafe impl Sync for Slab {}
+
+impl Slab {
+    /// new allocates a slab of zeroed slots for the given number of banks.
+    pub(crate) fn new(banks: usize) -> Slab {
+        let slots = (0..banks)
+            .map(|_| Slot { refs: AtomicUsize::new(0), slab: ptr::null(), data: UnsafeCell::new([0; MEM_16K]) })
+            .collect();
+        let inner = Box::into_raw(Box::new(SlabInner {
+            slots,
+            free: Mutex::new((0..banks).rev().collect()),
+            users: AtomicUsize::new(1),
+        }));
+        // SAFETY: the slab was just allocated and nothing else refers to it yet
+        unsafe {
+            for slot in (*inner).slots.iter_mut() {
+                slot.slab = inner;
+            }
+            Slab { inner: NonNull::new_unchecked(inner) }
+        }
+    }
+
+    /// take returns memory from a free slot of the slab, holding whatever its last user
+    /// left there, or None if every slot is in use.
+    pub(crate) fn take(&self) -> Option<Memory> {
+        // SAFETY: the slab is kept alive by this handle
+        unsafe { take(self.inner.as_ptr()) }
+    }
+
+    /// free_slots returns the number of slots not in use.
+    pub(crate) fn free_slots(&self) -> usize {
+        // SAFETY: the slab is kept alive by this handle
+        unsafe { self.inner.as_ref() }.free.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+impl Drop for Slab {
+    fn drop(&mut self) {
+        // SAFETY: the handle is one of the slab's users
+        unsafe { release(self.inner.as_ptr()) }
+    }
+}
+
+// takes a free slot from a slab that is kept alive by the caller
+unsafe fn take(slab: *const SlabInner) -> Option<Memory> {
+    let index = (*slab).free.lock().unwrap_or_else(|e| e.into_inner()).pop()?;
+    (*slab).users.fetch_add(1, Ordering::Relaxed);
+    let slot = &(*slab).slots[index];
+    slot.refs.store(1, Ordering::Relaxed);
+    Some(Memory { slot: NonNull::from(slot) })
+}
+
+// gives up one user of a slab, freeing it if that was the last
+unsafe fn release(slab: *const SlabInner) {
+    if (*slab).users.fetch_sub(1, Ordering::Release) == 1 {
+        fence(Ordering::Acquire);
+        drop(Box::from_raw(slab as *mut SlabInner));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slab() {
+        let slab = Slab::new(3);
+        let mut first = slab.take().expect("The slab has free slots");
+        first.make_mut()[0] = 0x12;
+        let second = first.clone();
+        assert!(first.is_shared() && first.in_slab());
+        assert_eq!(slab.free_slots(), 2);
+
+        first.make_mut()[0] = 0x34;
+        assert_eq!((first[0], second[0]), (0x34, 0x12), "A write to shared memory should copy it");
+        assert!(first.in_slab() && !first.is_shared(), "The copy should come from the slab");
+        assert_eq!(slab.free_slots(), 1);
+
+        let third = slab.take().unwrap();
+        assert!(slab.take().is_none(), "Every slot is in use");
+        let mut fourth = third.clone();
+        fourth.make_mut()[0] = 0x56;
+        assert!(!fourth.in_slab(), "A full slab should copy to memory of its own");
+
+        drop(first);
+        drop(second);
+        assert_eq!(slab.free_slots(), 2, "Dropped memory should return to the slab");
+        drop(slab);
+        assert_eq!(third[0], 0, "Slots in use should outlive the slab handle");
+    }
+}
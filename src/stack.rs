@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::layout::{ATTRIBUTES, SCREEN};
+use crate::{SnaError, Snapshot, SnapshotType};
+
+const RAMTOP: u16 = 0x5CB2;
+
+/// Options controlling how a snapshot is saved.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SaveOptions {
+    /// Relocate a stack pointer that would crash the machine on load instead of failing.
+    /// Only possible when the PC is not stored on the stack, i.e. for 128K snapshots.
+    pub fix_stack: bool,
+}
+
+fn bad_stack(sp: u16, reason: String) -> Result<(), SnaError> {
+    Err(SnaError::BadStack { sp, reason })
+}
+
+impl Snapshot {
+    /// check_stack verifies that the snapshot will not crash as soon as it is loaded:
+    /// SP must point into writable RAM and, for 48K snapshots where the PC is popped
+    /// from the stack, the stacked return address must not point into the screen.
+    /// Returns SnaError::BadStack saying why if it would crash.
+    pub fn check_stack(&self) -> Result<(), SnaError> {
+        let sp = { self.header.sp };
+        match self.snapshot_type {
+            SnapshotType::Snapshot48 => {
+                if sp == 0xFFFF {
+                    return bad_stack(sp, "the stacked PC is split across the top of memory".to_string());
+                }
+                if self.is_rom(sp) || self.is_rom(sp + 1) {
+                    return bad_stack(sp, "the PC cannot be stored on the stack in ROM".to_string());
+                }
+                let pc = self.peek_word(sp)?;
+                if (SCREEN.start..ATTRIBUTES.end).contains(&pc) {
+                    return bad_stack(sp, format!("the stacked return address 0x{:04X} points into screen memory", pc));
+                }
+            }
+            SnapshotType::Snapshot128 => {
+                // a push writes the two bytes below SP, wrapping round to the top of memory
+                if self.is_rom(sp.wrapping_sub(1)) || self.is_rom(sp.wrapping_sub(2)) {
+                    return bad_stack(sp, "the first push would write into ROM".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// fix_stack moves an SP that points into ROM to just below RAMTOP, or the top of
+    /// memory if RAMTOP is not plausible. Returns whether SP was changed. 48K snapshots
+    /// are never changed, since their PC is only stored on the stack.
+    pub fn fix_stack(&mut self) -> Result<bool, SnaError> {
+        if self.snapshot_type == SnapshotType::Snapshot48 || self.check_stack().is_ok() {
+            return Ok(false);
+        }
+        let ramtop = self.peek_word(RAMTOP)?;
+        self.header.sp = if ramtop > ATTRIBUTES.end { ramtop } else { 0x0000 };
+        Ok(true)
+    }
+
+    /// save_with_options checks the stack and writes the snapshot in .sna format to the
+    /// file at the given path, returning SnaError::BadStack rather than writing a
+    /// snapshot that would crash on load.
+    pub fn save_with_options<P: AsRef<Path>>(&self, path: P, options: SaveOptions) -> Result<(), SnaError> {
+        if options.fix_stack && self.check_stack().is_err() {
+            let mut fixed = self.clone();
+            fixed.fix_stack()?;
+            fixed.check_stack()?;
+            return Ok(fixed.write_to(BufWriter::new(File::create(path)?))?);
+        }
+        self.check_stack()?;
+        Ok(self.write_to(BufWriter::new(File::create(path)?))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn test_check_stack() {
+        for case in fixtures::pathological() {
+            let snapshot = Snapshot::try_from(case.bin).expect("Failed to parse snapshot");
+            assert_eq!(snapshot.check_stack().is_err(), case.name == "sp-at-ffff", "Unexpected stack check for {}", case.name);
+        }
+
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, Default::default());
+        let sp = { snapshot.header.sp };
+        snapshot.poke_word(sp, 0x4000).unwrap();
+        let error = snapshot.check_stack().expect_err("Return address in the screen should fail");
+        assert!(matches!(error, SnaError::BadStack { .. }) && error.to_string().contains("screen"), "Unexpected error: {}", error);
+        assert!(!snapshot.fix_stack().unwrap(), "A 48K stack cannot be fixed");
+
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        snapshot.header.sp = 0x1000;
+        assert!(snapshot.check_stack().is_err());
+        assert!(snapshot.fix_stack().unwrap());
+        assert!(snapshot.check_stack().is_ok(), "Fixed stack should pass the check");
+
+        snapshot.header.sp = 0x4001;
+        assert!(matches!(snapshot.check_stack(), Err(SnaError::BadStack { sp: 0x4001, .. })), "The first push writes its high byte to 0x3FFF");
+        snapshot.header.sp = 0x4002;
+        assert!(snapshot.check_stack().is_ok());
+
+        let mut snapshot = Snapshot::new_plus3();
+        snapshot.header.sp = 0x1000;
+        assert!(snapshot.check_stack().is_err());
+        snapshot.write_0x1ffd(0x01).unwrap();
+        assert!(snapshot.check_stack().is_ok(), "Special paging maps RAM at 0x0000");
+    }
+}
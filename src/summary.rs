@@ -0,0 +1,143 @@
+use std::fmt;
+
+use crate::{scan, RegisterPair, Snapshot, SnapshotType};
+
+const COLOUR_NAMES: [&str; 8] = ["black", "blue", "red", "magenta", "green", "cyan", "yellow", "white"];
+
+impl fmt::Display for SnapshotType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotType::Snapshot48 => write!(f, "48K"),
+            SnapshotType::Snapshot128 => write!(f, "128K"),
+        }
+    }
+}
+
+impl Snapshot {
+    // the RAM banks mapped into the address space and where, e.g. "5/2/0 at 0x4000/0x8000/0xC000",
+    // which includes 0x0000 when +2A/+3 special paging maps RAM there
+    fn mapped_banks(&self) -> String {
+        let windows: Vec<(usize, usize)> = self.layout().windows.iter().enumerate()
+            .filter_map(|(window, bank)| bank.map(|bank| (window, bank.0)))
+            .collect();
+        let banks: Vec<String> = windows.iter().map(|(_, bank)| bank.to_string()).collect();
+        let addresses: Vec<String> = windows.iter().map(|(window, _)| format!("0x{:04X}", window * 0x4000)).collect();
+        format!("{} at {}", banks.join("/"), addresses.join("/"))
+    }
+
+    /// summary returns a one-paragraph description of the snapshot for logs and tool output.
+    /// It covers the machine type, PC and SP, interrupt mode, border colour, paging
+    /// and which banks contain any non-zero bytes.
+    /// For 48K snapshots the PC is read from the top of the stack, where the .sna format keeps it.
+    pub fn summary(&self) -> String {
+        let sp = self.header.sp;
+        let border = self.header.border_color;
+        let colour = COLOUR_NAMES.get(border as usize).copied().unwrap_or("invalid");
+
+        let pc = match &self.extension {
+            Some(extension) => format!("PC 0x{:04X}", { extension.pc }),
+            None if (0x4000..0xFFFF).contains(&sp) => format!("PC 0x{:04X} (on stack)", self.peek_word(sp).unwrap_or_default()),
+            None => "PC unknown (SP outside RAM)".to_string(),
+        };
+
+        let paging = match &self.extension {
+            Some(extension) => {
+                let x1ffd = self.x1ffd.map(|x1ffd| format!(", 0x1FFD=0x{:02X}", x1ffd)).unwrap_or_default();
+                format!("0x7FFD=0x{:02X}{}, banks {}", extension.x7ffd, x1ffd, self.mapped_banks())
+            }
+            None => "no paging".to_string(),
+        };
+
+        let non_empty: Vec<String> = self.banks.iter().enumerate()
+            .filter(|(_, bank)| bank.iter().any(|&byte| byte != 0))
+            .map(|(index, _)| index.to_string())
+            .collect();
+        let non_empty = if non_empty.is_empty() { "none".to_string() } else { non_empty.join(", ") };
+
+        format!(
+            "{} snapshot: {}, SP 0x{:04X}, interrupt mode {}, border {} ({}), {}, non-empty banks: {}.",
+            self.snapshot_type, pc, sp, self.header.int_mode, border, colour, paging, non_empty
+        )
+    }
+}
+
+/// A multi-line register dump with the machine type, paging and a checksum per bank,
+/// for CLI inspection and debugging output. The PC of a 48K snapshot is marked as
+/// read from the stack.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} snapshot", self.snapshot_type)?;
+        for row in RegisterPair::ALL.chunks(4) {
+            let cells: Vec<String> = row.iter().map(|&pair| format!("{:<3} {:04X}", pair.name(), self.get_pair(pair))).collect();
+            let line = cells.join("  ");
+            if row.contains(&RegisterPair::SP) {
+                match (self.pc(), &self.extension) {
+                    (Some(pc), Some(_)) => writeln!(f, "{}  PC  {:04X}", line, pc)?,
+                    (Some(pc), None) => writeln!(f, "{}  PC  {:04X} (on stack)", line, pc)?,
+                    (None, _) => writeln!(f, "{}  PC  ????", line)?,
+                }
+            } else {
+                writeln!(f, "{}", line)?;
+            }
+        }
+
+        let border = self.header.border_color;
+        let colour = COLOUR_NAMES.get(border as usize).copied().unwrap_or("invalid");
+        let iff2 = if self.header.interrupt & 0x04 != 0 { "on" } else { "off" };
+        writeln!(f, "I   {:02X}    R   {:02X}    IM {}  IFF2 {}  border {} ({})", self.header.i, self.header.r, self.header.int_mode, iff2, border, colour)?;
+
+        match &self.extension {
+            Some(extension) => {
+                let x1ffd = self.x1ffd.map(|x1ffd| format!(", 0x1FFD 0x{:02X}", x1ffd)).unwrap_or_default();
+                writeln!(f, "0x7FFD 0x{:02X}{}, banks {}", extension.x7ffd, x1ffd, self.mapped_banks())?
+            }
+            None => writeln!(f, "No paging")?,
+        }
+        let checksums: Vec<String> = self.banks.iter().enumerate().map(|(bank, data)| format!("{}:{:04X}", bank, scan::sum_bytes(data) as u16)).collect();
+        write!(f, "Checksums {}", checksums.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_summary() {
+        let file = File::open("128k.sna").expect("Failed to open snapshot file");
+        let snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        let summary = snapshot.summary();
+        assert!(summary.starts_with("128K snapshot: PC 0x"), "Unexpected summary: {}", summary);
+        assert!(summary.ends_with("non-empty banks: 0, 5, 7."), "Unexpected summary: {}", summary);
+
+        let file = File::open("48k.sna").expect("Failed to open snapshot file");
+        let snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        let summary = snapshot.summary();
+        assert!(summary.contains("(on stack)") && summary.contains("no paging"), "Unexpected summary: {}", summary);
+
+        let mut snapshot = Snapshot::new_plus3();
+        snapshot.write_0x1ffd(0x07).unwrap();
+        let summary = snapshot.summary();
+        assert!(summary.contains("0x7FFD=0x00, 0x1FFD=0x07, banks 4/7/6/3 at 0x0000/0x4000/0x8000/0xC000"), "Unexpected summary: {}", summary);
+    }
+
+    #[test]
+    fn test_display() {
+        let snapshot = crate::fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        let dump = snapshot.to_string();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 7, "Unexpected dump:\n{}", dump);
+        assert_eq!(lines[0], "128K snapshot");
+        assert!(lines[3].starts_with("IX  ") && lines[3].ends_with("PC  8000"), "Unexpected registers: {}", lines[3]);
+        assert_eq!(lines[5], "0x7FFD 0x10, banks 5/2/0 at 0x4000/0x8000/0xC000");
+        assert_eq!(lines[6].split(' ').count(), 9, "There should be a checksum per bank");
+
+        let snapshot = crate::fixtures::generate(SnapshotType::Snapshot48, Default::default());
+        assert!(snapshot.to_string().contains("PC  8000 (on stack)"));
+
+        let mut snapshot = Snapshot::new_plus3();
+        snapshot.write_0x1ffd(0x01).unwrap();
+        assert!(snapshot.to_string().contains("0x7FFD 0x00, 0x1FFD 0x01, banks 0/1/2/3 at 0x0000/0x4000/0x8000/0xC000"), "Unexpected dump:\n{}", snapshot);
+    }
+}
@@ -0,0 +1,281 @@
+use crate::layout::{SYSVARS, SYSVARS_START};
+use crate::{SnaError, Snapshot};
+
+/// Size of the system variables area of the 48K ROM, which the 128K ROMs share.
+pub const SYSVARS_LEN: usize = (SYSVARS.end - SYSVARS.start) as usize;
+
+/// The system variables at 0x5C00-0x5CB5, named as in the Spectrum manual.
+/// Addresses are the pointers into memory the ROM keeps, such as PROG for the start
+/// of the BASIC program, and line numbers are as BASIC uses them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SystemVariables {
+    pub kstate: [u8; 8],        // 0x5C00 keyboard state
+    pub last_k: u8,             // 0x5C08 the last key pressed
+    pub repdel: u8,             // 0x5C09 frames before a held key repeats
+    pub repper: u8,             // 0x5C0A frames between repeats
+    pub defadd: u16,            // 0x5C0B the arguments of a user defined function being evaluated
+    pub k_data: u8,             // 0x5C0D the second byte of colour controls typed at the keyboard
+    pub tvdata: u16,            // 0x5C0E colour, AT and TAB controls going to the screen
+    pub strms: [u8; 38],        // 0x5C10 the channel of each stream
+    pub chars: u16,             // 0x5C36 256 less than the address of the character set
+    pub rasp: u8,               // 0x5C38 length of the warning buzz
+    pub pip: u8,                // 0x5C39 length of the keyboard click
+    pub err_nr: u8,             // 0x5C3A one less than the report code
+    pub flags: u8,              // 0x5C3B BASIC flags
+    pub tv_flag: u8,            // 0x5C3C television flags
+    pub err_sp: u16,            // 0x5C3D the machine stack entry used as the error return
+    pub list_sp: u16,           // 0x5C3F the return address from an automatic listing
+    pub mode: u8,               // 0x5C41 the K, L, C, E or G cursor
+    pub newppc: u16,            // 0x5C42 the line to be jumped to
+    pub nsppc: u8,              // 0x5C44 the statement in that line to be jumped to
+    pub ppc: u16,               // 0x5C45 the line being executed
+    pub subppc: u8,             // 0x5C47 the statement in that line being executed
+    pub bordcr: u8,             // 0x5C48 border colour times 8, and the lower screen attributes
+    pub e_ppc: u16,             // 0x5C49 the line with the program cursor
+    pub vars: u16,              // 0x5C4B the start of the variables
+    pub dest: u16,              // 0x5C4D the variable being assigned to
+    pub chans: u16,             // 0x5C4F the start of the channel information
+    pub curchl: u16,            // 0x5C51 the current channel
+    pub prog: u16,              // 0x5C53 the start of the BASIC program
+    pub nxtlin: u16,            // 0x5C55 the next line of the program
+    pub datadd: u16,            // 0x5C57 the end of the last DATA item read
+    pub e_line: u16,            // 0x5C59 the start of the line being edited
+    pub k_cur: u16,             // 0x5C5B the cursor
+    pub ch_add: u16,            // 0x5C5D the next character to be interpreted
+    pub x_ptr: u16,             // 0x5C5F the character after a syntax error
+    pub worksp: u16,            // 0x5C61 the start of the workspace
+    pub stkbot: u16,            // 0x5C63 the bottom of the calculator stack
+    pub stkend: u16,            // 0x5C65 the start of spare memory
+    pub breg: u8,               // 0x5C67 the calculator's B register
+    pub mem: u16,               // 0x5C68 the calculator's memory area
+    pub flags2: u8,             // 0x5C6A more flags
+    pub df_sz: u8,              // 0x5C6B lines in the lower part of the screen
+    pub s_top: u16,             // 0x5C6C the top line of an automatic listing
+    pub oldppc: u16,            // 0x5C6E the line CONTINUE jumps to
+    pub osppc: u8,              // 0x5C70 the statement in that line CONTINUE jumps to
+    pub flagx: u8,              // 0x5C71 various flags
+    pub strlen: u16,            // 0x5C72 the length of the string being assigned
+    pub t_addr: u16,            // 0x5C74 the next item in the syntax table
+    pub seed: u16,              // 0x5C76 the seed for RND
+    pub frames: u32,            // 0x5C78 frames since the machine was switched on, 3 bytes
+    pub udg: u16,               // 0x5C7B the address of the first user defined graphic
+    pub coords_x: u8,           // 0x5C7D x of the last point plotted
+    pub coords_y: u8,           // 0x5C7E y of the last point plotted
+    pub p_posn: u8,             // 0x5C7F 33 less the printer column
+    pub pr_cc: u16,             // 0x5C80 the next position in the printer buffer
+    pub echo_e: u16,            // 0x5C82 33 less the column and 24 less the line of the end of the input buffer
+    pub df_cc: u16,             // 0x5C84 the print position in the display file
+    pub df_ccl: u16,            // 0x5C86 the print position in the lower screen
+    pub s_posn: u16,            // 0x5C88 33 less the column and 24 less the line of the print position
+    pub sposnl: u16,            // 0x5C8A s_posn for the lower screen
+    pub scr_ct: u8,             // 0x5C8C one more than the scrolls before "scroll?"
+    pub attr_p: u8,             // 0x5C8D permanent attributes
+    pub mask_p: u8,             // 0x5C8E permanent attributes that are transparent
+    pub attr_t: u8,             // 0x5C8F temporary attributes
+    pub mask_t: u8,             // 0x5C90 temporary attributes that are transparent
+    pub p_flag: u8,             // 0x5C91 more flags
+    pub membot: [u8; 30],       // 0x5C92 the calculator's memory area
+    pub nmiadd: u16,            // 0x5CB0 the NMI handler, unused by the ROM
+    pub ramtop: u16,            // 0x5CB2 the last byte of the BASIC system area
+    pub p_ramt: u16,            // 0x5CB4 the last byte of RAM
+}
+
+// reads the fields in order from the bytes of the area
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl Fields<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (field, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        field.try_into().expect("The split is N bytes")
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn word(&mut self) -> u16 {
+        u16::from_le_bytes(self.take())
+    }
+}
+
+impl SystemVariables {
+    /// from_bytes decodes the 182 bytes of the system variables area.
+    pub fn from_bytes(bytes: &[u8; SYSVARS_LEN]) -> SystemVariables {
+        let mut f = Fields { bytes };
+        SystemVariables {
+            kstate: f.take(),
+            last_k: f.byte(),
+            repdel: f.byte(),
+            repper: f.byte(),
+            defadd: f.word(),
+            k_data: f.byte(),
+            tvdata: f.word(),
+            strms: f.take(),
+            chars: f.word(),
+            rasp: f.byte(),
+            pip: f.byte(),
+            err_nr: f.byte(),
+            flags: f.byte(),
+            tv_flag: f.byte(),
+            err_sp: f.word(),
+            list_sp: f.word(),
+            mode: f.byte(),
+            newppc: f.word(),
+            nsppc: f.byte(),
+            ppc: f.word(),
+            subppc: f.byte(),
+            bordcr: f.byte(),
+            e_ppc: f.word(),
+            vars: f.word(),
+            dest: f.word(),
+            chans: f.word(),
+            curchl: f.word(),
+            prog: f.word(),
+            nxtlin: f.word(),
+            datadd: f.word(),
+            e_line: f.word(),
+            k_cur: f.word(),
+            ch_add: f.word(),
+            x_ptr: f.word(),
+            worksp: f.word(),
+            stkbot: f.word(),
+            stkend: f.word(),
+            breg: f.byte(),
+            mem: f.word(),
+            flags2: f.byte(),
+            df_sz: f.byte(),
+            s_top: f.word(),
+            oldppc: f.word(),
+            osppc: f.byte(),
+            flagx: f.byte(),
+            strlen: f.word(),
+            t_addr: f.word(),
+            seed: f.word(),
+            frames: u32::from_le_bytes([f.byte(), f.byte(), f.byte(), 0]),
+            udg: f.word(),
+            coords_x: f.byte(),
+            coords_y: f.byte(),
+            p_posn: f.byte(),
+            pr_cc: f.word(),
+            echo_e: f.word(),
+            df_cc: f.word(),
+            df_ccl: f.word(),
+            s_posn: f.word(),
+            sposnl: f.word(),
+            scr_ct: f.byte(),
+            attr_p: f.byte(),
+            mask_p: f.byte(),
+            attr_t: f.byte(),
+            mask_t: f.byte(),
+            p_flag: f.byte(),
+            membot: f.take(),
+            nmiadd: f.word(),
+            ramtop: f.word(),
+            p_ramt: f.word(),
+        }
+    }
+
+    /// to_bytes encodes the system variables as the bytes of the area.
+    /// Only the low 24 bits of FRAMES are stored.
+    pub fn to_bytes(&self) -> [u8; SYSVARS_LEN] {
+        let mut bytes = Vec::with_capacity(SYSVARS_LEN);
+        bytes.extend_from_slice(&self.kstate);
+        bytes.extend_from_slice(&[self.last_k, self.repdel, self.repper]);
+        bytes.extend_from_slice(&self.defadd.to_le_bytes());
+        bytes.push(self.k_data);
+        bytes.extend_from_slice(&self.tvdata.to_le_bytes());
+        bytes.extend_from_slice(&self.strms);
+        bytes.extend_from_slice(&self.chars.to_le_bytes());
+        bytes.extend_from_slice(&[self.rasp, self.pip, self.err_nr, self.flags, self.tv_flag]);
+        bytes.extend_from_slice(&self.err_sp.to_le_bytes());
+        bytes.extend_from_slice(&self.list_sp.to_le_bytes());
+        bytes.push(self.mode);
+        bytes.extend_from_slice(&self.newppc.to_le_bytes());
+        bytes.push(self.nsppc);
+        bytes.extend_from_slice(&self.ppc.to_le_bytes());
+        bytes.extend_from_slice(&[self.subppc, self.bordcr]);
+        for word in [self.e_ppc, self.vars, self.dest, self.chans, self.curchl, self.prog, self.nxtlin, self.datadd,
+                     self.e_line, self.k_cur, self.ch_add, self.x_ptr, self.worksp, self.stkbot, self.stkend] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.push(self.breg);
+        bytes.extend_from_slice(&self.mem.to_le_bytes());
+        bytes.extend_from_slice(&[self.flags2, self.df_sz]);
+        bytes.extend_from_slice(&self.s_top.to_le_bytes());
+        bytes.extend_from_slice(&self.oldppc.to_le_bytes());
+        bytes.extend_from_slice(&[self.osppc, self.flagx]);
+        bytes.extend_from_slice(&self.strlen.to_le_bytes());
+        bytes.extend_from_slice(&self.t_addr.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.frames.to_le_bytes()[..3]);
+        bytes.extend_from_slice(&self.udg.to_le_bytes());
+        bytes.extend_from_slice(&[self.coords_x, self.coords_y, self.p_posn]);
+        for word in [self.pr_cc, self.echo_e, self.df_cc, self.df_ccl, self.s_posn, self.sposnl] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[self.scr_ct, self.attr_p, self.mask_p, self.attr_t, self.mask_t, self.p_flag]);
+        bytes.extend_from_slice(&self.membot);
+        for word in [self.nmiadd, self.ramtop, self.p_ramt] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.try_into().expect("Every system variable is written")
+    }
+}
+
+impl Snapshot {
+    /// sysvars returns the system variables. They are only meaningful if the ROM has
+    /// initialised them, which is not the case for snapshots of machine code that has
+    /// taken over the whole machine.
+    pub fn sysvars(&self) -> SystemVariables {
+        let mut bytes = [0u8; SYSVARS_LEN];
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.peek(SYSVARS_START + offset as u16);
+        }
+        SystemVariables::from_bytes(&bytes)
+    }
+
+    /// set_sysvars writes the system variables back to memory. Returns
+    /// SnaError::Guarded, writing nothing, if a guard blocks any of them.
+    pub fn set_sysvars(&mut self, sysvars: &SystemVariables) -> Result<(), SnaError> {
+        self.poke_slice(SYSVARS_START, &sysvars.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_sysvars() {
+        let options = FixtureOptions { basic: true, ..FixtureOptions::default() };
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, options);
+        let mut sysvars = snapshot.sysvars();
+        assert_eq!(sysvars.prog, 0x5CCB);
+        assert_eq!(sysvars.vars, 0x5CCB + fixtures::FIXTURE_BASIC.len() as u16);
+        assert_eq!(sysvars.ramtop, 0xFF57);
+
+        let bytes: Vec<u8> = (0..SYSVARS_LEN).map(|offset| offset as u8).collect();
+        let decoded = SystemVariables::from_bytes(bytes.as_slice().try_into().unwrap());
+        assert_eq!(decoded.to_bytes().as_slice(), bytes.as_slice(), "Every byte should round trip");
+        assert_eq!(decoded.chars, 0x3736, "CHARS is at 0x5C36");
+        assert_eq!(decoded.frames, 0x7A7978, "FRAMES is three bytes at 0x5C78");
+        assert_eq!(decoded.p_ramt, 0xB5B4, "P_RAMT is the last system variable");
+
+        sysvars.frames = 0x123456;
+        sysvars.udg = 0xFF58;
+        snapshot.set_sysvars(&sysvars).unwrap();
+        assert_eq!(snapshot.peek_u32(0x5C78).unwrap() & 0xFFFFFF, 0x123456);
+        assert_eq!(snapshot.peek_word(0x5C7B).unwrap(), 0xFF58);
+        assert_eq!(snapshot.sysvars(), sysvars);
+
+        snapshot.guard("frames", 0x5C78..0x5C7B, crate::GuardAction::Block);
+        sysvars.udg = 0xFF00;
+        assert!(matches!(snapshot.set_sysvars(&sysvars), Err(SnaError::Guarded { address: 0x5C78, .. })));
+        assert_eq!(snapshot.sysvars().udg, 0xFF58, "Nothing is written when a guard blocks");
+    }
+}
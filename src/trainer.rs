@@ -0,0 +1,233 @@
+use std::fmt;
+
+use crate::json::Value;
+use crate::{SnaError, Snapshot, SnapshotType};
+
+/// A single poke belonging to a trainer.
+/// `bank` is the 128K bank that must be paged in at 0xC000 for addresses at or above
+/// 0xC000, or None if the poke applies to whatever memory is mapped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Poke {
+    pub bank: Option<u8>,
+    pub address: u16,
+    pub value: u8,
+    pub original: u8,   // value before the poke, or 0 if unknown
+}
+
+/// A named group of pokes, such as "Infinite lives".
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Trainer {
+    pub name: String,
+    pub pokes: Vec<Poke>,
+}
+
+/// An error found while parsing a poke script, with the 1-based line it occurred on.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Trainer {
+    /// parse_script compiles magazine-style poke text into a trainer.
+    /// Each line holds one or more `POKE address,value` statements separated by colons.
+    /// The address may be a range (`POKE 35899-35901,0`) to poke every address in it, and may be
+    /// prefixed with a bank (`POKE 3:49152,0`). Numbers are decimal, or hex with a `0x`, `$`
+    /// or `&` prefix. Blank lines and lines starting with `REM`, `#` or `;` are ignored.
+    pub fn parse_script(name: &str, text: &str) -> Result<Trainer, ParseError> {
+        let mut pokes = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ParseError { line: index + 1, message };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') || line.to_ascii_uppercase().starts_with("REM") {
+                continue;
+            }
+
+            let upper = line.to_ascii_uppercase();
+            if !upper.starts_with("POKE") {
+                return Err(error(format!("expected POKE, found '{}'", line)));
+            }
+            for statement in upper.split("POKE").skip(1) {
+                let statement = statement.trim().trim_end_matches(':').trim();
+                let (target, value) = statement.split_once(',')
+                    .ok_or_else(|| error(format!("expected 'address,value' in '{}'", statement)))?;
+                let value = parse_number(value.trim())
+                    .filter(|&value| value <= 0xFF)
+                    .ok_or_else(|| error(format!("invalid value '{}'", value.trim())))?;
+
+                let (bank, range) = match target.split_once(':') {
+                    Some((bank, range)) => {
+                        let bank = parse_number(bank.trim()).filter(|&bank| bank <= 7)
+                            .ok_or_else(|| error(format!("invalid bank '{}'", bank.trim())))?;
+                        (Some(bank as u8), range.trim())
+                    }
+                    None => (None, target.trim()),
+                };
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (start.trim(), end.trim()),
+                    None => (range, range),
+                };
+                let start = parse_number(start).filter(|&address| address <= 0xFFFF)
+                    .ok_or_else(|| error(format!("invalid address '{}'", start)))?;
+                let end = parse_number(end).filter(|&address| address <= 0xFFFF && address >= start)
+                    .ok_or_else(|| error(format!("invalid address range '{}'", range)))?;
+
+                for address in start..=end {
+                    pokes.push(Poke { bank, address: address as u16, value: value as u8, original: 0 });
+                }
+            }
+        }
+        Ok(Trainer { name: name.to_string(), pokes })
+    }
+}
+
+impl Trainer {
+    // the JSON object the sidecar records an applied trainer as
+    pub(crate) fn to_json(&self) -> Value {
+        let pokes = self.pokes.iter().map(|poke| {
+            let mut members = Vec::new();
+            if let Some(bank) = poke.bank {
+                members.push(("bank".to_string(), Value::Number(bank as f64)));
+            }
+            members.push(("address".to_string(), Value::Number(poke.address as f64)));
+            members.push(("value".to_string(), Value::Number(poke.value as f64)));
+            members.push(("original".to_string(), Value::Number(poke.original as f64)));
+            Value::Object(members)
+        }).collect();
+        Value::Object(vec![("name".to_string(), Value::String(self.name.clone())), ("pokes".to_string(), Value::Array(pokes))])
+    }
+
+    // reads a trainer written by to_json
+    pub(crate) fn from_json(value: &Value) -> Result<Trainer, SnaError> {
+        let invalid = || SnaError::InvalidFormat("Invalid trainer in sidecar".to_string());
+        let number = |value: &Value, key: &str, max: u16| match value.get(key).and_then(Value::as_f64) {
+            Some(n) if n >= 0.0 && n <= max as f64 && n.fract() == 0.0 => Ok(n as u16),
+            _ => Err(invalid()),
+        };
+        let name = value.get("name").and_then(Value::as_str).ok_or_else(invalid)?;
+        let pokes = value.get("pokes").and_then(Value::as_array).ok_or_else(invalid)?
+            .iter()
+            .map(|poke| Ok(Poke {
+                bank: poke.get("bank").map(|_| number(poke, "bank", 0xFF)).transpose()?.map(|bank| bank as u8),
+                address: number(poke, "address", 0xFFFF)?,
+                value: number(poke, "value", 0xFF)? as u8,
+                original: number(poke, "original", 0xFF)? as u8,
+            }))
+            .collect::<Result<_, SnaError>>()?;
+        Ok(Trainer { name: name.to_string(), pokes })
+    }
+}
+
+pub(crate) fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')).or_else(|| lower.strip_prefix('&')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// to_pok formats trainers as a .POK file.
+/// Each trainer becomes an `N` line followed by `M` lines for its pokes, the last of which is a
+/// `Z` line, and the file is terminated with `Y`. Pokes without a bank are written with bank 8.
+/// Trainers without pokes are left out, as .POK files cannot hold them, and line breaks in
+/// names are replaced with spaces. Returns SnaError::BankOutOfRange for a poke into a bank
+/// above 7, such as the upper banks of a Scorpion 256, as the bank field of a .POK file
+/// uses bit 3 to mean no bank.
+pub fn to_pok(trainers: &[Trainer]) -> Result<String, SnaError> {
+    let mut pok = String::new();
+    for trainer in trainers.iter().filter(|trainer| !trainer.pokes.is_empty()) {
+        if let Some(bank) = trainer.pokes.iter().filter_map(|poke| poke.bank).find(|&bank| bank > 7) {
+            return Err(SnaError::BankOutOfRange { bank: bank as usize, banks: 8 });
+        }
+        pok.push_str(&format!("N{}\n", trainer.name.replace(['\r', '\n'], " ")));
+        for (index, poke) in trainer.pokes.iter().enumerate() {
+            let marker = if index + 1 == trainer.pokes.len() { 'Z' } else { 'M' };
+            pok.push_str(&format!("{} {:>2} {:>5} {:>3} {:>3}\n", marker, poke.bank.unwrap_or(8), poke.address, poke.value, poke.original));
+        }
+    }
+    pok.push_str("Y\n");
+    Ok(pok)
+}
+
+impl Snapshot {
+    /// apply_trainer performs every poke in the trainer.
+    /// On 128K snapshots, pokes with a bank at or above 0xC000 are written into that bank
+    /// whether or not it is currently paged in; all other pokes go to mapped memory.
+    /// Stops with an error at the first poke into ROM or a bank the snapshot does not have.
+    /// A trainer applied in full is recorded, with the values its pokes replaced, in
+    /// [`Snapshot::applied_trainers`] and saved in the sidecar by
+    /// [`Snapshot::save_with_sidecar`].
+    pub fn apply_trainer(&mut self, trainer: &Trainer) -> Result<(), SnaError> {
+        let mut applied = Trainer { name: trainer.name.clone(), pokes: Vec::with_capacity(trainer.pokes.len()) };
+        self.grouped(|snapshot| trainer.pokes.iter().try_for_each(|poke| -> Result<(), SnaError> {
+            let original = match poke.bank {
+                Some(bank) if poke.address >= 0xC000 && snapshot.snapshot_type == SnapshotType::Snapshot128 => {
+                    let original = snapshot.bank_peek(bank as usize, poke.address)?;
+                    snapshot.bank_poke(bank as usize, poke.address, poke.value)?;
+                    original
+                }
+                _ => {
+                    let original = snapshot.peek(poke.address);
+                    snapshot.poke(poke.address, poke.value)?;
+                    original
+                }
+            };
+            applied.pokes.push(Poke { original, ..*poke });
+            Ok(())
+        }))?;
+        self.trainers.push(applied);
+        Ok(())
+    }
+
+    /// applied_trainers returns the trainers applied to the snapshot, in order, with the
+    /// values each poke replaced. Undoing a trainer does not remove it from the list.
+    pub fn applied_trainers(&self) -> &[Trainer] {
+        &self.trainers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+
+    #[test]
+    fn test_parse_script() {
+        let script = "REM infinite lives\nPOKE 35899,0: POKE &8C3C,$C9\n\npoke 3:49152-49153,0x3E\n";
+        let trainer = Trainer::parse_script("Lives", script).expect("Failed to parse script");
+        assert_eq!(trainer.pokes, vec![
+            Poke { bank: None, address: 35899, value: 0, original: 0 },
+            Poke { bank: None, address: 0x8C3C, value: 0xC9, original: 0 },
+            Poke { bank: Some(3), address: 49152, value: 0x3E, original: 0 },
+            Poke { bank: Some(3), address: 49153, value: 0x3E, original: 0 },
+        ]);
+
+        let error = Trainer::parse_script("Bad", "POKE 35899,0\nPOKE 35899,256").unwrap_err();
+        assert_eq!(error.line, 2, "Error reported on the wrong line");
+    }
+
+    #[test]
+    fn test_apply_and_export() {
+        let trainer = Trainer::parse_script("Lives", "POKE 35899,0\nPOKE 4:49152,201").expect("Failed to parse script");
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        snapshot.apply_trainer(&trainer).unwrap();
+        assert_eq!(snapshot.peek(35899), 0);
+        assert_eq!(snapshot.bank_peek(4, 0).unwrap(), 201, "Banked poke was not written to bank 4");
+        assert_ne!(snapshot.peek(49152), 201, "Banked poke was written to the paged bank");
+
+        assert_eq!(to_pok(&[trainer]).unwrap(), "NLives\nM  8 35899   0   0\nZ  4 49152 201   0\nY\n");
+
+        let scorpion = Trainer { name: "Upper".to_string(), pokes: vec![Poke { bank: Some(9), address: 0xC000, value: 0, original: 0 }] };
+        assert!(matches!(to_pok(&[scorpion]), Err(SnaError::BankOutOfRange { bank: 9, banks: 8 })), "Bank 9 would read back as no bank");
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{SnaError, Snapshot, SnapshotExtension, SnapshotType};
+
+impl Snapshot {
+    /// replace_cpu_state_from takes the registers, interrupt state, border and paging of another
+    /// snapshot, both port 0x7FFD and the 0x1FFD of a +2A/+3, while keeping this snapshot's memory.
+    /// On 48K snapshots the PC lives on the stack, so the two bytes at the new SP are also copied
+    /// from the other snapshot; the rest of memory is untouched.
+    /// The paging and the stacked PC are journaled as one change; the registers are not.
+    /// Returns a TypeMismatch error if the snapshots are not of the same type.
+    pub fn replace_cpu_state_from(&mut self, other: &Snapshot) -> Result<(), SnaError> {
+        if self.snapshot_type != other.snapshot_type {
+            return Err(SnaError::TypeMismatch { expected: self.snapshot_type, found: other.snapshot_type });
+        }
+        self.header = other.header;
+        let (x7ffd, x1ffd) = other.paging_registers();
+        self.extension = other.extension.map(|extension| SnapshotExtension { x7ffd: self.paging_registers().0, ..extension });
+
+        let sp = self.header.sp;
+        self.grouped(|snapshot| {
+            if snapshot.snapshot_type == SnapshotType::Snapshot128 {
+                snapshot.set_paging(x7ffd, x1ffd);
+            }
+            if snapshot.snapshot_type == SnapshotType::Snapshot48 && (0x4000..0xFFFF).contains(&sp) {
+                snapshot.poke_word(sp, other.peek_word(sp)?)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// replace_memory_from takes every bank of another snapshot while keeping this snapshot's
+    /// CPU state. Banks are shared with the other snapshot until either is written to.
+    /// On 48K snapshots this snapshot's PC is written back onto the stack after the copy, so
+    /// execution still resumes where it would have.
+    /// The banks that change are journaled as one change with the stacked PC, unless the
+    /// snapshots have different numbers of banks, which the journal cannot undo, in which
+    /// case the changes recorded so far are discarded.
+    /// Returns a TypeMismatch error if the snapshots are not of the same type.
+    pub fn replace_memory_from(&mut self, other: &Snapshot) -> Result<(), SnaError> {
+        if self.snapshot_type != other.snapshot_type {
+            return Err(SnaError::TypeMismatch { expected: self.snapshot_type, found: other.snapshot_type });
+        }
+        let sp = self.header.sp;
+        let stacked_pc = (self.snapshot_type == SnapshotType::Snapshot48 && (0x4000..0xFFFF).contains(&sp))
+            .then(|| self.peek_word(sp))
+            .transpose()?;
+
+        self.grouped(|snapshot| {
+            snapshot.replace_banks(&other.banks);
+            if let Some(pc) = stacked_pc {
+                snapshot.poke_word(sp, pc)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+
+    #[test]
+    fn test_cpu_state_transplant() {
+        let mut target = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        let mut source = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        source.header.hl = 0xBEEF;
+        source.write_0x7ffd(0x14).unwrap();
+        target.poke(0x8000, 0x42).unwrap();
+
+        target.replace_cpu_state_from(&source).unwrap();
+        assert_eq!({ target.header.hl }, 0xBEEF, "Registers were not transplanted");
+        assert_eq!(target.mapping[2], 4, "Paging was not transplanted");
+        assert_eq!(target.peek(0x8000), 0x42, "Memory should be kept");
+        assert_eq!(target.peek(0xC000), target.bank_peek(4, 0).unwrap(), "Window table was not refreshed");
+    }
+
+    #[test]
+    fn test_cpu_state_transplant_plus3() {
+        let mut target = Snapshot::new_plus3();
+        let mut source = Snapshot::new_plus3();
+        source.write_0x1ffd(0x07).unwrap();
+        source.bank_poke(3, 0x3FFF, 0x33).unwrap();
+        target.bank_poke(3, 0x3FFF, 0x33).unwrap();
+
+        target.replace_cpu_state_from(&source).unwrap();
+        assert_eq!(target.x1ffd(), Some(0x07), "Port 0x1FFD was not transplanted");
+        assert_eq!(target.peek(0xFFFF), 0x33, "Special paging should map bank 3 at 0xC000");
+        assert!(target.poke(0x0000, 0).is_ok(), "Special paging should map RAM at 0x0000");
+    }
+
+    #[test]
+    fn test_transplant_undo() {
+        let mut target = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        let mut source = fixtures::generate(SnapshotType::Snapshot128, FixtureOptions::default());
+        source.write_0x7ffd(0x03).unwrap();
+        source.bank_poke(6, 0, 0x66).unwrap();
+        let before = target.bank_peek(6, 0).unwrap();
+        target.start_journal();
+
+        target.replace_cpu_state_from(&source).unwrap();
+        target.replace_memory_from(&source).unwrap();
+        assert_eq!((target.changes().len(), target.bank_peek(6, 0).unwrap()), (2, 0x66));
+        assert!(target.undo() && target.undo());
+        assert_eq!((target.mapping[2], target.bank_peek(6, 0).unwrap()), (0, before), "Paging and memory are restored");
+        assert_eq!(target.peek(0xC000), target.bank_peek(0, 0).unwrap());
+        assert!(target.redo() && target.redo());
+        assert_eq!(target.peek(0xC000), target.bank_peek(3, 0).unwrap());
+    }
+
+    #[test]
+    fn test_memory_transplant_keeps_stacked_pc() {
+        let mut target = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        let mut source = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        target.poke_word(fixtures::FIXTURE_SP, 0x9000).unwrap();
+        source.poke(0x8000, 0x42).unwrap();
+
+        target.replace_memory_from(&source).unwrap();
+        assert_eq!(target.peek(0x8000), 0x42, "Memory was not transplanted");
+        assert_eq!(target.peek_word(fixtures::FIXTURE_SP).unwrap(), 0x9000, "Stacked PC should be kept");
+    }
+}
@@ -0,0 +1,158 @@
+//! Extracting snapshots embedded in TZX tape images.
+//!
+//! Early versions of the TZX format had a "snapshot" block (ID 0x40) holding a
+//! complete .Z80 or .SNA image. The block is deprecated and most tools ignore it,
+//! so these helpers walk a tape's blocks to find and recover them.
+
+use crate::{SnaError, Snapshot};
+
+const SIGNATURE: &[u8] = b"ZXTape!\x1A";
+const HEADER_LEN: usize = 10;
+const SNAPSHOT_BLOCK: u8 = 0x40;
+
+/// The format of an embedded snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TzxSnapshotKind {
+    Z80,
+    Sna,
+}
+
+/// A snapshot image found in a TZX snapshot block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TzxSnapshot {
+    pub block: usize,           // index of the block in the tape, counting from 0
+    pub kind: TzxSnapshotKind,
+    pub bin: Vec<u8>,           // the snapshot file
+}
+
+fn invalid(message: String) -> SnaError {
+    SnaError::InvalidFormat(message)
+}
+
+fn u16_at(bin: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([bin[offset], bin[offset + 1]]) as usize
+}
+
+fn u24_at(bin: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes([bin[offset], bin[offset + 1], bin[offset + 2], 0]) as usize
+}
+
+fn u32_at(bin: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes([bin[offset], bin[offset + 1], bin[offset + 2], bin[offset + 3]]) as usize
+}
+
+// the length of a block's body after its ID byte, reading only the fixed-size part
+// of the body that holds the length
+fn body_len(id: u8, body: &[u8]) -> Option<usize> {
+    let fixed = match id {
+        0x10 => 4, 0x11 => 0x12, 0x12 => 4, 0x13 => 1, 0x14 => 0x0A, 0x15 => 8,
+        0x20 | 0x23 | 0x24 => 2, 0x21 | 0x30 | 0x33 => 1, 0x22 | 0x25 | 0x27 => 0,
+        0x26 | 0x28 | 0x31 | 0x32 => 2, 0x34 => 8, 0x35 => 0x14, 0x40 => 4, 0x5A => 9,
+        // every other block, including 0x18, 0x19, 0x2A and 0x2B, starts with a 32-bit length
+        _ => 4,
+    };
+    if body.len() < fixed {
+        return None;
+    }
+    Some(match id {
+        0x10 => 4 + u16_at(body, 2),
+        0x11 => 0x12 + u24_at(body, 0x0F),
+        0x12 => 4,
+        0x13 => 1 + body[0] as usize * 2,
+        0x14 => 0x0A + u24_at(body, 0x07),
+        0x15 => 8 + u24_at(body, 0x05),
+        0x20 | 0x23 | 0x24 => 2,
+        0x21 | 0x30 => 1 + body[0] as usize,
+        0x22 | 0x25 | 0x27 => 0,
+        0x26 => 2 + u16_at(body, 0) * 2,
+        0x28 | 0x32 => 2 + u16_at(body, 0),
+        0x31 => 2 + body[1] as usize,
+        0x33 => 1 + body[0] as usize * 3,
+        0x34 => 8,
+        0x35 => 0x14 + u32_at(body, 0x10),
+        0x40 => 4 + u24_at(body, 1),
+        0x5A => 9,
+        _ => 4 + u32_at(body, 0),
+    })
+}
+
+/// find_snapshots walks the blocks of a TZX file and returns the contents of every
+/// snapshot block. Returns SnaError::InvalidFormat if the file is not a TZX or a block
+/// runs past the end of the file.
+pub fn find_snapshots(tzx: &[u8]) -> Result<Vec<TzxSnapshot>, SnaError> {
+    if !tzx.starts_with(SIGNATURE) || tzx.len() < HEADER_LEN {
+        return Err(invalid("Not a TZX file".to_string()));
+    }
+    let mut snapshots = Vec::new();
+    let mut offset = HEADER_LEN;
+    let mut block = 0;
+    while offset < tzx.len() {
+        let id = tzx[offset];
+        let body = &tzx[offset + 1..];
+        let len = body_len(id, body).filter(|&len| len <= body.len())
+            .ok_or_else(|| invalid(format!("Block {} (ID 0x{:02X}) at offset {} is truncated", block, id, offset)))?;
+        if id == SNAPSHOT_BLOCK {
+            let kind = match body[0] {
+                0 => TzxSnapshotKind::Z80,
+                1 => TzxSnapshotKind::Sna,
+                other => return Err(invalid(format!("Block {} has unknown snapshot type {}", block, other))),
+            };
+            snapshots.push(TzxSnapshot { block, kind, bin: body[4..len].to_vec() });
+        }
+        offset += 1 + len;
+        block += 1;
+    }
+    Ok(snapshots)
+}
+
+/// extract_snapshots returns every .SNA and .Z80 snapshot embedded in a TZX file as a Snapshot.
+/// Returns SnaError::InvalidFormat naming the block if an embedded snapshot is invalid.
+pub fn extract_snapshots(tzx: &[u8]) -> Result<Vec<Snapshot>, SnaError> {
+    find_snapshots(tzx)?.into_iter()
+        .map(|snapshot| match snapshot.kind {
+            TzxSnapshotKind::Z80 => Snapshot::from_z80(&snapshot.bin)
+                .map_err(|e| invalid(format!("Block {} is not a valid .Z80: {}", snapshot.block, e))),
+            TzxSnapshotKind::Sna => Snapshot::try_from(&snapshot.bin[..])
+                .map_err(|e| invalid(format!("Block {} is not a valid .SNA: {}", snapshot.block, e))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_block(kind: u8, bin: &[u8]) -> Vec<u8> {
+        let mut block = vec![SNAPSHOT_BLOCK, kind];
+        block.extend_from_slice(&(bin.len() as u32).to_le_bytes()[..3]);
+        block.extend_from_slice(bin);
+        block
+    }
+
+    #[test]
+    fn test_extract_snapshots() {
+        let sna = std::fs::read("48k.sna").expect("Failed to read snapshot file");
+        let mut tzx = SIGNATURE.to_vec();
+        tzx.extend_from_slice(&[1, 20]);
+        tzx.extend_from_slice(&[0x30, 5, b'T', b'e', b's', b't', b'!']);   // text description
+        tzx.extend_from_slice(&[0x10, 0xE8, 0x03, 3, 0, 0x00, 0x12, 0x34]); // standard data block
+        let mut z80 = vec![0u8; 30];
+        z80[6..10].copy_from_slice(&[0x00, 0x80, 0x00, 0xFF]);    // PC 0x8000, SP 0xFF00
+        z80.extend_from_slice(&sna[27..]);
+        tzx.extend_from_slice(&snapshot_block(0, &z80));
+        tzx.extend_from_slice(&snapshot_block(1, &sna));
+
+        let found = find_snapshots(&tzx).expect("Failed to walk TZX");
+        assert_eq!(found.iter().map(|s| (s.block, s.kind)).collect::<Vec<_>>(), [(2, TzxSnapshotKind::Z80), (3, TzxSnapshotKind::Sna)]);
+        assert_eq!(found[0].bin, z80);
+
+        let snapshots = extract_snapshots(&tzx).expect("Failed to extract snapshots");
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].peek_word(0xFEFE).ok(), Some(0x8000), "The .Z80 PC should be on the stack");
+        assert_eq!(snapshots[1].checksum(0).unwrap(), 59066);
+
+        tzx.truncate(tzx.len() - 1);
+        assert!(find_snapshots(&tzx).is_err(), "Truncated block should be an error");
+        assert!(matches!(find_snapshots(b"not a tape"), Err(SnaError::InvalidFormat(_))));
+    }
+}
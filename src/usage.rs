@@ -0,0 +1,138 @@
+use crate::{Addr, BankAddr, BankId, Snapshot};
+
+const STKEND: u16 = 0x5C65;
+const RAMTOP: u16 = 0x5CB2;
+const PROG_START: u16 = 0x5CCB;
+
+/// The shortest run of zero bytes reported as free by [`Snapshot::memory_usage`].
+pub const MIN_ZERO_RUN: usize = 256;
+
+/// Why a region of memory is believed to be free.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FreeSource {
+    ZeroRun,        // a long run of zero bytes
+    BasicSpare,     // between STKEND and the machine stack, according to the BASIC system variables
+    EmptyBank,      // a bank that is entirely zero
+}
+
+/// A region of a bank believed to be free.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FreeRegion {
+    pub start: BankAddr,
+    pub len: usize,
+    pub source: FreeSource,
+}
+
+/// A report of the free memory in a snapshot, for placing injected code safely.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub free: Vec<FreeRegion>,      // free regions in bank and offset order
+}
+
+impl MemoryUsage {
+    /// total_free returns the number of free bytes.
+    pub fn total_free(&self) -> usize {
+        self.free.iter().map(|region| region.len).sum()
+    }
+
+    /// largest returns the largest free region.
+    pub fn largest(&self) -> Option<&FreeRegion> {
+        self.free.iter().max_by_key(|region| region.len)
+    }
+
+    /// in_bank iterates over the free regions in one bank.
+    pub fn in_bank(&self, bank: BankId) -> impl Iterator<Item = &FreeRegion> {
+        self.free.iter().filter(move |region| region.start.bank == bank)
+    }
+}
+
+impl Snapshot {
+    /// memory_usage estimates the free memory in each bank, combining the spare
+    /// space recorded by the BASIC system variables (STKEND to the machine stack),
+    /// banks that are entirely empty, and runs of at least MIN_ZERO_RUN zero bytes.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory_usage_with(MIN_ZERO_RUN)
+    }
+
+    /// memory_usage_with is memory_usage with a custom minimum zero run length.
+    pub fn memory_usage_with(&self, min_zero_run: usize) -> MemoryUsage {
+        // the best reason each byte is free, if it is
+        let mut free: Vec<Vec<Option<FreeSource>>> = self.banks.iter().map(|bank| {
+            let source = if bank.iter().all(|&byte| byte == 0) { Some(FreeSource::EmptyBank) } else { None };
+            vec![source; bank.len()]
+        }).collect();
+
+        for (bank, data) in self.banks.iter().enumerate() {
+            let mut offset = 0;
+            while offset < data.len() {
+                let run = data[offset..].iter().take_while(|&&byte| byte == 0).count();
+                if run >= min_zero_run {
+                    for source in &mut free[bank][offset..offset + run] {
+                        *source = (*source).max(Some(FreeSource::ZeroRun));
+                    }
+                }
+                offset += run.max(1);
+            }
+        }
+
+        if let Some((start, end)) = self.basic_spare() {
+            for address in start..end {
+                if let Some(location) = self.resolve(Addr(address)) {
+                    let source = &mut free[location.bank.0][location.offset as usize];
+                    *source = (*source).max(Some(FreeSource::BasicSpare));
+                }
+            }
+        }
+
+        let mut usage = MemoryUsage::default();
+        for (bank, sources) in free.iter().enumerate() {
+            let mut offset = 0;
+            while offset < sources.len() {
+                let run = sources[offset..].iter().take_while(|&&source| source == sources[offset]).count();
+                if let Some(source) = sources[offset] {
+                    usage.free.push(FreeRegion { start: BankAddr { bank: BankId(bank), offset: offset as u16 }, len: run, source });
+                }
+                offset += run;
+            }
+        }
+        usage
+    }
+
+    // the spare memory between STKEND and the machine stack, if the system variables are plausible
+    fn basic_spare(&self) -> Option<(u16, u16)> {
+        let stkend = self.peek_word(STKEND).ok()?;
+        let ramtop = self.peek_word(RAMTOP).ok()?;
+        if stkend < PROG_START || ramtop <= stkend {
+            return None;
+        }
+        let sp = { self.header.sp };
+        let end = if (stkend..ramtop).contains(&sp) { sp } else { ramtop };
+        Some((stkend, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::SnapshotType;
+
+    #[test]
+    fn test_memory_usage() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot128, Default::default());
+        snapshot.banks_mut(|id, bank, _| if id.0 == 4 { bank.fill(0) });
+        snapshot.bank_poke(1, 0x3000, 0xFF).unwrap();
+        for offset in 0x1000..0x1400 {
+            snapshot.bank_poke(3, offset, 0).unwrap();
+        }
+        snapshot.poke_word(STKEND, 0x6000).unwrap();
+        snapshot.poke_word(RAMTOP, 0x6100).unwrap();
+
+        let usage = snapshot.memory_usage();
+        assert_eq!(usage.in_bank(BankId(4)).collect::<Vec<_>>(), [&FreeRegion { start: BankAddr::new(BankId(4), 0).unwrap(), len: 0x4000, source: FreeSource::EmptyBank }]);
+        assert_eq!(usage.in_bank(BankId(3)).collect::<Vec<_>>(), [&FreeRegion { start: BankAddr::new(BankId(3), 0x1000).unwrap(), len: 0x400, source: FreeSource::ZeroRun }]);
+        assert_eq!(usage.in_bank(BankId(5)).collect::<Vec<_>>(), [&FreeRegion { start: BankAddr::new(BankId(5), 0x2000).unwrap(), len: 0x100, source: FreeSource::BasicSpare }]);
+        assert_eq!(usage.largest().map(|region| region.start.bank), Some(BankId(4)));
+        assert_eq!(usage.total_free(), 0x4000 + 0x400 + 0x100);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{SnaError, Snapshot};
+
+/// A read-only view of a snapshot with a set of pokes overlaid on top.
+/// The underlying snapshot is never modified; reads of poked addresses
+/// return the overlaid value and every other address falls through to
+/// the snapshot. Created by `Snapshot::with_pokes`.
+pub struct PokeView<'a> {
+    snapshot: &'a Snapshot,
+    pokes: &'a [(u16, u8)],
+}
+
+impl<'a> PokeView<'a> {
+    /// peek reads a byte from the memory MAPPED to the given address,
+    /// returning the overlaid value if the address has been poked.
+    /// Where an address is poked more than once the last poke wins.
+    pub fn peek(&self, address: u16) -> u8 {
+        match self.pokes.iter().rev().find(|(poked, _)| *poked == address) {
+            Some((_, value)) => *value,
+            None => self.snapshot.peek(address),
+        }
+    }
+
+    /// peek_word reads a little-endian 16-bit value through the overlay.
+    /// Returns an AddressOverflow error if the address is 0xFFFF.
+    pub fn peek_word(&self, address: u16) -> Result<u16, SnaError> {
+        if address == 0xFFFF {
+            return Err(SnaError::AddressOverflow { address, len: 2 });
+        }
+        Ok((self.peek(address) as u16) | ((self.peek(address + 1) as u16) << 8))
+    }
+
+    /// Returns the snapshot underneath the overlay.
+    pub fn snapshot(&self) -> &Snapshot {
+        self.snapshot
+    }
+}
+
+impl Snapshot {
+    /// with_pokes evaluates a closure against a view of the snapshot with the given pokes applied.
+    /// The pokes are held in an overlay for the duration of the closure so the snapshot itself is
+    /// left untouched, which is cheaper than cloning, poking and dropping when trying many candidates.
+    /// Returns a RomWrite error, without calling the closure, if any poke is in ROM.
+    pub fn with_pokes<R, F: FnOnce(&PokeView) -> R>(&self, pokes: &[(u16, u8)], f: F) -> Result<R, SnaError> {
+        if let Some((address, _)) = pokes.iter().find(|(address, _)| self.is_rom(*address)) {
+            return Err(SnaError::RomWrite(*address));
+        }
+        Ok(f(&PokeView { snapshot: self, pokes }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_with_pokes() {
+        let file = File::open("48k.sna").expect("Failed to open snapshot file");
+        let snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        let original = snapshot.peek_word(0x8000).unwrap();
+
+        let word = snapshot.with_pokes(&[(0x8000, 0x34), (0x8001, 0x12), (0x8000, 0x78)], |view| {
+            assert_eq!(view.peek(0x9000), snapshot.peek(0x9000), "Unpoked address should fall through to the snapshot");
+            view.peek_word(0x8000).unwrap()
+        }).expect("Pokes are in RAM");
+        assert_eq!(word, 0x1278, "Overlaid word is incorrect, the last poke should win");
+        assert_eq!(snapshot.peek_word(0x8000).unwrap(), original, "Snapshot was modified by with_pokes");
+        assert!(snapshot.with_pokes(&[(0x3FFF, 0)], |_| ()).is_err(), "A poke into ROM should fail");
+    }
+}
@@ -0,0 +1,353 @@
+//! Watch expressions over snapshot state.
+//! Expressions combine numbers, registers, memory reads and named regions with
+//! the usual arithmetic, bitwise, comparison and logical operators, e.g.
+//! `peek(0x5C78) + 256*peek(0x5C79)`, `bc + 2` or `lives > 0 && hl == $4000`.
+//!
+//! - numbers are decimal, or hex with a `0x`, `$` or `&` prefix
+//! - registers are `a f b c d e h l i r ixh ixl iyh iyl af bc de hl ix iy sp pc` and the primes `a' f' ... af' bc' de' hl'`
+//! - `peek(addr)` reads a byte and `dpeek(addr)` a little-endian word from mapped memory
+//! - any other name reads the named region of that name
+//! - comparisons and logical operators give 1 for true and 0 for false
+//!
+//! Values are 64-bit signed integers. Division by zero is an evaluation error.
+
+use std::fmt;
+
+use crate::{RegionValue, Register, RegisterPair, Snapshot};
+
+/// An error from parsing or evaluating a watch expression.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WatchError {
+    pub message: String,
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+fn error<T>(message: String) -> Result<T, WatchError> {
+    Err(WatchError { message })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum BinaryOp {
+    Mul, Div, Rem, Add, Sub, Shl, Shr, Lt, Le, Gt, Ge, Eq, Ne, BitAnd, BitXor, BitOr, And, Or,
+}
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitOr => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Or => 1,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Node {
+    Number(i64),
+    Name(String),
+    Peek(Box<Node>),
+    DPeek(Box<Node>),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    BitNot(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+/// A parsed watch expression that can be evaluated against any snapshot.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Watch {
+    source: String,
+    root: Node,
+}
+
+const OPERATORS: [&str; 21] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~", "=",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, WatchError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+        } else if c.is_ascii_digit() || ((c == '$' || c == '&') && chars.get(i + 1).is_some_and(|c| c.is_ascii_hexdigit())) {
+            let (radix, start) = if c == '$' || c == '&' {
+                (16, i + 1)
+            } else if c == '0' && matches!(chars.get(i + 1), Some('x') | Some('X')) {
+                (16, i + 2)
+            } else {
+                (10, i)
+            };
+            let mut end = start;
+            while end < chars.len() && chars[end].is_digit(radix) {
+                end += 1;
+            }
+            let digits: String = chars[start..end].iter().collect();
+            match i64::from_str_radix(&digits, radix) {
+                Ok(value) => tokens.push(Token::Number(value)),
+                Err(_) => return error(format!("invalid number '{}'", chars[i..end].iter().collect::<String>())),
+            }
+            i = end;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = i;
+            while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            if chars.get(end) == Some(&'\'') {
+                end += 1;
+            }
+            tokens.push(Token::Name(chars[i..end].iter().collect()));
+            i = end;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(&"=") => return error("unexpected '=', use '==' for comparison".to_string()),
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => return error(format!("unexpected character '{}'", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_binary(&self) -> Option<BinaryOp> {
+        let Some(Token::Op(op)) = self.tokens.get(self.position) else {
+            return None;
+        };
+        Some(match *op {
+            "*" => BinaryOp::Mul, "/" => BinaryOp::Div, "%" => BinaryOp::Rem,
+            "+" => BinaryOp::Add, "-" => BinaryOp::Sub,
+            "<<" => BinaryOp::Shl, ">>" => BinaryOp::Shr,
+            "<" => BinaryOp::Lt, "<=" => BinaryOp::Le, ">" => BinaryOp::Gt, ">=" => BinaryOp::Ge,
+            "==" => BinaryOp::Eq, "!=" => BinaryOp::Ne,
+            "&" => BinaryOp::BitAnd, "^" => BinaryOp::BitXor, "|" => BinaryOp::BitOr,
+            "&&" => BinaryOp::And, "||" => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+
+    // precedence climbing: parse operands joined by operators binding at least as tightly as min_precedence
+    fn expression(&mut self, min_precedence: u8) -> Result<Node, WatchError> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek_binary() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.expression(op.precedence() + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, WatchError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Op("-")) => Ok(Node::Neg(Box::new(self.unary()?))),
+            Some(Token::Op("!")) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Op("~")) => Ok(Node::BitNot(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let node = self.expression(0)?;
+                self.expect_close()?;
+                Ok(node)
+            }
+            Some(Token::Name(name)) => {
+                let function = name.to_ascii_lowercase();
+                if (function == "peek" || function == "dpeek") && self.tokens.get(self.position) == Some(&Token::Open) {
+                    self.position += 1;
+                    let argument = Box::new(self.expression(0)?);
+                    self.expect_close()?;
+                    Ok(if function == "peek" { Node::Peek(argument) } else { Node::DPeek(argument) })
+                } else {
+                    Ok(Node::Name(name))
+                }
+            }
+            Some(token) => error(format!("unexpected {:?}", token)),
+            None => error("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn expect_close(&mut self) -> Result<(), WatchError> {
+        match self.next() {
+            Some(Token::Close) => Ok(()),
+            _ => error("expected ')'".to_string()),
+        }
+    }
+}
+
+impl Watch {
+    /// Parses a watch expression.
+    pub fn parse(text: &str) -> Result<Watch, WatchError> {
+        let mut parser = Parser { tokens: tokenize(text)?, position: 0 };
+        let root = parser.expression(0)?;
+        if parser.position < parser.tokens.len() {
+            return error(format!("unexpected {:?} after expression", parser.tokens[parser.position]));
+        }
+        Ok(Watch { source: text.to_string(), root })
+    }
+
+    /// Returns the text the expression was parsed from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluates the expression against a snapshot.
+    pub fn eval(&self, snapshot: &Snapshot) -> Result<i64, WatchError> {
+        eval(&self.root, snapshot)
+    }
+}
+
+fn address(value: i64) -> Result<u16, WatchError> {
+    u16::try_from(value).or_else(|_| error(format!("address {} is out of range", value)))
+}
+
+fn register(snapshot: &Snapshot, name: &str) -> Option<i64> {
+    if name.eq_ignore_ascii_case("pc") {
+        return snapshot.pc().map(i64::from);
+    }
+    if let Some(&register) = Register::ALL.iter().find(|register| register.name().eq_ignore_ascii_case(name)) {
+        return Some(snapshot.get_reg(register) as i64);
+    }
+    RegisterPair::ALL.iter()
+        .find(|pair| pair.name().eq_ignore_ascii_case(name))
+        .map(|&pair| snapshot.get_pair(pair) as i64)
+}
+
+fn eval(node: &Node, snapshot: &Snapshot) -> Result<i64, WatchError> {
+    Ok(match node {
+        Node::Number(value) => *value,
+        Node::Name(name) => match register(snapshot, name) {
+            Some(value) => value,
+            None => match snapshot.peek_named(name) {
+                Some(RegionValue::Number(value)) => value,
+                Some(RegionValue::Bytes(_)) => return error(format!("region '{}' does not hold a number", name)),
+                None if snapshot.region(name).is_some() => return error(format!("region '{}' holds an invalid value", name)),
+                None => return error(format!("unknown name '{}'", name)),
+            },
+        },
+        Node::Peek(argument) => snapshot.peek(address(eval(argument, snapshot)?)?) as i64,
+        Node::DPeek(argument) => {
+            let address = address(eval(argument, snapshot)?)?;
+            (snapshot.peek(address) as i64) | ((snapshot.peek(address.wrapping_add(1)) as i64) << 8)
+        }
+        Node::Neg(operand) => eval(operand, snapshot)?.wrapping_neg(),
+        Node::Not(operand) => (eval(operand, snapshot)? == 0) as i64,
+        Node::BitNot(operand) => !eval(operand, snapshot)?,
+        Node::Binary(BinaryOp::And, left, right) => (eval(left, snapshot)? != 0 && eval(right, snapshot)? != 0) as i64,
+        Node::Binary(BinaryOp::Or, left, right) => (eval(left, snapshot)? != 0 || eval(right, snapshot)? != 0) as i64,
+        Node::Binary(op, left, right) => {
+            let (left, right) = (eval(left, snapshot)?, eval(right, snapshot)?);
+            match op {
+                BinaryOp::Mul => left.wrapping_mul(right),
+                BinaryOp::Div | BinaryOp::Rem if right == 0 => return error("division by zero".to_string()),
+                BinaryOp::Div => left.wrapping_div(right),
+                BinaryOp::Rem => left.wrapping_rem(right),
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Sub => left.wrapping_sub(right),
+                BinaryOp::Shl => left.wrapping_shl(right as u32),
+                BinaryOp::Shr => left.wrapping_shr(right as u32),
+                BinaryOp::Lt => (left < right) as i64,
+                BinaryOp::Le => (left <= right) as i64,
+                BinaryOp::Gt => (left > right) as i64,
+                BinaryOp::Ge => (left >= right) as i64,
+                BinaryOp::Eq => (left == right) as i64,
+                BinaryOp::Ne => (left != right) as i64,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::BitXor => left ^ right,
+                BinaryOp::BitOr => left | right,
+                BinaryOp::And | BinaryOp::Or => unreachable!("Logical operators are evaluated above"),
+            }
+        }
+    })
+}
+
+impl Snapshot {
+    /// eval parses and evaluates a watch expression against the snapshot.
+    /// Parse the expression once with `Watch::parse` when evaluating it repeatedly.
+    pub fn eval(&self, expression: &str) -> Result<i64, WatchError> {
+        Watch::parse(expression)?.eval(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, FixtureOptions};
+    use crate::{SnapshotType, ValueType};
+
+    #[test]
+    fn test_eval() {
+        let mut snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        snapshot.poke_word(0x5C78, 0x1234).unwrap();
+        snapshot.define_region("lives", 0x8000, 1, ValueType::U8).unwrap();
+        snapshot.poke(0x8000, 3).unwrap();
+
+        assert_eq!(snapshot.eval("peek(0x5C78) + 256*peek($5C79)"), Ok(0x1234));
+        assert_eq!(snapshot.eval("dpeek(&5C78)"), Ok(0x1234));
+        assert_eq!(snapshot.eval("bc + 2"), Ok(0x0B0E));
+        assert_eq!(snapshot.eval("h == 7 && l == 8"), Ok(1));
+        assert_eq!(snapshot.eval("pc"), Ok(fixtures::FIXTURE_PC as i64));
+        assert_eq!(snapshot.eval("af' - 6"), Ok(0x0500));
+        assert_eq!(snapshot.eval("lives > 0 || !1"), Ok(1));
+        assert_eq!(snapshot.eval("-(2 + 3) * 4 % 7"), Ok(-6));
+        assert_eq!(snapshot.eval("1 << 4 | 1"), Ok(17));
+    }
+
+    #[test]
+    fn test_errors() {
+        let snapshot = fixtures::generate(SnapshotType::Snapshot48, FixtureOptions::default());
+        assert!(Watch::parse("peek(0x4000").is_err(), "Unclosed parenthesis should not parse");
+        assert!(Watch::parse("1 2").is_err(), "Trailing tokens should not parse");
+        assert!(Watch::parse("a = 1").is_err(), "Assignment should not parse");
+        assert!(snapshot.eval("1 / 0").is_err(), "Division by zero should be an error");
+        assert!(snapshot.eval("ammo").is_err(), "Unknown names should be an error");
+        assert!(snapshot.eval("peek(70000)").is_err(), "Out of range addresses should be an error");
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::{SaveOptions, SnaError, Snapshot, SnapshotHeader, SnapshotType};
+
+impl SnapshotHeader {
+    /// to_bytes serializes the header into the 27 byte little-endian .sna layout.
+    pub fn to_bytes(&self) -> [u8; 27] {
+        let mut bytes = [0u8; 27];
+        bytes[0] = self.i;
+        bytes[1..3].copy_from_slice(&{ self.hl_prime }.to_le_bytes());
+        bytes[3..5].copy_from_slice(&{ self.de_prime }.to_le_bytes());
+        bytes[5..7].copy_from_slice(&{ self.bc_prime }.to_le_bytes());
+        bytes[7..9].copy_from_slice(&{ self.af_prime }.to_le_bytes());
+        bytes[9..11].copy_from_slice(&{ self.hl }.to_le_bytes());
+        bytes[11..13].copy_from_slice(&{ self.de }.to_le_bytes());
+        bytes[13..15].copy_from_slice(&{ self.bc }.to_le_bytes());
+        bytes[15..17].copy_from_slice(&{ self.iy }.to_le_bytes());
+        bytes[17..19].copy_from_slice(&{ self.ix }.to_le_bytes());
+        bytes[19] = self.interrupt;
+        bytes[20] = self.r;
+        bytes[21..23].copy_from_slice(&{ self.af }.to_le_bytes());
+        bytes[23..25].copy_from_slice(&{ self.sp }.to_le_bytes());
+        bytes[25] = self.int_mode;
+        bytes[26] = self.border_color;
+        bytes
+    }
+}
+
+impl Snapshot {
+    /// write_to streams the snapshot in .sna format to any writer.
+    /// The header and each bank are written directly from the snapshot without
+    /// building the file in memory first.
+    /// For 128K snapshots the banks mapped at 0x4000, 0x8000 and 0xC000 are
+    /// written first, followed by the extension and the remaining banks in
+    /// ascending order, matching the layout expected when loading. A Scorpion 256
+    /// ends with its banks 8 to 15. Returns an InvalidData error wrapping
+    /// SnaError::InvalidFormat, before anything is written, if the snapshot is missing
+    /// banks or a 128K snapshot has no extension.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        self.check_writable()?;
+        // the file can only record the banks 0x7FFD pages, so a Scorpion 256 with one of
+        // banks 8 to 15 paged in stores the bank 0x7FFD alone would select
+        let mut mapping = self.mapping;
+        if self.snapshot_type == SnapshotType::Snapshot128 {
+            mapping[2] &= 0x07;
+        }
+        writer.write_all(&self.header.to_bytes())?;
+        for bank in mapping {
+            writer.write_all(&self.banks[bank as usize])?;
+        }
+
+        if let (SnapshotType::Snapshot128, Some(extension)) = (self.snapshot_type, self.extension.as_ref()) {
+            writer.write_all(&{ extension.pc }.to_le_bytes())?;
+            writer.write_all(&[extension.x7ffd, extension.tr_dos])?;
+            for bank in [0, 1, 3, 4, 6, 7] {
+                if bank != mapping[2] {
+                    writer.write_all(&self.banks[bank as usize])?;
+                }
+            }
+            // a Scorpion 256 follows with banks 8 to 15 in order
+            for bank in &self.banks[8..] {
+                writer.write_all(bank)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// write_mapped_48k_to streams the memory currently mapped at 0x4000-0xFFFF as a 48K .sna.
+    /// For 128K snapshots the PC is pushed onto the stack, as the 48K format requires,
+    /// and the extension and unmapped banks are dropped. 48K snapshots are written unchanged.
+    /// Returns an InvalidData error wrapping SnaError::RomWrite if pushing the PC would write
+    /// below 0x4000, or SnaError::AddressOverflow if it would run past 0xFFFF.
+    pub fn write_mapped_48k_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        self.check_writable()?;
+        let extension = match self.extension.as_ref() {
+            Some(extension) if self.snapshot_type == SnapshotType::Snapshot128 => extension,
+            _ => return self.write_to(writer),
+        };
+
+        let mut memory = Vec::with_capacity(3 * 16384);
+        for bank in self.mapping {
+            memory.extend_from_slice(&self.banks[bank as usize]);
+        }
+        let sp = { self.header.sp }.wrapping_sub(2);
+        if sp < 0x4000 {
+            return Err(SnaError::RomWrite(sp).into());
+        }
+        if sp == 0xFFFF {
+            return Err(SnaError::AddressOverflow { address: sp, len: 2 }.into());
+        }
+        let offset = (sp - 0x4000) as usize;
+        memory[offset..offset + 2].copy_from_slice(&{ extension.pc }.to_le_bytes());
+
+        let mut header = self.header;
+        header.sp = sp;
+        writer.write_all(&header.to_bytes())?;
+        writer.write_all(&memory)?;
+        writer.flush()
+    }
+
+    // returns SnaError::InvalidFormat if the public banks and extension do not hold
+    // everything the .sna format needs, so that writing cannot index past them
+    fn check_writable(&self) -> Result<(), SnaError> {
+        let needed = match self.snapshot_type {
+            SnapshotType::Snapshot48 => 3,
+            SnapshotType::Snapshot128 => 8,
+        };
+        if self.banks.len() < needed {
+            return Err(SnaError::InvalidFormat(format!("A {} snapshot needs {} banks, not {}", self.snapshot_type, needed, self.banks.len())));
+        }
+        if self.snapshot_type == SnapshotType::Snapshot128 && self.extension.is_none() {
+            return Err(SnaError::InvalidFormat("A 128K snapshot needs an extension".to_string()));
+        }
+        Ok(())
+    }
+
+    /// save_mapped_48k writes the currently mapped view of the snapshot as a 48K .sna file.
+    pub fn save_mapped_48k<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.write_mapped_48k_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// save writes the snapshot in .sna format to the file at the given path.
+    /// The stack is checked first (see [`Snapshot::check_stack`]) so that a snapshot
+    /// that would crash on load is not written; use write_to to skip the check.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnaError> {
+        self.save_with_options(path, SaveOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for name in ["48k.sna", "128k.sna"] {
+            let bin = std::fs::read(name).expect("Failed to read snapshot file");
+            let snapshot = Snapshot::try_from(bin.clone()).expect("Failed to parse snapshot");
+            let mut written = Vec::new();
+            snapshot.write_to(&mut written).expect("Failed to write snapshot");
+            assert!(written == bin, "Written {} does not match the original file", name);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_paged_bank_5() {
+        let file = std::fs::File::open("128k.sna").expect("Failed to open snapshot file");
+        let mut snapshot = Snapshot::try_from(file).expect("Failed to parse snapshot");
+        snapshot.write_0x7ffd(5).unwrap();
+
+        let mut written = Vec::new();
+        snapshot.write_to(&mut written).expect("Failed to write snapshot");
+        assert_eq!(written.len(), 27 + 3 * 16384 + 4 + 6 * 16384, "A paged bank of 5 should write 6 remaining banks");
+
+        let reloaded = Snapshot::try_from(written).expect("Failed to parse written snapshot");
+        for bank in 0..=7 {
+            assert_eq!(reloaded.checksum(bank).unwrap(), snapshot.checksum(bank).unwrap(), "Checksum for bank {} changed across save and load", bank);
+        }
+    }
+
+    #[test]
+    fn test_write_mapped_48k() {
+        let bin = std::fs::read("128k.sna").expect("Failed to read snapshot file");
+        let snapshot = Snapshot::try_from(bin).expect("Failed to parse snapshot");
+        let mut written = Vec::new();
+        snapshot.write_mapped_48k_to(&mut written).expect("Failed to write snapshot");
+        assert_eq!(written.len(), 49179);
+
+        let reloaded = Snapshot::try_from(written).expect("Failed to parse written snapshot");
+        assert_eq!(reloaded.snapshot_type, SnapshotType::Snapshot48);
+        let sp = { reloaded.header.sp };
+        assert_eq!(sp, { snapshot.header.sp } - 2);
+        assert_eq!(reloaded.peek_word(sp).unwrap(), { snapshot.extension.unwrap().pc }, "PC was not pushed onto the stack");
+        for address in (0x4000..sp).chain(sp + 2..=0xFFFF) {
+            assert_eq!(reloaded.peek(address), snapshot.peek(address), "Mapped memory differs at 0x{:04X}", address);
+        }
+    }
+
+    #[test]
+    fn test_write_invalid() {
+        let error = |snapshot: &Snapshot| snapshot.write_to(Vec::new()).unwrap_err().into_inner().unwrap().downcast::<SnaError>().unwrap();
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.extension = None;
+        assert!(matches!(*error(&snapshot), SnaError::InvalidFormat(_)), "A 128K snapshot needs an extension");
+        assert!(snapshot.write_mapped_48k_to(Vec::new()).is_err());
+
+        let mut snapshot = Snapshot::new_128k();
+        snapshot.banks.truncate(7);
+        assert!(matches!(*error(&snapshot), SnaError::InvalidFormat(_)), "A 128K snapshot needs 8 banks");
+        let mut snapshot = Snapshot::new_48k();
+        snapshot.banks.pop();
+        let mut written = Vec::new();
+        assert!(snapshot.write_to(&mut written).is_err());
+        assert!(written.is_empty(), "Nothing is written for an invalid snapshot");
+    }
+
+    #[test]
+    fn test_write_mapped_48k_stack() {
+        let mut snapshot = Snapshot::new_128k();
+        let error = |snapshot: &Snapshot| snapshot.write_mapped_48k_to(Vec::new()).unwrap_err().into_inner().unwrap().downcast::<SnaError>().unwrap();
+        snapshot.header.sp = 0x4001;
+        assert!(matches!(*error(&snapshot), SnaError::RomWrite(0x3FFF)));
+        snapshot.header.sp = 0x0001;
+        assert!(matches!(*error(&snapshot), SnaError::AddressOverflow { address: 0xFFFF, len: 2 }));
+        snapshot.header.sp = 0x0000;
+        let mut written = Vec::new();
+        snapshot.write_mapped_48k_to(&mut written).expect("A stack at the top of memory wraps to 0xFFFE");
+        assert_eq!({ Snapshot::try_from(written).unwrap().header.sp }, 0xFFFE);
+    }
+}
@@ -0,0 +1,306 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{AyState, Bank, Machine, SnaError, Snapshot, SnapshotExtension, SnapshotHeader, SnapshotType, MEM_16K, MEM_48K};
+
+const V1_HEADER_LEN: usize = 30;
+const V2_EXTRA_LEN: usize = 23;
+const V3_EXTRA_LEN: usize = 54;
+const UNCOMPRESSED_BLOCK: usize = 0xFFFF;
+
+fn invalid<T>(message: &str) -> Result<T, SnaError> {
+    Err(SnaError::InvalidFormat(format!(".Z80 {}", message)))
+}
+
+// expands ED ED nn bb runs until len bytes are produced, ignoring anything after them
+fn decompress(data: &[u8], len: usize) -> Result<Vec<u8>, SnaError> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while out.len() < len && i < data.len() {
+        if data[i] == 0xED && data.get(i + 1) == Some(&0xED) {
+            let (Some(&count), Some(&value)) = (data.get(i + 2), data.get(i + 3)) else {
+                return invalid("run is cut short");
+            };
+            out.resize(out.len() + count as usize, value);
+            i += 4;
+        } else {
+            out.push(data[i]);
+            i += 1;
+        }
+    }
+    if out.len() != len {
+        return invalid("memory block does not expand to the expected size");
+    }
+    Ok(out)
+}
+
+// the machine a hardware mode stands for, or None for machines this crate cannot hold;
+// the modify flag turns a 48K into a 16K, a 128K into a +2 and a +3 into a +2A
+fn machine(hardware: u8, extra_len: usize, modified: bool) -> Option<Machine> {
+    let machine = match (extra_len, hardware) {
+        (V2_EXTRA_LEN, 0 | 1) => Machine::Spectrum48,
+        (V2_EXTRA_LEN, 3 | 4) => Machine::Spectrum128,
+        (V2_EXTRA_LEN, _) => return None,
+        (_, 0 | 1 | 3) => Machine::Spectrum48,
+        (_, 4..=6) => Machine::Spectrum128,
+        (_, 7 | 8) => Machine::SpectrumPlus3,
+        (_, 9) => Machine::Pentagon128,
+        (_, 12) => Machine::SpectrumPlus2,
+        (_, 13) => Machine::SpectrumPlus2A,
+        _ => return None,
+    };
+    Some(match (machine, modified) {
+        (Machine::Spectrum48, true) => Machine::Spectrum16,
+        (Machine::Spectrum128, true) => Machine::SpectrumPlus2,
+        (Machine::SpectrumPlus3, true) => Machine::SpectrumPlus2A,
+        _ => machine,
+    })
+}
+
+impl Snapshot {
+    /// from_z80 builds a snapshot from the contents of a .Z80 file of any version,
+    /// expanding compressed memory. 48K snapshots have their PC pushed onto the stack,
+    /// as the .sna format expects. Version 2 and 3 files record the machine they were
+    /// taken on, which machine() then returns, and the last write to port 0x1FFD of a
+    /// +2A/+3 is restored if the file has it. Returns
+    /// SnaError::InvalidFormat if the data is not a .Z80 snapshot this crate can hold.
+    pub fn from_z80(bin: &[u8]) -> Result<Snapshot, SnaError> {
+        if bin.len() < V1_HEADER_LEN {
+            return invalid("header is cut short");
+        }
+        let word = |offset: usize| u16::from_le_bytes([bin[offset], bin[offset + 1]]);
+        let flags = if bin[12] == 0xFF { 1 } else { bin[12] };
+
+        let header = SnapshotHeader {
+            i: bin[10],
+            hl_prime: u16::from_le_bytes([bin[19], bin[20]]),
+            de_prime: u16::from_le_bytes([bin[17], bin[18]]),
+            bc_prime: u16::from_le_bytes([bin[15], bin[16]]),
+            af_prime: u16::from_le_bytes([bin[22], bin[21]]),
+            hl: u16::from_le_bytes([bin[4], bin[5]]),
+            de: u16::from_le_bytes([bin[13], bin[14]]),
+            bc: u16::from_le_bytes([bin[2], bin[3]]),
+            iy: word(23),
+            ix: word(25),
+            interrupt: if bin[28] != 0 { 0x04 } else { 0x00 },
+            r: (bin[11] & 0x7F) | ((flags & 0x01) << 7),
+            af: u16::from_le_bytes([bin[1], bin[0]]),
+            sp: word(8),
+            int_mode: bin[29] & 0x03,
+            border_color: (flags >> 1) & 0x07,
+        };
+
+        let mut snapshot = Snapshot { header, ..Snapshot::default() };
+        let pc = word(6);
+        if pc != 0 {
+            // version 1: a 48K snapshot with memory in one block
+            let data = &bin[V1_HEADER_LEN..];
+            let memory = if flags & 0x20 != 0 {
+                decompress(data, MEM_48K)?
+            } else if data.len() >= MEM_48K {
+                data[..MEM_48K].to_vec()
+            } else {
+                return invalid("memory is cut short");
+            };
+            snapshot.banks = memory.chunks(MEM_16K).map(Bank::from_slice).collect::<Result<_, _>>()?;
+            snapshot.mapping = [0, 1, 2];
+            snapshot.remap();
+            snapshot.push_pc(pc)?;
+            return Ok(snapshot);
+        }
+
+        // versions 2 and 3: an extra header followed by a block per 16K page
+        if bin.len() < V1_HEADER_LEN + 2 {
+            return invalid("extra header is cut short");
+        }
+        let extra_len = word(30) as usize;
+        if ![V2_EXTRA_LEN, V3_EXTRA_LEN, V3_EXTRA_LEN + 1].contains(&extra_len) {
+            return invalid("extra header has an unknown length");
+        }
+        let mut offset = V1_HEADER_LEN + 2 + extra_len;
+        if bin.len() < offset {
+            return invalid("extra header is cut short");
+        }
+        let pc = word(32);
+        let Some(machine) = machine(bin[34], extra_len, bin[37] & 0x80 != 0) else {
+            return Err(SnaError::InvalidFormat(format!("Unsupported .Z80 hardware mode {}", bin[34])));
+        };
+        let is_128k = machine.snapshot_type() == SnapshotType::Snapshot128;
+
+        let bank_count = if is_128k { 8 } else { 3 };
+        let mut banks: Vec<Option<Bank>> = vec![None; bank_count];
+        while offset < bin.len() {
+            if bin.len() < offset + 3 {
+                return invalid("memory block header is cut short");
+            }
+            let len = word(offset) as usize;
+            let page = bin[offset + 2];
+            offset += 3;
+            let stored = if len == UNCOMPRESSED_BLOCK { MEM_16K } else { len };
+            let Some(data) = bin.get(offset..offset + stored) else {
+                return invalid("memory block is cut short");
+            };
+            offset += stored;
+
+            let index = match (is_128k, page) {
+                (true, 3..=10) => page as usize - 3,
+                (false, 8) => 0,
+                (false, 4) => 1,
+                (false, 5) => 2,
+                _ => continue,  // ROM and interface pages
+            };
+            let memory = if len == UNCOMPRESSED_BLOCK { data.to_vec() } else { decompress(data, MEM_16K)? };
+            banks[index] = Some(Bank::from_slice(&memory)?);
+        }
+        snapshot.banks = match banks.into_iter().collect::<Option<Vec<Bank>>>() {
+            Some(banks) => banks,
+            None => return invalid("snapshot is missing a RAM page"),
+        };
+
+        if is_128k {
+            let x7ffd = bin[35];
+            snapshot.snapshot_type = SnapshotType::Snapshot128;
+            snapshot.extension = Some(SnapshotExtension { pc, x7ffd, tr_dos: 0 });
+            snapshot.mapping = [5, 2, x7ffd & 0x07];
+            let mut registers = [0u8; 16];
+            registers.copy_from_slice(&bin[39..55]);
+            snapshot.ay = Some(AyState { registers, selected: bin[38] & 0x0F });
+            snapshot.set_machine(machine)?;
+            if machine.has_0x1ffd() && extra_len == V3_EXTRA_LEN + 1 {
+                snapshot.x1ffd = Some(bin[V1_HEADER_LEN + 2 + V3_EXTRA_LEN]);
+                snapshot.remap();
+            }
+        } else {
+            snapshot.mapping = [0, 1, 2];
+            snapshot.set_machine(machine)?;
+            snapshot.push_pc(pc)?;
+        }
+        Ok(snapshot)
+    }
+
+    /// load_z80 reads a .Z80 file and builds a snapshot from it.
+    pub fn load_z80<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnaError> {
+        Snapshot::from_z80(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a simple encoder: runs of five or more, or two or more 0xED, become ED ED nn bb
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let byte = data[i];
+            let run = data[i..].iter().take(255).take_while(|&&b| b == byte).count();
+            if run >= 5 || (byte == 0xED && run >= 2) {
+                out.extend_from_slice(&[0xED, 0xED, run as u8, byte]);
+                i += run;
+            } else {
+                out.push(byte);
+                i += 1;
+                // the byte after a single 0xED is never the start of a run
+                if byte == 0xED && i < data.len() {
+                    out.push(data[i]);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    fn v1_header(pc: u16, sp: u16, flags: u8) -> Vec<u8> {
+        let mut header = vec![0u8; V1_HEADER_LEN];
+        header[0] = 0x12;   // A
+        header[1] = 0x34;   // F
+        header[6..8].copy_from_slice(&pc.to_le_bytes());
+        header[8..10].copy_from_slice(&sp.to_le_bytes());
+        header[11] = 0x05;
+        header[12] = flags;
+        header[28] = 1;     // IFF2
+        header[29] = 1;     // IM 1
+        header
+    }
+
+    #[test]
+    fn test_decompress() {
+        assert_eq!(decompress(&[1, 0xED, 0xED, 3, 7, 0xED, 2], 6).ok(), Some(vec![1, 7, 7, 7, 0xED, 2]));
+        assert!(decompress(&[0xED, 0xED, 3], 3).is_err(), "A run cut short should fail");
+        assert!(decompress(&[1, 2], 3).is_err(), "Too little data should fail");
+        let data: Vec<u8> = (0..MEM_16K).map(|i| if i % 1000 < 20 { 0xED } else { (i / 300) as u8 }).collect();
+        assert_eq!(decompress(&compress(&data), MEM_16K).ok(), Some(data));
+    }
+
+    #[test]
+    fn test_v1_48k() {
+        let sna = Snapshot::try_from(fs::read("48k.sna").expect("Failed to read snapshot file")).expect("Failed to parse snapshot");
+        let memory: Vec<u8> = (0x4000..=0xFFFF).map(|address| sna.peek(address)).collect();
+
+        // border 3, R bit 7 set, compressed
+        let mut bin = v1_header(0x8000, 0xFF00, 0x27);
+        bin.extend_from_slice(&compress(&memory));
+        bin.extend_from_slice(&[0x00, 0xED, 0xED, 0x00]);
+        let snapshot = Snapshot::from_z80(&bin).expect("Failed to read .Z80");
+
+        assert_eq!(snapshot.snapshot_type, SnapshotType::Snapshot48);
+        assert_eq!({ snapshot.header.af }, 0x1234);
+        assert_eq!({ snapshot.header.r }, 0x85);
+        assert_eq!(snapshot.header.border_color, 3);
+        assert_eq!((snapshot.header.interrupt, snapshot.header.int_mode), (0x04, 1));
+        assert_eq!({ snapshot.header.sp }, 0xFEFE, "The PC should be pushed onto the stack");
+        assert_eq!(snapshot.peek_word(0xFEFE).ok(), Some(0x8000));
+        assert!((0x4000..0xFEFE).all(|address| snapshot.peek(address) == sna.peek(address)), "Memory differs from the source");
+
+        let mut bin = v1_header(0x8000, 0x4001, 0x00);
+        bin.extend_from_slice(&memory);
+        assert!(Snapshot::from_z80(&bin).is_err(), "The PC cannot be pushed into ROM");
+    }
+
+    #[test]
+    fn test_v3_128k() {
+        let sna = Snapshot::try_from(fs::read("128k.sna").expect("Failed to read snapshot file")).expect("Failed to parse snapshot");
+        let mut bin = v1_header(0, 0x6000, 0x00);
+        let mut extra = vec![0u8; 2 + V3_EXTRA_LEN];
+        extra[0..2].copy_from_slice(&(V3_EXTRA_LEN as u16).to_le_bytes());
+        extra[2..4].copy_from_slice(&0xABCDu16.to_le_bytes());
+        extra[4] = 4;       // 128K
+        extra[5] = 0x13;    // bank 3 paged, ROM 1
+        extra[8] = 0x07;    // AY register 7 selected
+        extra[9..25].copy_from_slice(&[0x10; 16]);
+        bin.extend_from_slice(&extra);
+        for bank in (0..8).rev() {
+            let memory = &sna.banks[bank][..];
+            let compressed = compress(memory);
+            if bank == 6 {
+                bin.extend_from_slice(&[0xFF, 0xFF, bank as u8 + 3]);
+                bin.extend_from_slice(memory);
+            } else {
+                bin.extend_from_slice(&(compressed.len() as u16).to_le_bytes());
+                bin.push(bank as u8 + 3);
+                bin.extend_from_slice(&compressed);
+            }
+        }
+
+        let snapshot = Snapshot::from_z80(&bin).expect("Failed to read .Z80");
+        assert_eq!(snapshot.snapshot_type, SnapshotType::Snapshot128);
+        assert_eq!(snapshot.mapping, [5, 2, 3]);
+        assert_eq!(snapshot.extension.map(|extension| (extension.pc, extension.x7ffd)), Some((0xABCD, 0x13)));
+        assert_eq!(snapshot.ay.as_ref().map(|ay| (ay.selected, ay.registers[15])), Some((7, 0x10)));
+        for bank in 0..8 {
+            assert_eq!(snapshot.checksum(bank).unwrap(), sna.checksum(bank).unwrap(), "Checksum for bank {} differs", bank);
+        }
+        assert_eq!(snapshot.machine(), Machine::Spectrum128);
+        bin[37] = 0x80;     // modified: a +2
+        assert_eq!(Snapshot::from_z80(&bin).unwrap().machine(), Machine::SpectrumPlus2);
+        bin[34] = 7;        // a modified +3 is a +2A
+        assert_eq!(Snapshot::from_z80(&bin).unwrap().machine(), Machine::SpectrumPlus2A);
+        bin[34] = 10;
+        assert!(Snapshot::from_z80(&bin).is_err(), "A Scorpion cannot be read from a .Z80 file");
+        bin[34] = 4;
+
+        bin.truncate(bin.len() - 1);
+        assert!(Snapshot::from_z80(&bin).is_err(), "A truncated block should fail");
+    }
+}
@@ -0,0 +1,92 @@
+//! The 5-byte number format of the Spectrum's calculator, used for numbers in BASIC
+//! lines and variables.
+//!
+//! Whole numbers from -65535 to 65535 are usually stored as a small integer: a zero
+//! byte, 0x00 or 0xFF for the sign, the value as a little-endian word, and a zero byte.
+//! Anything else is floating point: an exponent biased by 128 in the first byte, then
+//! a 32-bit big-endian mantissa between 0.5 and 1 whose always-set top bit is replaced
+//! by the sign. A first byte of zero with any other layout is zero.
+
+/// decode returns the value of a number in 5-byte form.
+pub fn decode(bytes: &[u8; 5]) -> f64 {
+    match *bytes {
+        [0, sign @ (0x00 | 0xFF), low, high, 0] => {
+            let value = u16::from_le_bytes([low, high]) as f64;
+            if sign == 0xFF { value - 65536.0 } else { value }
+        }
+        [0, ..] => 0.0,
+        [exponent, a, b, c, d] => {
+            let mantissa = u32::from_be_bytes([a | 0x80, b, c, d]) as f64 / 4_294_967_296.0;
+            let value = mantissa * 2f64.powi(exponent as i32 - 128);
+            if a & 0x80 != 0 { -value } else { value }
+        }
+    }
+}
+
+/// encode returns the 5-byte form the ROM would store a number in: a small integer
+/// for whole numbers from -65535 to 65535 and floating point otherwise, rounded to
+/// the 32-bit mantissa. Numbers too small to represent become zero, as they do on the
+/// Spectrum. Returns None for NaN, infinities and numbers of 2^127 or more, which the
+/// ROM reports as "Number too big".
+pub fn encode(value: f64) -> Option<[u8; 5]> {
+    if !value.is_finite() {
+        return None;
+    }
+    if value.fract() == 0.0 && value.abs() <= 65535.0 {
+        let [low, high] = ((value as i32) as u16).to_le_bytes();
+        let sign = if value < 0.0 { 0xFF } else { 0x00 };
+        return Some([0, sign, low, high, 0]);
+    }
+    // value = mantissa * 2^exponent with the mantissa from 0.5 up to 1
+    let mut exponent = value.abs().log2().floor() as i32 + 1;
+    let mut mantissa = (value.abs() / 2f64.powi(exponent) * 4_294_967_296.0).round();
+    if mantissa >= 4_294_967_296.0 {
+        mantissa /= 2.0;
+        exponent += 1;
+    }
+    if exponent > 127 {
+        return None;
+    }
+    if exponent < -127 {
+        return Some([0; 5]);
+    }
+    let [a, b, c, d] = (mantissa as u32).to_be_bytes();
+    let sign = if value < 0.0 { 0x80 } else { 0x00 };
+    Some([(exponent + 128) as u8, a & 0x7F | sign, b, c, d])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(&[0x00, 0x00, 0x0A, 0x00, 0x00]), 10.0);
+        assert_eq!(decode(&[0x00, 0xFF, 0xFF, 0xFF, 0x00]), -1.0);
+        assert_eq!(decode(&[0x00, 0x00, 0xFF, 0xFF, 0x00]), 65535.0);
+        assert_eq!(decode(&[0x81, 0x00, 0x00, 0x00, 0x00]), 1.0);
+        assert_eq!(decode(&[0x80, 0x00, 0x00, 0x00, 0x00]), 0.5);
+        assert_eq!(decode(&[0x80, 0x80, 0x00, 0x00, 0x00]), -0.5);
+        assert!((decode(&[0x82, 0x49, 0x0F, 0xDA, 0xA2]) - std::f64::consts::PI).abs() < 1e-9, "PI as the ROM stores it");
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(0.0), Some([0; 5]));
+        assert_eq!(encode(10.0), Some([0x00, 0x00, 0x0A, 0x00, 0x00]));
+        assert_eq!(encode(-1.0), Some([0x00, 0xFF, 0xFF, 0xFF, 0x00]));
+        assert_eq!(encode(-65535.0), Some([0x00, 0xFF, 0x01, 0x00, 0x00]));
+        assert_eq!(encode(65536.0), Some([0x91, 0x00, 0x00, 0x00, 0x00]));
+        assert_eq!(encode(0.5), Some([0x80, 0x00, 0x00, 0x00, 0x00]));
+        assert_eq!(encode(-0.5), Some([0x80, 0x80, 0x00, 0x00, 0x00]));
+        assert_eq!(encode(std::f64::consts::PI), Some([0x82, 0x49, 0x0F, 0xDA, 0xA2]));
+        assert_eq!(encode(1e-40), Some([0; 5]), "Too small to represent");
+        assert_eq!(encode(1e39), None);
+        assert_eq!(encode(f64::NAN), None);
+
+        for value in [-65535.0, -1.0, 0.0, 1.0, 0.25, 1.5, -3.75, 1e6, 123456.5, 1.0 / 3.0] {
+            let bytes = encode(value).unwrap();
+            assert!((decode(&bytes) - value).abs() <= value.abs() / 2f64.powi(31), "{} did not survive encoding", value);
+        }
+    }
+}